@@ -0,0 +1,379 @@
+//! Content analysis used for method selection
+//!
+//! Every statistic the compressor bases its choices on is available here so
+//! profiles can be logged and compression policy tuned offline.
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// Block size used by [`repeated_block_fraction`] in [`ContentProfile`]
+pub const REPETITION_BLOCK_SIZE: usize = 64;
+
+/// Share of duplicate blocks above which data counts as block-repetitive
+pub const REPETITION_THRESHOLD: f64 = 0.1;
+
+/// Entropy spread (bits per byte) between windows above which content counts as mixed
+pub const MIXED_CONTENT_SPREAD: f64 = 2.0;
+
+/// Bytes [`predicted_ratio`] samples: this many from each of four evenly
+/// spaced spots in the input
+pub const PROBE_WINDOW: usize = 4096;
+
+/// Windows [`crate::Compressor::estimate_ratio`] compresses from large inputs
+pub const ESTIMATE_SAMPLES: usize = 8;
+
+/// Bytes in each window [`crate::Compressor::estimate_ratio`] compresses
+pub const ESTIMATE_WINDOW: usize = 64 * 1024;
+
+/// Shortest repeat counted by the probe's match pass
+const PROBE_MIN_MATCH: usize = 4;
+
+/// Order-0 byte statistics, the part of a [`ContentProfile`] that can be
+/// gathered from a histogram alone
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ByteStats {
+    pub len: usize,
+    /// Occurrences of each byte value, indexed by byte
+    pub histogram: Vec<u64>,
+    pub distinct_bytes: usize,
+    /// Order-0 Shannon entropy in bits per byte
+    pub entropy: f64,
+}
+
+impl ByteStats {
+    /// Statistics of the input counted in `histogram`
+    pub fn from_histogram(histogram: &[u64; 256]) -> Self {
+        Self {
+            len: histogram.iter().sum::<u64>() as usize,
+            histogram: histogram.to_vec(),
+            distinct_bytes: histogram.iter().filter(|&&c| c > 0).count(),
+            entropy: entropy_from_histogram(histogram),
+        }
+    }
+}
+
+/// Summary statistics of a buffer
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ContentProfile {
+    #[serde(flatten)]
+    pub bytes: ByteStats,
+    /// Entropy of each byte given the previous one, in bits per byte
+    pub order1_entropy: f64,
+    /// Fraction of bytes equal to the byte before them
+    pub run_fraction: f64,
+    /// Fraction of full 64-byte blocks that repeat an earlier block
+    pub repeated_block_fraction: f64,
+}
+
+impl ContentProfile {
+    /// Analyze `data`
+    pub fn of(data: &[u8]) -> Self {
+        Self {
+            bytes: ByteStats::from_histogram(&byte_histogram(data)),
+            order1_entropy: order1_entropy(data),
+            run_fraction: run_fraction(data),
+            repeated_block_fraction: repeated_block_fraction(data, REPETITION_BLOCK_SIZE),
+        }
+    }
+
+    /// Whether enough blocks repeat for dedup to pay off
+    pub fn has_repeated_blocks(&self) -> bool {
+        self.repeated_block_fraction > REPETITION_THRESHOLD
+    }
+}
+
+/// Count occurrences of every byte value
+pub fn byte_histogram(data: &[u8]) -> [u64; 256] {
+    let mut freq = [0u64; 256];
+    for &b in data {
+        freq[b as usize] += 1;
+    }
+    freq
+}
+
+/// Shannon entropy of data in bits per byte
+pub fn entropy(data: &[u8]) -> f64 {
+    entropy_from_histogram(&byte_histogram(data))
+}
+
+/// Shannon entropy in bits per byte of a byte histogram
+pub fn entropy_from_histogram(freq: &[u64; 256]) -> f64 {
+    let len = freq.iter().sum::<u64>() as f64;
+    if len == 0.0 {
+        return 0.0;
+    }
+    let mut entropy = 0.0;
+    for &f in freq {
+        if f > 0 {
+            let p = f as f64 / len;
+            entropy -= p * p.log2();
+        }
+    }
+    entropy
+}
+
+/// Entropy of one window of an [`entropy_profile`]
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct WindowEntropy {
+    pub offset: usize,
+    pub len: usize,
+    /// Order-0 entropy of the window in bits per byte
+    pub entropy: f64,
+}
+
+/// Entropy of consecutive `window`-byte windows of `data`; the last window may be short
+pub fn entropy_profile(data: &[u8], window: usize) -> Vec<WindowEntropy> {
+    data.chunks(window.max(1))
+        .enumerate()
+        .map(|(i, chunk)| WindowEntropy {
+            offset: i * window.max(1),
+            len: chunk.len(),
+            entropy: entropy(chunk),
+        })
+        .collect()
+}
+
+/// Whether window entropies differ by more than [`MIXED_CONTENT_SPREAD`],
+/// e.g. a text header in front of random data
+pub fn is_mixed_content(data: &[u8], window: usize) -> bool {
+    let profile = entropy_profile(data, window);
+    let (min, max) = profile
+        .iter()
+        .fold((f64::MAX, f64::MIN), |(lo, hi), w| (lo.min(w.entropy), hi.max(w.entropy)));
+    profile.len() > 1 && max - min > MIXED_CONTENT_SPREAD
+}
+
+/// Conditional entropy of each byte given its predecessor, in bits per byte
+pub fn order1_entropy(data: &[u8]) -> f64 {
+    if data.len() < 2 {
+        return 0.0;
+    }
+    let mut pairs = vec![0u64; 256 * 256];
+    for w in data.windows(2) {
+        pairs[(w[0] as usize) << 8 | w[1] as usize] += 1;
+    }
+    let total = (data.len() - 1) as f64;
+    let mut entropy = 0.0;
+    for context in pairs.chunks_exact(256) {
+        let count: u64 = context.iter().sum();
+        if count == 0 {
+            continue;
+        }
+        let mut h = 0.0;
+        for &f in context {
+            if f > 0 {
+                let p = f as f64 / count as f64;
+                h -= p * p.log2();
+            }
+        }
+        entropy += count as f64 / total * h;
+    }
+    entropy
+}
+
+/// Fraction of bytes equal to the byte before them
+pub fn run_fraction(data: &[u8]) -> f64 {
+    if data.len() < 2 {
+        return 0.0;
+    }
+    let repeats = data.windows(2).filter(|w| w[0] == w[1]).count();
+    repeats as f64 / (data.len() - 1) as f64
+}
+
+/// Multiplier of the polynomial rolling hash in [`repeated_block_fraction`]
+const ROLLING_BASE: u64 = 0x100000001b3;
+
+/// Fraction of full `block_size` blocks whose content appeared earlier in
+/// `data` at any offset. A Rabin-Karp hash rolls over every window, so a
+/// repeat shifted off the block grid counts as well as an aligned one.
+pub fn repeated_block_fraction(data: &[u8], block_size: usize) -> f64 {
+    let block_size = block_size.max(1);
+    let total_blocks = data.len() / block_size;
+    if total_blocks == 0 {
+        return 0.0;
+    }
+    let hash = |window: &[u8]| {
+        window
+            .iter()
+            .fold(0u64, |h, &b| h.wrapping_mul(ROLLING_BASE).wrapping_add(u64::from(b)))
+    };
+    // Blocks not yet seen earlier, by hash
+    let mut pending: HashMap<u64, Vec<usize>> = HashMap::new();
+    for (i, block) in data.chunks_exact(block_size).enumerate() {
+        pending.entry(hash(block)).or_default().push(i);
+    }
+    let top = (1..block_size).fold(1u64, |p, _| p.wrapping_mul(ROLLING_BASE));
+    let mut duplicates = 0;
+    let mut h = hash(&data[..block_size]);
+    for pos in 0..=data.len() - block_size {
+        if pos > 0 {
+            h = h
+                .wrapping_sub(u64::from(data[pos - 1]).wrapping_mul(top))
+                .wrapping_mul(ROLLING_BASE)
+                .wrapping_add(u64::from(data[pos + block_size - 1]));
+        }
+        if let Some(blocks) = pending.get_mut(&h) {
+            let window = &data[pos..pos + block_size];
+            // Only blocks starting after `pos` can still be found earlier
+            blocks.retain(|&b| {
+                let start = b * block_size;
+                if start <= pos {
+                    return false;
+                }
+                let repeated = data[start..start + block_size] == *window;
+                duplicates += repeated as usize;
+                !repeated
+            });
+        }
+    }
+    duplicates as f64 / total_blocks as f64
+}
+
+/// Whether `data` has enough repeated 64-byte blocks for dedup to pay off
+pub fn has_repeated_blocks(data: &[u8]) -> bool {
+    has_repeated_blocks_in(data, REPETITION_BLOCK_SIZE)
+}
+
+/// [`has_repeated_blocks`] with `window`-byte blocks
+pub fn has_repeated_blocks_in(data: &[u8], window: usize) -> bool {
+    let window = window.max(1);
+    data.len() >= 2 * window && repeated_block_fraction(data, window) > REPETITION_THRESHOLD
+}
+
+/// Fraction of bytes covered by greedy matches of at least four bytes
+/// against earlier data, found through a single-slot hash table
+pub fn match_fraction(data: &[u8]) -> f64 {
+    if data.len() < PROBE_MIN_MATCH {
+        return 0.0;
+    }
+    let mut table = vec![usize::MAX; 1 << 12];
+    let hash = |w: &[u8]| (u32::from_le_bytes([w[0], w[1], w[2], w[3]]).wrapping_mul(2654435761) >> 20) as usize;
+    let mut covered = 0;
+    let mut pos = 0;
+    while pos + PROBE_MIN_MATCH <= data.len() {
+        let slot = hash(&data[pos..]);
+        let candidate = table[slot];
+        table[slot] = pos;
+        if candidate != usize::MAX && data[candidate..candidate + PROBE_MIN_MATCH] == data[pos..pos + PROBE_MIN_MATCH] {
+            let len = data[pos..]
+                .iter()
+                .zip(&data[candidate..])
+                .take_while(|(a, b)| a == b)
+                .count();
+            covered += len;
+            pos += len;
+        } else {
+            pos += 1;
+        }
+    }
+    covered as f64 / data.len() as f64
+}
+
+/// Cheap estimate of the compressed-to-original ratio from a sample: bytes
+/// covered by repeats count as nearly free, the rest cost their order-0 entropy
+pub fn predicted_ratio(data: &[u8]) -> f64 {
+    let sample: Vec<u8> = if data.len() <= 4 * PROBE_WINDOW {
+        data.to_vec()
+    } else {
+        let stride = (data.len() - PROBE_WINDOW) / 3;
+        (0..4)
+            .flat_map(|i| &data[i * stride..i * stride + PROBE_WINDOW])
+            .copied()
+            .collect()
+    };
+    let matched = match_fraction(&sample);
+    (1.0 - matched) * entropy(&sample) / 8.0 + matched * 0.1
+}
+
+/// Whether compressing `data` is predicted to save less than `1 - threshold`
+pub fn is_incompressible(data: &[u8], threshold: f64) -> bool {
+    predicted_ratio(data) > threshold
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_order1_below_order0_for_patterns() {
+        let data = b"abcd".repeat(100);
+        assert!((entropy(&data) - 2.0).abs() < 1e-9);
+        assert!(order1_entropy(&data) < 1e-9);
+    }
+
+    #[test]
+    fn test_probe_separates_random_from_text() {
+        let mut state = 0x9E37_79B9_7F4A_7C15u64;
+        let random: Vec<u8> = (0..50_000)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                state as u8
+            })
+            .collect();
+        assert!(is_incompressible(&random, 0.95));
+        let text = b"log line with some words and a number 12345\n".repeat(1000);
+        assert!(match_fraction(&text) > 0.9);
+        assert!(predicted_ratio(&text) < 0.2);
+        assert!(!is_incompressible(&text, 0.95));
+    }
+
+    #[test]
+    fn test_shifted_repeats_detected() {
+        let mut state = 0x2545_F491_4F6C_DD1Du64;
+        let noise: Vec<u8> = (0..2000)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                state as u8
+            })
+            .collect();
+        let mut data = noise.clone();
+        data.extend_from_slice(&[9; 17]);
+        data.extend_from_slice(&noise);
+        // No aligned block of the second copy equals an aligned block of the first
+        assert!(repeated_block_fraction(&data, 64) > 0.4);
+        assert!(has_repeated_blocks(&data));
+        assert!(has_repeated_blocks_in(&data, 100));
+        assert_eq!(repeated_block_fraction(&noise, 64), 0.0);
+    }
+
+    #[test]
+    fn test_run_fraction() {
+        assert_eq!(run_fraction(b"aaaa"), 1.0);
+        assert_eq!(run_fraction(b"abab"), 0.0);
+        assert_eq!(run_fraction(b"a"), 0.0);
+    }
+
+    #[test]
+    fn test_profile() {
+        let data = vec![7u8; 640];
+        let profile = ContentProfile::of(&data);
+        assert_eq!(profile.bytes.len, 640);
+        assert_eq!(profile.bytes.distinct_bytes, 1);
+        assert_eq!(profile.bytes.histogram[7], 640);
+        // Byte statistics serialize inline, as they did before being split out
+        let json = serde_json::to_value(&profile).unwrap();
+        assert_eq!(json["distinct_bytes"], 1);
+        assert_eq!(serde_json::from_value::<ContentProfile>(json).unwrap(), profile);
+        assert!((profile.repeated_block_fraction - 0.9).abs() < 1e-9);
+        assert!(profile.has_repeated_blocks());
+        assert!(!has_repeated_blocks(&(0..=255u8).collect::<Vec<_>>()));
+    }
+
+    #[test]
+    fn test_entropy_profile_finds_text_in_noise() {
+        let mut data = b"HEADER ".repeat(147);
+        data.extend((0..4096u32).map(|i| (i.wrapping_mul(2654435761) >> 13) as u8));
+        let profile = entropy_profile(&data, 1029);
+        assert_eq!(profile[0].offset, 0);
+        assert_eq!(profile[1].offset, 1029);
+        assert_eq!(profile.iter().map(|w| w.len).sum::<usize>(), data.len());
+        assert!(profile[0].entropy < 3.0);
+        assert!(profile.last().unwrap().entropy > 6.0);
+        assert!(is_mixed_content(&data, 1029));
+        assert!(!is_mixed_content(&b"HEADER ".repeat(600), 1029));
+    }
+}
@@ -0,0 +1,415 @@
+//! Append-only log compression with periodic checkpoints
+//!
+//! Records are buffered into windows. Each window is Huffman-coded with an
+//! adaptive model learned from every window since the last checkpoint; the
+//! decoder replays the same updates, so no code tables are transmitted. A
+//! checkpoint resets the model, which makes it a valid starting point for
+//! decoding without reading anything before it.
+//!
+//! Stream layout:
+//! ```text
+//! checkpoint: [b'C'][first_record:u64]
+//! window:     [b'W'][record_count:u32][raw_len:u32][payload_len:u32][payload]
+//! ```
+//! The raw window contents are `[record_len:u32][record]...`.
+
+use crate::config::LogConfig;
+use crate::error::CompressError;
+use crate::huffman::HuffmanModel;
+use std::io::Write;
+
+const TAG_CHECKPOINT: u8 = b'C';
+const TAG_WINDOW: u8 = b'W';
+
+/// Counts are halved once their sum passes this, so old windows fade out
+const MODEL_LIMIT: u64 = 1 << 20;
+
+/// Byte frequencies learned from previous windows
+#[derive(Debug, Clone)]
+struct AdaptiveModel {
+    counts: [u64; 256],
+}
+
+impl AdaptiveModel {
+    fn new() -> Self {
+        // Every symbol starts at 1 so any byte stays encodable
+        Self { counts: [1; 256] }
+    }
+
+    fn huffman(&self) -> HuffmanModel {
+        HuffmanModel::from_frequencies(&self.counts).expect("model counts are never zero")
+    }
+
+    fn update(&mut self, data: &[u8]) {
+        for &b in data {
+            self.counts[b as usize] += 1;
+        }
+        if self.counts.iter().sum::<u64>() > MODEL_LIMIT {
+            for c in &mut self.counts {
+                *c = (*c / 2).max(1);
+            }
+        }
+    }
+}
+
+/// A point in the stream where decoding can begin
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Checkpoint {
+    /// Byte offset of the checkpoint frame
+    pub offset: u64,
+    /// Index of the first record following the checkpoint
+    pub first_record: u64,
+}
+
+/// Compresses a continuously growing sequence of records
+pub struct LogCompressor<W: Write> {
+    sink: W,
+    config: LogConfig,
+    model: AdaptiveModel,
+    window: Vec<u8>,
+    window_records: u32,
+    windows_since_checkpoint: usize,
+    offset: u64,
+    records: u64,
+    checkpoints: Vec<Checkpoint>,
+}
+
+impl<W: Write> LogCompressor<W> {
+    pub fn new(sink: W, config: LogConfig) -> Self {
+        Self {
+            sink,
+            config,
+            model: AdaptiveModel::new(),
+            window: Vec::new(),
+            window_records: 0,
+            windows_since_checkpoint: 0,
+            offset: 0,
+            records: 0,
+            checkpoints: Vec::new(),
+        }
+    }
+
+    /// Append a record, emitting a window frame once the window is full
+    pub fn append(&mut self, record: &[u8]) -> Result<(), CompressError> {
+        let len = u32::try_from(record.len())
+            .map_err(|_| CompressError::LogError("log record exceeds 4 GB".into()))?;
+        self.window.extend_from_slice(&len.to_le_bytes());
+        self.window.extend_from_slice(record);
+        self.window_records += 1;
+        self.records += 1;
+        if self.window.len() >= self.config.window_size {
+            self.emit_window()?;
+        }
+        Ok(())
+    }
+
+    /// Emit any buffered records and flush the sink
+    pub fn flush(&mut self) -> Result<(), CompressError> {
+        self.emit_window()?;
+        self.sink.flush()?;
+        Ok(())
+    }
+
+    /// Checkpoints written so far
+    pub fn checkpoints(&self) -> &[Checkpoint] {
+        &self.checkpoints
+    }
+
+    /// Total records appended
+    pub fn records(&self) -> u64 {
+        self.records
+    }
+
+    /// Flush and return the sink
+    pub fn finish(mut self) -> Result<W, CompressError> {
+        self.flush()?;
+        Ok(self.sink)
+    }
+
+    fn emit_window(&mut self) -> Result<(), CompressError> {
+        if self.window_records == 0 {
+            return Ok(());
+        }
+        if self.windows_since_checkpoint == 0 {
+            let first_record = self.records - self.window_records as u64;
+            let mut frame = vec![TAG_CHECKPOINT];
+            frame.extend_from_slice(&first_record.to_le_bytes());
+            self.write_frame(&frame)?;
+            self.checkpoints.push(Checkpoint {
+                offset: self.offset - frame.len() as u64,
+                first_record,
+            });
+            self.model = AdaptiveModel::new();
+        }
+
+        let payload = self.model.huffman().encode(&self.window)?;
+        let mut frame = Vec::with_capacity(13 + payload.len());
+        frame.push(TAG_WINDOW);
+        frame.extend_from_slice(&self.window_records.to_le_bytes());
+        frame.extend_from_slice(&(self.window.len() as u32).to_le_bytes());
+        frame.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+        frame.extend_from_slice(&payload);
+        self.write_frame(&frame)?;
+
+        self.model.update(&self.window);
+        self.window.clear();
+        self.window_records = 0;
+        self.windows_since_checkpoint = (self.windows_since_checkpoint + 1) % self.config.checkpoint_interval.max(1);
+        Ok(())
+    }
+
+    fn write_frame(&mut self, frame: &[u8]) -> Result<(), CompressError> {
+        self.sink.write_all(frame)?;
+        self.offset += frame.len() as u64;
+        Ok(())
+    }
+}
+
+/// Iterates over the records of a compressed log
+pub struct LogReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+    model: AdaptiveModel,
+    pending: std::vec::IntoIter<Vec<u8>>,
+    skip: u64,
+}
+
+impl<'a> LogReader<'a> {
+    /// Read the whole log from the beginning
+    pub fn new(data: &'a [u8]) -> Self {
+        Self {
+            data,
+            pos: 0,
+            model: AdaptiveModel::new(),
+            pending: Vec::new().into_iter(),
+            skip: 0,
+        }
+    }
+
+    /// Start decoding at `checkpoint` without touching earlier frames
+    pub fn at(data: &'a [u8], checkpoint: &Checkpoint) -> Result<Self, CompressError> {
+        if data.get(checkpoint.offset as usize) != Some(&TAG_CHECKPOINT) {
+            return Err(CompressError::LogError("offset is not a checkpoint frame".into()));
+        }
+        let mut reader = Self::new(data);
+        reader.pos = checkpoint.offset as usize;
+        Ok(reader)
+    }
+
+    /// Start at the nearest checkpoint at or before `record`, skipping forward to it
+    pub fn from_record(data: &'a [u8], checkpoints: &[Checkpoint], record: u64) -> Result<Self, CompressError> {
+        let nearest = checkpoints
+            .iter()
+            .filter(|c| c.first_record <= record)
+            .max_by_key(|c| c.first_record);
+        let mut reader = match nearest {
+            Some(cp) => {
+                let mut r = Self::at(data, cp)?;
+                r.skip = record - cp.first_record;
+                r
+            }
+            None => {
+                let mut r = Self::new(data);
+                r.skip = record;
+                r
+            }
+        };
+        // Skipped records still have to be decoded to keep the model in sync
+        while reader.skip > 0 {
+            match reader.next() {
+                Some(Ok(_)) => reader.skip -= 1,
+                Some(Err(e)) => return Err(e),
+                None => break,
+            }
+        }
+        Ok(reader)
+    }
+
+    fn read_u32(&mut self) -> Result<u32, CompressError> {
+        let bytes = self
+            .data
+            .get(self.pos..self.pos + 4)
+            .ok_or_else(|| CompressError::LogError("truncated log frame".into()))?;
+        self.pos += 4;
+        Ok(u32::from_le_bytes(bytes.try_into().unwrap()))
+    }
+
+    /// Decode the next window frame, processing any checkpoints before it
+    fn next_window(&mut self) -> Result<Option<Vec<Vec<u8>>>, CompressError> {
+        loop {
+            let Some(&tag) = self.data.get(self.pos) else {
+                return Ok(None);
+            };
+            self.pos += 1;
+            match tag {
+                TAG_CHECKPOINT => {
+                    if self.pos + 8 > self.data.len() {
+                        return Err(CompressError::LogError("truncated checkpoint".into()));
+                    }
+                    self.pos += 8;
+                    self.model = AdaptiveModel::new();
+                }
+                TAG_WINDOW => {
+                    let count = self.read_u32()? as usize;
+                    let raw_len = self.read_u32()? as usize;
+                    let payload_len = self.read_u32()? as usize;
+                    let payload = self
+                        .data
+                        .get(self.pos..self.pos + payload_len)
+                        .ok_or_else(|| CompressError::LogError("truncated window".into()))?;
+                    self.pos += payload_len;
+
+                    let raw = self.model.huffman().decode(payload, raw_len)?;
+                    self.model.update(&raw);
+                    return split_records(&raw, count).map(Some);
+                }
+                other => {
+                    return Err(CompressError::LogError(format!("unknown log frame tag {:#x}", other)));
+                }
+            }
+        }
+    }
+}
+
+impl Iterator for LogReader<'_> {
+    type Item = Result<Vec<u8>, CompressError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(record) = self.pending.next() {
+                return Some(Ok(record));
+            }
+            match self.next_window() {
+                Ok(Some(records)) => self.pending = records.into_iter(),
+                Ok(None) => return None,
+                Err(e) => {
+                    self.pos = self.data.len();
+                    return Some(Err(e));
+                }
+            }
+        }
+    }
+}
+
+/// Rebuild the checkpoint list of a stream by scanning frame headers only
+pub fn scan_checkpoints(data: &[u8]) -> Result<Vec<Checkpoint>, CompressError> {
+    let mut checkpoints = Vec::new();
+    let mut pos = 0;
+    while pos < data.len() {
+        match data[pos] {
+            TAG_CHECKPOINT => {
+                let first = data
+                    .get(pos + 1..pos + 9)
+                    .ok_or_else(|| CompressError::LogError("truncated checkpoint".into()))?;
+                checkpoints.push(Checkpoint {
+                    offset: pos as u64,
+                    first_record: u64::from_le_bytes(first.try_into().unwrap()),
+                });
+                pos += 9;
+            }
+            TAG_WINDOW => {
+                let len = data
+                    .get(pos + 9..pos + 13)
+                    .ok_or_else(|| CompressError::LogError("truncated window".into()))?;
+                pos += 13 + u32::from_le_bytes(len.try_into().unwrap()) as usize;
+            }
+            other => {
+                return Err(CompressError::LogError(format!("unknown log frame tag {:#x}", other)));
+            }
+        }
+    }
+    Ok(checkpoints)
+}
+
+fn split_records(raw: &[u8], count: usize) -> Result<Vec<Vec<u8>>, CompressError> {
+    // Every record takes at least its 4-byte header
+    let mut records = Vec::with_capacity(count.min(raw.len() / 4));
+    let mut pos = 0;
+    for _ in 0..count {
+        let len = raw
+            .get(pos..pos + 4)
+            .map(|b| u32::from_le_bytes(b.try_into().unwrap()) as usize)
+            .ok_or_else(|| CompressError::LogError("truncated record header".into()))?;
+        pos += 4;
+        let record = raw
+            .get(pos..pos + len)
+            .ok_or_else(|| CompressError::LogError("truncated record".into()))?;
+        records.push(record.to_vec());
+        pos += len;
+    }
+    Ok(records)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample_records(n: usize) -> Vec<Vec<u8>> {
+        (0..n)
+            .map(|i| format!("2026-01-01T00:00:{:02} INFO request id={} status=200", i % 60, i).into_bytes())
+            .collect()
+    }
+
+    fn compress_records(records: &[Vec<u8>], config: LogConfig) -> (Vec<u8>, Vec<Checkpoint>) {
+        let mut log = LogCompressor::new(Vec::new(), config);
+        for r in records {
+            log.append(r).unwrap();
+        }
+        log.flush().unwrap();
+        let checkpoints = log.checkpoints().to_vec();
+        (log.finish().unwrap(), checkpoints)
+    }
+
+    #[test]
+    fn test_log_roundtrip() {
+        let records = sample_records(500);
+        let config = LogConfig {
+            window_size: 2048,
+            ..LogConfig::default()
+        };
+        let (data, _) = compress_records(&records, config);
+        let decoded: Vec<Vec<u8>> = LogReader::new(&data).collect::<Result<_, _>>().unwrap();
+        assert_eq!(decoded, records);
+        assert!(data.len() < records.iter().map(|r| r.len()).sum::<usize>());
+        // A hostile record count fails on the missing headers, not the allocation
+        assert!(split_records(&[0; 8], u32::MAX as usize).is_err());
+    }
+
+    #[test]
+    fn test_start_from_checkpoint() {
+        let records = sample_records(400);
+        let config = LogConfig {
+            window_size: 512,
+            checkpoint_interval: 4,
+        };
+        let (data, checkpoints) = compress_records(&records, config);
+        assert!(checkpoints.len() > 2);
+        assert_eq!(scan_checkpoints(&data).unwrap(), checkpoints);
+
+        let cp = checkpoints[2];
+        let tail: Vec<Vec<u8>> = LogReader::at(&data, &cp).unwrap().collect::<Result<_, _>>().unwrap();
+        assert_eq!(tail, records[cp.first_record as usize..]);
+    }
+
+    #[test]
+    fn test_from_record_uses_nearest_checkpoint() {
+        let records = sample_records(300);
+        let config = LogConfig {
+            window_size: 256,
+            checkpoint_interval: 3,
+        };
+        let (data, checkpoints) = compress_records(&records, config);
+        let mut reader = LogReader::from_record(&data, &checkpoints, 123).unwrap();
+        assert_eq!(reader.next().unwrap().unwrap(), records[123]);
+    }
+
+    #[test]
+    fn test_at_rejects_non_checkpoint_offset() {
+        let (data, _) = compress_records(&sample_records(10), LogConfig::default());
+        let bogus = Checkpoint {
+            offset: 9,
+            first_record: 0,
+        };
+        assert!(LogReader::at(&data, &bogus).is_err());
+    }
+}
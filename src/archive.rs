@@ -0,0 +1,1286 @@
+//! Multi-file archives on top of the block store
+//!
+//! An archive is a directory holding a pack-file [`BlockStore`] and a central
+//! directory (`directory.json`) that maps entry paths to the chunks of their
+//! contents. Entries are chunked and deduplicated against everything already
+//! stored, so adding a file to an existing archive only writes its new chunks.
+//!
+//! Updates are staged in memory and published by [`Archive::commit`], which
+//! makes the blocks durable first and then appends the changes to a journal
+//! (`journal.jsonl`, one synced JSON record per line). Opening an archive
+//! replays the journal over the central directory; a record torn by a crash
+//! is dropped, so a commit either happened entirely or not at all. Once the
+//! journal grows long it is folded into a new central directory, written to
+//! a temp file and renamed into place, and then truncated. Replaying a record
+//! twice is harmless, so a crash between the two steps is safe too.
+//!
+//! [`Archive::extract_matching`] writes the entries selected by a glob or a
+//! predicate to disk, decompressing them on several threads. Unless disabled,
+//! entry paths are sanitized first, so a crafted directory cannot write
+//! outside the destination ("zip slip").
+//!
+//! [`Archive::add_path`] also records file metadata: permissions and
+//! symlinks on unix, extended attributes on Linux. Which of them extraction
+//! restores is controlled by [`ExtractOptions`]. Symlinks are created only
+//! after every regular file has been written, so no write follows a link
+//! taken from the archive.
+//!
+//! Files with identical contents share one payload: a new entry whose BLAKE3
+//! content hash matches an existing entry takes references to that entry's
+//! chunks instead of chunking and storing the data again.
+//! [`Archive::add_dir`] also recognizes hard links and records them as links
+//! to the first path archived, recreated as hard links on extraction.
+//! [`Archive::stats`] reports what this saved.
+//!
+//! [`ArchiveOptions::filters`] holds gitignore-style rules that
+//! [`Archive::add_dir_with`] applies while walking, so excluded directories
+//! such as `target/` are never descended into.
+
+use crate::atomic::{sync_parent, write_atomic};
+use crate::chunker;
+use crate::error::CompressError;
+use crate::store::{BlockHash, BlockStore};
+use crate::CompressionMethod;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use std::time::UNIX_EPOCH;
+
+/// Current central directory format version
+pub const ARCHIVE_VERSION: u32 = 1;
+
+/// Name of the central directory inside the archive root
+pub const DIRECTORY_FILE: &str = "directory.json";
+
+/// Name of the update journal inside the archive root
+pub const JOURNAL_FILE: &str = "journal.jsonl";
+
+/// Journal records after which a commit folds them into the directory
+const CHECKPOINT_RECORDS: usize = 256;
+
+/// Links followed when resolving a symlink target before it counts as
+/// escaping, as the kernel gives up on loops
+const MAX_LINK_HOPS: usize = 40;
+
+/// A file stored in an archive
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ArchiveEntry {
+    /// Path inside the archive, `/`-separated
+    pub path: String,
+    pub size: u64,
+    /// Modification time in nanoseconds since the Unix epoch, when known
+    pub modified_ns: Option<u64>,
+    /// CRC-32 of the contents (absent in older archives)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub checksum: Option<u32>,
+    /// Unix permission bits
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mode: Option<u32>,
+    /// Target of a symbolic link; such entries have no contents
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub symlink: Option<String>,
+    /// Extended attributes, by name
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub xattrs: BTreeMap<String, Vec<u8>>,
+    /// BLAKE3 hash of the whole contents (absent in older archives)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content_hash: Option<BlockHash>,
+    /// Earlier entry this one was a hard link to when archived
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hardlink: Option<String>,
+    pub chunks: Vec<BlockHash>,
+}
+
+/// File-level deduplication figures, from [`Archive::stats`]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ArchiveStats {
+    pub entries: usize,
+    /// Sum of all entry sizes
+    pub total_size: u64,
+    /// Entries whose contents equal an earlier entry's, hard links included
+    pub duplicate_files: usize,
+    /// Entries recorded as hard links
+    pub hardlinks: usize,
+    /// Bytes not stored again thanks to duplicate files
+    pub dedup_saved_bytes: u64,
+    /// Stored bytes of all blocks in the archive's store
+    pub stored_bytes: u64,
+}
+
+/// Metadata captured from the filesystem alongside an entry's contents
+#[derive(Debug, Default)]
+struct FileMeta {
+    modified_ns: Option<u64>,
+    mode: Option<u32>,
+    symlink: Option<String>,
+    xattrs: BTreeMap<String, Vec<u8>>,
+    hardlink: Option<String>,
+}
+
+/// Metadata of an entry, as listed by [`Archive::entries`]
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct EntryInfo {
+    pub path: String,
+    pub size: u64,
+    /// Stored bytes of the entry's chunks; chunks shared with other entries
+    /// are counted in full for each of them
+    pub stored_size: u64,
+    /// Method of the entry's chunks; `None` if they differ or are unknown
+    pub method: Option<CompressionMethod>,
+    pub modified_ns: Option<u64>,
+    pub checksum: Option<u32>,
+}
+
+/// Central directory: every entry of the archive, in insertion order
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Directory {
+    version: u32,
+    entries: Vec<ArchiveEntry>,
+}
+
+/// One committed change to the central directory
+#[derive(Debug, Clone, Serialize, Deserialize)]
+enum JournalRecord {
+    /// Add an entry, replacing any entry with the same path
+    Put(ArchiveEntry),
+}
+
+impl JournalRecord {
+    fn apply(self, entries: &mut Vec<ArchiveEntry>) {
+        match self {
+            JournalRecord::Put(entry) => match entries.iter().position(|e| e.path == entry.path) {
+                Some(i) => entries[i] = entry,
+                None => entries.push(entry),
+            },
+        }
+    }
+}
+
+/// Which entries [`Archive::extract_matching`] extracts
+pub enum EntrySelector<'a> {
+    /// Paths matching a glob: `*` and `?` stay within one path component,
+    /// `**` matches any number of components
+    Glob(&'a str),
+    /// Entries for which the predicate returns `true`
+    Predicate(&'a (dyn Fn(&ArchiveEntry) -> bool + Sync)),
+}
+
+impl EntrySelector<'_> {
+    fn matches(&self, entry: &ArchiveEntry) -> bool {
+        match self {
+            EntrySelector::Glob(pattern) => glob_match(pattern, &entry.path),
+            EntrySelector::Predicate(predicate) => predicate(entry),
+        }
+    }
+}
+
+impl<'a> From<&'a str> for EntrySelector<'a> {
+    fn from(pattern: &'a str) -> Self {
+        EntrySelector::Glob(pattern)
+    }
+}
+
+impl<'a, F: Fn(&ArchiveEntry) -> bool + Sync> From<&'a F> for EntrySelector<'a> {
+    fn from(predicate: &'a F) -> Self {
+        EntrySelector::Predicate(predicate)
+    }
+}
+
+/// Options for [`Archive::extract_matching`]
+#[derive(Debug, Clone)]
+pub struct ExtractOptions {
+    /// Worker threads; 0 uses the available parallelism
+    pub threads: usize,
+    /// Refuse entries whose path is absolute or escapes the destination,
+    /// and symlinks pointing outside it
+    pub sanitize_paths: bool,
+    /// Apply recorded permission bits (unix)
+    pub restore_permissions: bool,
+    /// Apply recorded modification times
+    pub restore_mtime: bool,
+    /// Recreate symlinks (unix); when off, symlink entries are skipped
+    pub restore_symlinks: bool,
+    /// Apply recorded extended attributes (Linux)
+    pub restore_xattrs: bool,
+}
+
+impl Default for ExtractOptions {
+    fn default() -> Self {
+        Self {
+            threads: 0,
+            sanitize_paths: true,
+            restore_permissions: true,
+            restore_mtime: true,
+            restore_symlinks: true,
+            restore_xattrs: false,
+        }
+    }
+}
+
+/// Options for [`Archive::add_dir_with`]
+#[derive(Debug, Clone, Default)]
+pub struct ArchiveOptions {
+    /// Gitignore-style rules, matched against paths relative to the
+    /// directory being added: `#` starts a comment, `!` re-includes, a
+    /// trailing `/` matches directories only, and a pattern containing
+    /// another `/` is anchored to the directory while one without matches
+    /// names at any depth. The last matching rule wins.
+    pub filters: Vec<String>,
+}
+
+/// One parsed filter rule
+struct FilterRule {
+    pattern: String,
+    negate: bool,
+    dir_only: bool,
+    anchored: bool,
+}
+
+impl FilterRule {
+    fn parse(line: &str) -> Option<Self> {
+        let line = line.trim_end();
+        if line.is_empty() || line.starts_with('#') {
+            return None;
+        }
+        let (negate, line) = match line.strip_prefix('!') {
+            Some(rest) => (true, rest),
+            None => (false, line),
+        };
+        let (dir_only, line) = match line.strip_suffix('/') {
+            Some(rest) => (true, rest),
+            None => (false, line),
+        };
+        let anchored = line.contains('/');
+        let pattern = line.trim_start_matches('/');
+        if pattern.is_empty() {
+            return None;
+        }
+        Some(Self {
+            pattern: pattern.to_string(),
+            negate,
+            dir_only,
+            anchored,
+        })
+    }
+
+    fn matches(&self, path: &str, is_dir: bool) -> bool {
+        if self.dir_only && !is_dir {
+            return false;
+        }
+        if self.anchored {
+            glob_match(&self.pattern, path)
+        } else {
+            glob_match(&self.pattern, path.rsplit('/').next().unwrap_or(path))
+        }
+    }
+}
+
+/// Whether the last rule matching `path` excludes it
+fn is_excluded(rules: &[FilterRule], path: &str, is_dir: bool) -> bool {
+    rules
+        .iter()
+        .rev()
+        .find(|rule| rule.matches(path, is_dir))
+        .is_some_and(|rule| !rule.negate)
+}
+
+/// An archive opened for reading or appending
+pub struct Archive {
+    root: PathBuf,
+    store: BlockStore,
+    entries: Vec<ArchiveEntry>,
+    writable: bool,
+    /// Changes since the last commit
+    pending: Vec<JournalRecord>,
+    /// Records in the journal file
+    journal_records: usize,
+}
+
+impl Archive {
+    /// Create an empty archive at `root`; fails if one already exists there
+    pub fn create(root: impl AsRef<Path>) -> Result<Self, CompressError> {
+        let root = root.as_ref().to_path_buf();
+        if root.join(DIRECTORY_FILE).exists() {
+            return Err(CompressError::ArchiveError(format!(
+                "archive already exists at {}",
+                root.display()
+            )));
+        }
+        let mut archive = Self {
+            store: BlockStore::open(&root)?,
+            root,
+            entries: Vec::new(),
+            writable: true,
+            pending: Vec::new(),
+            journal_records: 0,
+        };
+        archive.checkpoint()?;
+        Ok(archive)
+    }
+
+    /// Open an existing archive read-only
+    pub fn open(root: impl AsRef<Path>) -> Result<Self, CompressError> {
+        Self::load(root.as_ref(), false)
+    }
+
+    /// Open an existing archive to add entries, reusing its block store so
+    /// content already archived is not stored again
+    pub fn open_append(root: impl AsRef<Path>) -> Result<Self, CompressError> {
+        Self::load(root.as_ref(), true)
+    }
+
+    fn load(root: &Path, writable: bool) -> Result<Self, CompressError> {
+        let raw = fs::read(root.join(DIRECTORY_FILE))?;
+        let directory: Directory =
+            serde_json::from_slice(&raw).map_err(|e| CompressError::SerializationError(e.to_string()))?;
+        if directory.version != ARCHIVE_VERSION {
+            return Err(CompressError::ArchiveError(format!(
+                "unsupported archive version {}",
+                directory.version
+            )));
+        }
+        let mut entries = directory.entries;
+
+        // Replay complete journal records; anything after the first torn or
+        // unparsable line is the remains of an interrupted commit
+        let journal_path = root.join(JOURNAL_FILE);
+        let journal = match fs::read(&journal_path) {
+            Ok(raw) => raw,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(e.into()),
+        };
+        let mut valid_len = 0;
+        let mut journal_records = 0;
+        for line in journal.split_inclusive(|&b| b == b'\n') {
+            if line.last() != Some(&b'\n') {
+                break;
+            }
+            let Ok(record) = serde_json::from_slice::<JournalRecord>(line) else {
+                break;
+            };
+            record.apply(&mut entries);
+            valid_len += line.len();
+            journal_records += 1;
+        }
+        if writable && valid_len < journal.len() {
+            let file = OpenOptions::new().write(true).open(&journal_path)?;
+            file.set_len(valid_len as u64)?;
+            file.sync_all()?;
+        }
+
+        Ok(Self {
+            root: root.to_path_buf(),
+            store: BlockStore::open(root)?,
+            entries,
+            writable,
+            pending: Vec::new(),
+            journal_records,
+        })
+    }
+
+    /// Root directory of the archive
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Underlying block store
+    pub fn store(&self) -> &BlockStore {
+        &self.store
+    }
+
+    /// Number of entries, including uncommitted ones
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Metadata of every entry, in archive order, without reading any block
+    pub fn entries(&self) -> impl Iterator<Item = EntryInfo> + '_ {
+        self.entries.iter().map(|entry| {
+            let mut stored_size = 0;
+            let mut methods = entry.chunks.iter().map(|hash| {
+                let (stored, method) = self.store.block_info(hash).unwrap_or((0, None));
+                stored_size += stored;
+                method
+            });
+            let first = methods.next().flatten();
+            let method = if methods.all(|m| m.is_some() && m == first) { first } else { None };
+            EntryInfo {
+                path: entry.path.clone(),
+                size: entry.size,
+                stored_size,
+                method,
+                modified_ns: entry.modified_ns,
+                checksum: entry.checksum,
+            }
+        })
+    }
+
+    /// Look up an entry by path
+    pub fn entry(&self, path: &str) -> Option<&ArchiveEntry> {
+        self.entries.iter().find(|e| e.path == path)
+    }
+
+    /// Add `data` under `path`, replacing any entry with the same path
+    pub fn add_file(&mut self, path: &str, data: &[u8]) -> Result<(), CompressError> {
+        self.add_entry(path, data, FileMeta::default())
+    }
+
+    /// Add the file at `source` under `path`, keeping its modification time,
+    /// permissions and extended attributes. A symlink is stored as a link,
+    /// not followed.
+    pub fn add_path(&mut self, path: &str, source: impl AsRef<Path>) -> Result<(), CompressError> {
+        let source = source.as_ref();
+        self.add_source(path, source, &fs::symlink_metadata(source)?, None)
+    }
+
+    /// Add every file below `dir`, recursively and in name order, with paths
+    /// prefixed by `prefix` (which may be empty). Hard links to a file already
+    /// added are recorded as links to it. Returns the number of entries added.
+    pub fn add_dir(&mut self, prefix: &str, dir: impl AsRef<Path>) -> Result<usize, CompressError> {
+        self.add_dir_with(prefix, dir, &ArchiveOptions::default())
+    }
+
+    /// [`Archive::add_dir`], skipping whatever `options.filters` excludes
+    pub fn add_dir_with(
+        &mut self,
+        prefix: &str,
+        dir: impl AsRef<Path>,
+        options: &ArchiveOptions,
+    ) -> Result<usize, CompressError> {
+        let rules: Vec<FilterRule> = options.filters.iter().filter_map(|f| FilterRule::parse(f)).collect();
+        let mut walk = DirWalk {
+            root_prefix: prefix.trim_end_matches('/').to_string(),
+            rules,
+            inodes: std::collections::HashMap::new(),
+        };
+        self.add_dir_inner("", dir.as_ref(), &mut walk)
+    }
+
+    fn add_dir_inner(&mut self, relative: &str, dir: &Path, walk: &mut DirWalk) -> Result<usize, CompressError> {
+        let mut children = fs::read_dir(dir)?.collect::<Result<Vec<_>, _>>()?;
+        children.sort_by_key(|c| c.file_name());
+        let mut added = 0;
+        for child in children {
+            let name = child.file_name();
+            let name = name
+                .to_str()
+                .ok_or_else(|| CompressError::ArchiveError(format!("non-UTF-8 file name in {}", dir.display())))?;
+            let relative = if relative.is_empty() {
+                name.to_string()
+            } else {
+                format!("{}/{}", relative, name)
+            };
+            let metadata = fs::symlink_metadata(child.path())?;
+            if is_excluded(&walk.rules, &relative, metadata.is_dir()) {
+                continue;
+            }
+            if metadata.is_dir() {
+                added += self.add_dir_inner(&relative, &child.path(), walk)?;
+                continue;
+            }
+            let path = if walk.root_prefix.is_empty() {
+                relative
+            } else {
+                format!("{}/{}", walk.root_prefix, relative)
+            };
+            if !metadata.is_file() && !metadata.file_type().is_symlink() {
+                continue;
+            }
+            let mut hardlink = None;
+            #[cfg(unix)]
+            {
+                use std::os::unix::fs::MetadataExt;
+                if metadata.is_file() && metadata.nlink() > 1 {
+                    match walk.inodes.get(&(metadata.dev(), metadata.ino())) {
+                        Some(first) => hardlink = Some(first.clone()),
+                        None => {
+                            walk.inodes.insert((metadata.dev(), metadata.ino()), path.clone());
+                        }
+                    }
+                }
+            }
+            self.add_source(&path, &child.path(), &metadata, hardlink)?;
+            added += 1;
+        }
+        Ok(added)
+    }
+
+    fn add_source(
+        &mut self,
+        path: &str,
+        source: &Path,
+        metadata: &fs::Metadata,
+        hardlink: Option<String>,
+    ) -> Result<(), CompressError> {
+        let mut meta = FileMeta {
+            hardlink,
+            modified_ns: metadata
+                .modified()
+                .ok()
+                .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+                .map(|d| d.as_nanos() as u64),
+            ..FileMeta::default()
+        };
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            meta.mode = Some(metadata.permissions().mode() & 0o7777);
+        }
+        #[cfg(target_os = "linux")]
+        {
+            meta.xattrs = xattr::read_all(source)?;
+        }
+        if metadata.file_type().is_symlink() {
+            let target = fs::read_link(source)?;
+            let target = target
+                .to_str()
+                .ok_or_else(|| CompressError::ArchiveError(format!("non-UTF-8 link target in {}", source.display())))?;
+            meta.symlink = Some(target.to_string());
+            return self.add_entry(path, &[], meta);
+        }
+        self.add_entry(path, &fs::read(source)?, meta)
+    }
+
+    fn add_entry(&mut self, path: &str, data: &[u8], meta: FileMeta) -> Result<(), CompressError> {
+        if !self.writable {
+            return Err(CompressError::ArchiveError("archive opened read-only".into()));
+        }
+        if path.is_empty() || path.split('/').any(|c| c.is_empty() || c == "." || c == "..") {
+            return Err(CompressError::ArchiveError(format!("invalid entry path: {}", path)));
+        }
+        let content_hash = BlockHash::of(data);
+        let duplicate = self
+            .entries
+            .iter()
+            .find(|e| e.content_hash == Some(content_hash) && e.size == data.len() as u64)
+            .map(|e| e.chunks.clone());
+        let chunks = match duplicate {
+            Some(chunks) => {
+                for hash in &chunks {
+                    self.store.retain(hash);
+                }
+                chunks
+            }
+            None => chunker::chunk(data)
+                .into_iter()
+                .map(|piece| self.store.put(piece))
+                .collect::<Result<Vec<_>, _>>()?,
+        };
+        let entry = ArchiveEntry {
+            path: path.to_string(),
+            size: data.len() as u64,
+            modified_ns: meta.modified_ns,
+            checksum: Some(crc32fast::hash(data)),
+            mode: meta.mode,
+            symlink: meta.symlink,
+            xattrs: meta.xattrs,
+            content_hash: Some(content_hash),
+            hardlink: meta.hardlink,
+            chunks,
+        };
+        if let Some(old) = self.entry(path) {
+            for hash in old.chunks.clone() {
+                self.store.release(&hash);
+            }
+        }
+        let record = JournalRecord::Put(entry);
+        record.clone().apply(&mut self.entries);
+        self.pending.push(record);
+        Ok(())
+    }
+
+    /// File-level deduplication figures for the current entries
+    pub fn stats(&self) -> ArchiveStats {
+        let mut stats = ArchiveStats {
+            entries: self.entries.len(),
+            stored_bytes: self.store.stored_bytes(),
+            ..ArchiveStats::default()
+        };
+        let mut seen = std::collections::HashSet::new();
+        for entry in &self.entries {
+            stats.total_size += entry.size;
+            if entry.hardlink.is_some() {
+                stats.hardlinks += 1;
+            }
+            if let Some(hash) = entry.content_hash {
+                if entry.size > 0 && !seen.insert(hash) {
+                    stats.duplicate_files += 1;
+                    stats.dedup_saved_bytes += entry.size;
+                }
+            }
+        }
+        stats
+    }
+
+    /// Read an entry's contents
+    pub fn read(&self, path: &str) -> Result<Vec<u8>, CompressError> {
+        let entry = self
+            .entry(path)
+            .ok_or_else(|| CompressError::ArchiveError(format!("no entry {}", path)))?;
+        let mut contents = Vec::with_capacity((entry.size as usize).min(crate::MAX_PREALLOC));
+        for hash in &entry.chunks {
+            contents.extend_from_slice(&self.store.get(hash)?);
+        }
+        if contents.len() as u64 != entry.size {
+            return Err(CompressError::SizeMismatch {
+                expected: entry.size as usize,
+                actual: contents.len(),
+            });
+        }
+        if let Some(expected) = entry.checksum {
+            let actual = crc32fast::hash(&contents);
+            if actual != expected {
+                return Err(CompressError::ChecksumMismatch { expected, actual });
+            }
+        }
+        Ok(contents)
+    }
+
+    /// Write every entry selected by `selector` below `dest`, decompressing
+    /// entries in parallel. Returns the paths extracted, in archive order.
+    pub fn extract_matching<'a>(
+        &self,
+        dest: impl AsRef<Path>,
+        selector: impl Into<EntrySelector<'a>>,
+        options: &ExtractOptions,
+    ) -> Result<Vec<String>, CompressError> {
+        let dest = dest.as_ref();
+        let selector = selector.into();
+        let selected: Vec<&ArchiveEntry> = self
+            .entries
+            .iter()
+            .filter(|e| selector.matches(e) && (e.symlink.is_none() || options.restore_symlinks))
+            .collect();
+        let symlinks: BTreeMap<String, &str> = selected
+            .iter()
+            .filter_map(|e| Some((normalize(&e.path), e.symlink.as_deref()?)))
+            .collect();
+        for entry in &selected {
+            if options.sanitize_paths && !is_safe_path(&entry.path) {
+                return Err(CompressError::ArchiveError(format!("unsafe entry path: {}", entry.path)));
+            }
+            if let Some(first) = &entry.hardlink {
+                if options.sanitize_paths && !is_safe_path(first) {
+                    return Err(CompressError::ArchiveError(format!("unsafe hard link target: {}", first)));
+                }
+            }
+            if let Some(target) = &entry.symlink {
+                if options.sanitize_paths && symlink_escapes(&entry.path, target, &symlinks) {
+                    return Err(CompressError::ArchiveError(format!(
+                        "symlink {} points outside the destination",
+                        entry.path
+                    )));
+                }
+            }
+        }
+        // Hard links are made once their target is written, and only when the
+        // target is extracted too; otherwise the contents are written out
+        let linkable = |e: &ArchiveEntry| {
+            e.hardlink
+                .as_ref()
+                .is_some_and(|first| selected.iter().any(|s| &s.path == first && s.symlink.is_none()))
+        };
+        let (links, files): (Vec<&ArchiveEntry>, Vec<&ArchiveEntry>) =
+            selected.iter().partition(|e| e.symlink.is_some() || linkable(e));
+
+        let threads = match options.threads {
+            0 => thread::available_parallelism().map_or(1, |n| n.get()),
+            n => n,
+        }
+        .min(files.len())
+        .max(1);
+        let next = AtomicUsize::new(0);
+        let failure = Mutex::new(None);
+        thread::scope(|scope| {
+            for _ in 0..threads {
+                scope.spawn(|| loop {
+                    let i = next.fetch_add(1, Ordering::Relaxed);
+                    if i >= files.len() || failure.lock().unwrap().is_some() {
+                        break;
+                    }
+                    if let Err(e) = self.extract_file(files[i], &dest.join(&files[i].path), options) {
+                        failure.lock().unwrap().get_or_insert(e);
+                        break;
+                    }
+                });
+            }
+        });
+        if let Some(e) = failure.into_inner().unwrap() {
+            return Err(e);
+        }
+        let (hardlinks, symlinks): (Vec<&ArchiveEntry>, Vec<&ArchiveEntry>) =
+            links.into_iter().partition(|e| e.symlink.is_none());
+        for entry in hardlinks {
+            let target = dest.join(&entry.path);
+            if let Some(parent) = target.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            if fs::symlink_metadata(&target).is_ok() {
+                fs::remove_file(&target)?;
+            }
+            fs::hard_link(dest.join(entry.hardlink.as_deref().unwrap_or_default()), &target)?;
+        }
+        for entry in symlinks {
+            extract_symlink(entry, &dest.join(&entry.path), options)?;
+        }
+        Ok(selected.iter().map(|e| e.path.clone()).collect())
+    }
+
+    fn extract_file(&self, entry: &ArchiveEntry, target: &Path, options: &ExtractOptions) -> Result<(), CompressError> {
+        let contents = self.read(&entry.path)?;
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(target, contents)?;
+        if options.restore_mtime {
+            if let Some(ns) = entry.modified_ns {
+                let modified = UNIX_EPOCH + std::time::Duration::from_nanos(ns);
+                OpenOptions::new().write(true).open(target)?.set_modified(modified)?;
+            }
+        }
+        #[cfg(target_os = "linux")]
+        if options.restore_xattrs {
+            for (name, value) in &entry.xattrs {
+                xattr::write(target, name, value)?;
+            }
+        }
+        // Permissions last: a read-only mode would block the steps above
+        #[cfg(unix)]
+        if options.restore_permissions {
+            if let Some(mode) = entry.mode {
+                use std::os::unix::fs::PermissionsExt;
+                fs::set_permissions(target, fs::Permissions::from_mode(mode))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Make added blocks durable, then journal the changes since the last commit
+    pub fn commit(&mut self) -> Result<(), CompressError> {
+        self.append_journal()?;
+        if self.journal_records >= CHECKPOINT_RECORDS {
+            self.checkpoint()?;
+        }
+        Ok(())
+    }
+
+    /// Commit, then fold the journal into a new central directory and empty it
+    pub fn checkpoint(&mut self) -> Result<(), CompressError> {
+        if !self.writable {
+            return Err(CompressError::ArchiveError("archive opened read-only".into()));
+        }
+        self.append_journal()?;
+        let directory = Directory {
+            version: ARCHIVE_VERSION,
+            entries: self.entries.clone(),
+        };
+        let raw = serde_json::to_vec_pretty(&directory).map_err(|e| CompressError::SerializationError(e.to_string()))?;
+        write_atomic(&self.root.join(DIRECTORY_FILE), &raw)?;
+        File::create(self.root.join(JOURNAL_FILE))?.sync_all()?;
+        self.journal_records = 0;
+        Ok(())
+    }
+
+    fn append_journal(&mut self) -> Result<(), CompressError> {
+        if self.pending.is_empty() {
+            return Ok(());
+        }
+        self.store.flush()?;
+        let mut raw = Vec::new();
+        for record in &self.pending {
+            serde_json::to_writer(&mut raw, record).map_err(|e| CompressError::SerializationError(e.to_string()))?;
+            raw.push(b'\n');
+        }
+        let journal_path = self.root.join(JOURNAL_FILE);
+        let created = !journal_path.exists();
+        let mut journal = OpenOptions::new().create(true).append(true).open(&journal_path)?;
+        journal.write_all(&raw)?;
+        journal.sync_all()?;
+        if created {
+            sync_parent(&journal_path)?;
+        }
+        self.journal_records += self.pending.len();
+        self.pending.clear();
+        Ok(())
+    }
+}
+
+/// State of one [`Archive::add_dir_with`] walk
+struct DirWalk {
+    root_prefix: String,
+    rules: Vec<FilterRule>,
+    /// First archive path seen for each (device, inode) with several links
+    inodes: std::collections::HashMap<(u64, u64), String>,
+}
+
+/// Create the symlink described by `entry` at `target`
+fn extract_symlink(entry: &ArchiveEntry, target: &Path, options: &ExtractOptions) -> Result<(), CompressError> {
+    let Some(link) = &entry.symlink else {
+        return Ok(());
+    };
+    if let Some(parent) = target.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    #[cfg(unix)]
+    {
+        match fs::symlink_metadata(target) {
+            Ok(_) => fs::remove_file(target)?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e.into()),
+        }
+        std::os::unix::fs::symlink(link, target)?;
+        #[cfg(target_os = "linux")]
+        if options.restore_xattrs {
+            for (name, value) in &entry.xattrs {
+                xattr::write(target, name, value)?;
+            }
+        }
+        Ok(())
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = options;
+        Err(CompressError::ArchiveError(format!(
+            "cannot create symlink {} -> {} on this platform",
+            entry.path, link
+        )))
+    }
+}
+
+/// `path` without empty or `.` components
+fn normalize(path: &str) -> String {
+    path.split('/').filter(|c| !c.is_empty() && *c != ".").collect::<Vec<_>>().join("/")
+}
+
+/// Whether a link at `path` pointing at `target` resolves outside the root,
+/// following the links in `symlinks` (by normalized path) it passes through
+fn symlink_escapes(path: &str, target: &str, symlinks: &BTreeMap<String, &str>) -> bool {
+    let absolute = |target: &str| target.starts_with('/') || target.contains('\\') || target.contains(':');
+    if absolute(target) {
+        return true;
+    }
+    let mut dir: Vec<&str> = path.split('/').filter(|c| !c.is_empty() && *c != ".").collect();
+    dir.pop();
+    // Components still to walk, last first
+    let mut pending: Vec<&str> = target.split('/').rev().collect();
+    let mut hops = 0;
+    while let Some(component) = pending.pop() {
+        match component {
+            "" | "." => {}
+            ".." => {
+                if dir.pop().is_none() {
+                    return true;
+                }
+            }
+            name => {
+                dir.push(name);
+                if let Some(next) = symlinks.get(&dir.join("/")) {
+                    hops += 1;
+                    if hops > MAX_LINK_HOPS || absolute(next) {
+                        return true;
+                    }
+                    dir.pop();
+                    pending.extend(next.split('/').rev());
+                }
+            }
+        }
+    }
+    false
+}
+
+/// Extended attributes through the `l*xattr` calls, which act on a symlink
+/// itself rather than its target
+#[cfg(target_os = "linux")]
+mod xattr {
+    use std::collections::BTreeMap;
+    use std::ffi::CString;
+    use std::io;
+    use std::os::unix::ffi::OsStrExt;
+    use std::path::Path;
+
+    fn c_path(path: &Path) -> io::Result<CString> {
+        Ok(CString::new(path.as_os_str().as_bytes())?)
+    }
+
+    /// Every attribute with a UTF-8 name; empty if the filesystem has none
+    pub(super) fn read_all(path: &Path) -> io::Result<BTreeMap<String, Vec<u8>>> {
+        let path = c_path(path)?;
+        let mut attrs = BTreeMap::new();
+        // SAFETY: a null buffer of size 0 only queries the required length
+        let len = unsafe { libc::llistxattr(path.as_ptr(), std::ptr::null_mut(), 0) };
+        if len < 0 {
+            let err = io::Error::last_os_error();
+            return match err.raw_os_error() {
+                Some(libc::ENOTSUP) => Ok(attrs),
+                _ => Err(err),
+            };
+        }
+        let mut names = vec![0u8; len as usize];
+        // SAFETY: `names` is writable for `names.len()` bytes
+        let len = unsafe { libc::llistxattr(path.as_ptr(), names.as_mut_ptr().cast(), names.len()) };
+        if len < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        names.truncate(len as usize);
+        for name in names.split(|&b| b == 0).filter(|n| !n.is_empty()) {
+            let Ok(text) = std::str::from_utf8(name) else {
+                continue;
+            };
+            let c_name = CString::new(name)?;
+            // SAFETY: as above, a length query
+            let size = unsafe { libc::lgetxattr(path.as_ptr(), c_name.as_ptr(), std::ptr::null_mut(), 0) };
+            if size < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            let mut value = vec![0u8; size as usize];
+            // SAFETY: `value` is writable for `value.len()` bytes
+            let size =
+                unsafe { libc::lgetxattr(path.as_ptr(), c_name.as_ptr(), value.as_mut_ptr().cast(), value.len()) };
+            if size < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            value.truncate(size as usize);
+            attrs.insert(text.to_string(), value);
+        }
+        Ok(attrs)
+    }
+
+    pub(super) fn write(path: &Path, name: &str, value: &[u8]) -> io::Result<()> {
+        let path = c_path(path)?;
+        let name = CString::new(name)?;
+        // SAFETY: all pointers are valid for the lengths passed
+        let rc = unsafe { libc::lsetxattr(path.as_ptr(), name.as_ptr(), value.as_ptr().cast(), value.len(), 0) };
+        if rc < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+}
+
+/// Relative path made only of normal components
+fn is_safe_path(path: &str) -> bool {
+    !path.is_empty()
+        && !path.contains('\\')
+        && !path.contains(':')
+        && path.split('/').all(|c| !c.is_empty() && c != "." && c != "..")
+}
+
+/// Match `path` against a glob pattern, component by component
+fn glob_match(pattern: &str, path: &str) -> bool {
+    let pattern: Vec<&str> = pattern.split('/').collect();
+    let path: Vec<&str> = path.split('/').collect();
+    match_components(&pattern, &path)
+}
+
+fn match_components(pattern: &[&str], path: &[&str]) -> bool {
+    match pattern.split_first() {
+        None => path.is_empty(),
+        Some((&"**", rest)) => (0..=path.len()).any(|skip| match_components(rest, &path[skip..])),
+        Some((first, rest)) => match path.split_first() {
+            Some((component, path_rest)) => {
+                match_component(first.as_bytes(), component.as_bytes()) && match_components(rest, path_rest)
+            }
+            None => false,
+        },
+    }
+}
+
+fn match_component(pattern: &[u8], name: &[u8]) -> bool {
+    match pattern.split_first() {
+        None => name.is_empty(),
+        Some((b'*', rest)) => (0..=name.len()).any(|skip| match_component(rest, &name[skip..])),
+        Some((b'?', rest)) => !name.is_empty() && match_component(rest, &name[1..]),
+        Some((c, rest)) => name.first() == Some(c) && match_component(rest, &name[1..]),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_append_to_existing_archive() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut archive = Archive::create(dir.path()).unwrap();
+        let body = b"archived line of text\n".repeat(400);
+        archive.add_file("a.txt", &body).unwrap();
+        archive.commit().unwrap();
+        let blocks = archive.store().len();
+        drop(archive);
+
+        let mut archive = Archive::open_append(dir.path()).unwrap();
+        archive.add_file("b/copy.txt", &body).unwrap();
+        assert_eq!(archive.store().len(), blocks, "identical content reuses stored blocks");
+        archive.add_file("b/new.txt", b"something new").unwrap();
+        archive.commit().unwrap();
+
+        let archive = Archive::open(dir.path()).unwrap();
+        assert_eq!(archive.len(), 3);
+        assert_eq!(archive.read("a.txt").unwrap(), body);
+        assert_eq!(archive.read("b/copy.txt").unwrap(), body);
+        assert_eq!(archive.read("b/new.txt").unwrap(), b"something new");
+    }
+
+    #[test]
+    fn test_uncommitted_entries_not_published() {
+        let dir = tempfile::tempdir().unwrap();
+        Archive::create(dir.path()).unwrap();
+        let mut archive = Archive::open_append(dir.path()).unwrap();
+        archive.add_file("pending.txt", b"not yet").unwrap();
+        drop(archive);
+        assert!(Archive::open(dir.path()).unwrap().is_empty());
+        assert!(std::fs::read_dir(dir.path())
+            .unwrap()
+            .all(|e| !e.unwrap().file_name().to_string_lossy().starts_with(".tmp")));
+    }
+
+    #[test]
+    fn test_torn_journal_record_dropped() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut archive = Archive::create(dir.path()).unwrap();
+        archive.add_file("kept.txt", b"committed").unwrap();
+        archive.commit().unwrap();
+        archive.add_file("torn.txt", b"interrupted").unwrap();
+        archive.commit().unwrap();
+        drop(archive);
+
+        // Simulate a crash halfway through writing the second record
+        let journal = dir.path().join(JOURNAL_FILE);
+        let raw = fs::read(&journal).unwrap();
+        let first_end = raw.iter().position(|&b| b == b'\n').unwrap() + 1;
+        fs::write(&journal, &raw[..first_end + 10]).unwrap();
+
+        let mut archive = Archive::open_append(dir.path()).unwrap();
+        assert_eq!(archive.len(), 1);
+        assert_eq!(archive.read("kept.txt").unwrap(), b"committed");
+        assert_eq!(fs::read(&journal).unwrap().len(), first_end);
+        archive.add_file("after.txt", b"recovered").unwrap();
+        archive.checkpoint().unwrap();
+        assert!(fs::read(&journal).unwrap().is_empty());
+
+        let archive = Archive::open(dir.path()).unwrap();
+        assert_eq!(archive.len(), 2);
+        assert_eq!(archive.read("after.txt").unwrap(), b"recovered");
+    }
+
+    #[test]
+    fn test_replace_and_read_only() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut archive = Archive::create(dir.path()).unwrap();
+        archive.add_file("f", b"old contents").unwrap();
+        let old = archive.entry("f").unwrap().chunks[0];
+        archive.add_file("f", b"new contents").unwrap();
+        archive.commit().unwrap();
+        assert_eq!(archive.len(), 1);
+        assert_eq!(archive.store().refcount(&old), Some(0));
+
+        let mut reader = Archive::open(dir.path()).unwrap();
+        assert_eq!(reader.read("f").unwrap(), b"new contents");
+        assert!(reader.add_file("g", b"x").is_err());
+        assert!(Archive::create(dir.path()).is_err());
+        assert!(Archive::open_append(dir.path()).unwrap().add_file("../escape", b"x").is_err());
+    }
+
+    #[test]
+    fn test_extract_matching() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut archive = Archive::create(dir.path().join("archive")).unwrap();
+        archive.add_file("serde/src/lib.rs", b"pub mod de;").unwrap();
+        archive.add_file("serde/src/de/mod.rs", b"pub trait Deserialize {}").unwrap();
+        archive.add_file("serde/Cargo.toml", b"[package]").unwrap();
+        archive.add_file("tokio/src/lib.rs", b"pub mod io;").unwrap();
+        archive.commit().unwrap();
+
+        let out = dir.path().join("out");
+        let options = ExtractOptions {
+            threads: 2,
+            ..Default::default()
+        };
+        let extracted = archive.extract_matching(&out, "serde/**/*.rs", &options).unwrap();
+        assert_eq!(extracted, ["serde/src/lib.rs", "serde/src/de/mod.rs"]);
+        assert_eq!(fs::read(out.join("serde/src/de/mod.rs")).unwrap(), b"pub trait Deserialize {}");
+        assert!(!out.join("serde/Cargo.toml").exists());
+
+        let small = |e: &ArchiveEntry| e.size < 10;
+        let extracted = archive.extract_matching(&out, &small, &options).unwrap();
+        assert_eq!(extracted, ["serde/Cargo.toml"]);
+        assert!(glob_match("*/src/?ib.rs", "tokio/src/lib.rs"));
+        assert!(!glob_match("*.rs", "tokio/src/lib.rs"));
+    }
+
+    #[test]
+    fn test_extract_rejects_unsafe_paths() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut archive = Archive::create(dir.path().join("archive")).unwrap();
+        archive.add_file("ok.txt", b"fine").unwrap();
+        archive.checkpoint().unwrap();
+        // A crafted directory bypassing add_file's validation
+        archive.entries[0].path = "../escaped.txt".into();
+        let out = dir.path().join("out");
+        assert!(archive.extract_matching(&out, "**", &ExtractOptions::default()).is_err());
+        assert!(!dir.path().join("escaped.txt").exists());
+    }
+
+    #[test]
+    fn test_entries_metadata() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut archive = Archive::create(dir.path()).unwrap();
+        let text = b"listing entries without decompressing ".repeat(100);
+        archive.add_file("docs/a.txt", &text).unwrap();
+        archive.add_file("empty", b"").unwrap();
+        archive.commit().unwrap();
+
+        let archive = Archive::open(dir.path()).unwrap();
+        let infos: Vec<EntryInfo> = archive.entries().collect();
+        assert_eq!(infos.len(), 2);
+        assert_eq!(infos[0].path, "docs/a.txt");
+        assert_eq!(infos[0].size, text.len() as u64);
+        assert!(infos[0].stored_size > 0 && infos[0].stored_size < infos[0].size);
+        assert!(infos[0].method.is_some());
+        assert_eq!(infos[0].checksum, Some(crc32fast::hash(&text)));
+        assert_eq!(infos[1].size, 0);
+        assert_eq!(infos[1].stored_size, 0);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_metadata_roundtrip() {
+        use std::os::unix::fs::PermissionsExt;
+        let dir = tempfile::tempdir().unwrap();
+        let src = dir.path().join("src");
+        fs::create_dir(&src).unwrap();
+        fs::write(src.join("run.sh"), b"#!/bin/sh\necho hi\n").unwrap();
+        fs::set_permissions(src.join("run.sh"), fs::Permissions::from_mode(0o750)).unwrap();
+        std::os::unix::fs::symlink("run.sh", src.join("start")).unwrap();
+        let mtime = UNIX_EPOCH + std::time::Duration::from_secs(1_600_000_000);
+        File::options().write(true).open(src.join("run.sh")).unwrap().set_modified(mtime).unwrap();
+        #[cfg(target_os = "linux")]
+        let has_xattrs = xattr::write(&src.join("run.sh"), "user.origin", b"backup").is_ok();
+
+        let mut archive = Archive::create(dir.path().join("archive")).unwrap();
+        archive.add_path("bin/run.sh", src.join("run.sh")).unwrap();
+        archive.add_path("bin/start", src.join("start")).unwrap();
+        archive.commit().unwrap();
+        assert_eq!(archive.entry("bin/start").unwrap().symlink.as_deref(), Some("run.sh"));
+
+        let out = dir.path().join("out");
+        let options = ExtractOptions {
+            restore_xattrs: true,
+            ..Default::default()
+        };
+        archive.extract_matching(&out, "**", &options).unwrap();
+        let meta = fs::metadata(out.join("bin/run.sh")).unwrap();
+        assert_eq!(meta.permissions().mode() & 0o7777, 0o750);
+        assert_eq!(meta.modified().unwrap(), mtime);
+        assert_eq!(fs::read_link(out.join("bin/start")).unwrap(), Path::new("run.sh"));
+        assert_eq!(fs::read(out.join("bin/start")).unwrap(), b"#!/bin/sh\necho hi\n");
+        #[cfg(target_os = "linux")]
+        if has_xattrs {
+            assert_eq!(xattr::read_all(&out.join("bin/run.sh")).unwrap()["user.origin"], b"backup");
+        }
+
+        let plain = dir.path().join("plain");
+        let options = ExtractOptions {
+            restore_permissions: false,
+            restore_symlinks: false,
+            ..Default::default()
+        };
+        assert_eq!(archive.extract_matching(&plain, "**", &options).unwrap(), ["bin/run.sh"]);
+        assert!(!plain.join("bin/start").exists());
+    }
+
+    #[test]
+    fn test_escaping_symlink_rejected() {
+        let none = BTreeMap::new();
+        assert!(symlink_escapes("a/link", "../../etc", &none));
+        assert!(symlink_escapes("link", "/etc/passwd", &none));
+        assert!(!symlink_escapes("a/b/link", "../c/file", &none));
+        assert!(!symlink_escapes("link", "./target", &none));
+
+        // Links extracted alongside are followed: y/.. is the parent of the root
+        let links = BTreeMap::from([("y".to_string(), "."), ("x".to_string(), "y/..")]);
+        assert!(!symlink_escapes("y", ".", &links));
+        assert!(symlink_escapes("x", "y/..", &links));
+        let links = BTreeMap::from([("a/up".to_string(), ".."), ("a/b/link".to_string(), "../up/c")]);
+        assert!(!symlink_escapes("a/b/link", "../up/c", &links));
+        let looped = BTreeMap::from([("p".to_string(), "q"), ("q".to_string(), "p")]);
+        assert!(symlink_escapes("p", "q", &looped));
+    }
+
+    #[test]
+    fn test_duplicate_files_share_payload() {
+        let dir = tempfile::tempdir().unwrap();
+        let src = dir.path().join("src");
+        fs::create_dir_all(src.join("vendor")).unwrap();
+        let body = b"vendored dependency source\n".repeat(300);
+        fs::write(src.join("a.rs"), &body).unwrap();
+        fs::write(src.join("vendor/a.rs"), &body).unwrap();
+        fs::write(src.join("b.rs"), b"unique").unwrap();
+        #[cfg(unix)]
+        fs::hard_link(src.join("b.rs"), src.join("c.rs")).unwrap();
+
+        let mut archive = Archive::create(dir.path().join("archive")).unwrap();
+        let added = archive.add_dir("pkg", &src).unwrap();
+        archive.commit().unwrap();
+        let a = archive.entry("pkg/a.rs").unwrap();
+        assert_eq!(a.chunks, archive.entry("pkg/vendor/a.rs").unwrap().chunks);
+        assert_eq!(archive.store().refcount(&a.chunks[0]), Some(2));
+
+        let stats = archive.stats();
+        assert_eq!(stats.entries, added);
+        assert_eq!(stats.dedup_saved_bytes, body.len() as u64 + if cfg!(unix) { 6 } else { 0 });
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::MetadataExt;
+            assert_eq!(added, 4);
+            assert_eq!(stats.hardlinks, 1);
+            assert_eq!(stats.duplicate_files, 2);
+            assert_eq!(archive.entry("pkg/c.rs").unwrap().hardlink.as_deref(), Some("pkg/b.rs"));
+            let out = dir.path().join("out");
+            archive.extract_matching(&out, "**", &ExtractOptions::default()).unwrap();
+            let b = fs::metadata(out.join("pkg/b.rs")).unwrap();
+            let c = fs::metadata(out.join("pkg/c.rs")).unwrap();
+            assert_eq!((b.ino(), b.nlink()), (c.ino(), 2));
+            // Without its target, a hard link is extracted as a plain file
+            let lone = dir.path().join("lone");
+            archive.extract_matching(&lone, "pkg/c.rs", &ExtractOptions::default()).unwrap();
+            assert_eq!(fs::read(lone.join("pkg/c.rs")).unwrap(), b"unique");
+        }
+    }
+
+    #[test]
+    fn test_add_dir_filters() {
+        let dir = tempfile::tempdir().unwrap();
+        let src = dir.path().join("crate");
+        for sub in ["src", "target/debug", ".git", "docs/target"] {
+            fs::create_dir_all(src.join(sub)).unwrap();
+        }
+        for file in ["Cargo.toml", "src/lib.rs", "src/gen.o", "src/keep.o", "target/debug/app", ".git/HEAD", "docs/target/x"] {
+            fs::write(src.join(file), file.as_bytes()).unwrap();
+        }
+        let options = ArchiveOptions {
+            filters: vec![
+                "# build output".into(),
+                "/target/".into(),
+                ".git/".into(),
+                "*.o".into(),
+                "!keep.o".into(),
+            ],
+        };
+        let mut archive = Archive::create(dir.path().join("archive")).unwrap();
+        assert_eq!(archive.add_dir_with("crate/", &src, &options).unwrap(), 4);
+        let mut paths: Vec<String> = archive.entries().map(|e| e.path).collect();
+        paths.sort();
+        assert_eq!(paths, ["crate/Cargo.toml", "crate/docs/target/x", "crate/src/keep.o", "crate/src/lib.rs"]);
+    }
+}
@@ -0,0 +1,133 @@
+//! Crash-safe file writes
+//!
+//! [`AtomicFile`] stages everything in a uniquely named temp file next to
+//! the target, and only [`AtomicFile::commit`] moves it into place: the temp
+//! file is synced, renamed over the target and the parent directory synced
+//! so the rename itself survives a crash. Readers see either the old file or
+//! the complete new one, never a torn frame, and concurrent writers to the
+//! same target never share a temp file.
+
+use crate::error::CompressError;
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use tempfile::NamedTempFile;
+
+/// File written in full before it replaces its target
+pub struct AtomicFile {
+    path: PathBuf,
+    file: Option<NamedTempFile>,
+}
+
+impl AtomicFile {
+    /// Start writing a replacement for `path`
+    pub fn create(path: impl AsRef<Path>) -> Result<Self, CompressError> {
+        let path = path.as_ref().to_path_buf();
+        let file = NamedTempFile::new_in(parent_dir(&path))?;
+        Ok(Self { path, file: Some(file) })
+    }
+
+    /// Make the contents durable and move them into place
+    pub fn commit(mut self) -> Result<(), CompressError> {
+        if let Some(file) = self.file.take() {
+            file.as_file().sync_all()?;
+            file.persist(&self.path).map_err(|e| e.error)?;
+        }
+        sync_parent(&self.path)
+    }
+}
+
+impl Write for AtomicFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match &mut self.file {
+            Some(file) => file.write(buf),
+            None => Err(io::Error::other("atomic file already committed")),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match &mut self.file {
+            Some(file) => file.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Replace `path` with `value` atomically
+pub fn write_atomic(path: &Path, value: &[u8]) -> Result<(), CompressError> {
+    let mut file = AtomicFile::create(path)?;
+    file.write_all(value)?;
+    file.commit()
+}
+
+/// Directory holding `path`
+fn parent_dir(path: &Path) -> &Path {
+    match path.parent() {
+        Some(p) if !p.as_os_str().is_empty() => p,
+        _ => Path::new("."),
+    }
+}
+
+/// Persist directory entry changes (creates, renames) under `path`'s parent
+pub(crate) fn sync_parent(path: &Path) -> Result<(), CompressError> {
+    #[cfg(unix)]
+    File::open(parent_dir(path))?.sync_all()?;
+    #[cfg(not(unix))]
+    let _ = path;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    /// Names of the entries in `dir`
+    fn entries(dir: &Path) -> Vec<String> {
+        fs::read_dir(dir)
+            .unwrap()
+            .map(|e| e.unwrap().file_name().to_string_lossy().into_owned())
+            .collect()
+    }
+
+    #[test]
+    fn test_commit_replaces_target() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("out.sgma");
+        fs::write(&path, b"old").unwrap();
+        let mut file = AtomicFile::create(&path).unwrap();
+        file.write_all(b"new contents").unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"old");
+        file.commit().unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"new contents");
+        assert_eq!(entries(dir.path()), ["out.sgma"]);
+    }
+
+    #[test]
+    fn test_abandoned_write_leaves_target() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("out.sgma");
+        fs::write(&path, b"old").unwrap();
+        {
+            let mut file = AtomicFile::create(&path).unwrap();
+            file.write_all(b"half a fr").unwrap();
+        }
+        assert_eq!(fs::read(&path).unwrap(), b"old");
+        assert_eq!(entries(dir.path()), ["out.sgma"]);
+    }
+
+    #[test]
+    fn test_concurrent_writers_stage_separately() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("out.sgma");
+        let mut first = AtomicFile::create(&path).unwrap();
+        let mut second = AtomicFile::create(&path).unwrap();
+        first.write_all(b"first").unwrap();
+        second.write_all(b"second").unwrap();
+        assert_eq!(entries(dir.path()).len(), 2);
+        second.commit().unwrap();
+        first.commit().unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"first");
+        assert_eq!(entries(dir.path()), ["out.sgma"]);
+    }
+}
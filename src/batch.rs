@@ -0,0 +1,279 @@
+//! Columnar serialization of many [`CompressedOutput`] records
+//!
+//! Persisting thousands of outputs one by one (e.g. as JSON in a manifest)
+//! repeats the field names and the derived metadata of every record.
+//! [`encode_outputs`] writes the batch column by column instead: methods are
+//! dictionary-encoded against a per-batch table, sizes and block counts are
+//! zigzag deltas from the previous record, and presence flags share one byte
+//! per record. As with [`CompressedOutput::write_to`], the ratio, overhead
+//! and dedup statistics are recomputed on read rather than stored; codec
+//! parameters (see [`crate::params`]) are kept whether or not the record's
+//! frame header carries them.
+//!
+//! Layout (integers little-endian):
+//!
+//! ```text
+//! [SGCB][version u8][records varint]
+//! [methods varint][method id u8]*     method table
+//! [method index u8]*                  one per record
+//! [flags u8]*                         frame header flags, plus
+//!                                     FLAG_DETACHED_PARAMS (version 2)
+//! [original_size delta varint]*
+//! [compressed_size delta varint]*
+//! [block_count delta varint]*
+//! [checksum u32]*                     records with bit 0 only
+//! [dictionary_id varint]*             records with bit 1 only
+//! [entropy_bits f64]*
+//! [params 6]*                         records with FLAG_PARAMS or
+//!                                     FLAG_DETACHED_PARAMS only
+//! [payload]*                          compressed_size bytes each
+//! ```
+//!
+//! Version 1 batches have no parameters and are still read.
+
+use crate::error::CompressError;
+use crate::frame::{FrameHeader, FLAG_CHECKSUM, FLAG_DICTIONARY, FLAG_PARAMS, FLAG_UTF8, FORMAT_VERSION};
+use crate::params::{self, CodecParams};
+use crate::{varint, CompressedOutput, CompressionMethod};
+
+/// Magic bytes opening a batch
+pub const BATCH_MAGIC: [u8; 4] = *b"SGCB";
+
+/// Batch format version
+pub const BATCH_VERSION: u8 = 2;
+
+/// Flag bit: the record has codec parameters its frame header leaves out
+const FLAG_DETACHED_PARAMS: u8 = 0x40;
+
+/// Serialize `outputs` column by column
+pub fn encode_outputs(outputs: &[CompressedOutput]) -> Vec<u8> {
+    let payload_len: usize = outputs.iter().map(|o| o.data.len()).sum();
+    let mut out = Vec::with_capacity(payload_len + outputs.len() * 16 + 16);
+    out.extend_from_slice(&BATCH_MAGIC);
+    out.push(BATCH_VERSION);
+    varint::write_u64(&mut out, outputs.len() as u64);
+
+    let mut methods: Vec<CompressionMethod> = Vec::new();
+    let indices: Vec<u8> = outputs
+        .iter()
+        .map(|o| match methods.iter().position(|&m| m == o.method) {
+            Some(i) => i as u8,
+            None => {
+                methods.push(o.method);
+                (methods.len() - 1) as u8
+            }
+        })
+        .collect();
+    varint::write_u64(&mut out, methods.len() as u64);
+    out.extend(methods.iter().map(|m| m.id()));
+    out.extend_from_slice(&indices);
+    out.extend(outputs.iter().map(|o| {
+        let checksum = if o.checksum.is_some() { FLAG_CHECKSUM } else { 0 };
+        let dictionary = if o.dictionary_id.is_some() { FLAG_DICTIONARY } else { 0 };
+        let params = match (o.metadata.params, o.params_in_frame) {
+            (Some(_), true) => FLAG_PARAMS,
+            (Some(_), false) => FLAG_DETACHED_PARAMS,
+            (None, _) => 0,
+        };
+        checksum | dictionary | params | if o.utf8 { FLAG_UTF8 } else { 0 }
+    }));
+    for column in [
+        |o: &CompressedOutput| o.original_size,
+        |o: &CompressedOutput| o.data.len(),
+        |o: &CompressedOutput| o.metadata.block_count,
+    ] {
+        let mut previous = 0i64;
+        for output in outputs {
+            let value = column(output) as i64;
+            varint::write_i64(&mut out, value.wrapping_sub(previous));
+            previous = value;
+        }
+    }
+    for checksum in outputs.iter().filter_map(|o| o.checksum) {
+        out.extend_from_slice(&checksum.to_le_bytes());
+    }
+    for id in outputs.iter().filter_map(|o| o.dictionary_id) {
+        varint::write_u64(&mut out, u64::from(id));
+    }
+    for output in outputs {
+        out.extend_from_slice(&output.metadata.entropy_bits.to_le_bytes());
+    }
+    for params in outputs.iter().filter_map(|o| o.metadata.params) {
+        params.write(&mut out);
+    }
+    for output in outputs {
+        out.extend_from_slice(&output.data);
+    }
+    out
+}
+
+/// Total original size of a batch, read from the size column alone
+pub(crate) fn original_size(data: &[u8]) -> Result<u64, CompressError> {
+    let truncated = || CompressError::BatchError("truncated batch".into());
+    let mut pos = 5;
+    let count = varint::read_usize(data, &mut pos).ok_or_else(truncated)?;
+    let methods = varint::read_usize(data, &mut pos).ok_or_else(truncated)?;
+    // Skip the method table, method indices and flags
+    pos = methods
+        .checked_add(count.checked_mul(2).ok_or_else(truncated)?)
+        .and_then(|skip| pos.checked_add(skip))
+        .ok_or_else(truncated)?;
+    let (mut total, mut previous) = (0u64, 0i64);
+    for _ in 0..count {
+        previous = previous.wrapping_add(varint::read_i64(data, &mut pos).ok_or_else(truncated)?);
+        total = u64::try_from(previous)
+            .ok()
+            .and_then(|size| total.checked_add(size))
+            .ok_or_else(|| CompressError::BatchError("invalid size column".into()))?;
+    }
+    Ok(total)
+}
+
+/// Read a batch written by [`encode_outputs`]
+pub fn decode_outputs(data: &[u8]) -> Result<Vec<CompressedOutput>, CompressError> {
+    if data.len() < 5 || data[..4] != BATCH_MAGIC {
+        return Err(CompressError::BatchError("not an output batch".into()));
+    }
+    if data[4] == 0 || data[4] > BATCH_VERSION {
+        return Err(CompressError::BatchError(format!("unsupported version {}", data[4])));
+    }
+    let truncated = || CompressError::BatchError("truncated batch".into());
+    let mut pos = 5;
+    let count = varint::read_usize(data, &mut pos).ok_or_else(truncated)?;
+    if count > data.len() {
+        return Err(truncated());
+    }
+    let take = |len: usize, pos: &mut usize| -> Result<&[u8], CompressError> {
+        let bytes = data.get(*pos..pos.checked_add(len).ok_or_else(truncated)?).ok_or_else(truncated)?;
+        *pos += len;
+        Ok(bytes)
+    };
+
+    let method_count = varint::read_usize(data, &mut pos).ok_or_else(truncated)?;
+    let methods = take(method_count, &mut pos)?
+        .iter()
+        .map(|&id| CompressionMethod::from_id(id).ok_or_else(|| CompressError::BatchError(format!("unknown method id {}", id))))
+        .collect::<Result<Vec<_>, _>>()?;
+    let indices = take(count, &mut pos)?;
+    let flags = take(count, &mut pos)?;
+    let mut columns = [vec![0usize; count], vec![0usize; count], vec![0usize; count]];
+    for column in &mut columns {
+        let mut previous = 0i64;
+        for value in column.iter_mut() {
+            previous = previous.wrapping_add(varint::read_i64(data, &mut pos).ok_or_else(truncated)?);
+            *value = usize::try_from(previous).map_err(|_| CompressError::BatchError("negative size".into()))?;
+        }
+    }
+    let [original_sizes, compressed_sizes, block_counts] = columns;
+    let mut checksums = Vec::with_capacity(count);
+    for &f in flags {
+        checksums.push(if f & FLAG_CHECKSUM != 0 {
+            Some(u32::from_le_bytes(take(4, &mut pos)?.try_into().unwrap()))
+        } else {
+            None
+        });
+    }
+    let mut dictionary_ids = Vec::with_capacity(count);
+    for &f in flags {
+        dictionary_ids.push(if f & FLAG_DICTIONARY != 0 {
+            let id = varint::read_u64(data, &mut pos).ok_or_else(truncated)?;
+            Some(u32::try_from(id).map_err(|_| CompressError::BatchError("dictionary id exceeds u32".into()))?)
+        } else {
+            None
+        });
+    }
+    let entropies = take(count.checked_mul(8).ok_or_else(truncated)?, &mut pos)?
+        .chunks_exact(8)
+        .map(|c| f64::from_le_bytes(c.try_into().unwrap()))
+        .collect::<Vec<_>>();
+    let mut codec_params = Vec::with_capacity(count);
+    for (&f, &dictionary_id) in flags.iter().zip(&dictionary_ids) {
+        codec_params.push(if f & (FLAG_PARAMS | FLAG_DETACHED_PARAMS) != 0 {
+            Some(CodecParams::parse(take(params::ENCODED_LEN, &mut pos)?, dictionary_id)?)
+        } else {
+            None
+        });
+    }
+
+    let mut outputs = Vec::with_capacity(count);
+    for i in 0..count {
+        let method = *methods
+            .get(indices[i] as usize)
+            .ok_or_else(|| CompressError::BatchError("method index out of range".into()))?;
+        let header = FrameHeader {
+            version: FORMAT_VERSION,
+            method,
+            flags: flags[i] & !FLAG_DETACHED_PARAMS,
+            original_size: original_sizes[i] as u64,
+            payload_len: compressed_sizes[i] as u64,
+            checksum: checksums[i],
+            dictionary_id: dictionary_ids[i],
+            padding: None,
+            params: codec_params[i].filter(|_| flags[i] & FLAG_PARAMS != 0),
+        };
+        let payload = take(compressed_sizes[i], &mut pos)?.to_vec();
+        let mut output = CompressedOutput::from_header(&header, payload)?;
+        output.metadata.entropy_bits = entropies[i];
+        output.metadata.block_count = block_counts[i];
+        output.metadata.params = codec_params[i];
+        outputs.push(output);
+    }
+    if pos != data.len() {
+        return Err(CompressError::BatchError("trailing bytes after batch".into()));
+    }
+    Ok(outputs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::CompressionConfig;
+    use crate::Compressor;
+
+    fn sample_outputs() -> Vec<CompressedOutput> {
+        let compressor = Compressor::default();
+        let recording = Compressor::new(CompressionConfig {
+            record_params: true,
+            ..CompressionConfig::default()
+        });
+        let methods = [CompressionMethod::Huffman, CompressionMethod::Stored, CompressionMethod::Lz4Semantic];
+        (0..300)
+            .map(|i| {
+                let data = format!("part {} of the corpus, {}", i, "x".repeat(i % 40));
+                let compressor = if i % 5 == 0 { &recording } else { &compressor };
+                let mut output = compressor.compress(data.as_bytes(), methods[i % 3]).unwrap();
+                output.utf8 = i % 2 == 0;
+                if i % 5 == 3 {
+                    // As read back from a frame without parameters
+                    output.metadata.params = None;
+                }
+                output
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_batch_roundtrip() {
+        let outputs = sample_outputs();
+        let decoded = decode_outputs(&encode_outputs(&outputs)).unwrap();
+        assert_eq!(decoded.len(), outputs.len());
+        for (a, b) in outputs.iter().zip(&decoded) {
+            assert_eq!(serde_json::to_value(a).unwrap(), serde_json::to_value(b).unwrap());
+        }
+        assert!(decode_outputs(&encode_outputs(&[])).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_batch_metadata_is_compact() {
+        let outputs = sample_outputs();
+        let payloads: usize = outputs.iter().map(|o| o.data.len()).sum();
+        let columns = encode_outputs(&outputs).len() - payloads;
+        let json: usize = outputs
+            .iter()
+            .map(|o| serde_json::to_vec(o).unwrap().len() - serde_json::to_vec(&o.data).unwrap().len())
+            .sum();
+        assert!(columns * 10 < json, "{} vs {}", columns, json);
+        let batch = encode_outputs(&outputs);
+        assert!(decode_outputs(&batch[..batch.len() - 1]).is_err());
+    }
+}
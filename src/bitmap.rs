@@ -0,0 +1,227 @@
+//! Roaring-style compression of integer sets and posting lists
+//!
+//! Inverted indexes store sorted document ids, which as raw bytes cost eight
+//! bytes each no matter how dense they are. Values are split by their high
+//! 48 bits into containers of up to 65536 low halves, and each container is
+//! stored in whichever of three forms is smallest:
+//!
+//! - array: the sorted low halves, two bytes each (sparse containers)
+//! - bitmap: 65536 bits, one per possible low half (dense containers)
+//! - runs: `(start, length - 1)` pairs (clustered containers)
+//!
+//! Layout:
+//!
+//! ```text
+//! [SGBM][version u8][containers varint]
+//! per container: [key delta varint][kind u8][cardinality - 1 varint] body
+//! array body:    [low u16 LE]*
+//! bitmap body:   [u64 LE; 1024]
+//! runs body:     [runs varint] ([start u16 LE][length - 1 u16 LE])*
+//! ```
+
+use crate::error::CompressError;
+use crate::varint;
+
+/// Magic bytes opening a compressed bitmap
+pub const BITMAP_MAGIC: [u8; 4] = *b"SGBM";
+
+/// Bitmap format version
+pub const BITMAP_VERSION: u8 = 1;
+
+const KIND_ARRAY: u8 = 0;
+const KIND_BITMAP: u8 = 1;
+const KIND_RUNS: u8 = 2;
+
+/// Bytes in a bitmap container body
+const BITMAP_BYTES: usize = 8192;
+
+/// Compress a strictly ascending set of values
+pub fn compress_bitmap(values: &[u64]) -> Result<Vec<u8>, CompressError> {
+    if let Some(i) = values.windows(2).position(|w| w[0] >= w[1]) {
+        return Err(CompressError::BitmapError(format!("value {} is not ascending", i + 1)));
+    }
+    let containers = values.chunk_by(|a, b| a >> 16 == b >> 16).collect::<Vec<_>>();
+    let mut output = Vec::with_capacity(values.len() * 2 + 16);
+    output.extend_from_slice(&BITMAP_MAGIC);
+    output.push(BITMAP_VERSION);
+    varint::write_u64(&mut output, containers.len() as u64);
+    let mut previous_key = 0;
+    for container in containers {
+        let key = container[0] >> 16;
+        varint::write_u64(&mut output, key - previous_key);
+        previous_key = key;
+        write_container(container, &mut output);
+    }
+    Ok(output)
+}
+
+/// Compress a sorted posting list of document ids
+pub fn compress_postings(doc_ids: &[u32]) -> Result<Vec<u8>, CompressError> {
+    compress_bitmap(&doc_ids.iter().map(|&id| u64::from(id)).collect::<Vec<_>>())
+}
+
+pub fn decompress_bitmap(data: &[u8]) -> Result<Vec<u64>, CompressError> {
+    if data.len() < 5 || data[..4] != BITMAP_MAGIC {
+        return Err(CompressError::BitmapError("not a compressed bitmap".into()));
+    }
+    if data[4] != BITMAP_VERSION {
+        return Err(CompressError::BitmapError(format!("unsupported version {}", data[4])));
+    }
+    let truncated = || CompressError::BitmapError("truncated container".into());
+    let mut pos = 5;
+    let containers = varint::read_usize(data, &mut pos).ok_or_else(truncated)?;
+    let mut values = Vec::new();
+    let mut key = 0u64;
+    for i in 0..containers {
+        let delta = varint::read_u64(data, &mut pos).ok_or_else(truncated)?;
+        if i > 0 && delta == 0 {
+            return Err(CompressError::BitmapError("repeated container key".into()));
+        }
+        key = key
+            .checked_add(delta)
+            .filter(|&k| k <= u64::MAX >> 16)
+            .ok_or_else(|| CompressError::BitmapError("container key out of range".into()))?;
+        let kind = *data.get(pos).ok_or_else(truncated)?;
+        pos += 1;
+        let cardinality = varint::read_usize(data, &mut pos)
+            .and_then(|c| c.checked_add(1))
+            .filter(|&c| c <= 1 << 16)
+            .ok_or_else(truncated)?;
+        let base = key << 16;
+        let start = values.len();
+        match kind {
+            KIND_ARRAY => {
+                let body = data.get(pos..pos + cardinality * 2).ok_or_else(truncated)?;
+                values.extend(body.chunks_exact(2).map(|c| base | u64::from(u16::from_le_bytes([c[0], c[1]]))));
+                pos += body.len();
+            }
+            KIND_BITMAP => {
+                let body = data.get(pos..pos + BITMAP_BYTES).ok_or_else(truncated)?;
+                for (w, word) in body.chunks_exact(8).enumerate() {
+                    let mut bits = u64::from_le_bytes(word.try_into().unwrap());
+                    while bits != 0 {
+                        values.push(base | (w as u64 * 64 + u64::from(bits.trailing_zeros())));
+                        bits &= bits - 1;
+                    }
+                }
+                pos += BITMAP_BYTES;
+            }
+            KIND_RUNS => {
+                let runs = varint::read_usize(data, &mut pos).ok_or_else(truncated)?;
+                let body = data.get(pos..pos.saturating_add(runs.saturating_mul(4))).ok_or_else(truncated)?;
+                for run in body.chunks_exact(4) {
+                    let first = u64::from(u16::from_le_bytes([run[0], run[1]]));
+                    let last = first + u64::from(u16::from_le_bytes([run[2], run[3]]));
+                    if last > 0xFFFF {
+                        return Err(CompressError::BitmapError("run overflows its container".into()));
+                    }
+                    if values.len() - start + (last - first + 1) as usize > cardinality {
+                        return Err(CompressError::BitmapError("runs exceed the container cardinality".into()));
+                    }
+                    values.extend((first..=last).map(|low| base | low));
+                }
+                pos += body.len();
+            }
+            other => return Err(CompressError::BitmapError(format!("unknown container kind {}", other))),
+        }
+        let decoded = &values[start..];
+        if decoded.len() != cardinality || decoded.windows(2).any(|w| w[0] >= w[1]) {
+            return Err(CompressError::BitmapError("container does not match its cardinality".into()));
+        }
+    }
+    if pos != data.len() {
+        return Err(CompressError::BitmapError("trailing bytes after containers".into()));
+    }
+    Ok(values)
+}
+
+pub fn decompress_postings(data: &[u8]) -> Result<Vec<u32>, CompressError> {
+    decompress_bitmap(data)?
+        .into_iter()
+        .map(|v| u32::try_from(v).map_err(|_| CompressError::BitmapError(format!("doc id {} exceeds u32", v))))
+        .collect()
+}
+
+/// Write one container in its smallest form
+fn write_container(container: &[u64], output: &mut Vec<u8>) {
+    let lows = container.iter().map(|&v| v as u16);
+    let runs = container
+        .chunk_by(|a, b| a + 1 == *b)
+        .map(|run| (run[0] as u16, (run.len() - 1) as u16))
+        .collect::<Vec<_>>();
+    let array_size = container.len() * 2;
+    let runs_size = runs.len() * 4 + varint::encoded_len(runs.len() as u64);
+
+    let kind = if runs_size < array_size.min(BITMAP_BYTES) {
+        KIND_RUNS
+    } else if array_size <= BITMAP_BYTES {
+        KIND_ARRAY
+    } else {
+        KIND_BITMAP
+    };
+    output.push(kind);
+    varint::write_u64(output, container.len() as u64 - 1);
+    match kind {
+        KIND_RUNS => {
+            varint::write_u64(output, runs.len() as u64);
+            for (start, extra) in runs {
+                output.extend_from_slice(&start.to_le_bytes());
+                output.extend_from_slice(&extra.to_le_bytes());
+            }
+        }
+        KIND_ARRAY => {
+            for low in lows {
+                output.extend_from_slice(&low.to_le_bytes());
+            }
+        }
+        _ => {
+            let mut words = [0u64; BITMAP_BYTES / 8];
+            for low in lows {
+                words[low as usize / 64] |= 1 << (low % 64);
+            }
+            for word in words {
+                output.extend_from_slice(&word.to_le_bytes());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bitmap_container_kinds() {
+        let sparse = (0..1000u64).map(|i| i * 61);
+        let dense = (0..30_000u64).map(|i| (5 << 16) + i * 2);
+        let clustered = (0..50_000u64).map(|i| (9 << 16) + i);
+        let far = [u64::MAX - 1, u64::MAX];
+        let values: Vec<u64> = sparse.chain(dense).chain(clustered).chain(far).collect();
+
+        let packed = compress_bitmap(&values).unwrap();
+        assert_eq!(decompress_bitmap(&packed).unwrap(), values);
+        // Array, bitmap and run containers, not eight bytes per value
+        assert!(packed.len() < 1000 * 2 + BITMAP_BYTES + 64 + 100, "{}", packed.len());
+        assert!(compress_bitmap(&[3, 3]).is_err());
+        assert!(decompress_bitmap(&packed[..packed.len() - 1]).is_err());
+
+        // Full runs under a cardinality of one fail before they are expanded
+        let mut bomb = packed[..5].to_vec();
+        bomb.extend_from_slice(&[1, 0, KIND_RUNS, 0]);
+        varint::write_u64(&mut bomb, 1000);
+        for _ in 0..1000 {
+            bomb.extend_from_slice(&[0, 0, 0xFF, 0xFF]);
+        }
+        assert!(decompress_bitmap(&bomb).is_err());
+    }
+
+    #[test]
+    fn test_postings_roundtrip() {
+        let postings: Vec<u32> = (0..5000).map(|i| i * 13 + i % 3).collect();
+        let packed = compress_postings(&postings).unwrap();
+        assert!(packed.len() < postings.len() * 4 / 2 + 64);
+        assert_eq!(decompress_postings(&packed).unwrap(), postings);
+        assert!(decompress_postings(&compress_bitmap(&[1 << 40]).unwrap()).is_err());
+        assert_eq!(decompress_postings(&compress_postings(&[]).unwrap()).unwrap(), Vec::<u32>::new());
+    }
+}
@@ -0,0 +1,209 @@
+//! Shared cache of decompressed blocks for serving layers
+//!
+//! A service answering reads from the same compressed objects decodes the
+//! same frames over and over. A [`BlockCache`] installed with
+//! [`Compressor::with_block_cache`] sits inside the decompression path: each
+//! unsegmented frame (a whole frame, one member of back-to-back frames, or
+//! one segment of a chunked frame) is looked up by the BLAKE3 hash of its
+//! encoded bytes before it is decoded, and verified output is kept for the
+//! next request. The hash covers the header and payload, so it identifies
+//! both the frame and the block within it.
+//!
+//! Blocks are stored as `Arc<[u8]>`, so one cache can be shared by any
+//! number of compressors and threads, and
+//! [`Compressor::decompress_frame_shared`] hands out cached blocks without
+//! copying. Capacity is in decompressed bytes; the least recently used
+//! blocks are evicted first.
+
+use crate::error::CompressError;
+use crate::store::BlockHash;
+use crate::{frame, Compressor};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+
+/// Counters of a [`BlockCache`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BlockCacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub evictions: u64,
+    /// Decompressed bytes currently cached
+    pub bytes: usize,
+    pub blocks: usize,
+}
+
+#[derive(Default)]
+struct State {
+    blocks: HashMap<BlockHash, (Arc<[u8]>, u64)>,
+    /// Hashes by last use, oldest first
+    recency: BTreeMap<u64, BlockHash>,
+    clock: u64,
+    stats: BlockCacheStats,
+}
+
+/// LRU cache of decompressed blocks keyed by encoded-block hash
+pub struct BlockCache {
+    capacity: usize,
+    state: Mutex<State>,
+}
+
+impl BlockCache {
+    /// Cache holding at most `capacity` decompressed bytes
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            state: Mutex::new(State::default()),
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub fn stats(&self) -> BlockCacheStats {
+        self.state.lock().unwrap().stats
+    }
+
+    /// Drop every cached block; counters are kept
+    pub fn clear(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.blocks.clear();
+        state.recency.clear();
+        state.stats.bytes = 0;
+        state.stats.blocks = 0;
+    }
+
+    pub(crate) fn get(&self, hash: &BlockHash) -> Option<Arc<[u8]>> {
+        let mut state = self.state.lock().unwrap();
+        state.clock += 1;
+        let clock = state.clock;
+        let Some((block, last_used)) = state.blocks.get_mut(hash) else {
+            state.stats.misses += 1;
+            return None;
+        };
+        let (block, previous) = (block.clone(), std::mem::replace(last_used, clock));
+        state.recency.remove(&previous);
+        state.recency.insert(clock, *hash);
+        state.stats.hits += 1;
+        Some(block)
+    }
+
+    /// Cache `block`; blocks larger than the whole capacity are not kept
+    pub(crate) fn insert(&self, hash: BlockHash, block: Arc<[u8]>) {
+        if block.len() > self.capacity {
+            return;
+        }
+        let mut state = self.state.lock().unwrap();
+        state.clock += 1;
+        let clock = state.clock;
+        let len = block.len();
+        if let Some((old, last_used)) = state.blocks.insert(hash, (block, clock)) {
+            state.recency.remove(&last_used);
+            state.stats.bytes -= old.len();
+            state.stats.blocks -= 1;
+        }
+        state.recency.insert(clock, hash);
+        state.stats.bytes += len;
+        state.stats.blocks += 1;
+        while state.stats.bytes > self.capacity {
+            let Some((_, oldest)) = state.recency.pop_first() else {
+                break;
+            };
+            if let Some((evicted, _)) = state.blocks.remove(&oldest) {
+                state.stats.bytes -= evicted.len();
+                state.stats.blocks -= 1;
+                state.stats.evictions += 1;
+            }
+        }
+    }
+}
+
+impl Compressor {
+    /// Look up and keep decompressed blocks in `cache` (see [`block_cache`](crate::block_cache))
+    pub fn with_block_cache(mut self, cache: Arc<BlockCache>) -> Self {
+        self.block_cache = Some(cache);
+        self
+    }
+
+    pub fn block_cache(&self) -> Option<&Arc<BlockCache>> {
+        self.block_cache.as_ref()
+    }
+
+    /// [`Compressor::decompress_frame`] returning shared data: for a single
+    /// unsegmented frame with a block cache installed, the cached block
+    /// itself, without a copy
+    pub fn decompress_frame_shared(&self, data: &[u8]) -> Result<Arc<[u8]>, CompressError> {
+        let header = frame::FrameHeader::parse(data)?;
+        let members = self.frame_members(data)?;
+        let single = header.flags & frame::FLAG_SEGMENTED == 0 && members.len() == 1;
+        match &self.block_cache {
+            Some(cache) if single => {
+                let data = members[0];
+                let hash = BlockHash::of(data);
+                if let Some(block) = cache.get(&hash) {
+                    return Ok(block);
+                }
+                let block: Arc<[u8]> = self.decode_member(data, &header)?.into();
+                cache.insert(hash, block.clone());
+                Ok(block)
+            }
+            _ => Ok(self.decompress_frame(data)?.into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::CompressionMethod;
+
+    fn frame_of(compressor: &Compressor, n: usize) -> Vec<u8> {
+        let data: Vec<u8> = (0..2000).flat_map(|i| format!("block {} row {}\n", n, i).into_bytes()).collect();
+        compressor.compress(&data, CompressionMethod::Lz4Semantic).unwrap().to_frame()
+    }
+
+    #[test]
+    fn test_block_cache_serves_repeat_reads() {
+        let cache = Arc::new(BlockCache::new(1 << 20));
+        let compressor = Compressor::default().with_block_cache(cache.clone());
+        let frame = frame_of(&compressor, 1);
+        let first = compressor.decompress_frame_shared(&frame).unwrap();
+        let second = compressor.decompress_frame_shared(&frame).unwrap();
+        assert!(Arc::ptr_eq(&first, &second));
+        assert_eq!(compressor.decompress_frame(&frame).unwrap(), &first[..]);
+
+        // Another compressor sharing the cache hits too, also per member
+        let other = Compressor::default().with_block_cache(cache.clone());
+        let mut stream = frame.clone();
+        stream.extend_from_slice(&frame_of(&compressor, 2));
+        other.decompress_frame(&stream).unwrap();
+        let stats = cache.stats();
+        assert_eq!((stats.hits, stats.misses, stats.blocks), (3, 2, 2));
+
+        // Corrupt frames are never cached
+        let mut corrupt = frame_of(&compressor, 3);
+        let last = corrupt.len() - 1;
+        corrupt[last] ^= 0xFF;
+        assert!(compressor.decompress_frame(&corrupt).is_err());
+        assert_eq!(cache.stats().blocks, 2);
+    }
+
+    #[test]
+    fn test_block_cache_evicts_lru() {
+        let compressor = Compressor::default();
+        let frames: Vec<Vec<u8>> = (0..3).map(|n| frame_of(&compressor, n)).collect();
+        let block_len = compressor.decompress_frame(&frames[0]).unwrap().len();
+        let cache = Arc::new(BlockCache::new(block_len * 2 + 100));
+        let compressor = compressor.with_block_cache(cache.clone());
+        compressor.decompress_frame(&frames[0]).unwrap();
+        compressor.decompress_frame(&frames[1]).unwrap();
+        compressor.decompress_frame(&frames[0]).unwrap();
+        compressor.decompress_frame(&frames[2]).unwrap();
+        assert_eq!(cache.stats().evictions, 1);
+        compressor.decompress_frame(&frames[0]).unwrap();
+        assert_eq!(cache.stats().hits, 2);
+        assert!(cache.stats().bytes <= cache.capacity());
+        cache.clear();
+        assert_eq!(cache.stats().blocks, 0);
+    }
+}
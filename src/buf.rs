@@ -0,0 +1,105 @@
+//! [`bytes`] integration for network services (feature `bytes`)
+//!
+//! Input is taken as any [`Buf`], whose chunks are handed to the codec in
+//! place through [`Compressor::compress_vectored`], so a rope or ring buffer
+//! is not first copied into one `Vec`. Output is [`Bytes`]: frames produced
+//! together are written into one allocation and handed out as slices of it.
+
+use crate::error::CompressError;
+use crate::{CompressionMethod, Compressor};
+use bytes::{Buf, Bytes};
+use std::io::IoSlice;
+
+/// Chunks requested from a [`Buf`] per call, doubled until all are seen
+const INITIAL_SLICES: usize = 16;
+
+/// Every chunk of `buf`, without copying
+fn chunks_of<B: Buf>(buf: &B) -> Vec<IoSlice<'_>> {
+    let mut count = INITIAL_SLICES;
+    loop {
+        let mut slices = vec![IoSlice::new(&[]); count];
+        let filled = buf.chunks_vectored(&mut slices);
+        slices.truncate(filled);
+        let seen: usize = slices.iter().map(|s| s.len()).sum();
+        if seen >= buf.remaining() || filled < count {
+            return slices;
+        }
+        count *= 2;
+    }
+}
+
+impl Compressor {
+    /// Compress the remaining contents of `buf` into a frame. `buf` is
+    /// consumed only on success.
+    pub fn compress_buf<B: Buf>(&self, mut buf: B, method: CompressionMethod) -> Result<Bytes, CompressError> {
+        let slices = chunks_of(&buf);
+        if slices.iter().map(|s| s.len()).sum::<usize>() < buf.remaining() {
+            // A Buf that cannot expose all its chunks at once is gathered
+            let data = buf.copy_to_bytes(buf.remaining());
+            return Ok(Bytes::from(self.compress(&data, method)?.to_frame()));
+        }
+        let output = self.compress_vectored(&slices, method)?;
+        let remaining = buf.remaining();
+        buf.advance(remaining);
+        Ok(Bytes::from(output.to_frame()))
+    }
+
+    /// Compress `buf` as independent frames of at most `frame_size` input
+    /// bytes each. All frames share one allocation; each is a slice of it.
+    pub fn compress_frames_buf<B: Buf>(
+        &self,
+        mut buf: B,
+        method: CompressionMethod,
+        frame_size: usize,
+    ) -> Result<Vec<Bytes>, CompressError> {
+        if !buf.has_remaining() {
+            return Err(CompressError::EmptyInput);
+        }
+        let frame_size = frame_size.max(1);
+        let mut encoded = Vec::new();
+        let mut bounds = Vec::new();
+        while buf.has_remaining() {
+            let take = frame_size.min(buf.remaining());
+            let segment = buf.copy_to_bytes(take);
+            let start = encoded.len();
+            self.compress(&segment, method)?.append_frame(&mut encoded);
+            bounds.push(start..encoded.len());
+        }
+        let shared = Bytes::from(encoded);
+        Ok(bounds.into_iter().map(|range| shared.slice(range)).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compress_chained_buf() {
+        let compressor = Compressor::default();
+        let head = Bytes::from_static(b"HTTP/1.1 200 OK\r\ncontent-type: text/plain\r\n\r\n");
+        let body = Bytes::from(b"response body from a hyper service ".repeat(50));
+        let mut buf = head.clone().chain(body.clone());
+        let frame = compressor.compress_buf(&mut buf, CompressionMethod::Lz4Semantic).unwrap();
+        assert!(!buf.has_remaining());
+        assert_eq!(compressor.decompress_frame(&frame).unwrap(), [head, body].concat());
+    }
+
+    #[test]
+    fn test_frames_share_allocation() {
+        let compressor = Compressor::default();
+        let data = Bytes::from(b"framed for the wire ".repeat(500));
+        let frames = compressor
+            .compress_frames_buf(data.clone(), CompressionMethod::Huffman, 4000)
+            .unwrap();
+        assert_eq!(frames.len(), 3);
+        let base = frames[0].as_ptr() as usize;
+        let offset = frames[1].as_ptr() as usize - base;
+        assert_eq!(offset, frames[0].len());
+        let decoded: Vec<u8> = frames
+            .iter()
+            .flat_map(|f| compressor.decompress_frame(f).unwrap())
+            .collect();
+        assert_eq!(decoded, data);
+    }
+}
@@ -0,0 +1,244 @@
+//! In-memory cache holding its values compressed
+//!
+//! [`CompressedCache`] is a map from keys to byte values that keeps every
+//! value as a frame, with the method chosen per entry by
+//! [`CompressionMethod::Auto`], and decompresses on [`CompressedCache::get`].
+//! Values are deduplicated by content hash: keys whose values are identical
+//! share one frame, which is only dropped with the last key referencing it.
+//!
+//! With a memory limit set, the least recently used keys are evicted until
+//! the frames fit. The limit counts frame bytes only, not keys or
+//! bookkeeping. [`CacheStats`] tracks hits, misses, evictions and how much
+//! memory compression and deduplication save.
+
+use crate::error::CompressError;
+use crate::store::BlockHash;
+use crate::{CompressionMethod, Compressor};
+use std::collections::{BTreeMap, HashMap};
+use std::hash::Hash;
+
+/// Counters of a [`CompressedCache`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub evictions: u64,
+    /// Uncompressed size of every cached value, duplicates included
+    pub logical_bytes: usize,
+    /// Frame bytes actually held
+    pub stored_bytes: usize,
+}
+
+impl CacheStats {
+    /// Bytes the cache would take holding every value uncompressed
+    pub fn memory_saved(&self) -> usize {
+        self.logical_bytes.saturating_sub(self.stored_bytes)
+    }
+
+    /// Fraction of lookups that hit; 0 before the first lookup
+    pub fn hit_rate(&self) -> f64 {
+        let lookups = self.hits + self.misses;
+        if lookups == 0 {
+            return 0.0;
+        }
+        self.hits as f64 / lookups as f64
+    }
+}
+
+struct Entry {
+    hash: BlockHash,
+    len: usize,
+    last_used: u64,
+}
+
+struct Value {
+    /// Empty for the empty value, which frames cannot hold
+    frame: Vec<u8>,
+    refs: usize,
+}
+
+/// Map of compressed, deduplicated values with optional LRU eviction
+pub struct CompressedCache<K> {
+    compressor: Compressor,
+    max_memory: Option<usize>,
+    entries: HashMap<K, Entry>,
+    values: HashMap<BlockHash, Value>,
+    /// Keys by last use, oldest first
+    recency: BTreeMap<u64, K>,
+    clock: u64,
+    stats: CacheStats,
+}
+
+impl<K: Eq + Hash + Clone> CompressedCache<K> {
+    /// Unbounded cache
+    pub fn new(compressor: Compressor) -> Self {
+        Self {
+            compressor,
+            max_memory: None,
+            entries: HashMap::new(),
+            values: HashMap::new(),
+            recency: BTreeMap::new(),
+            clock: 0,
+            stats: CacheStats::default(),
+        }
+    }
+
+    /// Evict least recently used keys once frames exceed `bytes`
+    pub fn with_max_memory(mut self, bytes: usize) -> Self {
+        self.max_memory = Some(bytes);
+        self.evict();
+        self
+    }
+
+    /// Compress and store `value` under `key`, replacing any previous value.
+    /// A value too large to fit the memory limit even alone is not kept.
+    pub fn insert(&mut self, key: K, value: &[u8]) -> Result<(), CompressError> {
+        let hash = BlockHash::of(value);
+        if !self.values.contains_key(&hash) {
+            let frame = if value.is_empty() {
+                Vec::new()
+            } else {
+                self.compressor.compress(value, CompressionMethod::Auto)?.to_frame()
+            };
+            self.stats.stored_bytes += frame.len();
+            self.values.insert(hash, Value { frame, refs: 0 });
+        }
+        self.values.get_mut(&hash).unwrap().refs += 1;
+        self.remove(&key);
+        self.stats.logical_bytes += value.len();
+        self.clock += 1;
+        self.recency.insert(self.clock, key.clone());
+        self.entries.insert(
+            key,
+            Entry {
+                hash,
+                len: value.len(),
+                last_used: self.clock,
+            },
+        );
+        self.evict();
+        Ok(())
+    }
+
+    /// Decompressed value under `key`, counting a hit or a miss
+    pub fn get(&mut self, key: &K) -> Result<Option<Vec<u8>>, CompressError> {
+        let Some(entry) = self.entries.get_mut(key) else {
+            self.stats.misses += 1;
+            return Ok(None);
+        };
+        self.stats.hits += 1;
+        self.clock += 1;
+        let key = self.recency.remove(&entry.last_used).unwrap();
+        self.recency.insert(self.clock, key);
+        entry.last_used = self.clock;
+        let frame = &self.values[&entry.hash].frame;
+        if frame.is_empty() {
+            return Ok(Some(Vec::new()));
+        }
+        self.compressor.decompress_frame(frame).map(Some)
+    }
+
+    /// Drop `key`, returning whether it was cached
+    pub fn remove(&mut self, key: &K) -> bool {
+        let Some(entry) = self.entries.remove(key) else {
+            return false;
+        };
+        self.recency.remove(&entry.last_used);
+        self.stats.logical_bytes -= entry.len;
+        let value = self.values.get_mut(&entry.hash).unwrap();
+        value.refs -= 1;
+        if value.refs == 0 {
+            self.stats.stored_bytes -= value.frame.len();
+            self.values.remove(&entry.hash);
+        }
+        true
+    }
+
+    pub fn contains_key(&self, key: &K) -> bool {
+        self.entries.contains_key(key)
+    }
+
+    /// Number of keys
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Distinct values held, after deduplication
+    pub fn unique_values(&self) -> usize {
+        self.values.len()
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        self.stats
+    }
+
+    fn evict(&mut self) {
+        let Some(limit) = self.max_memory else {
+            return;
+        };
+        while self.stats.stored_bytes > limit {
+            let Some((_, key)) = self.recency.first_key_value() else {
+                break;
+            };
+            let key = key.clone();
+            self.remove(&key);
+            self.stats.evictions += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn document(n: usize) -> Vec<u8> {
+        (0..200).flat_map(|i| format!("{{\"doc\":{},\"row\":{},\"state\":\"ok\"}}\n", n, i).into_bytes()).collect()
+    }
+
+    #[test]
+    fn test_cache_compresses_and_dedups() {
+        let mut cache = CompressedCache::new(Compressor::default());
+        cache.insert("a", &document(1)).unwrap();
+        cache.insert("b", &document(1)).unwrap();
+        cache.insert("c", &document(2)).unwrap();
+        cache.insert("empty", b"").unwrap();
+        assert_eq!((cache.len(), cache.unique_values()), (4, 3));
+        assert_eq!(cache.get(&"b").unwrap().unwrap(), document(1));
+        assert_eq!(cache.get(&"empty").unwrap().unwrap(), b"");
+        assert!(cache.get(&"missing").unwrap().is_none());
+
+        let stats = cache.stats();
+        assert_eq!((stats.hits, stats.misses), (2, 1));
+        assert_eq!(stats.logical_bytes, 3 * document(1).len());
+        assert!(stats.memory_saved() > stats.logical_bytes * 3 / 4, "{:?}", stats);
+
+        assert!(cache.remove(&"a"));
+        assert_eq!(cache.get(&"b").unwrap().unwrap(), document(1));
+        cache.remove(&"b");
+        assert_eq!(cache.unique_values(), 2);
+    }
+
+    #[test]
+    fn test_cache_evicts_least_recently_used() {
+        let compressor = Compressor::default();
+        let frame_len = compressor.compress(&document(0), CompressionMethod::Auto).unwrap().to_frame().len();
+        let mut cache = CompressedCache::new(compressor).with_max_memory(frame_len * 3 + frame_len / 2);
+        for n in 0..3 {
+            cache.insert(n, &document(n)).unwrap();
+        }
+        cache.get(&0).unwrap();
+        cache.insert(3, &document(3)).unwrap();
+        assert!(!cache.contains_key(&1));
+        assert!(cache.contains_key(&0) && cache.contains_key(&3));
+        assert_eq!(cache.stats().evictions, 1);
+        assert!(cache.stats().stored_bytes <= frame_len * 3 + frame_len / 2);
+
+        let mut tiny = CompressedCache::new(Compressor::default()).with_max_memory(8);
+        tiny.insert(0, &document(0)).unwrap();
+        assert!(tiny.is_empty());
+    }
+}
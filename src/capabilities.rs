@@ -0,0 +1,144 @@
+//! What each compression method can do
+//!
+//! UIs and policy engines choosing a method need to know more than its
+//! name: whether it can encode a stream without seeing all of it first,
+//! whether a reader can start decoding in the middle, whether a static
+//! dictionary or several threads help, and roughly how fast it runs and how
+//! well it compresses. [`CompressionMethod::capabilities`] answers that in
+//! one place instead of every caller hardcoding it. `Auto` reports what
+//! holds whichever method it picks.
+
+use crate::codec_stream;
+use crate::CompressionMethod;
+use serde::{Deserialize, Serialize};
+
+/// Typical throughput, relative to the other methods
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub enum SpeedClass {
+    Slow,
+    Moderate,
+    Fast,
+}
+
+/// Typical size reduction on compressible input, relative to the other methods
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub enum RatioClass {
+    /// Data is kept as-is
+    None,
+    Low,
+    Medium,
+    High,
+}
+
+/// Features and performance class of a method
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MethodCapabilities {
+    /// Encodes in a single pass, without the input's histogram up front
+    pub streaming_encode: bool,
+    /// Decodes block by block (see [`crate::CompressedOutput::blocks`])
+    pub streaming_decode: bool,
+    /// Blocks are coded independently, so decoding can start at any block
+    pub random_access: bool,
+    /// Can run behind a static dictionary (see [`crate::dictionary`])
+    pub dictionaries: bool,
+    /// Independent blocks can be encoded and decoded on separate threads
+    pub parallel: bool,
+    /// Decodable by the heapless [`crate::embedded::decode_frame`]
+    pub embedded_decode: bool,
+    pub speed: SpeedClass,
+    pub ratio: RatioClass,
+}
+
+impl MethodCapabilities {
+    /// What holds for both `self` and `other`
+    fn meet(self, other: Self) -> Self {
+        Self {
+            streaming_encode: self.streaming_encode && other.streaming_encode,
+            streaming_decode: self.streaming_decode && other.streaming_decode,
+            random_access: self.random_access && other.random_access,
+            dictionaries: self.dictionaries && other.dictionaries,
+            parallel: self.parallel && other.parallel,
+            embedded_decode: self.embedded_decode && other.embedded_decode,
+            speed: self.speed.min(other.speed),
+            ratio: self.ratio.min(other.ratio),
+        }
+    }
+}
+
+impl CompressionMethod {
+    /// Every concrete method, i.e. all but `Auto`
+    pub const CONCRETE: [CompressionMethod; 8] = [
+        CompressionMethod::Huffman,
+        CompressionMethod::Lz4Semantic,
+        CompressionMethod::EntropyCoding,
+        CompressionMethod::SemanticDedupe,
+        CompressionMethod::LogDedupe,
+        CompressionMethod::Stored,
+        CompressionMethod::Lzss,
+        CompressionMethod::SemanticLz,
+    ];
+
+    /// Features and performance class of this method
+    pub fn capabilities(self) -> MethodCapabilities {
+        use CompressionMethod::*;
+        let (speed, ratio) = match self {
+            Huffman => (SpeedClass::Moderate, RatioClass::Low),
+            Lz4Semantic => (SpeedClass::Fast, RatioClass::Medium),
+            EntropyCoding => (SpeedClass::Fast, RatioClass::Low),
+            SemanticDedupe => (SpeedClass::Fast, RatioClass::Medium),
+            LogDedupe => (SpeedClass::Moderate, RatioClass::High),
+            Stored => (SpeedClass::Fast, RatioClass::None),
+            Lzss => (SpeedClass::Slow, RatioClass::High),
+            SemanticLz => (SpeedClass::Moderate, RatioClass::High),
+            Auto => {
+                return Self::CONCRETE
+                    .into_iter()
+                    .map(Self::capabilities)
+                    .reduce(MethodCapabilities::meet)
+                    .unwrap()
+            }
+        };
+        MethodCapabilities {
+            streaming_encode: !codec_stream::needs_histogram(self),
+            streaming_decode: true,
+            random_access: matches!(self, Lz4Semantic | SemanticDedupe | Stored),
+            dictionaries: true,
+            parallel: matches!(self, Lz4Semantic | Stored),
+            embedded_decode: matches!(self, Huffman | SemanticDedupe | Stored),
+            speed,
+            ratio,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{embedded, Compressor};
+
+    #[test]
+    fn test_capabilities_match_behaviour() {
+        let compressor = Compressor::default();
+        let data = b"capabilities are checked against what the codecs do ".repeat(30);
+        let mut buffer = vec![0u8; data.len()];
+        for method in CompressionMethod::CONCRETE {
+            let caps = method.capabilities();
+            let output = compressor.compress(&data, method).unwrap();
+            assert_eq!(caps.embedded_decode, embedded::decode_frame(&output.to_frame(), &mut buffer).is_ok(), "{:?}", method);
+            if caps.ratio == RatioClass::None {
+                assert!(output.data.len() >= data.len());
+            }
+        }
+    }
+
+    #[test]
+    fn test_auto_reports_common_ground() {
+        let auto = CompressionMethod::Auto.capabilities();
+        assert!(auto.streaming_decode && auto.dictionaries);
+        assert!(!auto.streaming_encode && !auto.random_access && !auto.embedded_decode);
+        assert_eq!((auto.speed, auto.ratio), (SpeedClass::Slow, RatioClass::None));
+        let fast: Vec<_> =
+            CompressionMethod::CONCRETE.into_iter().filter(|m| m.capabilities().speed == SpeedClass::Fast).collect();
+        assert!(fast.contains(&CompressionMethod::Lz4Semantic) && !fast.contains(&CompressionMethod::Lzss));
+    }
+}
@@ -0,0 +1,106 @@
+//! Content-defined chunking
+//!
+//! Splits data at positions chosen by a rolling gear hash so that an insertion
+//! near the start of a file only disturbs the chunks around it, instead of
+//! shifting every fixed-size block after it.
+
+/// Smallest chunk the chunker will emit (except for the final tail)
+pub const MIN_CHUNK_SIZE: usize = 2 * 1024;
+
+/// Chunk size the boundary mask is tuned for
+pub const AVG_CHUNK_SIZE: usize = 8 * 1024;
+
+/// Largest chunk the chunker will emit
+pub const MAX_CHUNK_SIZE: usize = 64 * 1024;
+
+const BOUNDARY_MASK: u64 = (AVG_CHUNK_SIZE as u64 - 1) << 48;
+
+const GEAR: [u64; 256] = build_gear_table();
+
+const fn build_gear_table() -> [u64; 256] {
+    // splitmix64 seeded with a fixed constant so boundaries are stable across builds
+    let mut table = [0u64; 256];
+    let mut state: u64 = 0x9E37_79B9_7F4A_7C15;
+    let mut i = 0;
+    while i < 256 {
+        table[i] = crate::splitmix64(&mut state);
+        i += 1;
+    }
+    table
+}
+
+/// Split `data` into content-defined chunks
+pub fn chunk(data: &[u8]) -> Vec<&[u8]> {
+    let mut chunks = Vec::new();
+    let mut start = 0;
+    while start < data.len() {
+        let len = next_boundary(&data[start..]);
+        chunks.push(&data[start..start + len]);
+        start += len;
+    }
+    chunks
+}
+
+/// Length of the first chunk of `data`
+fn next_boundary(data: &[u8]) -> usize {
+    if data.len() <= MIN_CHUNK_SIZE {
+        return data.len();
+    }
+    let end = data.len().min(MAX_CHUNK_SIZE);
+    let mut hash: u64 = 0;
+    for (i, &b) in data[..end].iter().enumerate() {
+        hash = (hash << 1).wrapping_add(GEAR[b as usize]);
+        if i >= MIN_CHUNK_SIZE && hash & BOUNDARY_MASK == 0 {
+            return i + 1;
+        }
+    }
+    end
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pseudo_random(len: usize, seed: u64) -> Vec<u8> {
+        let mut state = seed;
+        (0..len)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                state as u8
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_chunks_cover_input() {
+        let data = pseudo_random(300_000, 7);
+        let chunks = chunk(&data);
+        assert_eq!(chunks.concat(), data);
+        for c in &chunks[..chunks.len() - 1] {
+            assert!(c.len() >= MIN_CHUNK_SIZE && c.len() <= MAX_CHUNK_SIZE);
+        }
+    }
+
+    #[test]
+    fn test_small_input_single_chunk() {
+        let data = b"tiny";
+        assert_eq!(chunk(data), vec![&data[..]]);
+        assert!(chunk(b"").is_empty());
+    }
+
+    #[test]
+    fn test_boundaries_resync_after_insert() {
+        let data = pseudo_random(200_000, 11);
+        let mut shifted = b"inserted prefix".to_vec();
+        shifted.extend_from_slice(&data);
+
+        let original: std::collections::HashSet<&[u8]> = chunk(&data).into_iter().collect();
+        let shared = chunk(&shifted)
+            .into_iter()
+            .filter(|c| original.contains(c))
+            .count();
+        assert!(shared >= original.len() - 2, "insert should only disturb nearby chunks");
+    }
+}
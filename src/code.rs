@@ -0,0 +1,193 @@
+//! Source-code preprocessing: identifier dictionary transform
+//!
+//! Identifiers and keywords that recur in a file are collected into a
+//! per-frame dictionary, most frequent first, and every occurrence is replaced
+//! by a short reference. The backend codec then sees a much smaller symbol
+//! vocabulary. Works on arbitrary bytes; only ASCII identifiers are coded.
+//!
+//! Layout: `[entries:varint][len:varint,name...][body]`, where the body holds
+//! literal bytes, `ESCAPE index+1:varint` for a dictionary reference, and
+//! `ESCAPE 0` for a literal escape byte.
+
+use crate::error::CompressError;
+use crate::{varint, CompressionMethod, Compressor};
+use std::collections::HashMap;
+
+/// Marks a reference in the transformed body
+pub const ESCAPE: u8 = 0x01;
+
+/// Identifiers shorter than this are never worth a reference
+const MIN_IDENT_LEN: usize = 3;
+
+fn is_ident_start(b: u8) -> bool {
+    b.is_ascii_alphabetic() || b == b'_'
+}
+
+fn is_ident_continue(b: u8) -> bool {
+    b.is_ascii_alphanumeric() || b == b'_'
+}
+
+/// Split into `(is_identifier, bytes)` runs
+fn tokens(data: &[u8]) -> impl Iterator<Item = (bool, &[u8])> {
+    let mut pos = 0;
+    std::iter::from_fn(move || {
+        if pos >= data.len() {
+            return None;
+        }
+        let start = pos;
+        let ident = is_ident_start(data[pos]);
+        pos += 1;
+        if ident {
+            while pos < data.len() && is_ident_continue(data[pos]) {
+                pos += 1;
+            }
+        } else {
+            // Digits outside identifiers stay in the literal run
+            while pos < data.len() && !is_ident_start(data[pos]) {
+                pos += 1;
+            }
+        }
+        Some((ident, &data[start..pos]))
+    })
+}
+
+/// Apply the identifier dictionary transform
+pub fn encode(data: &[u8]) -> Vec<u8> {
+    let mut counts: HashMap<&[u8], usize> = HashMap::new();
+    for (ident, token) in tokens(data) {
+        if ident && token.len() >= MIN_IDENT_LEN {
+            *counts.entry(token).or_default() += 1;
+        }
+    }
+    let mut dictionary: Vec<(&[u8], usize)> = counts.into_iter().filter(|&(_, n)| n >= 2).collect();
+    dictionary.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+    // Keep entries whose references are shorter than the names they replace
+    dictionary.retain({
+        let mut index = 0u64;
+        move |(name, _)| {
+            index += 1;
+            let keep = 1 + varint::encoded_len(index) < name.len();
+            if !keep {
+                index -= 1;
+            }
+            keep
+        }
+    });
+    let index: HashMap<&[u8], u64> = dictionary
+        .iter()
+        .enumerate()
+        .map(|(i, (name, _))| (*name, i as u64 + 1))
+        .collect();
+
+    let mut out = Vec::with_capacity(data.len());
+    varint::write_u64(&mut out, dictionary.len() as u64);
+    for (name, _) in &dictionary {
+        varint::write_u64(&mut out, name.len() as u64);
+        out.extend_from_slice(name);
+    }
+    for (ident, token) in tokens(data) {
+        match index.get(token).filter(|_| ident) {
+            Some(&reference) => {
+                out.push(ESCAPE);
+                varint::write_u64(&mut out, reference);
+            }
+            None => {
+                for &b in token {
+                    out.push(b);
+                    if b == ESCAPE {
+                        out.push(0);
+                    }
+                }
+            }
+        }
+    }
+    out
+}
+
+/// Undo [`encode`]
+pub fn decode(data: &[u8]) -> Result<Vec<u8>, CompressError> {
+    let err = |msg: &str| CompressError::CodeError(msg.into());
+    let mut pos = 0;
+    let entries = varint::read_usize(data, &mut pos).ok_or_else(|| err("truncated dictionary"))?;
+    let mut dictionary = Vec::with_capacity(entries.min(data.len()));
+    for _ in 0..entries {
+        let len = varint::read_usize(data, &mut pos).ok_or_else(|| err("truncated dictionary"))?;
+        let name = data
+            .get(pos..pos.saturating_add(len))
+            .ok_or_else(|| err("truncated dictionary"))?;
+        dictionary.push(name);
+        pos += len;
+    }
+
+    let mut out = Vec::with_capacity(data.len() * 2);
+    while pos < data.len() {
+        let b = data[pos];
+        pos += 1;
+        if b != ESCAPE {
+            out.push(b);
+            continue;
+        }
+        match varint::read_usize(data, &mut pos).ok_or_else(|| err("truncated reference"))? {
+            0 => out.push(ESCAPE),
+            reference => out.extend_from_slice(
+                dictionary
+                    .get(reference - 1)
+                    .ok_or_else(|| err("reference outside dictionary"))?,
+            ),
+        }
+    }
+    Ok(out)
+}
+
+/// Transform `data`, then compress it into a frame with `method`
+pub fn compress(compressor: &Compressor, data: &[u8], method: CompressionMethod) -> Result<Vec<u8>, CompressError> {
+    Ok(compressor.compress(&encode(data), method)?.to_frame())
+}
+
+/// Decode a frame from [`compress`]
+pub fn decompress(compressor: &Compressor, frame: &[u8]) -> Result<Vec<u8>, CompressError> {
+    decode(&compressor.decompress_frame(frame)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn source() -> String {
+        (0..40)
+            .map(|i| {
+                format!(
+                    "fn handle_request_{i}(request_context: &RequestContext) -> Result<ResponseBody, HandlerError> {{\n    \
+                     let response_body = request_context.build_response_body({i});\n    Ok(response_body)\n}}\n"
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_code_transform_roundtrip() {
+        let src = source();
+        let encoded = encode(src.as_bytes());
+        assert!(encoded.len() < src.len());
+        assert_eq!(decode(&encoded).unwrap(), src.as_bytes());
+
+        let binary = [0x01, 0x00, b'a', b'b', b'c', 0x01, b'a', b'b', b'c', 0xFF];
+        assert_eq!(decode(&encode(&binary)).unwrap(), binary);
+    }
+
+    #[test]
+    fn test_code_transform_helps_backend() {
+        let compressor = Compressor::default();
+        let src = source();
+        let framed = compress(&compressor, src.as_bytes(), CompressionMethod::Huffman).unwrap();
+        let plain = compressor.compress(src.as_bytes(), CompressionMethod::Huffman).unwrap();
+        assert!(framed.len() < plain.total_encoded_size());
+        assert_eq!(decompress(&compressor, &framed).unwrap(), src.as_bytes());
+    }
+
+    #[test]
+    fn test_code_decode_rejects_bad_reference() {
+        assert!(decode(&[0, ESCAPE, 5]).is_err());
+        assert!(decode(&[1, 10, b'a']).is_err());
+    }
+}
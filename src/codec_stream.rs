@@ -0,0 +1,153 @@
+//! Block-wise streaming access to every codec
+//!
+//! Lets callers move data between methods one block at a time instead of
+//! materializing the whole original buffer.
+
+use crate::config::CompressionConfig;
+use crate::error::CompressError;
+use crate::semantic::SemanticReport;
+use crate::{entropy, huffman, log_dedupe, lz4_wrapper, lzss, semantic, semantic_lz, stored, CompressionMethod};
+
+/// Target size of blocks yielded by decoders of formats without native blocks
+pub(crate) const STREAM_BLOCK_SIZE: usize = 64 * 1024;
+
+/// Iterator over decompressed blocks
+pub(crate) type BlockIter<'a> = Box<dyn Iterator<Item = Result<Vec<u8>, CompressError>> + 'a>;
+
+/// Decode a codec payload block by block
+pub(crate) fn decode_blocks(
+    method: CompressionMethod,
+    data: &[u8],
+    original_size: usize,
+) -> Result<BlockIter<'_>, CompressError> {
+    Ok(match method {
+        CompressionMethod::Huffman => Box::new(huffman::BlockDecoder::new(data, original_size, STREAM_BLOCK_SIZE)?),
+        CompressionMethod::Lz4Semantic => Box::new(lz4_wrapper::BlockDecoder::new(data)?),
+        CompressionMethod::EntropyCoding => Box::new(entropy::BlockDecoder::new(data, STREAM_BLOCK_SIZE)?),
+        CompressionMethod::SemanticDedupe => Box::new(semantic::BlockDecoder::bounded(data, original_size, STREAM_BLOCK_SIZE)?),
+        CompressionMethod::LogDedupe => Box::new(log_dedupe::BlockDecoder::new(data, STREAM_BLOCK_SIZE)?),
+        CompressionMethod::Stored => Box::new(stored::BlockDecoder::new(data, STREAM_BLOCK_SIZE)),
+        CompressionMethod::Lzss => Box::new(lzss::BlockDecoder::new(data, original_size, STREAM_BLOCK_SIZE)?),
+        CompressionMethod::SemanticLz => Box::new(semantic_lz::BlockDecoder::new(data, original_size, STREAM_BLOCK_SIZE)?),
+        CompressionMethod::Auto => return Err(CompressError::InvalidMethod),
+    })
+}
+
+/// Structural bytes inside a codec payload, as `(tables, block_headers)`
+pub(crate) fn payload_overhead(method: CompressionMethod, data: &[u8]) -> Result<(usize, usize), CompressError> {
+    Ok(match method {
+        CompressionMethod::Huffman => (huffman::table_len(data)?, 0),
+        CompressionMethod::Lz4Semantic => (0, lz4_wrapper::header_len(data)?),
+        CompressionMethod::EntropyCoding => (0, 0),
+        CompressionMethod::SemanticDedupe => (semantic::index_len(data)?, 0),
+        CompressionMethod::LogDedupe | CompressionMethod::Stored => (0, 0),
+        CompressionMethod::Lzss => (lzss::table_len(data)?, 0),
+        CompressionMethod::SemanticLz => (0, semantic_lz::header_len(data)?),
+        CompressionMethod::Auto => return Err(CompressError::InvalidMethod),
+    })
+}
+
+/// Dedup statistics of payloads produced by the dedup methods
+pub(crate) fn semantic_report(method: CompressionMethod, data: &[u8]) -> Result<Option<SemanticReport>, CompressError> {
+    Ok(match method {
+        CompressionMethod::SemanticDedupe => Some(semantic::report(data)?),
+        CompressionMethod::SemanticLz => Some(semantic_lz::report(data)?),
+        _ => None,
+    })
+}
+
+/// Whether encoding with `method` needs the input's byte histogram up front
+pub(crate) fn needs_histogram(method: CompressionMethod) -> bool {
+    method == CompressionMethod::Huffman
+}
+
+/// Incremental encoder for any concrete method
+pub(crate) enum StreamEncoder {
+    Huffman(huffman::StreamEncoder),
+    Lz4(lz4_wrapper::StreamEncoder),
+    Entropy(entropy::StreamEncoder),
+    Semantic(semantic::StreamEncoder),
+    Log(log_dedupe::StreamEncoder),
+    Stored(stored::StreamEncoder),
+    Lzss(lzss::StreamEncoder),
+    SemanticLz(semantic_lz::StreamEncoder),
+}
+
+impl StreamEncoder {
+    /// `histogram` is required when [`needs_histogram`] is true for `method`
+    pub(crate) fn new(
+        method: CompressionMethod,
+        config: &CompressionConfig,
+        histogram: Option<&[u64; 256]>,
+        total_len: usize,
+    ) -> Result<Self, CompressError> {
+        Ok(match method {
+            CompressionMethod::Huffman => {
+                let freq = histogram.ok_or_else(|| CompressError::HuffmanError("histogram required".into()))?;
+                StreamEncoder::Huffman(huffman::StreamEncoder::new(freq, total_len)?)
+            }
+            CompressionMethod::Lz4Semantic => StreamEncoder::Lz4(lz4_wrapper::StreamEncoder::with_level(config.lz4_block_size, config.level)),
+            CompressionMethod::EntropyCoding => StreamEncoder::Entropy(entropy::StreamEncoder::new()),
+            CompressionMethod::SemanticDedupe => {
+                StreamEncoder::Semantic(semantic::StreamEncoder::with_table_limit(
+                    config.dedup_threshold,
+                    config.semantic_block_size,
+                    config.dedup_table_bytes,
+                ))
+            }
+            CompressionMethod::LogDedupe => StreamEncoder::Log(log_dedupe::StreamEncoder::new()),
+            CompressionMethod::Stored => StreamEncoder::Stored(stored::StreamEncoder::new()),
+            CompressionMethod::Lzss => StreamEncoder::Lzss(lzss::StreamEncoder::with_level(&config.lzss, config.level)?),
+            CompressionMethod::SemanticLz => StreamEncoder::SemanticLz(semantic_lz::StreamEncoder::new(
+                config.semantic_block_size,
+                config.lz4_block_size,
+                config.level,
+            )),
+            CompressionMethod::Auto => return Err(CompressError::InvalidMethod),
+        })
+    }
+
+    pub(crate) fn push(&mut self, data: &[u8]) -> Result<(), CompressError> {
+        match self {
+            StreamEncoder::Huffman(e) => e.push(data),
+            StreamEncoder::Lz4(e) => e.push(data),
+            StreamEncoder::Entropy(e) => {
+                e.push(data);
+                Ok(())
+            }
+            StreamEncoder::Semantic(e) => {
+                e.push(data);
+                Ok(())
+            }
+            StreamEncoder::Log(e) => {
+                e.push(data);
+                Ok(())
+            }
+            StreamEncoder::Stored(e) => {
+                e.push(data);
+                Ok(())
+            }
+            StreamEncoder::Lzss(e) => {
+                e.push(data);
+                Ok(())
+            }
+            StreamEncoder::SemanticLz(e) => {
+                e.push(data);
+                Ok(())
+            }
+        }
+    }
+
+    pub(crate) fn finish(self) -> Result<Vec<u8>, CompressError> {
+        match self {
+            StreamEncoder::Huffman(e) => Ok(e.finish()),
+            StreamEncoder::Lz4(e) => e.finish(),
+            StreamEncoder::Entropy(e) => Ok(e.finish()),
+            StreamEncoder::Semantic(e) => Ok(e.finish()),
+            StreamEncoder::Log(e) => e.finish(),
+            StreamEncoder::Stored(e) => Ok(e.finish()),
+            StreamEncoder::Lzss(e) => e.finish(),
+            StreamEncoder::SemanticLz(e) => e.finish(),
+        }
+    }
+}
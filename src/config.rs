@@ -1,24 +1,413 @@
 //! Configuration for sigma-compress
 
+use crate::dictionary::DictionarySelection;
+use crate::similarity::Similarity;
+use crate::CompressionMethod;
 use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct CompressionConfig {
     pub ryzanstein_url: String,
+    /// `Lz4Semantic` block size; [`AUTO`] (the default) lets
+    /// [`crate::Compressor::new`] pick one for the machine (see
+    /// [`crate::hardware`])
     pub lz4_block_size: usize,
+    /// Match search effort of the LZ paths (`Lz4Semantic` and `Lzss`)
+    #[serde(default)]
+    pub level: CompressionLevel,
+    /// Window and match lengths of [`crate::CompressionMethod::Lzss`]
+    #[serde(default)]
+    pub lzss: LzssConfig,
     pub dedup_threshold: f64,
+    /// Size of the units semantic dedup compares
+    #[serde(default = "default_semantic_block_size")]
+    pub semantic_block_size: usize,
+    /// Memory cap of the `SemanticDedupe` block table, in bytes; cold blocks
+    /// are evicted beyond it (see [`crate::semantic`]). 0 for no cap
+    #[serde(default)]
+    pub dedup_table_bytes: usize,
     pub max_input_size: usize,
     pub enable_semantic: bool,
+    /// Length of embedding vectors, including local fallback embeddings
+    #[serde(default = "default_embedding_dim")]
+    pub embedding_dim: usize,
+    /// Metric used to compare blocks when grouping by similarity
+    #[serde(default)]
+    pub similarity: Similarity,
+    /// Nearest-neighbor index used when grouping blocks by similarity
+    #[serde(default)]
+    pub lsh: LshConfig,
+    /// Static dictionary applied ahead of the codec
+    #[serde(default)]
+    pub dictionary: DictionarySelection,
+    /// Predicted ratio above which `Auto` and adaptive compression store
+    /// input as-is instead of compressing it
+    #[serde(default = "default_incompressible_ratio")]
+    pub incompressible_ratio: f64,
+    /// Share of near-uniform input `Huffman` must be expected to save (see
+    /// [`crate::huffman::expected_gain`]) before its code tree is built;
+    /// below it the input is stored, or run-length coded if it has runs.
+    /// Skewed input always keeps `Huffman`. Negative to always build the
+    /// tree.
+    #[serde(default = "default_huffman_min_gain")]
+    pub huffman_min_gain: f64,
+    /// How `compress_adaptive` ranks candidate outputs
+    #[serde(default)]
+    pub objective: Objective,
+    /// Block size `compress_adaptive` looks for repeated content in (see
+    /// [`crate::analysis::repeated_block_fraction`])
+    #[serde(default = "default_repetition_window")]
+    pub repetition_window: usize,
+    /// What to do with input that is already a sigma-compress frame
+    #[serde(default)]
+    pub nested_frames: NestedFramePolicy,
+    /// Same input and configuration always give byte-identical output:
+    /// adaptive selection ignores measured CPU time and the method history,
+    /// which depend on machine load and earlier calls, and defaults are not
+    /// adapted to the hardware (see [`crate::hardware`]). Codec output
+    /// itself is always deterministic.
+    #[serde(default)]
+    pub deterministic: bool,
+    /// Pad every frame with zeros to a multiple of this many bytes (0 or 1
+    /// for none), for storage that takes whole blocks (see [`crate::frame`])
+    #[serde(default)]
+    pub frame_padding: u32,
+    /// Start every segment [`crate::Compressor::compress_chunked`] writes on
+    /// a multiple of this many bytes (0 or 1 for no alignment)
+    #[serde(default)]
+    pub block_alignment: u32,
+    /// Write the codec parameters in effect into every frame header (see
+    /// [`crate::params`]), at 6 bytes per frame. They are recorded in the
+    /// output's metadata either way.
+    #[serde(default)]
+    pub record_params: bool,
+    /// Return decoded data even when its length differs from the original
+    /// size the frame or output declares, instead of failing with
+    /// `SizeMismatch`, to salvage what a damaged payload still holds. The
+    /// checksum is then not checked either, as it covers the declared data.
+    #[serde(default)]
+    pub lenient_sizes: bool,
+    /// What decoding does with bytes after the last complete frame that do
+    /// not start another one
+    #[serde(default)]
+    pub trailing_data: TrailingDataPolicy,
+}
+
+/// Value of a hardware-tuned setting left for [`crate::Compressor::new`] to
+/// fill in
+pub const AUTO: usize = 0;
+
+/// [`CompressionConfig::lz4_block_size`] of machine-independent
+/// configurations
+pub const DEFAULT_LZ4_BLOCK_SIZE: usize = 64 * 1024;
+
+fn default_embedding_dim() -> usize {
+    128
+}
+
+fn default_incompressible_ratio() -> f64 {
+    0.95
+}
+
+fn default_huffman_min_gain() -> f64 {
+    0.02
+}
+
+fn default_repetition_window() -> usize {
+    crate::analysis::REPETITION_BLOCK_SIZE
+}
+
+fn default_semantic_block_size() -> usize {
+    crate::semantic::BLOCK_SIZE
 }
 
 impl Default for CompressionConfig {
     fn default() -> Self {
         Self {
             ryzanstein_url: "http://localhost:8000".to_string(),
-            lz4_block_size: 65536,
+            lz4_block_size: AUTO,
+            level: CompressionLevel::default(),
+            lzss: LzssConfig::default(),
             dedup_threshold: 0.95,
+            semantic_block_size: default_semantic_block_size(),
+            dedup_table_bytes: 0,
             max_input_size: 100 * 1024 * 1024, // 100 MB
             enable_semantic: true,
+            embedding_dim: default_embedding_dim(),
+            similarity: Similarity::default(),
+            lsh: LshConfig::default(),
+            dictionary: DictionarySelection::default(),
+            incompressible_ratio: default_incompressible_ratio(),
+            huffman_min_gain: default_huffman_min_gain(),
+            objective: Objective::default(),
+            repetition_window: default_repetition_window(),
+            nested_frames: NestedFramePolicy::default(),
+            deterministic: false,
+            frame_padding: 0,
+            block_alignment: 0,
+            record_params: false,
+            lenient_sizes: false,
+            trailing_data: TrailingDataPolicy::default(),
+        }
+    }
+}
+
+/// Per-call overrides for [`crate::Compressor::compress_with`]; unset fields
+/// keep the compressor's configured value
+#[derive(Debug, Clone, PartialEq)]
+pub struct CompressOptions {
+    pub method: CompressionMethod,
+    pub level: Option<CompressionLevel>,
+    /// Overrides `lz4_block_size`
+    pub block_size: Option<usize>,
+    /// Record a CRC-32 of the input (on unless set to `false`)
+    pub checksum: Option<bool>,
+    pub dictionary: Option<DictionarySelection>,
+}
+
+impl Default for CompressOptions {
+    fn default() -> Self {
+        Self {
+            method: CompressionMethod::Auto,
+            level: None,
+            block_size: None,
+            checksum: None,
+            dictionary: None,
+        }
+    }
+}
+
+impl CompressOptions {
+    /// `config` with these overrides applied
+    pub(crate) fn apply(&self, config: &CompressionConfig) -> CompressionConfig {
+        let mut config = config.clone();
+        if let Some(level) = self.level {
+            config.level = level;
+        }
+        if let Some(block_size) = self.block_size {
+            config.lz4_block_size = block_size.max(1);
+        }
+        if let Some(dictionary) = self.dictionary {
+            config.dictionary = dictionary;
+        }
+        config
+    }
+}
+
+/// Effort spent searching for matches on the LZ paths
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum CompressionLevel {
+    /// Greedy parsing: take the longest match at each position
+    #[default]
+    Fast,
+    /// Lazy matching: emit a literal instead when the next position has a longer match
+    Balanced,
+    /// Lazy matching with the deepest match search
+    Max,
+}
+
+impl CompressionLevel {
+    /// Equivalent deflate level for the blocks behind `Lz4Semantic`
+    pub(crate) fn deflate(self) -> flate2::Compression {
+        match self {
+            CompressionLevel::Fast => flate2::Compression::fast(),
+            CompressionLevel::Balanced => flate2::Compression::new(6),
+            CompressionLevel::Max => flate2::Compression::best(),
+        }
+    }
+}
+
+/// Handling of input that already starts with a valid frame header, which
+/// compressing again would only make bigger
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum NestedFramePolicy {
+    /// Compress as requested and set `metadata.nested_frame` on the output
+    #[default]
+    Warn,
+    /// Store the input as-is (method `Stored`), flagged the same way
+    Store,
+    /// Fail with [`crate::error::CompressError::AlreadyCompressed`]
+    Reject,
+}
+
+/// Handling of bytes that follow the complete frames of a decode input,
+/// such as a torn append or a framing bug in the writer
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TrailingDataPolicy {
+    /// Fail with a [`crate::error::CompressError::FrameError`] naming the
+    /// offset of the trailing bytes
+    #[default]
+    Error,
+    /// Decode the frames and drop the trailing bytes
+    Ignore,
+    /// Decode the frames and hand the trailing bytes back to the caller
+    /// (see [`crate::Compressor::decompress_with_remainder`]); decoders with
+    /// no way to return them fail as with `Error`
+    ReturnRemainder,
+}
+
+/// Parameters of the LZSS matcher
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LzssConfig {
+    /// How far back matches may reach
+    pub window_size: usize,
+    /// Shortest match worth a back-reference; at least 3
+    pub min_match: usize,
+    pub max_match: usize,
+    /// Width of the match finder's hash table, 10 to 20 bits; wider tables
+    /// have fewer collisions but use more memory. Not part of the format.
+    /// 0 (the default) lets [`crate::Compressor::new`] pick one for the
+    /// machine; codecs used directly take [`DEFAULT_HASH_BITS`].
+    #[serde(default)]
+    pub hash_bits: u32,
+}
+
+/// [`LzssConfig::hash_bits`] of machine-independent configurations
+pub const DEFAULT_HASH_BITS: u32 = 15;
+
+impl Default for LzssConfig {
+    fn default() -> Self {
+        Self {
+            window_size: 32 * 1024,
+            min_match: 4,
+            max_match: 258,
+            hash_bits: 0,
+        }
+    }
+}
+
+/// Weights trading output size against CPU time and working memory when
+/// [`crate::Compressor::compress_adaptive`] compares candidates; the lowest
+/// [`Objective::cost`] wins. The default ranks by size alone.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Objective {
+    /// Cost of the compression ratio (encoded size over input size)
+    pub ratio_weight: f64,
+    /// Cost of one second of compression time per MiB of input
+    pub cpu_weight: f64,
+    /// Cost of working memory, per byte of memory per byte of input
+    pub memory_weight: f64,
+}
+
+impl Default for Objective {
+    fn default() -> Self {
+        Self {
+            ratio_weight: 1.0,
+            cpu_weight: 0.0,
+            memory_weight: 0.0,
+        }
+    }
+}
+
+impl Objective {
+    pub fn cost(&self, ratio: f64, seconds_per_mib: f64, memory_per_byte: f64) -> f64 {
+        self.ratio_weight * ratio + self.cpu_weight * seconds_per_mib + self.memory_weight * memory_per_byte
+    }
+}
+
+/// Configuration for [`crate::append_log::LogCompressor`]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LogConfig {
+    /// Raw bytes buffered before a window frame is emitted
+    pub window_size: usize,
+    /// Number of windows between checkpoints
+    pub checkpoint_interval: usize,
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        Self {
+            window_size: 64 * 1024,
+            checkpoint_interval: 16,
+        }
+    }
+}
+
+/// Retry, timeout and circuit-breaker policy for
+/// [`crate::ryzanstein_integration::RyzansteinCompressClient`]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ClientPolicy {
+    /// Attempts after the first before a request counts as failed
+    pub max_retries: u32,
+    /// Delay before the first retry; doubles on every further retry
+    pub initial_backoff_ms: u64,
+    pub max_backoff_ms: u64,
+    /// Limit on each individual attempt
+    pub request_timeout_ms: u64,
+    /// Consecutive failed requests that open the circuit
+    pub breaker_threshold: u32,
+    /// How long an open circuit skips the server before trying it again
+    pub breaker_cooldown_ms: u64,
+    /// Blocks sent per embedding request
+    pub batch_size: usize,
+    /// Upper bound on requests per second, retries included; 0 disables limiting
+    pub max_requests_per_sec: f64,
+}
+
+impl Default for ClientPolicy {
+    fn default() -> Self {
+        Self {
+            max_retries: 2,
+            initial_backoff_ms: 50,
+            max_backoff_ms: 2_000,
+            request_timeout_ms: 5_000,
+            breaker_threshold: 3,
+            breaker_cooldown_ms: 30_000,
+            batch_size: 64,
+            max_requests_per_sec: 20.0,
+        }
+    }
+}
+
+/// Search parameters for [`crate::tuning::AutoTuner`]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TunerConfig {
+    /// Every this many calls per size class, a neighboring setting is tried
+    pub explore_interval: u64,
+    /// Cost of one second per MiB, in units of compression ratio; 0 tunes for ratio alone
+    pub time_weight: f64,
+    /// Weight of the newest measurement in a setting's smoothed cost
+    pub smoothing: f64,
+    pub min_lz4_block_size: usize,
+    pub max_lz4_block_size: usize,
+    pub min_semantic_block_size: usize,
+    pub max_semantic_block_size: usize,
+}
+
+impl Default for TunerConfig {
+    fn default() -> Self {
+        Self {
+            explore_interval: 4,
+            time_weight: 0.1,
+            smoothing: 0.3,
+            min_lz4_block_size: 1024,
+            max_lz4_block_size: 16 * 1024 * 1024,
+            min_semantic_block_size: 16,
+            max_semantic_block_size: 4096,
+        }
+    }
+}
+
+/// Build and query parameters for [`crate::semantic::LshIndex`]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LshConfig {
+    /// Independent hash tables; more tables raise recall
+    pub num_tables: usize,
+    /// Hyperplanes per table (at most 64); more bits make buckets more selective
+    pub bits_per_table: usize,
+    /// Candidates re-scored exactly per query
+    pub max_candidates: usize,
+    /// Seed for the random hyperplanes, so indexes are reproducible
+    pub seed: u64,
+}
+
+impl Default for LshConfig {
+    fn default() -> Self {
+        Self {
+            num_tables: 8,
+            bits_per_table: 12,
+            max_candidates: 64,
+            seed: 0x5EED_1DE5,
         }
     }
 }
@@ -0,0 +1,460 @@
+//! Pre-shared static dictionaries for common formats
+//!
+//! Small payloads are too short for a codec to learn their vocabulary, so
+//! frequent strings of a few well-known formats ship with the library. Before
+//! the backend codec runs, every occurrence of a dictionary entry is replaced
+//! by a two-byte reference; the frame header records the dictionary id so the
+//! decoder can undo the substitution.
+//!
+//! Custom dictionaries are added to a [`DictionaryRegistry`], which gives each
+//! a content-derived id and persists them, so producers and consumers that
+//! load the same registry file resolve the same ids.
+//!
+//! Coded body: literal bytes, `ESCAPE index+1` for an entry, and `ESCAPE 0` for
+//! a literal escape byte. The codec payload is prefixed with the coded length
+//! as a varint, since that is what the backend codec actually saw.
+
+use crate::error::CompressError;
+use crate::varint;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+/// Marks a reference in the coded body
+pub const ESCAPE: u8 = 0x01;
+
+/// Bytes of input sampled by [`DictionaryRegistry::detect`]
+const DETECT_SAMPLE: usize = 4096;
+
+/// Fraction of the sample a dictionary must save to be chosen by [`DictionaryRegistry::detect`]
+const DETECT_MIN_GAIN: f64 = 0.05;
+
+pub const JSON_KEYS: u32 = 1;
+pub const HTTP_HEADERS: u32 = 2;
+pub const ENGLISH: u32 = 3;
+pub const RUST_KEYWORDS: u32 = 4;
+pub const PYTHON_KEYWORDS: u32 = 5;
+
+/// Ids from here up are derived from dictionary contents; lower ids are
+/// reserved for built-ins and explicitly numbered dictionaries
+pub const CONTENT_ID_BASE: u32 = 0x8000_0000;
+
+/// Which dictionary [`crate::Compressor::compress`] codes input with
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DictionarySelection {
+    #[default]
+    None,
+    /// Pick the registered dictionary that saves the most on a sample, if any
+    Auto,
+    /// Always use the registered dictionary with this id
+    Id(u32),
+}
+
+/// A list of strings referenced by index; at most 255 entries
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Dictionary {
+    pub id: u32,
+    pub name: String,
+    pub entries: Vec<Vec<u8>>,
+    /// Entry indexes grouped by first byte, longest entry first
+    by_first: Vec<Vec<u8>>,
+}
+
+impl Dictionary {
+    pub fn new(id: u32, name: &str, entries: Vec<Vec<u8>>) -> Result<Self, CompressError> {
+        if entries.len() > 255 {
+            return Err(CompressError::DictionaryError(format!(
+                "{} entries, at most 255 supported",
+                entries.len()
+            )));
+        }
+        if entries.iter().any(Vec::is_empty) {
+            return Err(CompressError::DictionaryError("empty entry".into()));
+        }
+        let mut by_first = vec![Vec::new(); 256];
+        for (i, entry) in entries.iter().enumerate() {
+            by_first[entry[0] as usize].push(i as u8);
+        }
+        for candidates in &mut by_first {
+            candidates.sort_by_key(|&i| std::cmp::Reverse(entries[i as usize].len()));
+        }
+        Ok(Self {
+            id,
+            name: name.to_string(),
+            entries,
+            by_first,
+        })
+    }
+
+    /// Replace entry occurrences with references
+    pub fn encode(&self, data: &[u8]) -> Vec<u8> {
+        let mut out = Vec::with_capacity(data.len());
+        let mut pos = 0;
+        while pos < data.len() {
+            let rest = &data[pos..];
+            let hit = self.by_first[rest[0] as usize]
+                .iter()
+                .find(|&&i| self.entries[i as usize].len() > 2 && rest.starts_with(&self.entries[i as usize]));
+            match hit {
+                Some(&i) => {
+                    out.extend_from_slice(&[ESCAPE, i + 1]);
+                    pos += self.entries[i as usize].len();
+                }
+                None => {
+                    out.push(rest[0]);
+                    if rest[0] == ESCAPE {
+                        out.push(0);
+                    }
+                    pos += 1;
+                }
+            }
+        }
+        out
+    }
+
+    /// Undo [`Dictionary::encode`]
+    pub fn decode(&self, data: &[u8]) -> Result<Vec<u8>, CompressError> {
+        let mut out = Vec::with_capacity(data.len() * 2);
+        let mut bytes = data.iter();
+        while let Some(&b) = bytes.next() {
+            if b != ESCAPE {
+                out.push(b);
+                continue;
+            }
+            match bytes.next() {
+                None => return Err(CompressError::DictionaryError("truncated reference".into())),
+                Some(0) => out.push(ESCAPE),
+                Some(&i) => out.extend_from_slice(
+                    self.entries
+                        .get(i as usize - 1)
+                        .ok_or_else(|| CompressError::DictionaryError(format!("reference {} outside dictionary", i)))?,
+                ),
+            }
+        }
+        Ok(out)
+    }
+}
+
+fn from_strs(id: u32, name: &str, entries: &[&str]) -> Dictionary {
+    Dictionary::new(id, name, entries.iter().map(|e| e.as_bytes().to_vec()).collect())
+        .expect("built-in dictionaries are valid")
+}
+
+/// Built-in dictionary with stable id `id`
+pub fn builtin(id: u32) -> Option<Dictionary> {
+    Some(match id {
+        JSON_KEYS => from_strs(id, "json-keys", JSON_KEYS_ENTRIES),
+        HTTP_HEADERS => from_strs(id, "http-headers", HTTP_HEADERS_ENTRIES),
+        ENGLISH => from_strs(id, "english", ENGLISH_ENTRIES),
+        RUST_KEYWORDS => from_strs(id, "rust-keywords", RUST_ENTRIES),
+        PYTHON_KEYWORDS => from_strs(id, "python-keywords", PYTHON_ENTRIES),
+        _ => return None,
+    })
+}
+
+/// Ids of every built-in dictionary
+pub fn builtin_ids() -> [u32; 5] {
+    [JSON_KEYS, HTTP_HEADERS, ENGLISH, RUST_KEYWORDS, PYTHON_KEYWORDS]
+}
+
+/// Stable id for a dictionary with these entries, at or above [`CONTENT_ID_BASE`]
+pub fn content_id(entries: &[Vec<u8>]) -> u32 {
+    let mut hasher = blake3::Hasher::new();
+    for entry in entries {
+        hasher.update(&(entry.len() as u64).to_le_bytes());
+        hasher.update(entry);
+    }
+    let hash = hasher.finalize();
+    u32::from_le_bytes(hash.as_bytes()[..4].try_into().unwrap()) | CONTENT_ID_BASE
+}
+
+/// Substring lengths [`train`] considers as entries
+const TRAIN_LENGTHS: [usize; 3] = [24, 12, 6];
+
+/// Bytes of each sample [`train`] scans
+const TRAIN_SAMPLE: usize = 16 * 1024;
+
+/// Build a dictionary of the substrings that save the most across `samples`,
+/// with a content-derived id. Substrings must occur in at least three places;
+/// the result may be empty for samples without shared content.
+pub fn train<S: AsRef<[u8]>>(name: &str, samples: &[S]) -> Result<Dictionary, CompressError> {
+    let mut counts: HashMap<&[u8], usize> = HashMap::new();
+    for sample in samples {
+        let sample = sample.as_ref();
+        let sample = &sample[..sample.len().min(TRAIN_SAMPLE)];
+        for len in TRAIN_LENGTHS {
+            for window in sample.windows(len) {
+                *counts.entry(window).or_default() += 1;
+            }
+        }
+    }
+    let mut candidates: Vec<(&[u8], usize)> = counts.into_iter().filter(|&(_, n)| n >= 3).collect();
+    // Savings of replacing every occurrence by a two-byte reference
+    candidates.sort_by(|a, b| {
+        let savings = |&(entry, n): &(&[u8], usize)| (n - 1) * (entry.len() - 2);
+        savings(b).cmp(&savings(a)).then(a.0.cmp(b.0))
+    });
+    let mut entries: Vec<Vec<u8>> = Vec::new();
+    for (candidate, _) in candidates {
+        if entries.len() == 255 {
+            break;
+        }
+        if !entries.iter().any(|e| e.windows(candidate.len()).any(|w| w == candidate)) {
+            entries.push(candidate.to_vec());
+        }
+    }
+    Dictionary::new(content_id(&entries), name, entries)
+}
+
+/// On-disk form of a registered dictionary
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub(crate) struct SavedDictionary {
+    id: u32,
+    name: String,
+    entries: Vec<Vec<u8>>,
+}
+
+/// Dictionaries resolvable by id; starts out with every built-in
+#[derive(Debug, Clone)]
+pub struct DictionaryRegistry {
+    dictionaries: HashMap<u32, Dictionary>,
+}
+
+impl Default for DictionaryRegistry {
+    fn default() -> Self {
+        Self {
+            dictionaries: builtin_ids().into_iter().filter_map(builtin).map(|d| (d.id, d)).collect(),
+        }
+    }
+}
+
+impl DictionaryRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a dictionary under an id derived from its entries and return the id.
+    /// Registering the same entries twice yields the same id.
+    pub fn register(&mut self, name: &str, entries: Vec<Vec<u8>>) -> Result<u32, CompressError> {
+        let id = content_id(&entries);
+        self.insert(Dictionary::new(id, name, entries)?)?;
+        Ok(id)
+    }
+
+    /// Add a dictionary under its own id. Re-adding identical entries is a
+    /// no-op; different entries under a taken id are rejected.
+    pub fn insert(&mut self, dictionary: Dictionary) -> Result<(), CompressError> {
+        if let Some(existing) = self.dictionaries.get(&dictionary.id) {
+            if existing.entries != dictionary.entries {
+                return Err(CompressError::DictionaryError(format!(
+                    "id {} already holds a different dictionary",
+                    dictionary.id
+                )));
+            }
+            return Ok(());
+        }
+        self.dictionaries.insert(dictionary.id, dictionary);
+        Ok(())
+    }
+
+    pub fn get(&self, id: u32) -> Option<&Dictionary> {
+        self.dictionaries.get(&id)
+    }
+
+    /// Dictionary with `id`, or [`CompressError::UnknownDictionary`]
+    pub fn resolve(&self, id: u32) -> Result<&Dictionary, CompressError> {
+        self.get(id).ok_or(CompressError::UnknownDictionary(id))
+    }
+
+    /// Registered ids in ascending order
+    pub fn ids(&self) -> Vec<u32> {
+        let mut ids: Vec<u32> = self.dictionaries.keys().copied().collect();
+        ids.sort_unstable();
+        ids
+    }
+
+    /// Dictionary that shrinks a sample of `data` the most, if any saves a
+    /// meaningful fraction of it
+    pub fn detect(&self, data: &[u8]) -> Option<&Dictionary> {
+        let sample = &data[..data.len().min(DETECT_SAMPLE)];
+        self.ids()
+            .into_iter()
+            .filter_map(|id| self.get(id))
+            .map(|dict| (sample.len() as f64 - dict.encode(sample).len() as f64, dict))
+            .filter(|(saved, _)| *saved >= sample.len() as f64 * DETECT_MIN_GAIN)
+            .max_by(|a, b| a.0.total_cmp(&b.0))
+            .map(|(_, dict)| dict)
+    }
+
+    /// Write every non-built-in dictionary as JSON
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), CompressError> {
+        let raw =
+            serde_json::to_vec_pretty(&self.saved()).map_err(|e| CompressError::SerializationError(e.to_string()))?;
+        fs::write(path, raw)?;
+        Ok(())
+    }
+
+    /// Built-ins plus the dictionaries written by [`DictionaryRegistry::save`]
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, CompressError> {
+        let raw = fs::read(path)?;
+        let saved: Vec<SavedDictionary> =
+            serde_json::from_slice(&raw).map_err(|e| CompressError::SerializationError(e.to_string()))?;
+        Self::from_saved(saved)
+    }
+
+    /// Every non-built-in dictionary, in id order
+    pub(crate) fn saved(&self) -> Vec<SavedDictionary> {
+        self.ids()
+            .into_iter()
+            .filter(|id| !builtin_ids().contains(id))
+            .filter_map(|id| self.get(id))
+            .map(|d| SavedDictionary {
+                id: d.id,
+                name: d.name.clone(),
+                entries: d.entries.clone(),
+            })
+            .collect()
+    }
+
+    /// Built-ins plus `saved`
+    pub(crate) fn from_saved(saved: Vec<SavedDictionary>) -> Result<Self, CompressError> {
+        let mut registry = Self::default();
+        for d in saved {
+            registry.insert(Dictionary::new(d.id, &d.name, d.entries)?)?;
+        }
+        Ok(registry)
+    }
+}
+
+/// Prefix a codec payload with the length of the coded input it was built from
+pub(crate) fn wrap_payload(coded_len: usize, payload: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(varint::encoded_len(coded_len as u64) + payload.len());
+    varint::write_u64(&mut out, coded_len as u64);
+    out.extend_from_slice(payload);
+    out
+}
+
+/// Split a payload from [`wrap_payload`] into `(coded_len, codec payload)`
+pub(crate) fn unwrap_payload(data: &[u8]) -> Result<(usize, &[u8]), CompressError> {
+    let mut pos = 0;
+    let coded_len = varint::read_usize(data, &mut pos)
+        .ok_or_else(|| CompressError::DictionaryError("truncated coded length".into()))?;
+    Ok((coded_len, &data[pos..]))
+}
+
+const JSON_KEYS_ENTRIES: &[&str] = &[
+    "\"id\":", "\"name\":", "\"type\":", "\"value\":", "\"data\":", "\"status\":", "\"message\":",
+    "\"error\":", "\"code\":", "\"timestamp\":", "\"created_at\":", "\"updated_at\":", "\"version\":",
+    "\"description\":", "\"title\":", "\"items\":", "\"count\":", "\"total\":", "\"user\":",
+    "\"user_id\":", "\"email\":", "\"url\":", "\"key\":", "\"label\":", "\"tags\":", "\"content\":",
+    "\"text\":", "\"result\":", "\"results\":", "\"page\":", "\"size\":", "\"limit\":", "\"offset\":",
+    "\"metadata\":", "\"config\":", "\"enabled\":", "\"properties\":", "\"attributes\":", "\"parent\":",
+    "\"children\":", "\"path\":", "\"method\":", "\"params\":", "\"headers\":", "\"body\":",
+    "\"success\":", "\"score\":", "\"role\":", "\"model\":", "\"index\":", ": true", ": false", ": null",
+    "true", "false", "null", "\": \"", "\":\"", "\", \"", "\",\"", "},{", "}, {", "[{", "}]",
+];
+
+const HTTP_HEADERS_ENTRIES: &[&str] = &[
+    "HTTP/1.1 ", "HTTP/2 ", "200 OK", "404 Not Found", "GET ", "POST ", "PUT ", "DELETE ", "Host: ",
+    "User-Agent: ", "Accept: ", "Accept-Encoding: ", "Accept-Language: ", "Content-Type: ",
+    "Content-Length: ", "Content-Encoding: ", "Connection: ", "keep-alive", "Cache-Control: ",
+    "no-cache", "max-age=", "Authorization: ", "Bearer ", "Cookie: ", "Set-Cookie: ", "Date: ",
+    "Server: ", "Location: ", "Referer: ", "Origin: ", "ETag: ", "Last-Modified: ", "If-None-Match: ",
+    "Transfer-Encoding: chunked", "Vary: ", "X-Forwarded-For: ", "X-Request-Id: ",
+    "Access-Control-Allow-Origin: ", "application/json", "application/x-www-form-urlencoded",
+    "text/html", "text/plain", "charset=utf-8", "gzip, deflate, br", "Mozilla/5.0 ", " GMT",
+];
+
+const ENGLISH_ENTRIES: &[&str] = &[
+    " the ", " and ", " of ", " to ", " in ", " that ", " is ", " for ", " it ", " with ", " as ",
+    " was ", " on ", " be ", " this ", " are ", " by ", " at ", " from ", " have ", " not ", " but ",
+    " or ", " they ", " which ", " you ", " one ", " had ", " were ", " their ", " has ", " been ",
+    " will ", " there ", " would ", " can ", " all ", " an ", " we ", " more ", " when ", " about ",
+    " what ", " also ", " other ", " into ", " some ", " than ", " its ", " them ", " these ",
+    " could ", " should ", " because ", " between ", " through ", " people ", " after ", " first ",
+    " only ", "The ", "This ", "It ", "In ", "tion", "ing ", "ment", "ness", "ould", "ever",
+    ". The ", ", and ", "ed ", "er ", "ly ", "es ",
+];
+
+const RUST_ENTRIES: &[&str] = &[
+    "fn ", "let ", "mut ", "pub ", "pub(crate) ", "impl ", "struct ", "enum ", "trait ", "match ",
+    "return ", "self", "Self", "use ", "mod ", "where ", "const ", "static ", "unsafe ", "async ",
+    ".await", "move ", "for ", " in ", "while ", "loop ", "if let ", "else ", "break", "continue",
+    "crate::", "super::", "std::", "Option<", "Result<", "Vec<", "String", "HashMap<", "Box<",
+    "Some(", "None", "Ok(", "Err(", "&self", "&mut self", "&mut ", "#[derive(", "#[cfg(test)]",
+    "#[test]", "Debug", "Clone", "Default", "println!(", "format!(", "assert_eq!(", "unwrap()",
+    "::new(", ".iter()", ".collect()", ".clone()", "usize", "&str", "-> ", " => ", "    ",
+];
+
+const PYTHON_ENTRIES: &[&str] = &[
+    "def ", "self.", "self", "import ", "from ", "return ", "class ", "elif ", "else:", "None",
+    "True", "False", "lambda ", "yield ", "with ", "async ", "await ", "__init__", "raise ",
+    "except ", "try:", "finally:", "pass", "print(", "for ", " in ", "if ", "not ", " and ",
+    " or ", " is ", "while ", "range(", "len(", "list", "dict", "str(", "int(", "isinstance(",
+    "append(", "@property", "@staticmethod", "@classmethod", "__name__", "\"__main__\"",
+    "Exception", "kwargs", "args", "    ", "        ",
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_builtin_dictionaries_roundtrip() {
+        let samples: [&[u8]; 3] = [
+            br#"{"id": 7, "name": "widget", "enabled": true, "tags": ["a"]}"#,
+            b"GET /index.html HTTP/1.1\r\nHost: example.com\r\nAccept: text/html\r\n\r\n",
+            &[ESCAPE, 0, ESCAPE, ESCAPE, b'x', 0xFF],
+        ];
+        for id in builtin_ids() {
+            let dict = builtin(id).unwrap();
+            assert_eq!(dict.id, id);
+            assert!(dict.entries.iter().all(|e| e.len() > 1));
+            for sample in samples {
+                assert_eq!(dict.decode(&dict.encode(sample)).unwrap(), sample);
+            }
+        }
+        assert!(builtin(0).is_none());
+    }
+
+    #[test]
+    fn test_detect_picks_matching_format() {
+        let registry = DictionaryRegistry::default();
+        let detect = |data: &[u8]| registry.detect(data).cloned();
+        let json = br#"{"id": 1, "name": "a", "status": "ok", "created_at": null, "metadata": {}}"#;
+        assert_eq!(detect(json).map(|d| d.id), Some(JSON_KEYS));
+        let http = b"POST /v1 HTTP/1.1\r\nContent-Type: application/json\r\nContent-Length: 2\r\n";
+        assert_eq!(detect(http).map(|d| d.id), Some(HTTP_HEADERS));
+        let python = b"def __init__(self, name):\n    self.name = name\n    return None\n";
+        assert_eq!(detect(python).map(|d| d.id), Some(PYTHON_KEYWORDS));
+        assert!(detect(&[0xAB; 100]).is_none());
+    }
+
+    #[test]
+    fn test_registry_ids_and_persistence() {
+        let entries = vec![b"tenant_id=".to_vec(), b"region=eu-west-1".to_vec()];
+        let mut registry = DictionaryRegistry::new();
+        let id = registry.register("tenants", entries.clone()).unwrap();
+        assert!(id >= CONTENT_ID_BASE);
+        assert_eq!(registry.register("tenants again", entries.clone()).unwrap(), id);
+        assert!(registry
+            .insert(Dictionary::new(JSON_KEYS, "clash", entries).unwrap())
+            .is_err());
+        assert!(matches!(registry.resolve(7), Err(CompressError::UnknownDictionary(7))));
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("dictionaries.json");
+        registry.save(&path).unwrap();
+        let loaded = DictionaryRegistry::load(&path).unwrap();
+        assert_eq!(loaded.ids(), registry.ids());
+        assert_eq!(loaded.resolve(id).unwrap(), registry.resolve(id).unwrap());
+    }
+
+    #[test]
+    fn test_decode_rejects_bad_reference() {
+        let dict = builtin(ENGLISH).unwrap();
+        assert!(dict.decode(&[b'a', ESCAPE]).is_err());
+        assert!(dict.decode(&[ESCAPE, 255]).is_err());
+    }
+}
@@ -0,0 +1,161 @@
+//! Content hash, statistics and compression in one pass
+//!
+//! Storing a block usually means hashing it for addressing, profiling it
+//! for logs or method choice, and compressing it; done separately, that is
+//! three scans of the input. [`HashingEncoder`] feeds each chunk it is given
+//! to a BLAKE3 hasher, the byte histogram, the CRC and the codec while the
+//! chunk is still in cache. [`Compressor::hash_and_compress`] does the same
+//! for a buffer already in memory.
+//!
+//! Methods that must see the whole input before coding (`Huffman` needs the
+//! histogram up front, `Auto` and dictionary coding inspect the input) are
+//! buffered and coded at [`HashingEncoder::finish`]; hash and statistics
+//! are still gathered as the input arrives.
+
+use crate::error::CompressError;
+use crate::store::BlockHash;
+use crate::{analysis, codec_stream, dictionary, CompressedOutput, CompressionMethod, Compressor};
+
+/// Input fed to the codec per step by [`Compressor::hash_and_compress`]
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// Result of a combined pass
+#[derive(Debug, Clone)]
+pub struct HashedOutput {
+    /// BLAKE3 hash of the original data
+    pub hash: BlockHash,
+    /// Byte statistics of the original data
+    pub stats: analysis::ByteStats,
+    pub output: CompressedOutput,
+}
+
+enum Sink {
+    Streaming(codec_stream::StreamEncoder),
+    Buffered(Vec<u8>),
+}
+
+/// Incremental hash-and-compress of input arriving in pieces
+pub struct HashingEncoder<'a> {
+    compressor: &'a Compressor,
+    method: CompressionMethod,
+    hasher: blake3::Hasher,
+    crc: crc32fast::Hasher,
+    histogram: [u64; 256],
+    len: usize,
+    sink: Sink,
+}
+
+impl<'a> HashingEncoder<'a> {
+    pub fn new(compressor: &'a Compressor, method: CompressionMethod) -> Result<Self, CompressError> {
+        let buffered = method == CompressionMethod::Auto
+            || codec_stream::needs_histogram(method)
+            || compressor.config.dictionary != dictionary::DictionarySelection::None;
+        let sink = if buffered {
+            Sink::Buffered(Vec::new())
+        } else {
+            Sink::Streaming(codec_stream::StreamEncoder::new(method, &compressor.config, None, 0)?)
+        };
+        Ok(Self {
+            compressor,
+            method,
+            hasher: blake3::Hasher::new(),
+            crc: crc32fast::Hasher::new(),
+            histogram: [0; 256],
+            len: 0,
+            sink,
+        })
+    }
+
+    pub fn push(&mut self, data: &[u8]) -> Result<(), CompressError> {
+        let len = self.len + data.len();
+        if len > self.compressor.config.max_input_size {
+            return Err(CompressError::InputTooLarge {
+                size: len,
+                limit: self.compressor.config.max_input_size,
+            });
+        }
+        self.hasher.update(data);
+        self.crc.update(data);
+        for &b in data {
+            self.histogram[b as usize] += 1;
+        }
+        match &mut self.sink {
+            Sink::Streaming(encoder) => encoder.push(data)?,
+            Sink::Buffered(buffer) => buffer.extend_from_slice(data),
+        }
+        self.len = len;
+        Ok(())
+    }
+
+    pub fn finish(self) -> Result<HashedOutput, CompressError> {
+        if self.len == 0 {
+            return Err(CompressError::EmptyInput);
+        }
+        let stats = analysis::ByteStats::from_histogram(&self.histogram);
+        let output = match self.sink {
+            Sink::Buffered(buffer) => self.compressor.compress(&buffer, self.method)?,
+            Sink::Streaming(encoder) => {
+                let mut output = self.compressor.finish_output(
+                    self.method,
+                    self.len,
+                    encoder.finish()?,
+                    stats.entropy,
+                    Some(self.crc.finalize()),
+                    None,
+                )?;
+                output.metadata.block_count = (self.len / self.compressor.config.lz4_block_size).max(1);
+                output
+            }
+        };
+        Ok(HashedOutput {
+            hash: BlockHash(*self.hasher.finalize().as_bytes()),
+            stats,
+            output,
+        })
+    }
+}
+
+impl Compressor {
+    /// Hash, profile and compress `data` in a single pass over it
+    pub fn hash_and_compress(&self, data: &[u8], method: CompressionMethod) -> Result<HashedOutput, CompressError> {
+        let mut encoder = HashingEncoder::new(self, method)?;
+        for chunk in data.chunks(CHUNK_SIZE) {
+            encoder.push(chunk)?;
+        }
+        encoder.finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hash_and_compress_matches_separate_passes() {
+        let compressor = Compressor::default();
+        let data: Vec<u8> = (0..20_000).flat_map(|i| format!("record {} of the stream\n", i % 977).into_bytes()).collect();
+        for method in [CompressionMethod::Lz4Semantic, CompressionMethod::Huffman, CompressionMethod::Auto] {
+            let hashed = compressor.hash_and_compress(&data, method).unwrap();
+            assert_eq!(hashed.hash, BlockHash::of(&data));
+            assert_eq!(hashed.stats.len, data.len());
+            assert!((hashed.stats.entropy - analysis::entropy(&data)).abs() < 1e-9);
+            assert_eq!(hashed.output.checksum, Some(crc32fast::hash(&data)));
+            assert_eq!(compressor.decompress(&hashed.output).unwrap(), data);
+        }
+    }
+
+    #[test]
+    fn test_hashing_encoder_pieces() {
+        let compressor = Compressor::default();
+        let mut encoder = HashingEncoder::new(&compressor, CompressionMethod::Lzss).unwrap();
+        for piece in [&b"first piece, "[..], b"second piece, ", b"first piece again"] {
+            encoder.push(piece).unwrap();
+        }
+        let hashed = encoder.finish().unwrap();
+        let whole = b"first piece, second piece, first piece again";
+        assert_eq!(hashed.hash, BlockHash::of(whole));
+        assert_eq!(hashed.stats.histogram[b'p' as usize], 3);
+        assert_eq!(compressor.decompress(&hashed.output).unwrap(), whole);
+        assert!(HashingEncoder::new(&compressor, CompressionMethod::Stored).unwrap().finish().is_err());
+    }
+}
@@ -0,0 +1,509 @@
+//! Heapless decoding for memory-constrained targets
+//!
+//! Microcontrollers that receive sigma-compressed configuration blobs have no
+//! allocator to spare and a fixed RAM budget. [`decode_frame`] decodes one
+//! frame into a buffer the caller provides, using only `core` and a fixed
+//! amount of stack: the Huffman code table is rebuilt into a
+//! [`HUFFMAN_NODES`]-entry array and dedup references are resolved by
+//! rescanning the block table, so no memory is sized by the input. Blocks
+//! larger than [`DecodeLimits::max_block_size`] are rejected before any of
+//! them is decoded; `Stored` and `Huffman` payloads count as one block of
+//! the frame's original size.
+//!
+//! Supported payloads are `Stored`, `Huffman` and `SemanticDedupe`, with or
+//! without a checksum or padding. Other methods, dictionary-coded and
+//! segmented frames are rejected with [`DecodeError::Unsupported`]; blobs
+//! meant for such targets should be written with one of the supported
+//! methods.
+//!
+//! Building without default features (`default-features = false`) leaves
+//! out the `std` feature and with it every dependency, the encoders and
+//! everything built on them, compiling the crate as `no_std` with this
+//! module alone.
+
+use core::fmt;
+
+// Mirrors of the frame constants, which are not compiled without `std`
+const MAGIC: [u8; 4] = *b"SGMA";
+const FORMAT_VERSION: u8 = 2;
+const FLAG_CHECKSUM: u8 = 0x01;
+const FLAG_DICTIONARY: u8 = 0x02;
+const FLAG_SEGMENTED: u8 = 0x04;
+const FLAG_PADDED: u8 = 0x10;
+const FLAG_PARAMS: u8 = 0x20;
+const PARAMS_LEN: usize = 6;
+const FIXED_HEADER_LEN: usize = 4 + 1 + 1 + 1 + 8 + 8;
+const SEMANTIC_VERSION_MARKER: u8 = 0x00;
+const SEMANTIC_VERSION: u8 = 3;
+const SEMANTIC_VERSION_NO_RUNS: u8 = 2;
+const SEMANTIC_RUNS_FLAG: u8 = 0x80;
+
+const METHOD_HUFFMAN: u8 = 1;
+const METHOD_SEMANTIC_DEDUPE: u8 = 4;
+const METHOD_STORED: u8 = 6;
+
+/// Nodes in the largest code tree a 256-symbol alphabet needs
+pub const HUFFMAN_NODES: usize = 511;
+
+/// Default [`DecodeLimits::max_block_size`]
+pub const DEFAULT_MAX_BLOCK_SIZE: usize = 64 * 1024;
+
+/// Child slot holding no node
+const EMPTY: u16 = 0;
+/// Child slot holding a symbol in its low byte
+const LEAF: u16 = 0x8000;
+
+/// Bounds fixed by the target rather than by the frame being decoded
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DecodeLimits {
+    /// Largest block accepted, in decoded bytes
+    pub max_block_size: usize,
+}
+
+impl Default for DecodeLimits {
+    fn default() -> Self {
+        Self {
+            max_block_size: DEFAULT_MAX_BLOCK_SIZE,
+        }
+    }
+}
+
+/// Why a frame could not be decoded
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DecodeError {
+    /// Input ends before the frame does
+    Truncated,
+    BadMagic,
+    UnsupportedVersion(u8),
+    /// Method or header flags this decoder does not handle
+    Unsupported(&'static str),
+    /// Output buffer smaller than the frame's original size
+    OutputTooSmall { needed: u64, capacity: usize },
+    /// Malformed payload
+    Corrupt(&'static str),
+    ChecksumMismatch { expected: u32, actual: u32 },
+    /// Input continues past the end of the frame
+    TrailingBytes,
+    /// A block exceeds [`DecodeLimits::max_block_size`]
+    BlockTooLarge { size: u64, max: usize },
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodeError::Truncated => f.write_str("truncated frame"),
+            DecodeError::BadMagic => f.write_str("bad magic"),
+            DecodeError::UnsupportedVersion(v) => write!(f, "unsupported format version {}", v),
+            DecodeError::Unsupported(what) => write!(f, "unsupported {}", what),
+            DecodeError::OutputTooSmall { needed, capacity } => {
+                write!(f, "frame decodes to {} bytes, buffer holds {}", needed, capacity)
+            }
+            DecodeError::Corrupt(what) => write!(f, "corrupt payload: {}", what),
+            DecodeError::ChecksumMismatch { expected, actual } => {
+                write!(f, "checksum mismatch: expected {:08x}, got {:08x}", expected, actual)
+            }
+            DecodeError::TrailingBytes => f.write_str("trailing bytes after frame"),
+            DecodeError::BlockTooLarge { size, max } => write!(f, "{}-byte block exceeds the {}-byte limit", size, max),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for DecodeError {}
+
+#[cfg(feature = "std")]
+impl From<DecodeError> for crate::error::CompressError {
+    fn from(e: DecodeError) -> Self {
+        match e {
+            DecodeError::ChecksumMismatch { expected, actual } => {
+                crate::error::CompressError::ChecksumMismatch { expected, actual }
+            }
+            other => crate::error::CompressError::FrameError(other.to_string()),
+        }
+    }
+}
+
+/// Decode the single frame `frame` into `output` within the default
+/// [`DecodeLimits`], returning the decoded length
+pub fn decode_frame(frame: &[u8], output: &mut [u8]) -> Result<usize, DecodeError> {
+    decode_frame_with(frame, output, &DecodeLimits::default())
+}
+
+/// [`decode_frame`] within `limits`
+pub fn decode_frame_with(frame: &[u8], output: &mut [u8], limits: &DecodeLimits) -> Result<usize, DecodeError> {
+    let header = frame.get(..FIXED_HEADER_LEN).ok_or(DecodeError::Truncated)?;
+    if header[..4] != MAGIC {
+        return Err(DecodeError::BadMagic);
+    }
+    if header[4] != FORMAT_VERSION {
+        return Err(DecodeError::UnsupportedVersion(header[4]));
+    }
+    let (method, flags) = (header[5], header[6]);
+    if flags & (FLAG_DICTIONARY | FLAG_SEGMENTED) != 0 {
+        return Err(DecodeError::Unsupported("dictionary or segmented frame"));
+    }
+    let original_size = u64::from_le_bytes(header[7..15].try_into().unwrap());
+    let payload_len = u64::from_le_bytes(header[15..23].try_into().unwrap());
+
+    let mut pos = FIXED_HEADER_LEN;
+    let checksum = if flags & FLAG_CHECKSUM != 0 {
+        let bytes = frame.get(pos..pos + 4).ok_or(DecodeError::Truncated)?;
+        pos += 4;
+        Some(u32::from_le_bytes(bytes.try_into().unwrap()))
+    } else {
+        None
+    };
+    let padding = if flags & FLAG_PADDED != 0 {
+        let bytes = frame.get(pos..pos + 4).ok_or(DecodeError::Truncated)?;
+        pos += 4;
+        u32::from_le_bytes(bytes.try_into().unwrap()) as usize
+    } else {
+        0
+    };
+    // Codec parameters are informational; every payload here describes itself
+    if flags & FLAG_PARAMS != 0 {
+        pos += PARAMS_LEN;
+    }
+    let payload = frame.get(pos..).ok_or(DecodeError::Truncated)?;
+    let payload = match payload.len().checked_sub(padding) {
+        Some(end) if payload[end..].iter().all(|&b| b == 0) => &payload[..end],
+        Some(_) => return Err(DecodeError::TrailingBytes),
+        None => return Err(DecodeError::Truncated),
+    };
+    match usize::try_from(payload_len) {
+        Ok(len) if len == payload.len() => {}
+        Ok(len) if len < payload.len() => return Err(DecodeError::TrailingBytes),
+        _ => return Err(DecodeError::Truncated),
+    }
+    let output = match usize::try_from(original_size) {
+        Ok(len) if len <= output.len() => &mut output[..len],
+        _ => {
+            return Err(DecodeError::OutputTooSmall {
+                needed: original_size,
+                capacity: output.len(),
+            })
+        }
+    };
+
+    let max = limits.max_block_size;
+    if matches!(method, METHOD_STORED | METHOD_HUFFMAN) && output.len() > max {
+        return Err(DecodeError::BlockTooLarge { size: original_size, max });
+    }
+    match method {
+        METHOD_STORED => {
+            if payload.len() != output.len() {
+                return Err(DecodeError::Corrupt("stored size mismatch"));
+            }
+            output.copy_from_slice(payload);
+        }
+        METHOD_HUFFMAN => decode_huffman(payload, output)?,
+        METHOD_SEMANTIC_DEDUPE => decode_dedup(payload, output, max)?,
+        _ => return Err(DecodeError::Unsupported("method")),
+    }
+    if let Some(expected) = checksum {
+        let actual = crc32(output);
+        if actual != expected {
+            return Err(DecodeError::ChecksumMismatch { expected, actual });
+        }
+    }
+    Ok(output.len())
+}
+
+/// Huffman code as a binary tree in a fixed array; node 0 is the root
+struct CodeTree {
+    children: [[u16; 2]; HUFFMAN_NODES],
+    len: usize,
+}
+
+impl CodeTree {
+    fn new() -> Self {
+        Self {
+            children: [[EMPTY; 2]; HUFFMAN_NODES],
+            len: 1,
+        }
+    }
+
+    /// Add the `code_len`-bit code packed LSB-first in `code`
+    fn insert(&mut self, code: &[u8], code_len: usize, symbol: u8) -> Result<(), DecodeError> {
+        if code_len == 0 {
+            return Err(DecodeError::Corrupt("empty code"));
+        }
+        let mut node = 0;
+        for i in 0..code_len {
+            let bit = ((code[i / 8] >> (i % 8)) & 1) as usize;
+            let child = self.children[node][bit];
+            if i + 1 == code_len {
+                if child != EMPTY {
+                    return Err(DecodeError::Corrupt("ambiguous code"));
+                }
+                self.children[node][bit] = LEAF | u16::from(symbol);
+            } else if child & LEAF != 0 {
+                return Err(DecodeError::Corrupt("ambiguous code"));
+            } else if child == EMPTY {
+                if self.len == HUFFMAN_NODES {
+                    return Err(DecodeError::Corrupt("code table too large"));
+                }
+                self.children[node][bit] = self.len as u16;
+                node = self.len;
+                self.len += 1;
+            } else {
+                node = child as usize;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Decode a payload in the layout of [`crate::huffman::compress`]
+fn decode_huffman(payload: &[u8], output: &mut [u8]) -> Result<(), DecodeError> {
+    let count = payload.get(..2).ok_or(DecodeError::Truncated)?;
+    let num_symbols = u16::from_le_bytes([count[0], count[1]]) as usize;
+    let mut tree = CodeTree::new();
+    let mut pos = 2;
+    for _ in 0..num_symbols {
+        let entry = payload.get(pos..pos + 2).ok_or(DecodeError::Truncated)?;
+        let (symbol, code_len) = (entry[0], entry[1] as usize);
+        pos += 2;
+        let code = payload.get(pos..pos + code_len.div_ceil(8)).ok_or(DecodeError::Truncated)?;
+        tree.insert(code, code_len, symbol)?;
+        pos += code.len();
+    }
+    if read_varint(payload, &mut pos) != Some(output.len() as u64) {
+        return Err(DecodeError::Corrupt("declared length mismatch"));
+    }
+
+    let (mut node, mut written) = (0, 0);
+    'bits: for &byte in &payload[pos..] {
+        for bit in 0..8 {
+            if written == output.len() {
+                break 'bits;
+            }
+            match tree.children[node][((byte >> bit) & 1) as usize] {
+                EMPTY => return Err(DecodeError::Corrupt("invalid code")),
+                child if child & LEAF != 0 => {
+                    output[written] = child as u8;
+                    written += 1;
+                    node = 0;
+                }
+                child => node = child as usize,
+            }
+        }
+    }
+    if written != output.len() {
+        return Err(DecodeError::Truncated);
+    }
+    Ok(())
+}
+
+/// Decode a payload in the layout of [`crate::semantic::compress`]: plain
+/// or run-coded narrow refs, or legacy 4-byte refs
+fn decode_dedup(payload: &[u8], output: &mut [u8], max_block_size: usize) -> Result<(), DecodeError> {
+    let (mut pos, coding) = match payload {
+        [SEMANTIC_VERSION_MARKER, SEMANTIC_VERSION, coding, ..] => (3, *coding),
+        [SEMANTIC_VERSION_MARKER, SEMANTIC_VERSION_NO_RUNS, coding, ..] if coding & SEMANTIC_RUNS_FLAG == 0 => (3, *coding),
+        [SEMANTIC_VERSION_MARKER, _, _, ..] => return Err(DecodeError::Unsupported("dedup payload version")),
+        _ => (0, 4),
+    };
+    let width = (coding & !SEMANTIC_RUNS_FLAG) as usize;
+    if !matches!(width, 1 | 2 | 4) {
+        return Err(DecodeError::Corrupt("invalid ref width"));
+    }
+    let num_unique = read_varint(payload, &mut pos).ok_or(DecodeError::Truncated)?;
+    let table = pos;
+    for _ in 0..num_unique {
+        let size = skip_block(payload, &mut pos)?;
+        if size == 0 {
+            return Err(DecodeError::Corrupt("empty block"));
+        }
+        if size > max_block_size {
+            return Err(DecodeError::BlockTooLarge {
+                size: size as u64,
+                max: max_block_size,
+            });
+        }
+    }
+    let num_refs = read_varint(payload, &mut pos).ok_or(DecodeError::Truncated)?;
+    // Every block holds at least a byte, so no more refs than output bytes
+    if num_refs > output.len() as u64 {
+        return Err(DecodeError::Corrupt("refs exceed declared size"));
+    }
+    let num_runs = if coding & SEMANTIC_RUNS_FLAG != 0 {
+        read_varint(payload, &mut pos).ok_or(DecodeError::Truncated)?
+    } else {
+        let refs = payload.len() - pos;
+        if num_refs.checked_mul(width as u64) != Some(refs as u64) {
+            return Err(DecodeError::Corrupt("ref count mismatch"));
+        }
+        num_refs
+    };
+
+    let mut written = 0;
+    let mut refs = 0u64;
+    for _ in 0..num_runs {
+        let r = payload.get(pos..pos + width).ok_or(DecodeError::Truncated)?;
+        pos += width;
+        let mut idx = [0u8; 4];
+        idx[..width].copy_from_slice(r);
+        let idx = u32::from_le_bytes(idx) as u64;
+        if idx >= num_unique {
+            return Err(DecodeError::Corrupt("invalid ref"));
+        }
+        let count = if coding & SEMANTIC_RUNS_FLAG != 0 {
+            read_varint(payload, &mut pos).ok_or(DecodeError::Truncated)?
+        } else {
+            1
+        };
+        refs = refs.saturating_add(count);
+        let mut block_pos = table;
+        for _ in 0..idx {
+            skip_block(payload, &mut block_pos)?;
+        }
+        let start = block_pos;
+        skip_block(payload, &mut block_pos)?;
+        let mut data_pos = start;
+        let len = read_varint(payload, &mut data_pos).ok_or(DecodeError::Truncated)? as usize;
+        for _ in 0..count {
+            let dest = output
+                .get_mut(written..written + len)
+                .ok_or(DecodeError::Corrupt("blocks exceed declared size"))?;
+            dest.copy_from_slice(&payload[data_pos..block_pos]);
+            written += len;
+        }
+    }
+    if refs != num_refs || pos != payload.len() {
+        return Err(DecodeError::Corrupt("ref count mismatch"));
+    }
+    if written != output.len() {
+        return Err(DecodeError::Corrupt("blocks fall short of declared size"));
+    }
+    Ok(())
+}
+
+/// Advance `*pos` past one length-prefixed block, returning its length
+fn skip_block(payload: &[u8], pos: &mut usize) -> Result<usize, DecodeError> {
+    let len = read_varint(payload, pos).ok_or(DecodeError::Truncated)?;
+    match usize::try_from(len) {
+        Ok(len) if len <= payload.len() - *pos => {
+            *pos += len;
+            Ok(len)
+        }
+        _ => Err(DecodeError::Truncated),
+    }
+}
+
+/// Read a LEB128 varint at `*pos`, as [`crate::varint::read_u64`] does
+fn read_varint(data: &[u8], pos: &mut usize) -> Option<u64> {
+    let mut value = 0u64;
+    let mut p = *pos;
+    for shift in (0..64).step_by(7) {
+        let byte = *data.get(p)?;
+        p += 1;
+        let bits = (byte & 0x7F) as u64;
+        if shift == 63 && bits > 1 {
+            return None;
+        }
+        value |= bits << shift;
+        if byte & 0x80 == 0 {
+            *pos = p;
+            return Some(value);
+        }
+    }
+    None
+}
+
+/// CRC-32 (IEEE) lookup table, built at compile time
+const CRC_TABLE: [u32; 256] = {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut c = i as u32;
+        let mut k = 0;
+        while k < 8 {
+            c = if c & 1 != 0 { 0xEDB8_8320 ^ (c >> 1) } else { c >> 1 };
+            k += 1;
+        }
+        table[i] = c;
+        i += 1;
+    }
+    table
+};
+
+fn crc32(data: &[u8]) -> u32 {
+    !data
+        .iter()
+        .fold(!0u32, |c, &b| CRC_TABLE[((c ^ u32::from(b)) & 0xFF) as usize] ^ (c >> 8))
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    use crate::{frame, CompressionMethod, Compressor};
+
+    #[test]
+    fn test_heapless_decode_matches_compressor() {
+        let compressor = Compressor::default();
+        let config = b"wifi.ssid=office\nwifi.retries=3\nlog.level=warn\n".repeat(20);
+        let mut buffer = [0u8; 2048];
+        for method in [CompressionMethod::Stored, CompressionMethod::Huffman, CompressionMethod::SemanticDedupe] {
+            let frame = compressor.compress(&config, method).unwrap().to_frame();
+            let len = decode_frame(&frame, &mut buffer).unwrap();
+            assert_eq!(&buffer[..len], &config[..]);
+        }
+        // Identical blocks leave a run-coded ref stream
+        let blank = vec![0xFFu8; 1536];
+        let frame = compressor.compress(&blank, CompressionMethod::SemanticDedupe).unwrap().to_frame();
+        let len = decode_frame(&frame, &mut buffer).unwrap();
+        assert_eq!(&buffer[..len], &blank[..]);
+        assert_eq!(crc32(&config), crc32fast::hash(&config));
+        assert_eq!((MAGIC, FORMAT_VERSION), (frame::MAGIC, frame::FORMAT_VERSION));
+        assert_eq!((FLAG_PARAMS, PARAMS_LEN), (frame::FLAG_PARAMS, crate::params::ENCODED_LEN));
+    }
+
+    #[test]
+    fn test_heapless_decode_rejections() {
+        let compressor = Compressor::default();
+        let data = b"sensor.interval=250ms\n".repeat(10);
+        let frame = compressor.compress(&data, CompressionMethod::Huffman).unwrap().to_frame();
+        let mut small = [0u8; 16];
+        assert!(matches!(decode_frame(&frame, &mut small), Err(DecodeError::OutputTooSmall { .. })));
+
+        let mut buffer = [0u8; 512];
+        let lz = compressor.compress(&data, CompressionMethod::Lz4Semantic).unwrap().to_frame();
+        assert_eq!(decode_frame(&lz, &mut buffer), Err(DecodeError::Unsupported("method")));
+        assert_eq!(decode_frame(&frame[..frame.len() - 1], &mut buffer), Err(DecodeError::Truncated));
+
+        let mut flipped = frame.clone();
+        *flipped.last_mut().unwrap() ^= 0x01;
+        assert!(decode_frame(&flipped, &mut buffer).is_err());
+    }
+
+    #[test]
+    fn test_heapless_decode_block_limit() {
+        let compressor = Compressor::default();
+        let data = b"led.brightness=40\n".repeat(20);
+        let mut buffer = [0u8; 512];
+        let tight = DecodeLimits { max_block_size: 128 };
+        let huffman = compressor.compress(&data, CompressionMethod::Huffman).unwrap().to_frame();
+        assert_eq!(
+            decode_frame_with(&huffman, &mut buffer, &tight),
+            Err(DecodeError::BlockTooLarge {
+                size: data.len() as u64,
+                max: 128
+            })
+        );
+        assert_eq!(decode_frame(&huffman, &mut buffer).unwrap(), data.len());
+
+        // Dedup blocks are checked one by one, so the frame as a whole may exceed the limit
+        let dedup = compressor.compress(&data, CompressionMethod::SemanticDedupe).unwrap().to_frame();
+        assert_eq!(decode_frame_with(&dedup, &mut buffer, &tight).unwrap(), data.len());
+        let tiny = DecodeLimits { max_block_size: 8 };
+        assert!(matches!(decode_frame_with(&dedup, &mut buffer, &tiny), Err(DecodeError::BlockTooLarge { max: 8, .. })));
+
+        // Runs of an empty block would spin without writing anything
+        let mut empty = vec![SEMANTIC_VERSION_MARKER, SEMANTIC_VERSION, 1 | SEMANTIC_RUNS_FLAG, 1, 0];
+        crate::varint::write_u64(&mut empty, 1 << 62);
+        empty.extend_from_slice(&[1, 0]);
+        crate::varint::write_u64(&mut empty, 1 << 62);
+        assert_eq!(decode_dedup(&empty, &mut buffer, DEFAULT_MAX_BLOCK_SIZE), Err(DecodeError::Corrupt("empty block")));
+    }
+}
@@ -0,0 +1,316 @@
+//! Authenticated encryption of independently addressable blocks
+//!
+//! Encrypting a whole frame would force readers to decrypt everything in
+//! front of the part they want, which defeats [`crate::pages::PageArchive`].
+//! Instead every block is sealed on its own under a nonce derived from a
+//! per-archive salt and the block's index, so any block decrypts (and
+//! authenticates) without touching the others, and a block moved to another
+//! position or archive fails its tag.
+//!
+//! Blocks are sealed with XChaCha20-Poly1305, whose 24-byte nonce is
+//! `salt || index`. Callers pass the metadata that steers decoding of a
+//! block (archive header, index entry) as associated data, so it is
+//! authenticated along with the block without being encrypted. The salt is
+//! a keyed BLAKE3 hash of everything the archive seals and authenticates,
+//! so a nonce is never reused for a different block or different
+//! associated data, and no random source is needed; identical archives
+//! encrypt identically under the same key.
+//!
+//! Keys come from a [`KeyProvider`] installed with
+//! [`Compressor::with_key_provider`], never from configuration. Writers ask
+//! it for the current key; the key's id is stored in the clear, and readers
+//! ask the provider for that id, so after a rotation new archives use the
+//! new key while old ones keep decrypting with the retired key as long as
+//! the provider can still produce it. [`KeyRing`] is an in-memory provider;
+//! a KMS-backed one would unwrap data keys on demand.
+//!
+//! Sealed block layout: `[ciphertext][tag 16]`.
+
+use crate::error::CompressError;
+use crate::Compressor;
+use chacha20poly1305::aead::{Aead, KeyInit, Payload};
+use chacha20poly1305::{XChaCha20Poly1305, XNonce};
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, RwLock};
+
+/// Key length in bytes
+pub const KEY_LEN: usize = 32;
+
+/// Authentication tag appended to every sealed block
+pub const TAG_LEN: usize = 16;
+
+/// Per-archive salt mixed into every block nonce
+pub const SALT_LEN: usize = 16;
+
+const SALT_CONTEXT: &str = "sigma-compress 2024-06 block encryption salt";
+
+/// Key material with the id readers use to pick the right key
+#[derive(Clone)]
+pub struct EncryptionKey {
+    id: String,
+    key: [u8; KEY_LEN],
+}
+
+impl EncryptionKey {
+    /// `id` is stored in the clear so the key can be found again; it must
+    /// fit in 255 bytes
+    pub fn new(id: impl Into<String>, key: [u8; KEY_LEN]) -> Self {
+        Self { id: id.into(), key }
+    }
+
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+}
+
+impl fmt::Debug for EncryptionKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("EncryptionKey").field("id", &self.id).finish_non_exhaustive()
+    }
+}
+
+/// Source of encryption keys, consulted on every encrypt and decrypt;
+/// called from whichever thread is compressing
+pub trait KeyProvider: Send + Sync {
+    /// Key new archives are encrypted with
+    fn current_key(&self) -> Result<EncryptionKey, CompressError>;
+
+    /// Key with id `id`, current or retired; `None` if it is unknown
+    fn key(&self, id: &str) -> Result<Option<EncryptionKey>, CompressError>;
+}
+
+/// In-memory [`KeyProvider`] supporting rotation: retired keys stay
+/// available for decryption until removed
+#[derive(Debug)]
+pub struct KeyRing {
+    keys: RwLock<KeyRingState>,
+}
+
+#[derive(Debug)]
+struct KeyRingState {
+    current: String,
+    keys: HashMap<String, EncryptionKey>,
+}
+
+impl KeyRing {
+    /// Ring with `key` as the current key
+    pub fn new(key: EncryptionKey) -> Self {
+        Self {
+            keys: RwLock::new(KeyRingState {
+                current: key.id.clone(),
+                keys: HashMap::from([(key.id.clone(), key)]),
+            }),
+        }
+    }
+
+    /// Make `key` current; the previous key is retired but still decrypts
+    pub fn rotate(&self, key: EncryptionKey) {
+        let mut state = self.keys.write().unwrap();
+        state.current = key.id.clone();
+        state.keys.insert(key.id.clone(), key);
+    }
+
+    /// Forget a retired key, returning whether it was present; archives
+    /// encrypted with it no longer open. The current key cannot be removed.
+    pub fn remove(&self, id: &str) -> bool {
+        let mut state = self.keys.write().unwrap();
+        state.current != id && state.keys.remove(id).is_some()
+    }
+
+    pub fn current_id(&self) -> String {
+        self.keys.read().unwrap().current.clone()
+    }
+}
+
+impl KeyProvider for KeyRing {
+    fn current_key(&self) -> Result<EncryptionKey, CompressError> {
+        let state = self.keys.read().unwrap();
+        Ok(state.keys[&state.current].clone())
+    }
+
+    fn key(&self, id: &str) -> Result<Option<EncryptionKey>, CompressError> {
+        Ok(self.keys.read().unwrap().keys.get(id).cloned())
+    }
+}
+
+/// Seals and opens the blocks of one archive
+pub(crate) struct BlockCipher {
+    aead: XChaCha20Poly1305,
+    salt: [u8; SALT_LEN],
+}
+
+impl BlockCipher {
+    pub(crate) fn new(key: &EncryptionKey, salt: [u8; SALT_LEN]) -> Self {
+        Self {
+            aead: XChaCha20Poly1305::new(&key.key.into()),
+            salt,
+        }
+    }
+
+    /// Salt for an archive sealing and authenticating `parts`: archives
+    /// differing in any part never share one. Parts are length-delimited.
+    pub(crate) fn salt_for(key: &EncryptionKey, parts: &[&[u8]]) -> [u8; SALT_LEN] {
+        let mut hasher = blake3::Hasher::new_keyed(&blake3::derive_key(SALT_CONTEXT, &key.key));
+        for part in parts {
+            hasher.update(&(part.len() as u64).to_le_bytes());
+            hasher.update(part);
+        }
+        hasher.finalize().as_bytes()[..SALT_LEN].try_into().unwrap()
+    }
+
+    pub(crate) fn salt(&self) -> [u8; SALT_LEN] {
+        self.salt
+    }
+
+    /// Encrypt `data` as block `index`, authenticating `aad` with it;
+    /// returns ciphertext and tag
+    pub(crate) fn seal(&self, index: u64, data: &[u8], aad: &[u8]) -> Result<Vec<u8>, CompressError> {
+        self.aead
+            .encrypt(&self.nonce(index), Payload { msg: data, aad })
+            .map_err(|_| CompressError::EncryptionError(format!("block {} too large to seal", index)))
+    }
+
+    /// Authenticate block `index` together with `aad`, and decrypt it
+    pub(crate) fn open(&self, index: u64, sealed: &[u8], aad: &[u8]) -> Result<Vec<u8>, CompressError> {
+        if sealed.len() < TAG_LEN {
+            return Err(CompressError::EncryptionError("sealed block shorter than its tag".into()));
+        }
+        self.aead.decrypt(&self.nonce(index), Payload { msg: sealed, aad }).map_err(|_| {
+            CompressError::EncryptionError(format!(
+                "block {} failed authentication (wrong key or tampered data)",
+                index
+            ))
+        })
+    }
+
+    fn nonce(&self, index: u64) -> XNonce {
+        let mut nonce = XNonce::default();
+        nonce[..SALT_LEN].copy_from_slice(&self.salt);
+        nonce[SALT_LEN..].copy_from_slice(&index.to_le_bytes());
+        nonce
+    }
+}
+
+impl Compressor {
+    /// Encrypt the blocks of archives this compressor writes with keys from
+    /// `provider`, and decrypt them on read. Only
+    /// [`Compressor::compress_pages`] encrypts; paths that write plain frames
+    /// (`compress`, `compress_chunked`, frame writers, archives, multiplexed
+    /// streams) fail with [`CompressError::EncryptionError`] instead.
+    pub fn with_key_provider(mut self, provider: Arc<dyn KeyProvider>) -> Self {
+        self.key_provider = Some(provider);
+        self
+    }
+
+    pub fn key_provider(&self) -> Option<&Arc<dyn KeyProvider>> {
+        self.key_provider.as_ref()
+    }
+
+    /// Refuse to write `what` in the clear when a key provider is set
+    pub(crate) fn ensure_unencrypted(&self, what: &str) -> Result<(), CompressError> {
+        match self.key_provider {
+            Some(_) => Err(CompressError::EncryptionError(format!(
+                "{} cannot be encrypted; use compress_pages, or a compressor without a key provider",
+                what
+            ))),
+            None => Ok(()),
+        }
+    }
+
+    /// Key for a new archive, if encryption is enabled
+    pub(crate) fn current_key(&self) -> Result<Option<EncryptionKey>, CompressError> {
+        let Some(provider) = &self.key_provider else {
+            return Ok(None);
+        };
+        let key = provider.current_key()?;
+        if key.id.len() > u8::MAX as usize {
+            return Err(CompressError::EncryptionError(format!("key id {:?} is over 255 bytes", key.id)));
+        }
+        Ok(Some(key))
+    }
+
+    /// Key an existing archive names by `id`
+    pub(crate) fn key_for(&self, id: &[u8]) -> Result<EncryptionKey, CompressError> {
+        let id = String::from_utf8_lossy(id);
+        let provider = self.key_provider.as_ref().ok_or_else(|| {
+            CompressError::EncryptionError(format!("data is encrypted with key {:?} but no key provider is set", id))
+        })?;
+        provider
+            .key(&id)?
+            .ok_or_else(|| CompressError::EncryptionError(format!("key {:?} is not available", id)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(id: &str, byte: u8) -> EncryptionKey {
+        EncryptionKey::new(id, [byte; KEY_LEN])
+    }
+
+    #[test]
+    fn test_blocks_open_independently() {
+        let key = key("k1", 7);
+        let cipher = BlockCipher::new(&key, BlockCipher::salt_for(&key, &[b"archive"]));
+        let blocks: Vec<Vec<u8>> = (0..4u64).map(|i| cipher.seal(i, &[b'x'; 100], b"header").unwrap()).collect();
+        // Same plaintext, different ciphertext per block
+        assert_ne!(blocks[0], blocks[1]);
+        assert!(!blocks[0].windows(8).any(|w| w == b"xxxxxxxx"));
+        assert_eq!(cipher.open(2, &blocks[2], b"header").unwrap(), vec![b'x'; 100]);
+        assert!(cipher.open(1, &blocks[2], b"header").is_err());
+        // Associated data is authenticated, not encrypted
+        assert!(cipher.open(2, &blocks[2], b"headex").is_err());
+        assert_eq!(cipher.open(9, &cipher.seal(9, b"", b"").unwrap(), b"").unwrap(), b"");
+        assert!(format!("{:?}", key).contains("k1") && !format!("{:?}", key).contains("[7"));
+    }
+
+    #[test]
+    fn test_tampering_and_wrong_key_rejected() {
+        let key = key("k1", 7);
+        let salt = BlockCipher::salt_for(&key, &[b"archive"]);
+        assert_ne!(salt, BlockCipher::salt_for(&key, &[b"archivf"]));
+        assert_ne!(salt, BlockCipher::salt_for(&key, &[b"arch", b"ive"]));
+        let mut sealed = BlockCipher::new(&key, salt).seal(0, b"secret page contents", b"").unwrap();
+        assert!(BlockCipher::new(&self::key("k2", 8), salt).open(0, &sealed, b"").is_err());
+        sealed[3] ^= 1;
+        assert!(BlockCipher::new(&key, salt).open(0, &sealed, b"").is_err());
+        assert!(BlockCipher::new(&key, salt).open(0, &sealed[..TAG_LEN - 1], b"").is_err());
+    }
+
+    #[test]
+    fn test_key_ring_rotation() {
+        let ring = KeyRing::new(key("2024-01", 1));
+        ring.rotate(key("2024-02", 2));
+        assert_eq!(ring.current_id(), "2024-02");
+        assert_eq!(ring.current_key().unwrap().id(), "2024-02");
+        assert_eq!(ring.key("2024-01").unwrap().unwrap().key, [1; KEY_LEN]);
+        assert!(!ring.remove("2024-02"));
+        assert!(ring.remove("2024-01"));
+        assert!(ring.key("2024-01").unwrap().is_none());
+    }
+
+    #[test]
+    fn test_plain_frame_paths_refuse_a_key_provider() {
+        use crate::CompressionMethod;
+        use std::io::Write;
+        let plain = Compressor::default();
+        let keyed = Compressor::default().with_key_provider(Arc::new(KeyRing::new(key("k1", 7))));
+        let data = b"never written in the clear ".repeat(100);
+        let refused = |result: Result<_, CompressError>| matches!(result, Err(CompressError::EncryptionError(_)));
+        assert!(refused(keyed.compress(&data, CompressionMethod::Lz4Semantic).map(drop)));
+        assert!(refused(keyed.compress_chunked(&data, CompressionMethod::Lzss).map(drop)));
+        let slices = [std::io::IoSlice::new(&data[..50]), std::io::IoSlice::new(&data[50..])];
+        assert!(refused(keyed.compress_vectored(&slices, CompressionMethod::Lzss).map(drop)));
+        assert!(refused(keyed.hash_and_compress(&data, CompressionMethod::Lzss).map(drop)));
+        assert!(refused(keyed.compress_chunks([&data], CompressionMethod::Stored).map(drop)));
+        let mut writer = crate::stream::FrameWriter::new(Vec::new(), keyed, CompressionMethod::Stored);
+        writer.write_all(&data).unwrap();
+        assert!(writer.finish().is_err());
+        // Plain frames still decode with a provider set
+        let keyed = Compressor::default().with_key_provider(Arc::new(KeyRing::new(key("k1", 7))));
+        let frame = plain.compress(&data, CompressionMethod::Lzss).unwrap().to_frame();
+        assert_eq!(keyed.decompress_frame(&frame).unwrap(), data);
+    }
+}
@@ -1,41 +1,305 @@
-//! Entropy coding — arithmetic/range coding for near-optimal compression
+//! Entropy coding — run-length coding with literal, zero and pair runs
+//!
+//! Payloads open with `[0x00][version]`. Legacy payloads (a plain sequence of
+//! `[run:u8][byte]` pairs) never start with a zero run length, so both decode.
+//!
+//! Version 2 is a stream of opcodes:
+//! ```text
+//! 0x00..=0x7F  literal run: the next (op + 1) bytes, 1..=128
+//! 0x80..=0xBF  byte run: (op & 0x3F) + 3 copies of the next byte
+//! 0xC0..=0xDF  zero run: (op & 0x1F) + 2 zero bytes
+//! 0xE0..=0xFF  pair run: (op & 0x1F) + 2 copies of the next two bytes, 2..=33
+//! ```
+//! A byte or zero run whose length field is all ones is followed by a varint
+//! added to its length (after the run byte, for byte runs), so a run of any
+//! length takes a handful of bytes. Version 1 is the same without the varint,
+//! capping those runs at 66 and 33 bytes.
+//!
+//! Incompressible input costs one byte per 128, instead of doubling.
 
 use crate::error::CompressError;
+use crate::varint;
 
-/// Compress using simple run-length + byte-packing entropy coder
+/// Leading byte of versioned payloads; legacy runs are never empty
+const VERSION_MARKER: u8 = 0x00;
+/// Opcode format written by this build
+pub const FORMAT_VERSION: u8 = 2;
+/// Version without varint run extensions
+const FORMAT_VERSION_CAPPED: u8 = 1;
+
+const MAX_LITERAL: usize = 128;
+const BYTE_RUN: u8 = 0x80;
+const BYTE_RUN_FIELD: u8 = 0x3F;
+const MIN_BYTE_RUN: usize = 3;
+const ZERO_RUN: u8 = 0xC0;
+const ZERO_RUN_FIELD: u8 = 0x1F;
+const MIN_ZERO_RUN: usize = 2;
+const PAIR_RUN: u8 = 0xE0;
+const MIN_PAIR_RUN: usize = 2;
+const MAX_PAIR_RUN: usize = 33;
+
+/// Input the encoder must see past a position before deciding on it
+const LOOKAHEAD: usize = 2 * MAX_PAIR_RUN;
+
+/// Longest expansion of a literal or pair opcode, or a legacy run
+const MAX_OP_OUTPUT: usize = 255;
+
+/// Block size [`decompress`] expands runs in
+const DECODE_BLOCK_LEN: usize = 1 << 20;
+
+/// Compress using run-length coding
 pub fn compress(data: &[u8]) -> Result<Vec<u8>, CompressError> {
-    // Run-length encoding as a simple entropy-aware compressor
-    let mut output = Vec::new();
-    let mut i = 0;
-    while i < data.len() {
-        let byte = data[i];
-        let mut run = 1u16;
-        while i + (run as usize) < data.len() && data[i + (run as usize)] == byte && run < 255 {
-            run += 1;
+    let mut encoder = StreamEncoder::new();
+    encoder.push(data);
+    Ok(encoder.finish())
+}
+
+/// Decompress RLE-encoded data; runs declaring more than `original_size`
+/// bytes in total are rejected before they are expanded
+pub fn decompress(data: &[u8], original_size: usize) -> Result<Vec<u8>, CompressError> {
+    let mut output = Vec::with_capacity(original_size.min(crate::MAX_PREALLOC));
+    for block in BlockDecoder::new(data, DECODE_BLOCK_LEN)? {
+        output.extend_from_slice(&block?);
+        if output.len() > original_size {
+            return Err(CompressError::SizeMismatch {
+                expected: original_size,
+                actual: output.len(),
+            });
         }
-        output.push(run as u8);
-        output.push(byte);
-        i += run as usize;
     }
     Ok(output)
 }
 
-/// Decompress RLE-encoded data
-pub fn decompress(data: &[u8], original_size: usize) -> Result<Vec<u8>, CompressError> {
-    if data.len() % 2 != 0 {
-        return Err(CompressError::EntropyError("invalid RLE data".into()));
-    }
-    let mut output = Vec::with_capacity(original_size);
-    let mut i = 0;
-    while i < data.len() {
-        let run = data[i] as usize;
-        let byte = data[i + 1];
-        for _ in 0..run {
-            output.push(byte);
+/// Incremental encoder; holds back enough input to make the same choices
+/// as a single pass over the whole buffer. A run reaching the end of the
+/// input so far is kept open as `(byte, length)` rather than buffered.
+#[derive(Debug)]
+pub struct StreamEncoder {
+    output: Vec<u8>,
+    pending: Vec<u8>,
+    literals: Vec<u8>,
+    open_run: Option<(u8, u64)>,
+}
+
+impl Default for StreamEncoder {
+    fn default() -> Self {
+        Self {
+            output: vec![VERSION_MARKER, FORMAT_VERSION],
+            pending: Vec::new(),
+            literals: Vec::with_capacity(MAX_LITERAL),
+            open_run: None,
         }
-        i += 2;
     }
-    Ok(output)
+}
+
+impl StreamEncoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, data: &[u8]) {
+        self.pending.extend_from_slice(data);
+        self.encode(false);
+    }
+
+    pub fn finish(mut self) -> Vec<u8> {
+        self.encode(true);
+        self.flush_literals();
+        self.output
+    }
+
+    fn encode(&mut self, last: bool) {
+        let data = std::mem::take(&mut self.pending);
+        let mut pos = 0;
+        if let Some((byte, len)) = self.open_run.take() {
+            pos = data.iter().take_while(|&&b| b == byte).count();
+            if pos == data.len() && !last {
+                self.open_run = Some((byte, len + pos as u64));
+                return;
+            }
+            self.emit_run(byte, len + pos as u64);
+        }
+        while pos < data.len() && (last || pos + LOOKAHEAD <= data.len()) {
+            let rest = &data[pos..];
+            let run = rest.iter().take_while(|&&b| b == rest[0]).count();
+            let is_run = run >= if rest[0] == 0 { MIN_ZERO_RUN } else { MIN_BYTE_RUN };
+            if is_run && run == rest.len() && !last {
+                self.flush_literals();
+                self.open_run = Some((rest[0], run as u64));
+                pos += run;
+            } else if is_run {
+                self.emit_run(rest[0], run as u64);
+                pos += run;
+            } else if let Some(pairs) = pair_run(rest) {
+                self.emit(&[PAIR_RUN | (pairs - MIN_PAIR_RUN) as u8, rest[0], rest[1]]);
+                pos += 2 * pairs;
+            } else {
+                self.literals.push(rest[0]);
+                if self.literals.len() == MAX_LITERAL {
+                    self.flush_literals();
+                }
+                pos += 1;
+            }
+        }
+        self.pending = data[pos..].to_vec();
+    }
+
+    /// Emit a zero run of at least [`MIN_ZERO_RUN`] or a byte run of at least [`MIN_BYTE_RUN`]
+    fn emit_run(&mut self, byte: u8, len: u64) {
+        let (op, field, min) = if byte == 0 {
+            (ZERO_RUN, ZERO_RUN_FIELD, MIN_ZERO_RUN)
+        } else {
+            (BYTE_RUN, BYTE_RUN_FIELD, MIN_BYTE_RUN)
+        };
+        let extra = len - min as u64;
+        self.flush_literals();
+        self.output.push(op | extra.min(field as u64) as u8);
+        if byte != 0 {
+            self.output.push(byte);
+        }
+        if extra >= field as u64 {
+            varint::write_u64(&mut self.output, extra - field as u64);
+        }
+    }
+
+    fn emit(&mut self, op: &[u8]) {
+        self.flush_literals();
+        self.output.extend_from_slice(op);
+    }
+
+    fn flush_literals(&mut self) {
+        if !self.literals.is_empty() {
+            self.output.push((self.literals.len() - 1) as u8);
+            self.output.append(&mut self.literals);
+        }
+    }
+}
+
+/// Repeats of an alternating two-byte pattern at the start of `data`
+fn pair_run(data: &[u8]) -> Option<usize> {
+    if data.len() < 2 * MIN_PAIR_RUN || data[0] == data[1] {
+        return None;
+    }
+    let pairs = data
+        .chunks_exact(2)
+        .take(MAX_PAIR_RUN)
+        .take_while(|c| c[0] == data[0] && c[1] == data[1])
+        .count();
+    (pairs >= MIN_PAIR_RUN).then_some(pairs)
+}
+
+/// Expands runs into blocks of at most `block_len` bytes; long runs are
+/// split across blocks
+pub struct BlockDecoder<'a> {
+    data: &'a [u8],
+    pos: usize,
+    block_len: usize,
+    /// `None` for legacy `[run][byte]` pairs
+    version: Option<u8>,
+    run_byte: u8,
+    run_left: usize,
+}
+
+impl<'a> BlockDecoder<'a> {
+    pub fn new(data: &'a [u8], block_len: usize) -> Result<Self, CompressError> {
+        let err = |msg: String| Err(CompressError::EntropyError(msg));
+        let version = if data.first() == Some(&VERSION_MARKER) {
+            match data.get(1) {
+                Some(&v @ (FORMAT_VERSION | FORMAT_VERSION_CAPPED)) => Some(v),
+                Some(&v) => return err(format!("unsupported RLE version {}", v)),
+                None => return err("truncated RLE header".into()),
+            }
+        } else if data.len().is_multiple_of(2) {
+            None
+        } else {
+            return err("invalid RLE data".into());
+        };
+        Ok(Self {
+            data,
+            pos: if version.is_some() { 2 } else { 0 },
+            block_len: block_len.max(MAX_OP_OUTPUT),
+            version,
+            run_byte: 0,
+            run_left: 0,
+        })
+    }
+
+    /// Decode one opcode, appending literals to `block` and leaving runs in
+    /// `run_byte`/`run_left`
+    fn next_op(&mut self, block: &mut Vec<u8>) -> Result<(), CompressError> {
+        let truncated = || CompressError::EntropyError("truncated RLE data".into());
+        let op = self.data[self.pos];
+        self.pos += 1;
+        let Some(version) = self.version else {
+            self.run_byte = self.data[self.pos];
+            self.run_left = op as usize;
+            self.pos += 1;
+            return Ok(());
+        };
+        let run_len = |field: u8, min: usize, pos: &mut usize| -> Result<usize, CompressError> {
+            let mut len = (op & field) as usize + min;
+            if op & field == field && version >= FORMAT_VERSION {
+                len = varint::read_usize(self.data, pos)
+                    .and_then(|extra| extra.checked_add(len))
+                    .ok_or_else(truncated)?;
+            }
+            Ok(len)
+        };
+        match op {
+            0x00..=0x7F => {
+                let len = op as usize + 1;
+                let literals = self.data.get(self.pos..self.pos + len).ok_or_else(truncated)?;
+                block.extend_from_slice(literals);
+                self.pos += len;
+            }
+            0x80..=0xBF => {
+                let byte = *self.data.get(self.pos).ok_or_else(truncated)?;
+                let mut pos = self.pos + 1;
+                self.run_left = run_len(BYTE_RUN_FIELD, MIN_BYTE_RUN, &mut pos)?;
+                self.run_byte = byte;
+                self.pos = pos;
+            }
+            0xC0..=0xDF => {
+                let mut pos = self.pos;
+                self.run_left = run_len(ZERO_RUN_FIELD, MIN_ZERO_RUN, &mut pos)?;
+                self.run_byte = 0;
+                self.pos = pos;
+            }
+            0xE0..=0xFF => {
+                let pair = self.data.get(self.pos..self.pos + 2).ok_or_else(truncated)?;
+                for _ in 0..(op & 0x1F) as usize + MIN_PAIR_RUN {
+                    block.extend_from_slice(pair);
+                }
+                self.pos += 2;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Iterator for BlockDecoder<'_> {
+    type Item = Result<Vec<u8>, CompressError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.pos >= self.data.len() && self.run_left == 0 {
+            return None;
+        }
+        let mut block = Vec::new();
+        loop {
+            let take = self.run_left.min(self.block_len - block.len());
+            block.resize(block.len() + take, self.run_byte);
+            self.run_left -= take;
+            if self.run_left > 0 || self.pos >= self.data.len() || block.len() + MAX_OP_OUTPUT > self.block_len {
+                break;
+            }
+            if let Err(e) = self.next_op(&mut block) {
+                self.pos = self.data.len();
+                self.run_left = 0;
+                return Some(Err(e));
+            }
+        }
+        Some(Ok(block))
+    }
 }
 
 #[cfg(test)]
@@ -58,6 +322,33 @@ mod tests {
         assert_eq!(decompressed, data);
     }
 
+    #[test]
+    fn test_stream_encoder_joins_runs() {
+        let mut encoder = StreamEncoder::new();
+        encoder.push(b"aaa");
+        encoder.push(b"aab");
+        assert_eq!(encoder.finish(), compress(b"aaaaab").unwrap());
+
+        let data: Vec<u8> = (0..5000u32).map(|i| if i % 300 < 150 { 0 } else { (i % 7) as u8 }).collect();
+        let mut encoder = StreamEncoder::new();
+        for chunk in data.chunks(37) {
+            encoder.push(chunk);
+        }
+        assert_eq!(encoder.finish(), compress(&data).unwrap());
+    }
+
+    #[test]
+    fn test_block_decoder_bounds_blocks() {
+        let data = vec![7u8; 5000];
+        let compressed = compress(&data).unwrap();
+        let blocks: Vec<Vec<u8>> = BlockDecoder::new(&compressed, 1024)
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert!(blocks.iter().all(|b| b.len() <= 1024));
+        assert_eq!(blocks.concat(), data);
+    }
+
     #[test]
     fn test_entropy_no_runs() {
         let data: Vec<u8> = (0..50).collect();
@@ -65,4 +356,55 @@ mod tests {
         let decompressed = decompress(&compressed, data.len()).unwrap();
         assert_eq!(decompressed, data);
     }
+
+    #[test]
+    fn test_literal_runs_cost_one_byte_per_128() {
+        let data: Vec<u8> = (0..=255).cycle().take(128 * 40).collect();
+        let compressed = compress(&data).unwrap();
+        assert_eq!(compressed.len(), 2 + data.len() + data.len() / 128);
+        assert_eq!(decompress(&compressed, data.len()).unwrap(), data);
+    }
+
+    #[test]
+    fn test_zero_and_pair_runs() {
+        let data = [vec![0u8; 33], b"abababababab".to_vec(), vec![0u8; 2], vec![9u8; 66]].concat();
+        let compressed = compress(&data).unwrap();
+        assert_eq!(compressed, [0, 2, 0xDF, 0, 0xE0 | 4, b'a', b'b', 0xC0, 0xBF, 9, 0]);
+        assert_eq!(decompress(&compressed, data.len()).unwrap(), data);
+    }
+
+    #[test]
+    fn test_long_runs_use_varint_lengths() {
+        let data = [vec![0u8; 3 << 20], vec![0x5A; 1 << 20], b"end".to_vec()].concat();
+        let compressed = compress(&data).unwrap();
+        assert_eq!(compressed.len(), 2 + (1 + 4) + (2 + 3) + (1 + 3));
+        assert_eq!(decompress(&compressed, data.len()).unwrap(), data);
+        assert!(decompress(&compressed, data.len() - 1).is_err());
+
+        let mut encoder = StreamEncoder::new();
+        for chunk in data.chunks(4096) {
+            encoder.push(chunk);
+        }
+        assert_eq!(encoder.finish(), compressed);
+
+        let blocks: Vec<Vec<u8>> = BlockDecoder::new(&compressed, 1 << 16)
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert!(blocks.iter().all(|b| b.len() <= 1 << 16));
+        assert_eq!(blocks.concat(), data);
+    }
+
+    #[test]
+    fn test_capped_version_still_decodes() {
+        assert_eq!(decompress(&[0, 1, 0xBF, 9], 66).unwrap(), vec![9; 66]);
+        assert_eq!(decompress(&[0, 1, 0xDF], 33).unwrap(), vec![0; 33]);
+    }
+
+    #[test]
+    fn test_legacy_pairs_still_decode() {
+        assert_eq!(decompress(&[3, b'x', 1, b'y'], 4).unwrap(), b"xxxy");
+        assert!(decompress(&[0, 9, 1], 1).is_err());
+        assert!(decompress(&[0, 1, 5, b'a'], 6).is_err());
+    }
 }
@@ -10,24 +10,117 @@ pub enum CompressError {
     #[error("invalid compression method for this operation")]
     InvalidMethod,
 
+    #[error("input of {size} bytes exceeds max_input_size of {limit}")]
+    InputTooLarge { size: usize, limit: usize },
+
     #[error("huffman encoding error: {0}")]
     HuffmanError(String),
 
     #[error("lz4 error: {0}")]
     Lz4Error(String),
 
+    #[error("lzss error: {0}")]
+    LzssError(String),
+
     #[error("entropy coding error: {0}")]
     EntropyError(String),
 
     #[error("semantic dedup error: {0}")]
     SemanticError(String),
 
+    #[error("token coding error: {0}")]
+    TokenError(String),
+
+    #[error("tensor coding error: {0}")]
+    TensorError(String),
+
+    #[error("memory image error: {0}")]
+    MemoryImageError(String),
+
+    #[error("pcm error: {0}")]
+    PcmError(String),
+
+    #[error("raster filter error: {0}")]
+    RasterError(String),
+
+    #[error("code transform error: {0}")]
+    CodeError(String),
+
+    #[error("log dedup error: {0}")]
+    LogDedupeError(String),
+
+    #[error("dictionary error: {0}")]
+    DictionaryError(String),
+
+    #[error("unknown dictionary id {0}")]
+    UnknownDictionary(u32),
+
+    #[error("invalid frame: {0}")]
+    FrameError(String),
+
     #[error("decompression size mismatch: expected {expected}, got {actual}")]
     SizeMismatch { expected: usize, actual: usize },
 
+    #[error("checksum mismatch: expected {expected:#010x}, got {actual:#010x}")]
+    ChecksumMismatch { expected: u32, actual: u32 },
+
     #[error("ryzanstein integration error: {0}")]
     RyzansteinError(String),
 
+    #[error("block store error: {0}")]
+    StoreError(String),
+
+    #[error("snapshot error: {0}")]
+    SnapshotError(String),
+
+    #[error("archive error: {0}")]
+    ArchiveError(String),
+
+    #[error("incremental update error: {0}")]
+    IncrementalError(String),
+
+    #[error("stream error: {0}")]
+    StreamError(String),
+
+    #[error("manifest error: {0}")]
+    ManifestError(String),
+
+    #[error("small-input path error: {0}")]
+    SmallError(String),
+
+    #[error("sorted key stream error: {0}")]
+    KeysError(String),
+
+    #[error("bitmap error: {0}")]
+    BitmapError(String),
+
+    #[error("text error: {0}")]
+    TextError(String),
+
+    #[error("output batch error: {0}")]
+    BatchError(String),
+
+    #[error("columnar batch error: {0}")]
+    ColumnarError(String),
+
+    #[error("page archive error: {0}")]
+    PageError(String),
+
+    #[error("encryption error: {0}")]
+    EncryptionError(String),
+
+    #[error("negotiation failed: {0}")]
+    NegotiationError(String),
+
+    #[error("stream context error: {0}")]
+    ContextError(String),
+
+    #[error("input is already a sigma-compress frame")]
+    AlreadyCompressed,
+
+    #[error("log compression error: {0}")]
+    LogError(String),
+
     #[error("IO error: {0}")]
     IoError(#[from] std::io::Error),
 
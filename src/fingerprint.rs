@@ -0,0 +1,145 @@
+//! Content fingerprints for deciding whether blobs share enough to delta
+//!
+//! Delta-compressing one large blob against another only pays off when they
+//! share most of their content, and finding out by trying costs as much as
+//! the delta itself. A [`ContentFingerprint`] is computed once per blob and
+//! kept next to it: the set of its content-defined chunks (see
+//! [`crate::chunker`]) with their lengths, and a bottom-k MinHash sketch of
+//! the chunk hashes. [`estimated_overlap`] compares two fingerprints without
+//! touching the data: exactly at chunk granularity from the chunk sets, and
+//! as an estimated Jaccard similarity from the sketches, which stay
+//! [`SKETCH_SIZE`] entries however large the blob is.
+
+use crate::chunker;
+use serde::{Deserialize, Serialize};
+
+/// Smallest chunk hashes kept in a sketch
+pub const SKETCH_SIZE: usize = 128;
+
+/// Chunk set and MinHash sketch of a blob
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ContentFingerprint {
+    pub len: u64,
+    /// `(hash, length)` of each distinct chunk, sorted by hash
+    pub chunks: Vec<(u64, u32)>,
+    /// The [`SKETCH_SIZE`] smallest chunk hashes, ascending
+    pub sketch: Vec<u64>,
+}
+
+/// How much two fingerprinted blobs have in common
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Overlap {
+    /// Bytes of the first blob in chunks that also occur in the second
+    pub shared_bytes: u64,
+    /// `shared_bytes` as a fraction of the first blob: the share a delta
+    /// against the second could encode as copies
+    pub containment: f64,
+    /// Jaccard similarity of the chunk sets, estimated from the sketches
+    pub jaccard: f64,
+}
+
+/// Fingerprint `data`
+pub fn fingerprint(data: &[u8]) -> ContentFingerprint {
+    let mut chunks: Vec<(u64, u32)> = chunker::chunk(data)
+        .into_iter()
+        .map(|c| (chunk_hash(c), c.len() as u32))
+        .collect();
+    chunks.sort_unstable();
+    chunks.dedup_by_key(|&mut (hash, _)| hash);
+    let sketch = chunks.iter().take(SKETCH_SIZE).map(|&(hash, _)| hash).collect();
+    ContentFingerprint {
+        len: data.len() as u64,
+        chunks,
+        sketch,
+    }
+}
+
+/// Compare `a` against `b`; `containment` is relative to `a`
+pub fn estimated_overlap(a: &ContentFingerprint, b: &ContentFingerprint) -> Overlap {
+    let mut shared_bytes = 0u64;
+    let mut other = b.chunks.iter().peekable();
+    for &(hash, len) in &a.chunks {
+        while other.next_if(|&&(h, _)| h < hash).is_some() {}
+        if other.peek().is_some_and(|&&(h, _)| h == hash) {
+            shared_bytes += u64::from(len);
+        }
+    }
+    Overlap {
+        shared_bytes,
+        containment: if a.len == 0 { 0.0 } else { shared_bytes as f64 / a.len as f64 },
+        jaccard: sketch_jaccard(&a.sketch, &b.sketch),
+    }
+}
+
+/// Bottom-k estimate: the share of the union's k smallest hashes present in both
+fn sketch_jaccard(a: &[u64], b: &[u64]) -> f64 {
+    let (mut i, mut j, mut both, mut seen) = (0, 0, 0, 0);
+    while seen < SKETCH_SIZE && (i < a.len() || j < b.len()) {
+        match (a.get(i), b.get(j)) {
+            (Some(x), Some(y)) if x == y => {
+                both += 1;
+                i += 1;
+                j += 1;
+            }
+            (Some(x), Some(y)) if x < y => i += 1,
+            (Some(_), None) => i += 1,
+            _ => j += 1,
+        }
+        seen += 1;
+    }
+    if seen == 0 {
+        return 0.0;
+    }
+    both as f64 / seen as f64
+}
+
+fn chunk_hash(chunk: &[u8]) -> u64 {
+    u64::from_le_bytes(blake3::hash(chunk).as_bytes()[..8].try_into().unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pseudo_random(len: usize, seed: u64) -> Vec<u8> {
+        let mut state = seed;
+        (0..len)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                state as u8
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_overlap_of_edited_copy() {
+        let original = pseudo_random(2_000_000, 11);
+        let mut edited = original.clone();
+        edited.splice(700_000..700_000, b"inserted bytes shift everything after".iter().copied());
+        edited[1_500_000..1_520_000].copy_from_slice(&pseudo_random(20_000, 5));
+
+        let (a, b) = (fingerprint(&original), fingerprint(&edited));
+        assert!(a.sketch.len() == SKETCH_SIZE && a.sketch.windows(2).all(|w| w[0] < w[1]));
+        let overlap = estimated_overlap(&b, &a);
+        assert!(overlap.containment > 0.95, "{}", overlap.containment);
+        assert!(overlap.jaccard > 0.85, "{}", overlap.jaccard);
+
+        let unrelated = estimated_overlap(&a, &fingerprint(&pseudo_random(2_000_000, 99)));
+        assert_eq!(unrelated.shared_bytes, 0);
+        assert_eq!(unrelated.jaccard, 0.0);
+    }
+
+    #[test]
+    fn test_overlap_of_partial_copy() {
+        let whole = pseudo_random(1_500_000, 3);
+        let (whole_fp, half_fp) = (fingerprint(&whole), fingerprint(&whole[..750_000]));
+        // Half is contained in the whole, but is only about half of it
+        assert!(estimated_overlap(&half_fp, &whole_fp).containment > 0.95);
+        let overlap = estimated_overlap(&whole_fp, &half_fp);
+        assert!((overlap.containment - 0.5).abs() < 0.05, "{}", overlap.containment);
+        assert!((overlap.jaccard - 0.5).abs() < 0.15, "{}", overlap.jaccard);
+        assert_eq!(estimated_overlap(&fingerprint(b""), &whole_fp).containment, 0.0);
+    }
+}
@@ -0,0 +1,906 @@
+//! Self-describing binary frame around a compressed payload
+//!
+//! Layout (all integers little-endian):
+//! ```text
+//! [magic:"SGMA"][version:u8][method:u8][flags:u8]
+//! [original_size:u64][payload_len:u64]
+//! [checksum:u32]        if FLAG_CHECKSUM
+//! [dictionary_id:u32]   if FLAG_DICTIONARY
+//! [padding:u32]         if FLAG_PADDED
+//! [params:6]            if FLAG_PARAMS
+//! [payload][padding zero bytes]
+//! ```
+//!
+//! With `FLAG_SEGMENTED` the payload is `[segment_count:varint]` followed by
+//! complete, independently decodable frames; the outer header's method is the
+//! first segment's, and its size and checksum cover the whole input.
+//!
+//! Frames may also be concatenated back to back (like gzip members), e.g. by
+//! appending to a file; decoding yields the concatenation of their outputs.
+//!
+//! With `FLAG_PADDED` the frame ends in zero bytes that pad it to a size the
+//! writer chose, such as a multiple of a direct-IO block (see
+//! [`CompressedOutput::pad_to`]); readers skip them. Frames written one after
+//! another that are each padded to a multiple of `N` all start on `N`-byte
+//! boundaries.
+//!
+//! With `FLAG_PARAMS` the header records the block size, level and model
+//! order the payload was coded with (see [`crate::params`]).
+
+use crate::config::TrailingDataPolicy;
+use crate::error::CompressError;
+use crate::memory::MemoryStats;
+use crate::params::{self, CodecParams};
+use crate::{batch, codec_stream, dictionary, incremental, lz4_wrapper, small, varint, semantic, semantic_lz, CompressedOutput, CompressionMetadata, CompressionMethod, Overhead};
+use serde::{Deserialize, Serialize};
+use std::io::{Read, Write};
+
+/// Magic bytes opening every frame
+pub const MAGIC: [u8; 4] = *b"SGMA";
+
+/// Frame format version written by this build.
+///
+/// Version 2 moved every length and count inside codec payloads to varints.
+pub const FORMAT_VERSION: u8 = 2;
+
+/// Header carries a CRC-32 of the original data
+pub const FLAG_CHECKSUM: u8 = 0x01;
+/// Header carries the id of the dictionary the payload was coded with
+pub const FLAG_DICTIONARY: u8 = 0x02;
+/// Payload is a sequence of nested frames (see [`crate::Compressor::compress_chunked`])
+pub const FLAG_SEGMENTED: u8 = 0x04;
+/// Original data is UTF-8 text, validated when the frame was written (see
+/// [`crate::Compressor::compress_str`])
+pub const FLAG_UTF8: u8 = 0x08;
+/// Payload is followed by zero padding whose length the header carries
+pub const FLAG_PADDED: u8 = 0x10;
+/// Header carries the codec parameters the payload was coded with
+pub const FLAG_PARAMS: u8 = 0x20;
+
+const FIXED_HEADER_LEN: usize = 4 + 1 + 1 + 1 + 8 + 8;
+
+/// Versions of every format this build writes.
+///
+/// The golden files under `tests/golden` pin the encoded bytes of each
+/// method for the current frame version and are checked against this, so a
+/// format change cannot land without a version bump and new golden files.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FormatVersion {
+    /// Frame header and codec payloads ([`FORMAT_VERSION`])
+    pub frame: u8,
+    /// `EntropyCoding` payload marker
+    pub entropy: u8,
+    /// `SemanticDedupe` payload header, also inside `SemanticLz`
+    #[serde(default = "legacy_semantic_version")]
+    pub semantic: u8,
+    pub checkpoint: u8,
+    pub delta: u8,
+    pub batch: u8,
+    pub bitmap: u8,
+    pub keys: u8,
+    pub value: u8,
+    pub columnar: u8,
+    pub pages: u8,
+    pub sidecar: u32,
+    pub archive: u32,
+    pub snapshot_manifest: u32,
+    pub manifest: u32,
+    pub compressor_state: u32,
+}
+
+impl FormatVersion {
+    /// Versions written by this build
+    pub const fn current() -> Self {
+        Self {
+            frame: FORMAT_VERSION,
+            entropy: crate::entropy::FORMAT_VERSION,
+            semantic: crate::semantic::FORMAT_VERSION,
+            checkpoint: crate::stream::CHECKPOINT_VERSION,
+            delta: incremental::DELTA_VERSION,
+            batch: batch::BATCH_VERSION,
+            bitmap: crate::bitmap::BITMAP_VERSION,
+            keys: crate::keys::KEYS_VERSION,
+            value: crate::value::VALUE_VERSION,
+            columnar: crate::columnar::COLUMNAR_VERSION,
+            pages: crate::pages::PAGES_VERSION,
+            sidecar: crate::sidecar::SIDECAR_VERSION,
+            archive: crate::archive::ARCHIVE_VERSION,
+            snapshot_manifest: crate::snapshot::MANIFEST_VERSION,
+            manifest: crate::manifest::MANIFEST_VERSION,
+            compressor_state: crate::warm::STATE_VERSION,
+        }
+    }
+}
+
+/// Semantic payload version of records written before it was tracked
+fn legacy_semantic_version() -> u8 {
+    1
+}
+
+/// [`CompressedOutput::write_to`] trailer versions, in the top byte of the
+/// block count: the original 16-byte trailer, and one followed by detached
+/// codec parameters
+const TRAILER_PLAIN: u8 = 0;
+const TRAILER_PARAMS: u8 = 1;
+
+/// Longest possible frame header
+pub(crate) const MAX_HEADER_LEN: usize = FIXED_HEADER_LEN + 4 + 4 + 4 + params::ENCODED_LEN;
+
+/// Decoded frame header
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FrameHeader {
+    pub version: u8,
+    pub method: CompressionMethod,
+    pub flags: u8,
+    pub original_size: u64,
+    pub payload_len: u64,
+    pub checksum: Option<u32>,
+    pub dictionary_id: Option<u32>,
+    /// Zero bytes after the payload
+    pub padding: Option<u32>,
+    /// Codec parameters, including the dictionary id if any
+    pub params: Option<CodecParams>,
+}
+
+impl FrameHeader {
+    /// Encoded length of this header
+    pub fn encoded_len(&self) -> usize {
+        FIXED_HEADER_LEN
+            + 4 * self.checksum.is_some() as usize
+            + 4 * self.dictionary_id.is_some() as usize
+            + 4 * self.padding.is_some() as usize
+            + params::ENCODED_LEN * self.params.is_some() as usize
+    }
+
+    /// Header, payload and padding together, if that fits in a `usize`
+    pub fn frame_len(&self) -> Option<usize> {
+        usize::try_from(self.payload_len)
+            .ok()?
+            .checked_add(self.encoded_len())?
+            .checked_add(self.padding.unwrap_or(0) as usize)
+    }
+
+    pub fn write(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&MAGIC);
+        out.push(self.version);
+        out.push(self.method.id());
+        out.push(self.flags);
+        out.extend_from_slice(&self.original_size.to_le_bytes());
+        out.extend_from_slice(&self.payload_len.to_le_bytes());
+        if let Some(checksum) = self.checksum {
+            out.extend_from_slice(&checksum.to_le_bytes());
+        }
+        if let Some(id) = self.dictionary_id {
+            out.extend_from_slice(&id.to_le_bytes());
+        }
+        if let Some(padding) = self.padding {
+            out.extend_from_slice(&padding.to_le_bytes());
+        }
+        if let Some(params) = &self.params {
+            params.write(out);
+        }
+    }
+
+    /// Parse the header at the start of `data`
+    pub fn parse(data: &[u8]) -> Result<Self, CompressError> {
+        if data.len() < FIXED_HEADER_LEN {
+            return Err(CompressError::FrameError("truncated header".into()));
+        }
+        if data[..4] != MAGIC {
+            return Err(CompressError::FrameError("bad magic".into()));
+        }
+        let version = data[4];
+        if version != FORMAT_VERSION {
+            return Err(CompressError::FrameError(format!("unsupported format version {}", version)));
+        }
+        let method = CompressionMethod::from_id(data[5])
+            .ok_or_else(|| CompressError::FrameError(format!("unknown method id {}", data[5])))?;
+        let flags = data[6];
+        let original_size = u64::from_le_bytes(data[7..15].try_into().unwrap());
+        let payload_len = u64::from_le_bytes(data[15..23].try_into().unwrap());
+
+        let mut pos = FIXED_HEADER_LEN;
+        let mut read_u32 = |present: bool| -> Result<Option<u32>, CompressError> {
+            if !present {
+                return Ok(None);
+            }
+            let bytes = data
+                .get(pos..pos + 4)
+                .ok_or_else(|| CompressError::FrameError("truncated header".into()))?;
+            pos += 4;
+            Ok(Some(u32::from_le_bytes(bytes.try_into().unwrap())))
+        };
+        let checksum = read_u32(flags & FLAG_CHECKSUM != 0)?;
+        let dictionary_id = read_u32(flags & FLAG_DICTIONARY != 0)?;
+        let padding = read_u32(flags & FLAG_PADDED != 0)?;
+        let params = if flags & FLAG_PARAMS != 0 {
+            Some(CodecParams::parse(&data[pos..], dictionary_id)?)
+        } else {
+            None
+        };
+
+        Ok(Self {
+            version,
+            method,
+            flags,
+            original_size,
+            payload_len,
+            checksum,
+            dictionary_id,
+            padding,
+            params,
+        })
+    }
+}
+
+/// Whether `data` starts with a valid frame header, i.e. is most likely
+/// already compressed
+pub fn is_frame(data: &[u8]) -> bool {
+    FrameHeader::parse(data).is_ok()
+}
+
+/// Decompressed size declared by the headers of `data`, without decoding
+/// any payload. Recognizes frames (summed over back-to-back members; only
+/// the members whose header is present count), incremental deltas, output
+/// batches and small-input frames. Sizes come from untrusted headers: treat
+/// them as a claim to check quotas against, not a guarantee.
+pub fn estimated_decompressed_size(data: &[u8]) -> Result<u64, CompressError> {
+    let overflow = || CompressError::FrameError("declared size overflows u64".into());
+    if data.starts_with(&MAGIC) {
+        let mut total = FrameHeader::parse(data)?.original_size;
+        let mut pos = 0;
+        while let Ok(header) = FrameHeader::parse(&data[pos..]) {
+            if pos > 0 {
+                total = total.checked_add(header.original_size).ok_or_else(overflow)?;
+            }
+            match header.frame_len().and_then(|len| pos.checked_add(len)) {
+                Some(next) if next < data.len() => pos = next,
+                _ => break,
+            }
+        }
+        return Ok(total);
+    }
+    if data.starts_with(&incremental::DELTA_MAGIC) {
+        let mut pos = 5;
+        let truncated = || CompressError::IncrementalError("truncated delta header".into());
+        varint::read_u64(data, &mut pos).ok_or_else(truncated)?;
+        return varint::read_u64(data, &mut pos).ok_or_else(truncated);
+    }
+    if data.starts_with(&batch::BATCH_MAGIC) {
+        return batch::original_size(data);
+    }
+    if small::is_small_frame(data) {
+        return Ok(u64::from(data[1]));
+    }
+    Err(CompressError::FrameError("unrecognized format".into()))
+}
+
+/// Everything knowable about a frame without decompressing it
+#[derive(Debug, Clone, PartialEq)]
+pub struct FrameInfo {
+    pub format_version: u8,
+    pub method: CompressionMethod,
+    pub block_count: usize,
+    /// Decompressed size of every codec-level block
+    pub block_sizes: Vec<usize>,
+    pub dictionary_id: Option<u32>,
+    /// Independently compressed segments; 1 unless the frame is segmented
+    pub segment_count: usize,
+    pub has_checksum: bool,
+    pub checksum: Option<u32>,
+    /// Frame is marked as UTF-8 text
+    pub is_utf8: bool,
+    /// Decompressed size declared by the header
+    pub estimated_decompressed_size: u64,
+    /// Size of the compressed payload
+    pub payload_size: u64,
+    /// Header, payload and padding
+    pub frame_size: u64,
+    /// Codec parameters, if the header records them
+    pub params: Option<CodecParams>,
+}
+
+/// Read a frame's header and codec block headers
+pub fn inspect(frame: &[u8]) -> Result<FrameInfo, CompressError> {
+    let header = FrameHeader::parse(frame)?;
+    let payload = payload(frame, &header)?;
+
+    let (block_sizes, segment_count) = if header.flags & FLAG_SEGMENTED != 0 {
+        let segments = segments(payload)?;
+        let mut sizes = Vec::new();
+        for segment in &segments {
+            sizes.extend(inspect(segment)?.block_sizes);
+        }
+        (sizes, segments.len())
+    } else {
+        // Dictionary-coded payloads report block sizes of the coded input
+        let (coded_len, payload) = if header.dictionary_id.is_some() {
+            dictionary::unwrap_payload(payload)?
+        } else {
+            (header.original_size as usize, payload)
+        };
+        let sizes = match header.method {
+            CompressionMethod::Lz4Semantic => lz4_wrapper::block_sizes(payload)?,
+            CompressionMethod::SemanticDedupe => semantic::block_sizes(payload, coded_len)?,
+            CompressionMethod::SemanticLz => semantic_lz::block_sizes(payload, coded_len)?,
+            _ => vec![header.original_size as usize],
+        };
+        (sizes, 1)
+    };
+
+    Ok(FrameInfo {
+        format_version: header.version,
+        method: header.method,
+        block_count: block_sizes.len(),
+        block_sizes,
+        dictionary_id: header.dictionary_id,
+        segment_count,
+        has_checksum: header.checksum.is_some(),
+        checksum: header.checksum,
+        is_utf8: header.flags & FLAG_UTF8 != 0,
+        estimated_decompressed_size: header.original_size,
+        payload_size: header.payload_len,
+        frame_size: (header.encoded_len() as u64) + header.payload_len + u64::from(header.padding.unwrap_or(0)),
+        params: header.params,
+    })
+}
+
+/// Wrap already-framed segments into one segmented frame
+pub(crate) fn write_segmented(segments: &[Vec<u8>], method: CompressionMethod, original_size: u64, checksum: u32) -> Vec<u8> {
+    let mut payload = Vec::new();
+    varint::write_u64(&mut payload, segments.len() as u64);
+    for segment in segments {
+        payload.extend_from_slice(segment);
+    }
+    let header = FrameHeader {
+        version: FORMAT_VERSION,
+        method,
+        flags: FLAG_CHECKSUM | FLAG_SEGMENTED,
+        original_size,
+        payload_len: payload.len() as u64,
+        checksum: Some(checksum),
+        dictionary_id: None,
+        padding: None,
+        params: None,
+    };
+    let mut out = Vec::with_capacity(header.encoded_len() + payload.len());
+    header.write(&mut out);
+    out.extend_from_slice(&payload);
+    out
+}
+
+/// Split a segmented frame's payload into its nested frames
+pub(crate) fn segments(payload: &[u8]) -> Result<Vec<&[u8]>, CompressError> {
+    let mut pos = 0;
+    let count = varint::read_usize(payload, &mut pos)
+        .ok_or_else(|| CompressError::FrameError("truncated segment count".into()))?;
+    let mut segments = Vec::with_capacity(count.min(payload.len()));
+    for _ in 0..count {
+        let header = FrameHeader::parse(&payload[pos..])?;
+        if header.flags & FLAG_SEGMENTED != 0 {
+            return Err(CompressError::FrameError("nested segmented frame".into()));
+        }
+        let len = frame_len(&payload[pos..], &header)?;
+        segments.push(&payload[pos..pos + len]);
+        pos += len;
+    }
+    if pos != payload.len() {
+        return Err(CompressError::FrameError("trailing bytes after last segment".into()));
+    }
+    Ok(segments)
+}
+
+/// Split back-to-back frames into one slice per frame
+pub fn split_frames(data: &[u8]) -> Result<Vec<&[u8]>, CompressError> {
+    Ok(split_with_policy(data, TrailingDataPolicy::Error)?.0)
+}
+
+/// Split the frames at the start of `data` from the bytes after them under
+/// `policy`. Trailing bytes start where no frame header parses; a frame
+/// whose header parses but whose payload or padding is cut short is an
+/// error. `data` must start with a frame. The remainder is empty unless
+/// the policy is `ReturnRemainder`.
+pub(crate) fn split_with_policy(data: &[u8], policy: TrailingDataPolicy) -> Result<(Vec<&[u8]>, &[u8]), CompressError> {
+    let mut frames = Vec::new();
+    let mut pos = 0;
+    while pos < data.len() || frames.is_empty() {
+        let header = match FrameHeader::parse(&data[pos..]) {
+            Ok(header) => header,
+            Err(e) if frames.is_empty() => return Err(e),
+            Err(e) => return trailing(policy, &data[pos..], pos as u64, e).map(|tail| (frames, tail)),
+        };
+        let len = frame_len(&data[pos..], &header)?;
+        frames.push(&data[pos..pos + len]);
+        pos += len;
+    }
+    Ok((frames, &[]))
+}
+
+/// Apply `policy` to `tail`, found at `offset` where parsing failed with
+/// `cause`
+pub(crate) fn trailing<T: Default>(policy: TrailingDataPolicy, tail: T, offset: u64, cause: CompressError) -> Result<T, CompressError> {
+    match policy {
+        TrailingDataPolicy::Error => Err(CompressError::FrameError(format!(
+            "trailing bytes at offset {} do not start a frame: {}",
+            offset, cause
+        ))),
+        TrailingDataPolicy::Ignore => Ok(T::default()),
+        TrailingDataPolicy::ReturnRemainder => Ok(tail),
+    }
+}
+
+/// Next item of a stream of back-to-back frames
+pub(crate) enum Member {
+    Frame(Vec<u8>),
+    /// Clean end of stream
+    End,
+    /// Bytes read where a header was expected but none parses, and why
+    Trailing(Vec<u8>, CompressError),
+}
+
+/// Read the next whole frame from `reader`; `None` at a clean end of stream
+pub(crate) fn read_frame<R: Read>(reader: &mut R) -> Result<Option<Vec<u8>>, CompressError> {
+    match read_member(reader)? {
+        Member::Frame(frame) => Ok(Some(frame)),
+        Member::End => Ok(None),
+        Member::Trailing(_, cause) => Err(cause),
+    }
+}
+
+/// [`read_frame`], returning what was read of a header that does not parse
+/// instead of failing
+pub(crate) fn read_member<R: Read>(reader: &mut R) -> Result<Member, CompressError> {
+    let mut frame = Vec::with_capacity(FIXED_HEADER_LEN);
+    reader.by_ref().take(FIXED_HEADER_LEN as u64).read_to_end(&mut frame)?;
+    if frame.is_empty() {
+        return Ok(Member::End);
+    }
+    if frame.len() < FIXED_HEADER_LEN {
+        return Ok(Member::Trailing(frame, CompressError::FrameError("truncated header".into())));
+    }
+    // Garbage may claim optional fields too; they are part of the tail then
+    let flags = frame[6];
+    let extra = 4 * (flags & FLAG_CHECKSUM != 0) as u64
+        + 4 * (flags & FLAG_DICTIONARY != 0) as u64
+        + 4 * (flags & FLAG_PADDED != 0) as u64
+        + params::ENCODED_LEN as u64 * (flags & FLAG_PARAMS != 0) as u64;
+    reader.by_ref().take(extra).read_to_end(&mut frame)?;
+    let header = match FrameHeader::parse(&frame) {
+        Ok(header) => header,
+        Err(e) => return Ok(Member::Trailing(frame, e)),
+    };
+    let want = header
+        .payload_len
+        .checked_add(u64::from(header.padding.unwrap_or(0)))
+        .ok_or_else(|| CompressError::FrameError("payload too large".into()))?;
+    let read = reader.by_ref().take(want).read_to_end(&mut frame)?;
+    if (read as u64) < want {
+        return Err(CompressError::FrameError("truncated payload".into()));
+    }
+    Ok(Member::Frame(frame))
+}
+
+/// Length of the frame at the start of `data`, checking its padding is
+/// present and zero
+pub(crate) fn frame_len(data: &[u8], header: &FrameHeader) -> Result<usize, CompressError> {
+    let end = payload(data, header)?.len() + header.encoded_len();
+    let len = header.frame_len().ok_or_else(|| CompressError::FrameError("payload too large".into()))?;
+    let padding = data
+        .get(end..len)
+        .ok_or_else(|| CompressError::FrameError("truncated padding".into()))?;
+    if padding.iter().any(|&b| b != 0) {
+        return Err(CompressError::FrameError("non-zero frame padding".into()));
+    }
+    Ok(len)
+}
+
+pub(crate) fn payload<'a>(frame: &'a [u8], header: &FrameHeader) -> Result<&'a [u8], CompressError> {
+    let start = header.encoded_len();
+    let end = start
+        .checked_add(usize::try_from(header.payload_len).map_err(|_| CompressError::FrameError("payload too large".into()))?)
+        .ok_or_else(|| CompressError::FrameError("payload too large".into()))?;
+    frame
+        .get(start..end)
+        .ok_or_else(|| CompressError::FrameError("truncated payload".into()))
+}
+
+impl CompressedOutput {
+    fn frame_header(&self) -> FrameHeader {
+        let params = self.metadata.params.filter(|_| self.params_in_frame);
+        FrameHeader {
+            version: FORMAT_VERSION,
+            method: self.method,
+            flags: if self.checksum.is_some() { FLAG_CHECKSUM } else { 0 }
+                | if self.dictionary_id.is_some() { FLAG_DICTIONARY } else { 0 }
+                | if self.utf8 { FLAG_UTF8 } else { 0 }
+                | if self.padding.is_some() { FLAG_PADDED } else { 0 }
+                | if params.is_some() { FLAG_PARAMS } else { 0 },
+            original_size: self.original_size as u64,
+            payload_len: self.data.len() as u64,
+            checksum: self.checksum,
+            dictionary_id: self.dictionary_id,
+            padding: self.padding,
+            params,
+        }
+    }
+
+    /// Size of [`CompressedOutput::to_frame`]: frame header, payload and padding
+    pub fn total_encoded_size(&self) -> usize {
+        self.frame_header().encoded_len() + self.data.len() + self.padding.unwrap_or(0) as usize
+    }
+
+    /// Pad the frame with zeros to the next multiple of `multiple` bytes;
+    /// 0 or 1 removes any padding. The padding and its header field count
+    /// toward the container overhead and the ratio.
+    pub fn pad_to(&mut self, multiple: u32) {
+        let unpadded = self.total_encoded_size() - self.padding.unwrap_or(0) as usize;
+        self.padding = (multiple > 1).then(|| {
+            let len = unpadded + 4 * self.padding.is_none() as usize;
+            ((multiple as usize - len % multiple as usize) % multiple as usize) as u32
+        });
+        let container = self.total_encoded_size() - self.data.len();
+        self.metadata.overhead.container = container;
+        self.ratio = self.total_encoded_size() as f64 / self.original_size.max(1) as f64;
+    }
+
+    /// Serialize into a self-describing frame
+    pub fn to_frame(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(self.total_encoded_size());
+        self.append_frame(&mut out);
+        out
+    }
+
+    /// Append the frame encoding to `out`
+    pub(crate) fn append_frame(&self, out: &mut Vec<u8>) {
+        self.frame_header().write(out);
+        out.extend_from_slice(&self.data);
+        out.resize(out.len() + self.padding.unwrap_or(0) as usize, 0);
+    }
+
+    /// Serialize losslessly to `w`: the frame from [`CompressedOutput::to_frame`]
+    /// followed by the analysis metadata a frame does not carry,
+    /// `[entropy_bits:f64][block_count:u56][trailer_version:u8]`
+    /// (little-endian). Version 0 ends there, as the original layout always
+    /// did; version 1 is followed by `[params:6]`, the codec parameters of a
+    /// frame without `FLAG_PARAMS`. Everything else (overhead, dedup
+    /// statistics, ratio) is recomputed on read.
+    pub fn write_to<W: Write>(&self, mut w: W) -> Result<(), CompressError> {
+        let detached = self.metadata.params.filter(|_| self.frame_header().params.is_none());
+        let block_count = self.metadata.block_count as u64;
+        if block_count >> 56 != 0 {
+            return Err(CompressError::FrameError("block count exceeds 56 bits".into()));
+        }
+        let version = if detached.is_some() { TRAILER_PARAMS } else { TRAILER_PLAIN };
+        w.write_all(&self.to_frame())?;
+        w.write_all(&self.metadata.entropy_bits.to_le_bytes())?;
+        w.write_all(&(block_count | (version as u64) << 56).to_le_bytes())?;
+        if let Some(params) = detached {
+            let mut encoded = Vec::with_capacity(params::ENCODED_LEN);
+            params.write(&mut encoded);
+            w.write_all(&encoded)?;
+        }
+        Ok(())
+    }
+
+    /// Read one output written by [`CompressedOutput::write_to`]
+    pub fn read_from<R: Read>(mut r: R) -> Result<Self, CompressError> {
+        let frame = read_frame(&mut r)?.ok_or_else(|| CompressError::FrameError("no output to read".into()))?;
+        let mut output = Self::from_frame(&frame)?;
+        let mut trailer = [0u8; 16];
+        r.read_exact(&mut trailer)
+            .map_err(|_| CompressError::FrameError("truncated output metadata".into()))?;
+        output.metadata.entropy_bits = f64::from_le_bytes(trailer[..8].try_into().unwrap());
+        let block_count = u64::from_le_bytes(trailer[8..].try_into().unwrap());
+        output.metadata.block_count = usize::try_from(block_count & ((1 << 56) - 1))
+            .map_err(|_| CompressError::FrameError("block count exceeds address space".into()))?;
+        match (block_count >> 56) as u8 {
+            TRAILER_PLAIN => {}
+            TRAILER_PARAMS => {
+                let mut params = [0u8; params::ENCODED_LEN];
+                r.read_exact(&mut params)
+                    .map_err(|_| CompressError::FrameError("truncated output metadata".into()))?;
+                output.metadata.params = Some(CodecParams::parse(&params, output.dictionary_id)?);
+            }
+            version => {
+                return Err(CompressError::FrameError(format!("unsupported output trailer version {}", version)));
+            }
+        }
+        Ok(output)
+    }
+
+    /// Parse a single-segment frame produced by [`CompressedOutput::to_frame`].
+    ///
+    /// Analysis-only metadata (entropy) is not part of the frame and comes back
+    /// zeroed; overhead and dedup statistics are recomputed from the payload.
+    pub fn from_frame(frame: &[u8]) -> Result<Self, CompressError> {
+        let header = FrameHeader::parse(frame)?;
+        if header.flags & FLAG_SEGMENTED != 0 {
+            return Err(CompressError::FrameError(
+                "segmented frame; decode with Compressor::decompress_frame".into(),
+            ));
+        }
+        frame_len(frame, &header)?;
+        Self::from_header(&header, payload(frame, &header)?.to_vec())
+    }
+
+    /// Rebuild an output from a parsed header and its payload
+    pub(crate) fn from_header(header: &FrameHeader, data: Vec<u8>) -> Result<Self, CompressError> {
+        let original_size = usize::try_from(header.original_size)
+            .map_err(|_| CompressError::FrameError("original size exceeds address space".into()))?;
+        let codec_payload = if header.dictionary_id.is_some() {
+            dictionary::unwrap_payload(&data)?.1
+        } else {
+            &data
+        };
+        let (tables, block_headers) = codec_stream::payload_overhead(header.method, codec_payload)?;
+        let tables = tables + (data.len() - codec_payload.len());
+        let semantic = codec_stream::semantic_report(header.method, codec_payload)?;
+        let container = header.encoded_len() + header.padding.unwrap_or(0) as usize;
+        Ok(CompressedOutput {
+            method: header.method,
+            original_size,
+            compressed_size: data.len(),
+            ratio: (container + data.len()) as f64 / original_size.max(1) as f64,
+            data,
+            metadata: CompressionMetadata {
+                entropy_bits: 0.0,
+                semantic_dedup_count: semantic.as_ref().map_or(0, |r| r.duplicate_blocks),
+                block_count: 1,
+                overhead: Overhead {
+                    container,
+                    tables,
+                    block_headers,
+                },
+                semantic,
+                nested_frame: false,
+                memory: MemoryStats::default(),
+                params: header.params,
+            },
+            checksum: header.checksum,
+            dictionary_id: header.dictionary_id,
+            utf8: header.flags & FLAG_UTF8 != 0,
+            padding: header.padding,
+            params_in_frame: header.params.is_some(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Compressor;
+
+    #[test]
+    fn test_frame_roundtrip() {
+        let compressor = Compressor::default();
+        let data = b"framed payload framed payload".repeat(10);
+        let out = compressor.compress(&data, CompressionMethod::Huffman).unwrap();
+        let parsed = CompressedOutput::from_frame(&out.to_frame()).unwrap();
+        assert_eq!(parsed.method, out.method);
+        assert_eq!(parsed.checksum, out.checksum);
+        assert_eq!(compressor.decompress(&parsed).unwrap(), data);
+    }
+
+    #[test]
+    fn test_write_read_binary() {
+        let compressor = Compressor::default();
+        let first = compressor
+            .compress(&b"binary output ".repeat(300), CompressionMethod::SemanticDedupe)
+            .unwrap();
+        let second = compressor.compress(b"second", CompressionMethod::EntropyCoding).unwrap();
+        let recording = Compressor::new(crate::config::CompressionConfig {
+            record_params: true,
+            ..Default::default()
+        });
+        let third = recording.compress(&b"third ".repeat(50), CompressionMethod::Lzss).unwrap();
+        let mut raw = Vec::new();
+        first.write_to(&mut raw).unwrap();
+        second.write_to(&mut raw).unwrap();
+        third.write_to(&mut raw).unwrap();
+        assert!(raw.len() < serde_json::to_vec(&first).unwrap().len());
+
+        let mut reader = &raw[..];
+        for expected in [&first, &second, &third] {
+            let read = CompressedOutput::read_from(&mut reader).unwrap();
+            assert_eq!(
+                serde_json::to_value(&read).unwrap(),
+                serde_json::to_value(expected).unwrap()
+            );
+        }
+        assert!(reader.is_empty());
+        let mut truncated = &raw[..first.total_encoded_size() + 8];
+        assert!(CompressedOutput::read_from(&mut truncated).is_err());
+        let mut truncated = &raw[raw.len() - 1 - third.total_encoded_size() - 16..raw.len() - 1];
+        assert!(CompressedOutput::read_from(&mut truncated).is_err());
+    }
+
+    #[test]
+    fn test_read_original_binary_layout() {
+        // Streams from before detached params: frame, entropy bits, block count
+        let compressor = Compressor::default();
+        let outputs = [
+            compressor.compress(&b"layout ".repeat(100), CompressionMethod::Huffman).unwrap(),
+            compressor.compress(b"kept readable", CompressionMethod::Stored).unwrap(),
+        ];
+        let mut raw = Vec::new();
+        for output in &outputs {
+            raw.extend_from_slice(&output.to_frame());
+            raw.extend_from_slice(&output.metadata.entropy_bits.to_le_bytes());
+            raw.extend_from_slice(&(output.metadata.block_count as u64).to_le_bytes());
+        }
+        let mut reader = &raw[..];
+        for expected in &outputs {
+            let read = CompressedOutput::read_from(&mut reader).unwrap();
+            assert_eq!(read.data, expected.data);
+            assert_eq!(read.metadata.entropy_bits, expected.metadata.entropy_bits);
+            assert_eq!(read.metadata.block_count, expected.metadata.block_count);
+            assert_eq!(read.metadata.params, None);
+        }
+        assert!(reader.is_empty());
+
+        let mut future = raw.clone();
+        future[outputs[0].total_encoded_size() + 15] = 7;
+        assert!(CompressedOutput::read_from(&mut &future[..]).is_err());
+    }
+
+    #[test]
+    fn test_inspect_lz4_blocks() {
+        let config = crate::config::CompressionConfig {
+            lz4_block_size: 1000,
+            ..Default::default()
+        };
+        let compressor = Compressor::new(config);
+        let data = vec![5u8; 2500];
+        let frame = compressor.compress(&data, CompressionMethod::Lz4Semantic).unwrap().to_frame();
+        let info = inspect(&frame).unwrap();
+        assert_eq!(info.method, CompressionMethod::Lz4Semantic);
+        assert_eq!(info.block_sizes, vec![1000, 1000, 500]);
+        assert_eq!(info.estimated_decompressed_size, 2500);
+        assert!(info.has_checksum);
+        assert_eq!(info.dictionary_id, None);
+        assert_eq!(info.frame_size, frame.len() as u64);
+    }
+
+    #[test]
+    fn test_total_encoded_size_matches_frame() {
+        let compressor = Compressor::default();
+        let data = vec![3u8; 5000];
+        let out = compressor.compress(&data, CompressionMethod::Lz4Semantic).unwrap();
+        let frame = out.to_frame();
+        assert_eq!(out.total_encoded_size(), frame.len());
+        assert_eq!(out.ratio, frame.len() as f64 / data.len() as f64);
+        assert_eq!(out.metadata.overhead.container, frame.len() - out.data.len());
+        assert_eq!(CompressedOutput::from_frame(&frame).unwrap().metadata.overhead, out.metadata.overhead);
+    }
+
+    #[test]
+    fn test_rejects_old_format_version() {
+        let compressor = Compressor::default();
+        let mut frame = compressor.compress(b"versioned", CompressionMethod::Huffman).unwrap().to_frame();
+        frame[4] = 1;
+        assert!(matches!(inspect(&frame), Err(CompressError::FrameError(_))));
+    }
+
+    #[test]
+    fn test_inspect_rejects_garbage() {
+        assert!(inspect(b"not a frame at all, really not").is_err());
+        assert!(inspect(b"SGMA").is_err());
+    }
+
+    #[test]
+    fn test_inspect_truncated_payload() {
+        let compressor = Compressor::default();
+        let frame = compressor.compress(b"truncate", CompressionMethod::EntropyCoding).unwrap().to_frame();
+        assert!(inspect(&frame[..frame.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn test_estimated_decompressed_size() {
+        let compressor = Compressor::default();
+        let first = compressor.compress(&[1u8; 5000], CompressionMethod::Huffman).unwrap().to_frame();
+        let second = compressor.compress(&[2u8; 700], CompressionMethod::Lz4Semantic).unwrap().to_frame();
+        let stream = [first.clone(), second].concat();
+        assert_eq!(estimated_decompressed_size(&stream).unwrap(), 5700);
+        // The header alone is enough
+        assert_eq!(estimated_decompressed_size(&first[..MAX_HEADER_LEN]).unwrap(), 5000);
+
+        let mut incremental = crate::incremental::IncrementalCompressor::new(Default::default(), CompressionMethod::Lzss);
+        let delta = incremental.update_bytes("file", &[3u8; 1234]).unwrap().to_bytes();
+        assert_eq!(estimated_decompressed_size(&delta).unwrap(), 1234);
+        let outputs = vec![compressor.compress(&[4u8; 300], CompressionMethod::Stored).unwrap(); 3];
+        assert_eq!(estimated_decompressed_size(&crate::batch::encode_outputs(&outputs)).unwrap(), 900);
+        let tiny = crate::small::compress::<64>(b"tiny tiny tiny").unwrap();
+        assert_eq!(estimated_decompressed_size(tiny.as_bytes()).unwrap(), 14);
+        assert!(estimated_decompressed_size(b"not compressed").is_err());
+    }
+
+    #[test]
+    fn test_padded_frames() {
+        let compressor = Compressor::new(crate::config::CompressionConfig {
+            frame_padding: 512,
+            ..Default::default()
+        });
+        let data = b"padded to whole blocks ".repeat(100);
+        let output = compressor.compress(&data, CompressionMethod::Huffman).unwrap();
+        let frame = output.to_frame();
+        assert_eq!((frame.len() % 512, frame.len()), (0, output.total_encoded_size()));
+        let header = FrameHeader::parse(&frame).unwrap();
+        assert_ne!(header.flags & FLAG_PADDED, 0);
+        assert_eq!(inspect(&frame).unwrap().frame_size, frame.len() as u64);
+        assert_eq!(CompressedOutput::from_frame(&frame).unwrap().to_frame(), frame);
+
+        // Every reader skips the padding, and back-to-back frames stay aligned
+        let stream = [frame.clone(), frame.clone()].concat();
+        assert_eq!(split_frames(&stream).unwrap(), vec![&frame[..], &frame[..]]);
+        assert_eq!(Compressor::default().decompress_frame(&stream).unwrap(), data.repeat(2));
+        assert_eq!(estimated_decompressed_size(&stream).unwrap(), 2 * data.len() as u64);
+        let mut reader = &stream[..];
+        assert_eq!(read_frame(&mut reader).unwrap().unwrap(), frame);
+        let mut buffer = vec![0u8; data.len()];
+        assert_eq!(crate::embedded::decode_frame(&frame, &mut buffer).unwrap(), data.len());
+
+        let mut dirty = frame.clone();
+        *dirty.last_mut().unwrap() = 1;
+        assert!(Compressor::default().decompress_frame(&dirty).is_err());
+        assert!(split_frames(&frame[..frame.len() - 1]).is_err());
+
+        let mut unpadded = output.clone();
+        unpadded.pad_to(0);
+        assert_eq!(unpadded.to_frame(), Compressor::default().compress(&data, CompressionMethod::Huffman).unwrap().to_frame());
+    }
+
+    #[test]
+    fn test_chunked_segments_are_aligned() {
+        let compressor = Compressor::new(crate::config::CompressionConfig {
+            max_input_size: 3000,
+            block_alignment: 4096,
+            ..Default::default()
+        });
+        let data = b"aligned segment starts for direct IO ".repeat(200);
+        let frames = compressor.compress_chunked(&data, CompressionMethod::Lz4Semantic).unwrap();
+        let members = split_frames(&frames).unwrap();
+        assert_eq!(members.len(), data.len().div_ceil(3000));
+        assert!(members.iter().all(|m| m.len() % 4096 == 0));
+        assert_eq!(compressor.decompress_frame(&frames).unwrap(), data);
+    }
+
+    #[test]
+    fn test_trailing_data_policies() {
+        use crate::config::{CompressionConfig, TrailingDataPolicy};
+        let with_policy = |trailing_data| {
+            Compressor::new(CompressionConfig {
+                trailing_data,
+                ..CompressionConfig::default()
+            })
+        };
+        let data = b"frame followed by junk ".repeat(40);
+        let frame = Compressor::default().compress(&data, CompressionMethod::Lzss).unwrap().to_frame();
+        let input = [frame.clone(), frame.clone(), b"\x00junk after the frames".to_vec()].concat();
+
+        let strict = with_policy(TrailingDataPolicy::Error);
+        let message = strict.decompress_frame(&input).unwrap_err().to_string();
+        assert!(message.contains(&format!("offset {}", 2 * frame.len())), "{}", message);
+        assert!(strict.decompress_stream(&input[..], Vec::new()).is_err());
+        assert_eq!(strict.decompress_frame(&input[..2 * frame.len()]).unwrap(), data.repeat(2));
+
+        let ignore = with_policy(TrailingDataPolicy::Ignore);
+        assert_eq!(ignore.decompress_frame(&input).unwrap(), data.repeat(2));
+        assert_eq!(ignore.decompress_with_remainder(&input).unwrap().1, b"");
+        // The first bytes must still be a frame, and damaged frames still fail
+        assert!(ignore.decompress_frame(&input[2 * frame.len()..]).is_err());
+        assert!(ignore.decompress_frame(&input[..2 * frame.len() - 1]).is_err());
+
+        let remainder = with_policy(TrailingDataPolicy::ReturnRemainder);
+        let (decoded, tail) = remainder.decompress_with_remainder(&input).unwrap();
+        assert_eq!((decoded, tail), (data.repeat(2), &input[2 * frame.len()..]));
+        assert!(remainder.decompress_frame(&input).is_err());
+
+        // A stream hands back what it read of the bad header; the rest stays unread
+        let stream = [input.clone(), vec![0u8; 100]].concat();
+        let mut reader = &stream[..];
+        let mut out = Vec::new();
+        let (written, tail) = remainder.decompress_stream_with_remainder(&mut reader, &mut out).unwrap();
+        assert_eq!((written, out), (2 * data.len() as u64, data.repeat(2)));
+        assert_eq!([&tail[..], reader].concat(), &stream[2 * frame.len()..]);
+    }
+}
@@ -0,0 +1,197 @@
+//! Repackaging of multi-frame files without decoding payloads
+//!
+//! Tooling that rewrites archives, re-chunks uploads or drops members from
+//! appended logs needs the frames of a file, not their contents. [`split`]
+//! walks back-to-back frames (see [`crate::frame`]) and returns a
+//! [`FrameRef`] per frame, borrowing from the input; a segmented frame can
+//! be opened further with [`FrameRef::segments`]. [`concat`] joins frames,
+//! borrowed or owned, back into one buffer. Headers are parsed and padding
+//! is checked, but payloads are neither decoded nor checksummed.
+
+use crate::error::CompressError;
+use crate::frame::{self, FrameHeader, FLAG_SEGMENTED};
+
+/// One frame inside a larger buffer
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FrameRef<'a> {
+    /// Position of the frame in the buffer it was split from
+    pub offset: usize,
+    pub header: FrameHeader,
+    bytes: &'a [u8],
+}
+
+impl<'a> FrameRef<'a> {
+    /// Parse the frame at the start of `data`, which may continue past it
+    pub fn parse(data: &'a [u8]) -> Result<Self, CompressError> {
+        let header = FrameHeader::parse(data)?;
+        let len = frame::frame_len(data, &header)?;
+        Ok(Self {
+            offset: 0,
+            header,
+            bytes: &data[..len],
+        })
+    }
+
+    /// The whole frame: header, payload and padding
+    pub fn as_bytes(&self) -> &'a [u8] {
+        self.bytes
+    }
+
+    /// Coded payload, without header or padding
+    pub fn payload(&self) -> &'a [u8] {
+        let start = self.header.encoded_len();
+        &self.bytes[start..start + self.header.payload_len as usize]
+    }
+
+    pub fn is_segmented(&self) -> bool {
+        self.header.flags & FLAG_SEGMENTED != 0
+    }
+
+    /// Segments of a segmented frame, each a complete frame; offsets are
+    /// relative to this frame's buffer. Any other frame is its only segment.
+    pub fn segments(&self) -> Result<Vec<FrameRef<'a>>, CompressError> {
+        if !self.is_segmented() {
+            return Ok(vec![self.clone()]);
+        }
+        let payload = self.payload();
+        let segments = frame::segments(payload)?;
+        // Segments run to the end of the payload, after the segment count
+        let mut offset = self.offset + self.header.encoded_len() + payload.len()
+            - segments.iter().map(|s| s.len()).sum::<usize>();
+        segments
+            .into_iter()
+            .map(|segment| {
+                let mut frame = Self::parse(segment)?;
+                frame.offset = offset;
+                offset += segment.len();
+                Ok(frame)
+            })
+            .collect()
+    }
+
+    /// Copy into an owned [`Frame`]
+    pub fn to_frame(&self) -> Frame {
+        Frame {
+            header: self.header.clone(),
+            bytes: self.bytes.to_vec(),
+        }
+    }
+}
+
+impl AsRef<[u8]> for FrameRef<'_> {
+    fn as_ref(&self) -> &[u8] {
+        self.bytes
+    }
+}
+
+/// An owned, complete frame
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Frame {
+    header: FrameHeader,
+    bytes: Vec<u8>,
+}
+
+impl Frame {
+    /// Take `bytes`, which must hold exactly one frame
+    pub fn from_bytes(bytes: Vec<u8>) -> Result<Self, CompressError> {
+        let header = FrameRef::parse(&bytes)?.header;
+        if frame::frame_len(&bytes, &header)? != bytes.len() {
+            return Err(CompressError::FrameError("trailing bytes after frame".into()));
+        }
+        Ok(Self { header, bytes })
+    }
+
+    pub fn header(&self) -> &FrameHeader {
+        &self.header
+    }
+
+    pub fn as_frame_ref(&self) -> FrameRef<'_> {
+        FrameRef {
+            offset: 0,
+            header: self.header.clone(),
+            bytes: &self.bytes,
+        }
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.bytes
+    }
+}
+
+impl AsRef<[u8]> for Frame {
+    fn as_ref(&self) -> &[u8] {
+        &self.bytes
+    }
+}
+
+/// Every frame of `data`, a run of back-to-back frames; empty input has none
+pub fn split(data: &[u8]) -> Result<Vec<FrameRef<'_>>, CompressError> {
+    let mut frames = Vec::new();
+    let mut offset = 0;
+    while offset < data.len() {
+        let mut frame = FrameRef::parse(&data[offset..])?;
+        frame.offset = offset;
+        offset += frame.bytes.len();
+        frames.push(frame);
+    }
+    Ok(frames)
+}
+
+/// Join frames into one back-to-back buffer, which decodes to the
+/// concatenation of their outputs
+pub fn concat<F: AsRef<[u8]>>(frames: &[F]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(frames.iter().map(|f| f.as_ref().len()).sum());
+    for frame in frames {
+        out.extend_from_slice(frame.as_ref());
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::CompressionConfig;
+    use crate::{CompressionMethod, Compressor};
+
+    #[test]
+    fn test_split_and_concat_roundtrip() {
+        let compressor = Compressor::default();
+        let parts: Vec<Vec<u8>> =
+            (0..3).map(|i| format!("member {} of a multi-frame file; ", i).repeat(50 + i).into_bytes()).collect();
+        let file = concat(&parts.iter().map(|p| compressor.compress(p, CompressionMethod::Lzss).unwrap().to_frame()).collect::<Vec<_>>());
+
+        let frames = split(&file).unwrap();
+        assert_eq!(frames.len(), 3);
+        assert_eq!(frames[1].offset, frames[0].as_bytes().len());
+        assert_eq!(frames[2].header.original_size as usize, parts[2].len());
+        assert_eq!(concat(&frames), file);
+
+        // Drop the middle member and reorder the rest without decoding
+        let owned: Vec<Frame> = [&frames[2], &frames[0]].iter().map(|f| f.to_frame()).collect();
+        let repacked = concat(&owned);
+        assert_eq!(compressor.decompress_frame(&repacked).unwrap(), [parts[2].clone(), parts[0].clone()].concat());
+        assert_eq!(Frame::from_bytes(owned[1].clone().into_bytes()).unwrap(), owned[1]);
+        assert!(split(&[]).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_segments_and_bad_input() {
+        let compressor = Compressor::new(CompressionConfig {
+            max_input_size: 1000,
+            ..CompressionConfig::default()
+        });
+        let data = b"segmented frames open into their segments ".repeat(80);
+        let file = compressor.compress_chunked(&data, CompressionMethod::Huffman).unwrap();
+        let frames = split(&file).unwrap();
+        assert_eq!(frames.len(), 1);
+        let segments = frames[0].segments().unwrap();
+        assert_eq!(segments.len(), data.len().div_ceil(1000));
+        for segment in &segments {
+            assert_eq!(&file[segment.offset..segment.offset + segment.as_bytes().len()], segment.as_bytes());
+        }
+        assert_eq!(compressor.decompress_frame(&concat(&segments)).unwrap(), data);
+
+        assert!(split(&file[..file.len() - 1]).is_err());
+        assert!(Frame::from_bytes([file.clone(), vec![0]].concat()).is_err());
+    }
+}
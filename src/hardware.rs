@@ -0,0 +1,217 @@
+//! Defaults chosen for the machine the compressor runs on
+//!
+//! [`Compressor::new`] detects the available cores and SIMD features once
+//! per process and derives a [`HardwareProfile`]: the worker threads used
+//! where a thread count is left at 0, the `Lz4Semantic` block size, and the
+//! width of the LZSS match finder's hash table. Machines without vector
+//! units are usually small cores with small caches, so they get smaller
+//! blocks and tables that keep the working set cached; many-core machines
+//! with wide vectors get larger ones, for a better ratio per block.
+//!
+//! The block size and hash width fill in only settings left at [`AUTO`],
+//! which is their default; any value set explicitly is kept.
+//! Deterministic configurations (see [`CompressionConfig::deterministic`])
+//! always use [`HardwareProfile::baseline`], so output does not depend on
+//! the machine. [`Compressor::profile`] shows the choice and
+//! [`Compressor::with_profile`] overrides it.
+
+use crate::config::{CompressionConfig, AUTO, DEFAULT_HASH_BITS, DEFAULT_LZ4_BLOCK_SIZE};
+use crate::Compressor;
+use serde::{Deserialize, Serialize};
+use std::sync::OnceLock;
+use std::thread;
+
+/// Vector instruction sets the CPU supports
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SimdFeatures {
+    pub sse42: bool,
+    pub avx2: bool,
+    pub avx512: bool,
+    pub neon: bool,
+}
+
+impl SimdFeatures {
+    /// Features of the running CPU
+    pub fn detect() -> Self {
+        #[cfg(target_arch = "x86_64")]
+        return Self {
+            sse42: is_x86_feature_detected!("sse4.2"),
+            avx2: is_x86_feature_detected!("avx2"),
+            avx512: is_x86_feature_detected!("avx512f"),
+            neon: false,
+        };
+        #[cfg(target_arch = "aarch64")]
+        return Self {
+            neon: std::arch::is_aarch64_feature_detected!("neon"),
+            ..Self::default()
+        };
+        #[allow(unreachable_code)]
+        Self::default()
+    }
+
+    pub fn any(&self) -> bool {
+        self.sse42 || self.avx2 || self.avx512 || self.neon
+    }
+
+    /// 256-bit or wider vectors
+    pub fn wide(&self) -> bool {
+        self.avx2 || self.avx512
+    }
+}
+
+/// Machine description and the defaults derived from it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HardwareProfile {
+    pub cores: usize,
+    pub simd: SimdFeatures,
+    /// Worker threads where a thread count is left at 0
+    pub threads: usize,
+    /// `Lz4Semantic` block size
+    pub block_size: usize,
+    /// LZSS hash table width (see [`crate::config::LzssConfig::hash_bits`])
+    pub hash_bits: u32,
+}
+
+impl HardwareProfile {
+    /// Profile of the running machine, detected once per process
+    pub fn detect() -> Self {
+        static DETECTED: OnceLock<HardwareProfile> = OnceLock::new();
+        *DETECTED.get_or_init(|| {
+            let cores = thread::available_parallelism().map_or(1, |n| n.get());
+            Self::for_machine(cores, SimdFeatures::detect())
+        })
+    }
+
+    /// Defaults for a machine with `cores` cores and `simd` features
+    pub fn for_machine(cores: usize, simd: SimdFeatures) -> Self {
+        let cores = cores.max(1);
+        let (block_size, hash_bits) = if !simd.any() {
+            (DEFAULT_LZ4_BLOCK_SIZE / 2, DEFAULT_HASH_BITS - 1)
+        } else if simd.wide() && cores >= 8 {
+            (DEFAULT_LZ4_BLOCK_SIZE * 2, DEFAULT_HASH_BITS + 1)
+        } else {
+            (DEFAULT_LZ4_BLOCK_SIZE, DEFAULT_HASH_BITS)
+        };
+        Self {
+            cores,
+            simd,
+            threads: cores,
+            block_size,
+            hash_bits,
+        }
+    }
+
+    /// Machine-independent profile matching the stock configuration
+    pub fn baseline() -> Self {
+        Self {
+            cores: 1,
+            simd: SimdFeatures::default(),
+            threads: 1,
+            block_size: DEFAULT_LZ4_BLOCK_SIZE,
+            hash_bits: DEFAULT_HASH_BITS,
+        }
+    }
+
+    /// Profile [`Compressor::new`] uses for `config`
+    pub(crate) fn for_config(config: &CompressionConfig) -> Self {
+        if config.deterministic {
+            Self::baseline()
+        } else {
+            Self::detect()
+        }
+    }
+
+    /// Fill the settings of `config` left at [`AUTO`], returning which
+    /// they were
+    pub(crate) fn apply_defaults(&self, config: &mut CompressionConfig) -> AutoSettings {
+        let auto = AutoSettings {
+            block_size: config.lz4_block_size == AUTO,
+            hash_bits: config.lzss.hash_bits == AUTO as u32,
+        };
+        self.fill(config, auto);
+        auto
+    }
+
+    fn fill(&self, config: &mut CompressionConfig, auto: AutoSettings) {
+        if auto.block_size {
+            config.lz4_block_size = self.block_size;
+        }
+        if auto.hash_bits {
+            config.lzss.hash_bits = self.hash_bits;
+        }
+    }
+}
+
+/// Settings a configuration left to the hardware profile
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) struct AutoSettings {
+    block_size: bool,
+    hash_bits: bool,
+}
+
+impl Compressor {
+    /// Hardware profile the defaults were derived from
+    pub fn profile(&self) -> &HardwareProfile {
+        &self.profile
+    }
+
+    /// Use `profile` instead of the detected one for the settings the
+    /// configuration left at [`AUTO`]
+    pub fn with_profile(mut self, profile: HardwareProfile) -> Self {
+        profile.fill(&mut self.config, self.auto);
+        self.profile = profile;
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::CompressionMethod;
+
+    #[test]
+    fn test_profile_scales_with_machine() {
+        let small = HardwareProfile::for_machine(1, SimdFeatures::default());
+        let desktop = HardwareProfile::for_machine(4, SimdFeatures { sse42: true, avx2: true, ..Default::default() });
+        let server = HardwareProfile::for_machine(32, SimdFeatures { sse42: true, avx2: true, avx512: true, neon: false });
+        assert!(small.block_size < desktop.block_size && desktop.block_size < server.block_size);
+        assert!(small.hash_bits < server.hash_bits);
+        assert_eq!((server.threads, HardwareProfile::for_machine(0, SimdFeatures::default()).threads), (32, 1));
+
+        let detected = HardwareProfile::detect();
+        assert!(detected.cores >= 1);
+        assert_eq!(Compressor::default().profile(), &detected);
+    }
+
+    #[test]
+    fn test_profile_applies_to_defaults_only() {
+        let server = HardwareProfile::for_machine(32, SimdFeatures { avx2: true, ..Default::default() });
+        let data = b"hardware profiles only change defaults, never the format ".repeat(3000);
+        let compressor = Compressor::default().with_profile(server);
+        assert_eq!(compressor.config.lz4_block_size, server.block_size);
+        for method in [CompressionMethod::Lz4Semantic, CompressionMethod::Lzss] {
+            let frame = compressor.compress(&data, method).unwrap().to_frame();
+            assert_eq!(Compressor::default().decompress_frame(&frame).unwrap(), data);
+        }
+
+        let mut config = CompressionConfig { lz4_block_size: 4096, ..CompressionConfig::default() };
+        server.apply_defaults(&mut config);
+        assert_eq!((config.lz4_block_size, config.lzss.hash_bits), (4096, server.hash_bits));
+
+        // Explicit values equal to the machine-independent ones are kept too
+        let stock = CompressionConfig {
+            lz4_block_size: DEFAULT_LZ4_BLOCK_SIZE,
+            lzss: crate::config::LzssConfig {
+                hash_bits: DEFAULT_HASH_BITS,
+                ..Default::default()
+            },
+            ..CompressionConfig::default()
+        };
+        let explicit = Compressor::new(stock).with_profile(server);
+        assert_eq!(explicit.config.lz4_block_size, DEFAULT_LZ4_BLOCK_SIZE);
+        assert_eq!(explicit.config.lzss.hash_bits, DEFAULT_HASH_BITS);
+        let pinned = Compressor::new(CompressionConfig { deterministic: true, ..CompressionConfig::default() });
+        assert_eq!(pinned.profile(), &HardwareProfile::baseline());
+        assert_eq!(pinned.config.lz4_block_size, DEFAULT_LZ4_BLOCK_SIZE);
+    }
+}
@@ -0,0 +1,180 @@
+//! Remembered outcomes of adaptive method selection
+//!
+//! [`Compressor::compress_adaptive`] normally compresses with every candidate
+//! method and keeps the cheapest. A [`MethodHistory`] attached with
+//! [`Compressor::with_history`] records which method won for each coarse
+//! content profile (entropy bucket, size class, detected kind), and later
+//! inputs with the same profile go straight to that method. Every
+//! [`REVALIDATE_EVERY`] uses of a remembered winner the trials run again, so
+//! the history follows content that drifts. Histories persist as JSON.
+
+use crate::error::CompressError;
+use crate::tuning::size_class;
+use crate::CompressionMethod;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+
+/// Uses of a remembered method after which it is put to trial again
+pub const REVALIDATE_EVERY: u64 = 64;
+
+/// Bytes inspected to detect the content kind
+const KIND_SAMPLE: usize = 4096;
+
+/// Broad kind of content, detected from a prefix of the input
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ContentKind {
+    /// Text starting with `{` or `[`
+    Structured,
+    /// Mostly printable ASCII
+    Text,
+    Binary,
+}
+
+impl ContentKind {
+    /// Detect the kind of `data`
+    pub fn of(data: &[u8]) -> Self {
+        let sample = &data[..data.len().min(KIND_SAMPLE)];
+        let printable = sample
+            .iter()
+            .filter(|&&b| b.is_ascii_graphic() || b.is_ascii_whitespace())
+            .count();
+        if sample.is_empty() || printable * 100 < sample.len() * 95 {
+            return ContentKind::Binary;
+        }
+        match sample.iter().find(|b| !b.is_ascii_whitespace()) {
+            Some(b'{') | Some(b'[') => ContentKind::Structured,
+            _ => ContentKind::Text,
+        }
+    }
+}
+
+/// Coarse profile inputs are grouped by
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProfileKey {
+    /// Order-0 entropy rounded down to whole bits per byte
+    pub entropy_bucket: u8,
+    /// See [`size_class`]
+    pub size_class: u32,
+    pub kind: ContentKind,
+}
+
+impl ProfileKey {
+    /// Key of `data`, whose order-0 entropy is `entropy`
+    pub fn of(data: &[u8], entropy: f64) -> Self {
+        Self {
+            entropy_bucket: entropy.clamp(0.0, 8.0) as u8,
+            size_class: size_class(data.len()),
+            kind: ContentKind::of(data),
+        }
+    }
+}
+
+/// Winning method remembered for one profile
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HistoryRecord {
+    pub key: ProfileKey,
+    pub method: CompressionMethod,
+    /// Trials this method has won for the profile
+    pub wins: u64,
+    /// Uses since the last trial
+    pub uses: u64,
+}
+
+/// Winning methods by content profile
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct MethodHistory {
+    pub records: Vec<HistoryRecord>,
+}
+
+impl MethodHistory {
+    /// Method to use for `key` without a trial, if one is remembered and
+    /// not due for revalidation
+    pub fn lookup(&mut self, key: &ProfileKey) -> Option<CompressionMethod> {
+        let record = self.records.iter_mut().find(|r| r.key == *key)?;
+        if record.uses >= REVALIDATE_EVERY {
+            return None;
+        }
+        record.uses += 1;
+        Some(record.method)
+    }
+
+    /// Record the outcome of a trial for `key`
+    pub fn record(&mut self, key: ProfileKey, method: CompressionMethod) {
+        match self.records.iter_mut().find(|r| r.key == key) {
+            Some(record) if record.method == method => {
+                record.wins += 1;
+                record.uses = 0;
+            }
+            Some(record) => {
+                record.method = method;
+                record.wins = 1;
+                record.uses = 0;
+            }
+            None => self.records.push(HistoryRecord {
+                key,
+                method,
+                wins: 1,
+                uses: 0,
+            }),
+        }
+    }
+
+    /// Write the history as JSON
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), CompressError> {
+        let raw = serde_json::to_vec_pretty(self).map_err(|e| CompressError::SerializationError(e.to_string()))?;
+        fs::write(path, raw)?;
+        Ok(())
+    }
+
+    /// Read a history written by [`MethodHistory::save`]
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, CompressError> {
+        let raw = fs::read(path)?;
+        serde_json::from_slice(&raw).map_err(|e| CompressError::SerializationError(e.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Compressor;
+
+    #[test]
+    fn test_content_kind() {
+        assert_eq!(ContentKind::of(b"  {\"a\": 1}"), ContentKind::Structured);
+        assert_eq!(ContentKind::of(b"plain words"), ContentKind::Text);
+        assert_eq!(ContentKind::of(&[0u8, 200, 3, 7]), ContentKind::Binary);
+    }
+
+    #[test]
+    fn test_history_skips_trials_and_revalidates() {
+        let compressor = Compressor::default().with_history(MethodHistory::default());
+        let data = b"adaptive history remembers the winner ".repeat(40);
+        let first = compressor.compress_adaptive(&data).unwrap();
+        let history = compressor.history().unwrap();
+        assert_eq!(history.records.len(), 1);
+        assert_eq!(history.records[0].method, first.method);
+
+        let again = compressor.compress_adaptive(&data).unwrap();
+        assert_eq!(again.method, first.method);
+        assert_eq!(compressor.history().unwrap().records[0].uses, 1);
+
+        let mut history = compressor.history().unwrap();
+        history.records[0].uses = REVALIDATE_EVERY;
+        let key = history.records[0].key;
+        assert_eq!(history.lookup(&key), None);
+        history.record(key, first.method);
+        assert_eq!(history.records[0].wins, 2);
+        assert_eq!(history.lookup(&key), Some(first.method));
+    }
+
+    #[test]
+    fn test_history_save_load() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("history.json");
+        let mut history = MethodHistory::default();
+        history.record(ProfileKey::of(b"text", 2.0), CompressionMethod::Huffman);
+        history.save(&path).unwrap();
+        assert_eq!(MethodHistory::load(&path).unwrap(), history);
+    }
+}
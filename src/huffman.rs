@@ -31,12 +31,15 @@ impl Ord for HuffNode {
     }
 }
 
-fn build_tree(data: &[u8]) -> Option<HuffNode> {
+pub(crate) fn byte_frequencies(data: &[u8]) -> [u64; 256] {
     let mut freq = [0u64; 256];
     for &b in data {
         freq[b as usize] += 1;
     }
+    freq
+}
 
+fn build_tree_from_frequencies(freq: &[u64; 256]) -> Option<HuffNode> {
     let mut heap = BinaryHeap::new();
     for (i, &f) in freq.iter().enumerate() {
         if f > 0 {
@@ -99,55 +102,11 @@ fn build_codes(node: &HuffNode, prefix: Vec<bool>, codes: &mut HashMap<u8, Vec<b
     }
 }
 
-/// Compress data using Huffman coding
-pub fn compress(data: &[u8]) -> Result<Vec<u8>, CompressError> {
-    let tree = build_tree(data).ok_or_else(|| CompressError::HuffmanError("empty tree".into()))?;
-    let mut codes = HashMap::new();
-    build_codes(&tree, vec![], &mut codes);
-
-    // Encode: [num_symbols:u16][symbol:u8,code_len:u8,code_bits...][data_bits...]
-    let mut output = Vec::new();
-    let num_symbols = codes.len() as u16;
-    output.extend_from_slice(&num_symbols.to_le_bytes());
-
-    // Write code table
-    for (&sym, code) in &codes {
-        output.push(sym);
-        output.push(code.len() as u8);
-        let mut byte = 0u8;
-        let mut bit_pos = 0;
-        for &bit in code {
-            if bit {
-                byte |= 1 << bit_pos;
-            }
-            bit_pos += 1;
-            if bit_pos == 8 {
-                output.push(byte);
-                byte = 0;
-                bit_pos = 0;
-            }
-        }
-        if bit_pos > 0 {
-            output.push(byte);
-        }
-    }
-
-    // Write data length
-    let data_len = data.len() as u32;
-    output.extend_from_slice(&data_len.to_le_bytes());
-
-    // Encode data
-    let mut bits = Vec::new();
-    for &b in data {
-        if let Some(code) = codes.get(&b) {
-            bits.extend_from_slice(code);
-        }
-    }
-
-    // Pack bits into bytes
+/// Pack bits LSB-first into bytes
+fn pack_bits(bits: &[bool], output: &mut Vec<u8>) {
     let mut byte = 0u8;
     let mut bit_pos = 0;
-    for &bit in &bits {
+    for &bit in bits {
         if bit {
             byte |= 1 << bit_pos;
         }
@@ -161,12 +120,109 @@ pub fn compress(data: &[u8]) -> Result<Vec<u8>, CompressError> {
     if bit_pos > 0 {
         output.push(byte);
     }
+}
 
-    Ok(output)
+/// A Huffman code derived from an explicit frequency table.
+///
+/// Unlike [`compress`], which builds and ships a table per call, a model is
+/// reconstructed independently by encoder and decoder from the same counts,
+/// so nothing but the coded bits needs to be transmitted.
+#[derive(Debug, Clone)]
+pub struct HuffmanModel {
+    codes: HashMap<u8, Vec<bool>>,
+    code_to_symbol: HashMap<Vec<bool>, u8>,
+}
+
+impl HuffmanModel {
+    /// Build a model; symbols with zero frequency cannot be encoded
+    pub fn from_frequencies(freq: &[u64; 256]) -> Option<Self> {
+        let tree = build_tree_from_frequencies(freq)?;
+        let mut codes = HashMap::new();
+        build_codes(&tree, vec![], &mut codes);
+        let code_to_symbol = codes.iter().map(|(&sym, code)| (code.clone(), sym)).collect();
+        Some(Self { codes, code_to_symbol })
+    }
+
+    /// Encode `data` into packed bits
+    pub fn encode(&self, data: &[u8]) -> Result<Vec<u8>, CompressError> {
+        let mut bits = Vec::new();
+        for &b in data {
+            let code = self
+                .codes
+                .get(&b)
+                .ok_or_else(|| CompressError::HuffmanError(format!("symbol {} not in model", b)))?;
+            bits.extend_from_slice(code);
+        }
+        let mut output = Vec::with_capacity(bits.len().div_ceil(8));
+        pack_bits(&bits, &mut output);
+        Ok(output)
+    }
+
+    /// Decode exactly `len` symbols from packed bits
+    pub fn decode(&self, data: &[u8], len: usize) -> Result<Vec<u8>, CompressError> {
+        let mut output = Vec::with_capacity(len.min(crate::MAX_PREALLOC));
+        let mut current_code = Vec::new();
+        'outer: for &byte in data {
+            for bit_idx in 0..8 {
+                if output.len() == len {
+                    break 'outer;
+                }
+                current_code.push((byte >> bit_idx) & 1 == 1);
+                if let Some(&sym) = self.code_to_symbol.get(&current_code) {
+                    output.push(sym);
+                    current_code.clear();
+                }
+            }
+        }
+        if output.len() != len {
+            return Err(CompressError::HuffmanError("truncated bitstream".into()));
+        }
+        Ok(output)
+    }
+}
+
+/// Bytes Huffman coding can save at best on input with histogram `freq`:
+/// the order-0 entropy bound, less an estimate of the code table. Costs
+/// one pass over the histogram, against building a tree for [`compress`].
+pub fn expected_gain(freq: &[u64; 256]) -> f64 {
+    let len: u64 = freq.iter().sum();
+    let symbols = freq.iter().filter(|&&f| f > 0).count();
+    let bound = len as f64 * (1.0 - crate::analysis::entropy_from_histogram(freq) / 8.0);
+    // Symbol, code length and about one byte of code per entry, plus the
+    // symbol count and data length
+    bound - (3 * symbols + 12) as f64
+}
+
+/// Compress data using Huffman coding
+pub fn compress(data: &[u8]) -> Result<Vec<u8>, CompressError> {
+    let mut encoder = StreamEncoder::new(&byte_frequencies(data), data.len())?;
+    encoder.push(data)?;
+    Ok(encoder.finish())
 }
 
 /// Decompress Huffman-encoded data
 pub fn decompress(data: &[u8], original_size: usize) -> Result<Vec<u8>, CompressError> {
+    let mut output = Vec::with_capacity(original_size.min(crate::MAX_PREALLOC));
+    for block in BlockDecoder::new(data, original_size, usize::MAX)? {
+        output.extend_from_slice(&block?);
+    }
+    Ok(output)
+}
+
+/// Bytes taken by the code table and length header at the start of `data`
+pub fn table_len(data: &[u8]) -> Result<usize, CompressError> {
+    Ok(parse_header(data)?.bits_start)
+}
+
+/// Code table and declared length at the start of a stream
+struct Header {
+    code_to_symbol: HashMap<Vec<bool>, u8>,
+    stored_len: u64,
+    /// Offset of the first coded byte
+    bits_start: usize,
+}
+
+fn parse_header(data: &[u8]) -> Result<Header, CompressError> {
     if data.len() < 2 {
         return Err(CompressError::HuffmanError("data too short".into()));
     }
@@ -178,7 +234,7 @@ pub fn decompress(data: &[u8], original_size: usize) -> Result<Vec<u8>, Compress
     // Read code table
     let mut code_to_symbol: HashMap<Vec<bool>, u8> = HashMap::new();
     for _ in 0..num_symbols {
-        if pos >= data.len() {
+        if pos + 2 > data.len() {
             return Err(CompressError::HuffmanError("truncated table".into()));
         }
         let sym = data[pos];
@@ -186,7 +242,7 @@ pub fn decompress(data: &[u8], original_size: usize) -> Result<Vec<u8>, Compress
         let code_len = data[pos] as usize;
         pos += 1;
 
-        let num_bytes = (code_len + 7) / 8;
+        let num_bytes = code_len.div_ceil(8);
         let mut code = Vec::with_capacity(code_len);
         for byte_idx in 0..num_bytes {
             if pos >= data.len() {
@@ -204,31 +260,162 @@ pub fn decompress(data: &[u8], original_size: usize) -> Result<Vec<u8>, Compress
         code_to_symbol.insert(code, sym);
     }
 
-    // Read original data length
-    if pos + 4 > data.len() {
-        return Err(CompressError::HuffmanError("missing data length".into()));
+    let stored_len = crate::varint::read_u64(data, &mut pos)
+        .ok_or_else(|| CompressError::HuffmanError("missing data length".into()))?;
+    Ok(Header {
+        code_to_symbol,
+        stored_len,
+        bits_start: pos,
+    })
+}
+
+/// Incremental encoder for input whose byte histogram is known up front
+/// (e.g. from a first pass), producing the same layout as [`compress`]
+pub struct StreamEncoder {
+    codes: HashMap<u8, Vec<bool>>,
+    output: Vec<u8>,
+    byte: u8,
+    bit_pos: u8,
+}
+
+impl StreamEncoder {
+    /// `freq` must cover every byte later pushed; `total_len` is the input length
+    pub fn new(freq: &[u64; 256], total_len: usize) -> Result<Self, CompressError> {
+        Self::with_output(freq, total_len, Vec::new())
     }
-    let _stored_len = u32::from_le_bytes([data[pos], data[pos + 1], data[pos + 2], data[pos + 3]]) as usize;
-    pos += 4;
 
-    // Decode bits
-    let mut output = Vec::with_capacity(original_size);
-    let mut current_code = Vec::new();
+    /// [`StreamEncoder::new`] writing into a reused buffer, which is cleared
+    pub(crate) fn with_output(freq: &[u64; 256], total_len: usize, mut output: Vec<u8>) -> Result<Self, CompressError> {
+        let tree = build_tree_from_frequencies(freq).ok_or_else(|| CompressError::HuffmanError("empty tree".into()))?;
+        let mut codes = HashMap::new();
+        build_codes(&tree, vec![], &mut codes);
 
-    'outer: for &byte in &data[pos..] {
-        for bit_idx in 0..8 {
-            current_code.push((byte >> bit_idx) & 1 == 1);
-            if let Some(&sym) = code_to_symbol.get(&current_code) {
-                output.push(sym);
-                current_code.clear();
-                if output.len() >= original_size {
-                    break 'outer;
+        // Encode: [num_symbols:u16][symbol:u8,code_len:u8,code_bits...][data_len:varint][data_bits...]
+        output.clear();
+        let num_symbols = codes.len() as u16;
+        output.extend_from_slice(&num_symbols.to_le_bytes());
+
+        // Write code table in symbol order so equal input gives equal bytes
+        let mut table: Vec<(&u8, &Vec<bool>)> = codes.iter().collect();
+        table.sort_unstable_by_key(|&(&sym, _)| sym);
+        for (&sym, code) in table {
+            output.push(sym);
+            output.push(code.len() as u8);
+            pack_bits(code, &mut output);
+        }
+
+        crate::varint::write_u64(&mut output, total_len as u64);
+
+        Ok(Self {
+            codes,
+            output,
+            byte: 0,
+            bit_pos: 0,
+        })
+    }
+
+    pub fn push(&mut self, data: &[u8]) -> Result<(), CompressError> {
+        for &b in data {
+            let code = self
+                .codes
+                .get(&b)
+                .ok_or_else(|| CompressError::HuffmanError(format!("symbol {} missing from histogram", b)))?;
+            for &bit in code {
+                if bit {
+                    self.byte |= 1 << self.bit_pos;
+                }
+                self.bit_pos += 1;
+                if self.bit_pos == 8 {
+                    self.output.push(self.byte);
+                    self.byte = 0;
+                    self.bit_pos = 0;
                 }
             }
         }
+        Ok(())
     }
 
-    Ok(output)
+    pub fn finish(mut self) -> Vec<u8> {
+        if self.bit_pos > 0 {
+            self.output.push(self.byte);
+        }
+        self.output
+    }
+}
+
+/// Decodes symbols in blocks of `block_len` bytes
+pub struct BlockDecoder<'a> {
+    code_to_symbol: HashMap<Vec<bool>, u8>,
+    data: &'a [u8],
+    byte_pos: usize,
+    bit_idx: u8,
+    remaining: usize,
+    block_len: usize,
+}
+
+impl<'a> BlockDecoder<'a> {
+    pub fn new(data: &'a [u8], original_size: usize, block_len: usize) -> Result<Self, CompressError> {
+        let header = parse_header(data)?;
+        if header.stored_len != original_size as u64 {
+            return Err(CompressError::HuffmanError(format!(
+                "stream declares {} bytes, expected {}",
+                header.stored_len, original_size
+            )));
+        }
+
+        Ok(Self {
+            code_to_symbol: header.code_to_symbol,
+            data,
+            byte_pos: header.bits_start,
+            bit_idx: 0,
+            remaining: original_size,
+            block_len: block_len.max(1),
+        })
+    }
+}
+
+impl Iterator for BlockDecoder<'_> {
+    type Item = Result<Vec<u8>, CompressError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 || self.byte_pos >= self.data.len() {
+            return None;
+        }
+        let target = self.block_len.min(self.remaining);
+        let mut output = Vec::with_capacity(target.min(crate::MAX_PREALLOC));
+        let mut current_code = Vec::new();
+
+        'outer: while self.byte_pos < self.data.len() {
+            let byte = self.data[self.byte_pos];
+            while self.bit_idx < 8 {
+                current_code.push((byte >> self.bit_idx) & 1 == 1);
+                self.bit_idx += 1;
+                if let Some(&sym) = self.code_to_symbol.get(&current_code) {
+                    output.push(sym);
+                    current_code.clear();
+                    if output.len() >= target {
+                        break 'outer;
+                    }
+                }
+            }
+            self.bit_idx = 0;
+            self.byte_pos += 1;
+        }
+        if self.bit_idx == 8 {
+            self.bit_idx = 0;
+            self.byte_pos += 1;
+        }
+
+        self.remaining -= output.len();
+        if output.len() < target {
+            // Bitstream exhausted
+            self.remaining = 0;
+        }
+        if output.is_empty() {
+            return None;
+        }
+        Some(Ok(output))
+    }
 }
 
 #[cfg(test)]
@@ -259,10 +446,71 @@ mod tests {
         assert_eq!(decompressed, data);
     }
 
+    #[test]
+    fn test_block_decoder_streams() {
+        let data = "streaming huffman blocks ".repeat(40);
+        let compressed = compress(data.as_bytes()).unwrap();
+        let blocks: Vec<Vec<u8>> = BlockDecoder::new(&compressed, data.len(), 100)
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(blocks.len(), 10);
+        assert_eq!(blocks.concat(), data.as_bytes());
+    }
+
+    #[test]
+    fn test_stream_encoder_in_pieces() {
+        let data = b"abracadabra abracadabra";
+        let mut freq = [0u64; 256];
+        for &b in data.iter() {
+            freq[b as usize] += 1;
+        }
+        let mut encoder = StreamEncoder::new(&freq, data.len()).unwrap();
+        for piece in data.chunks(5) {
+            encoder.push(piece).unwrap();
+        }
+        let encoded = encoder.finish();
+        assert_eq!(decompress(&encoded, data.len()).unwrap(), data);
+    }
+
+    #[test]
+    fn test_model_roundtrip() {
+        let mut freq = [1u64; 256];
+        freq[b'a' as usize] = 1000;
+        let model = HuffmanModel::from_frequencies(&freq).unwrap();
+        let data = b"aaaaabaaaacaaaa\x00\xff";
+        let bits = model.encode(data).unwrap();
+        assert!(bits.len() < data.len());
+        assert_eq!(model.decode(&bits, data.len()).unwrap(), data);
+        // A length read from a hostile message must not size the buffer
+        assert!(model.decode(&bits, usize::MAX >> 3).is_err());
+    }
+
+    #[test]
+    fn test_model_rejects_unknown_symbol() {
+        let mut freq = [0u64; 256];
+        freq[b'x' as usize] = 1;
+        let model = HuffmanModel::from_frequencies(&freq).unwrap();
+        assert!(model.encode(b"y").is_err());
+    }
+
     #[test]
     fn test_huffman_compression_ratio() {
         let data = "aaabbbccc".repeat(100);
         let compressed = compress(data.as_bytes()).unwrap();
         assert!(compressed.len() < data.len());
     }
+
+    #[cfg(target_pointer_width = "64")]
+    #[test]
+    fn test_header_carries_64bit_length() {
+        let freq = byte_frequencies(b"ab");
+        let len = u32::MAX as usize + 7;
+        let header = StreamEncoder::new(&freq, len).unwrap().finish();
+        let parsed = parse_header(&header).unwrap();
+        assert_eq!(parsed.code_to_symbol.len(), 2);
+        assert_eq!(parsed.stored_len, len as u64);
+        assert_eq!(parsed.bits_start, header.len());
+        assert!(BlockDecoder::new(&header, len - 1, 1).is_err());
+    }
 }
@@ -0,0 +1,381 @@
+//! Incremental compression of files that grow or change in place
+//!
+//! [`IncrementalCompressor`] remembers the content-defined chunk hashes of
+//! every file it has seen. On the next [`IncrementalCompressor::update`] the
+//! file is re-chunked and only chunks missing from the previous version are
+//! compressed; everything else becomes a copy from the previous version. The
+//! result is a [`Delta`] that turns the previous contents into the new ones.
+//!
+//! Serialized delta layout:
+//! ```text
+//! [magic:"SGMD"][version:u8][base_size:varint][target_size:varint][checksum:u32]
+//! [op_count:varint] op...  [literal frame]
+//! op: [0][offset:varint][len:varint]   copy from the previous version
+//!     [1][len:varint]                  next bytes of the literal stream
+//! ```
+//! The literal stream is every new byte, in order, compressed as one frame;
+//! it is omitted when nothing changed.
+
+use crate::config::CompressionConfig;
+use crate::error::CompressError;
+use crate::store::BlockHash;
+use crate::{chunker, varint, CompressionMethod, Compressor};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::Path;
+
+/// Magic bytes opening a serialized delta
+pub const DELTA_MAGIC: [u8; 4] = *b"SGMD";
+
+/// Delta format version written by this build
+pub const DELTA_VERSION: u8 = 1;
+
+const OP_COPY: u8 = 0;
+const OP_LITERAL: u8 = 1;
+
+fn err(msg: impl Into<String>) -> CompressError {
+    CompressError::IncrementalError(msg.into())
+}
+
+/// One step of rebuilding the new version
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeltaOp {
+    /// Copy `len` bytes at `offset` of the previous version
+    Copy { offset: u64, len: u64 },
+    /// Take the next `len` bytes of the literal stream
+    Literal { len: u64 },
+}
+
+/// Changes turning one version of a file into the next
+#[derive(Debug, Clone, PartialEq)]
+pub struct Delta {
+    pub base_size: u64,
+    pub target_size: u64,
+    /// CRC-32 of the new version
+    pub checksum: u32,
+    pub ops: Vec<DeltaOp>,
+    /// Frame holding the concatenated literal bytes; empty if there are none
+    pub literals: Vec<u8>,
+}
+
+impl Delta {
+    /// Bytes taken from the previous version
+    pub fn reused_bytes(&self) -> u64 {
+        self.ops
+            .iter()
+            .map(|op| match op {
+                DeltaOp::Copy { len, .. } => *len,
+                DeltaOp::Literal { .. } => 0,
+            })
+            .sum()
+    }
+
+    /// Serialize into the layout described in the module docs
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(32 + self.ops.len() * 4 + self.literals.len());
+        out.extend_from_slice(&DELTA_MAGIC);
+        out.push(DELTA_VERSION);
+        varint::write_u64(&mut out, self.base_size);
+        varint::write_u64(&mut out, self.target_size);
+        out.extend_from_slice(&self.checksum.to_le_bytes());
+        varint::write_u64(&mut out, self.ops.len() as u64);
+        for op in &self.ops {
+            match *op {
+                DeltaOp::Copy { offset, len } => {
+                    out.push(OP_COPY);
+                    varint::write_u64(&mut out, offset);
+                    varint::write_u64(&mut out, len);
+                }
+                DeltaOp::Literal { len } => {
+                    out.push(OP_LITERAL);
+                    varint::write_u64(&mut out, len);
+                }
+            }
+        }
+        out.extend_from_slice(&self.literals);
+        out
+    }
+
+    /// Parse a delta written by [`Delta::to_bytes`]
+    pub fn from_bytes(data: &[u8]) -> Result<Self, CompressError> {
+        if data.len() < 5 || data[..4] != DELTA_MAGIC {
+            return Err(err("bad magic"));
+        }
+        if data[4] != DELTA_VERSION {
+            return Err(err(format!("unsupported delta version {}", data[4])));
+        }
+        let mut pos = 5;
+        let read = |pos: &mut usize| varint::read_u64(data, pos).ok_or_else(|| err("truncated header"));
+        let base_size = read(&mut pos)?;
+        let target_size = read(&mut pos)?;
+        let checksum = data.get(pos..pos + 4).ok_or_else(|| err("truncated header"))?;
+        let checksum = u32::from_le_bytes(checksum.try_into().unwrap());
+        pos += 4;
+        let count = read(&mut pos)? as usize;
+        let mut ops = Vec::with_capacity(count.min(data.len()));
+        for _ in 0..count {
+            let tag = *data.get(pos).ok_or_else(|| err("truncated op"))?;
+            pos += 1;
+            ops.push(match tag {
+                OP_COPY => DeltaOp::Copy {
+                    offset: read(&mut pos)?,
+                    len: read(&mut pos)?,
+                },
+                OP_LITERAL => DeltaOp::Literal { len: read(&mut pos)? },
+                other => return Err(err(format!("unknown op {}", other))),
+            });
+        }
+        Ok(Self {
+            base_size,
+            target_size,
+            checksum,
+            ops,
+            literals: data[pos..].to_vec(),
+        })
+    }
+
+    /// Rebuild the new version from the previous one
+    pub fn apply(&self, compressor: &Compressor, base: &[u8]) -> Result<Vec<u8>, CompressError> {
+        if base.len() as u64 != self.base_size {
+            return Err(CompressError::SizeMismatch {
+                expected: self.base_size as usize,
+                actual: base.len(),
+            });
+        }
+        let literals = if self.literals.is_empty() {
+            Vec::new()
+        } else {
+            compressor.decompress_frame(&self.literals)?
+        };
+        let mut literal_pos = 0usize;
+        let mut out = Vec::with_capacity((self.target_size as usize).min(crate::MAX_PREALLOC));
+        for op in &self.ops {
+            let len = match *op {
+                DeltaOp::Copy { len, .. } | DeltaOp::Literal { len } => len,
+            };
+            if (out.len() as u64).saturating_add(len) > self.target_size {
+                return Err(err("ops exceed the new version's size"));
+            }
+            match *op {
+                DeltaOp::Copy { offset, len } => {
+                    let range = usize::try_from(offset)
+                        .ok()
+                        .zip(usize::try_from(len).ok())
+                        .and_then(|(start, len)| base.get(start..start.checked_add(len)?))
+                        .ok_or_else(|| err("copy outside the previous version"))?;
+                    out.extend_from_slice(range);
+                }
+                DeltaOp::Literal { len } => {
+                    let end = usize::try_from(len)
+                        .ok()
+                        .and_then(|len| literal_pos.checked_add(len))
+                        .filter(|&end| end <= literals.len())
+                        .ok_or_else(|| err("literal stream exhausted"))?;
+                    out.extend_from_slice(&literals[literal_pos..end]);
+                    literal_pos = end;
+                }
+            }
+        }
+        if out.len() as u64 != self.target_size {
+            return Err(CompressError::SizeMismatch {
+                expected: self.target_size as usize,
+                actual: out.len(),
+            });
+        }
+        let actual = crc32fast::hash(&out);
+        if actual != self.checksum {
+            return Err(CompressError::ChecksumMismatch {
+                expected: self.checksum,
+                actual,
+            });
+        }
+        Ok(out)
+    }
+}
+
+/// A chunk of a previously seen version
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ChunkRecord {
+    pub hash: BlockHash,
+    pub len: u64,
+}
+
+/// Chunk lists of every tracked file, keyed by path
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct IncrementalState {
+    pub files: BTreeMap<String, Vec<ChunkRecord>>,
+}
+
+impl IncrementalState {
+    /// Write the state as JSON
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), CompressError> {
+        let raw = serde_json::to_vec_pretty(self).map_err(|e| CompressError::SerializationError(e.to_string()))?;
+        fs::write(path, raw)?;
+        Ok(())
+    }
+
+    /// Read a state written by [`IncrementalState::save`]
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, CompressError> {
+        let raw = fs::read(path)?;
+        serde_json::from_slice(&raw).map_err(|e| CompressError::SerializationError(e.to_string()))
+    }
+}
+
+/// Produces deltas against the last version of each file it was given
+pub struct IncrementalCompressor {
+    compressor: Compressor,
+    method: CompressionMethod,
+    state: IncrementalState,
+}
+
+impl IncrementalCompressor {
+    /// Compress new regions with `method` under `config`
+    pub fn new(config: CompressionConfig, method: CompressionMethod) -> Self {
+        Self {
+            compressor: Compressor::new(config),
+            method,
+            state: IncrementalState::default(),
+        }
+    }
+
+    /// Resume from previously saved chunk lists
+    pub fn with_state(mut self, state: IncrementalState) -> Self {
+        self.state = state;
+        self
+    }
+
+    pub fn state(&self) -> &IncrementalState {
+        &self.state
+    }
+
+    /// Read the file at `path` and diff it against its last version
+    pub fn update(&mut self, path: impl AsRef<Path>) -> Result<Delta, CompressError> {
+        let path = path.as_ref();
+        let data = fs::read(path)?;
+        self.update_bytes(&path.to_string_lossy(), &data)
+    }
+
+    /// Diff `data` against the last version recorded under `key`. The first
+    /// call for a key has nothing to reuse and compresses everything.
+    pub fn update_bytes(&mut self, key: &str, data: &[u8]) -> Result<Delta, CompressError> {
+        let previous = self.state.files.get(key).map(Vec::as_slice).unwrap_or_default();
+        let mut known: HashMap<BlockHash, u64> = HashMap::with_capacity(previous.len());
+        let mut offset = 0u64;
+        for chunk in previous {
+            known.entry(chunk.hash).or_insert(offset);
+            offset += chunk.len;
+        }
+        let base_size = offset;
+
+        let mut ops: Vec<DeltaOp> = Vec::new();
+        let mut literals = Vec::new();
+        let mut records = Vec::new();
+        for piece in chunker::chunk(data) {
+            let hash = BlockHash::of(piece);
+            let len = piece.len() as u64;
+            records.push(ChunkRecord { hash, len });
+            let op = match known.get(&hash) {
+                Some(&offset) => DeltaOp::Copy { offset, len },
+                None => {
+                    literals.extend_from_slice(piece);
+                    DeltaOp::Literal { len }
+                }
+            };
+            // Merge with the previous op when contiguous
+            match (ops.last_mut(), op) {
+                (Some(DeltaOp::Copy { offset: o, len: l }), DeltaOp::Copy { offset, len }) if *o + *l == offset => {
+                    *l += len;
+                }
+                (Some(DeltaOp::Literal { len: l }), DeltaOp::Literal { len }) => *l += len,
+                _ => ops.push(op),
+            }
+        }
+
+        let delta = Delta {
+            base_size,
+            target_size: data.len() as u64,
+            checksum: crc32fast::hash(data),
+            ops,
+            literals: if literals.is_empty() {
+                Vec::new()
+            } else {
+                self.compressor.compress_chunked(&literals, self.method)?
+            },
+        };
+        self.state.files.insert(key.to_string(), records);
+        Ok(delta)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn log_lines(range: std::ops::Range<u32>) -> Vec<u8> {
+        range
+            .map(|i| format!("2024-01-01T00:00:{:05} INFO request {} served in {}ms\n", i, i * 7, i % 13))
+            .collect::<String>()
+            .into_bytes()
+    }
+
+    #[test]
+    fn test_appended_file_reuses_prefix() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("app.log");
+        let v1 = log_lines(0..2000);
+        fs::write(&path, &v1).unwrap();
+        let mut inc = IncrementalCompressor::new(CompressionConfig::default(), CompressionMethod::Auto);
+        let first = inc.update(&path).unwrap();
+        assert_eq!(first.reused_bytes(), 0);
+        let compressor = Compressor::default();
+        assert_eq!(first.apply(&compressor, &[]).unwrap(), v1);
+
+        let mut v2 = v1.clone();
+        v2.extend_from_slice(&log_lines(2000..2100));
+        fs::write(&path, &v2).unwrap();
+        let second = inc.update(&path).unwrap();
+        assert!(second.reused_bytes() > v1.len() as u64 / 2);
+        let parsed = Delta::from_bytes(&second.to_bytes()).unwrap();
+        assert_eq!(parsed, second);
+        assert_eq!(parsed.apply(&compressor, &v1).unwrap(), v2);
+        assert!(second.to_bytes().len() < first.to_bytes().len() / 2);
+    }
+
+    #[test]
+    fn test_changed_middle_and_wrong_base() {
+        let mut inc = IncrementalCompressor::new(CompressionConfig::default(), CompressionMethod::Huffman);
+        let v1 = log_lines(0..3000);
+        inc.update_bytes("f", &v1).unwrap();
+        let mut v2 = v1.clone();
+        v2[v1.len() / 2..v1.len() / 2 + 6].copy_from_slice(b"EDITED");
+        let delta = inc.update_bytes("f", &v2).unwrap();
+        let compressor = Compressor::default();
+        assert!(delta.reused_bytes() > 0);
+        assert_eq!(delta.apply(&compressor, &v1).unwrap(), v2);
+        assert!(delta.apply(&compressor, &v2[1..]).is_err());
+
+        // Copies past the declared size fail before they are appended
+        let bomb = Delta {
+            ops: vec![DeltaOp::Copy { offset: 0, len: v1.len() as u64 }; 1 << 20],
+            ..delta
+        };
+        assert!(bomb.apply(&compressor, &v1).is_err());
+    }
+
+    #[test]
+    fn test_state_persists() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut inc = IncrementalCompressor::new(CompressionConfig::default(), CompressionMethod::Auto);
+        let v1 = log_lines(0..500);
+        inc.update_bytes("f", &v1).unwrap();
+        let path = dir.path().join("state.json");
+        inc.state().save(&path).unwrap();
+
+        let mut resumed = IncrementalCompressor::new(CompressionConfig::default(), CompressionMethod::Auto)
+            .with_state(IncrementalState::load(&path).unwrap());
+        let delta = resumed.update_bytes("f", &v1).unwrap();
+        assert_eq!(delta.reused_bytes(), v1.len() as u64);
+        assert_eq!(delta.ops.len(), 1);
+    }
+}
@@ -0,0 +1,184 @@
+//! Compression of lazily produced input
+//!
+//! Data serialized on the fly or produced by a generator would otherwise be
+//! collected into a `Vec` just to be compressed. [`Compressor::compress_from_iter`]
+//! takes bytes one at a time and [`Compressor::compress_chunks`] any
+//! iterator of buffers; both hand the codec [`CHUNK_SIZE`] pieces as the
+//! input is produced, so only the codec's own state grows with it. The
+//! `Lzss` and `LogDedupe` encoders buffer their whole input as that state,
+//! so with them memory still grows with the input.
+//!
+//! The first chunk is held back to decide how to code: methods that must
+//! see the whole input (`Auto`, `Huffman`, dictionary coding) and input that
+//! is itself a frame under a non-`Warn` [`NestedFramePolicy`] are gathered
+//! and compressed at the end. Either way the output is the same as
+//! compressing the collected input.
+//!
+//! [`NestedFramePolicy`]: crate::config::NestedFramePolicy
+
+use crate::config::NestedFramePolicy;
+use crate::dictionary::DictionarySelection;
+use crate::error::CompressError;
+use crate::{analysis, codec_stream, frame, CompressedOutput, CompressionMethod, Compressor};
+
+/// Input handed to the codec at a time
+pub const CHUNK_SIZE: usize = 64 * 1024;
+
+struct ChunkEncoder<'a> {
+    compressor: &'a Compressor,
+    method: CompressionMethod,
+    /// Input not yet given to the codec: the first chunk, or everything
+    /// when the input is coded whole
+    pending: Vec<u8>,
+    encoder: Option<codec_stream::StreamEncoder>,
+    whole: bool,
+    nested: bool,
+    crc: crc32fast::Hasher,
+    histogram: [u64; 256],
+    len: usize,
+}
+
+impl<'a> ChunkEncoder<'a> {
+    fn new(compressor: &'a Compressor, method: CompressionMethod) -> Self {
+        Self {
+            compressor,
+            method,
+            pending: Vec::new(),
+            encoder: None,
+            whole: false,
+            nested: false,
+            crc: crc32fast::Hasher::new(),
+            histogram: [0; 256],
+            len: 0,
+        }
+    }
+
+    fn push(&mut self, data: &[u8]) -> Result<(), CompressError> {
+        let config = &self.compressor.config;
+        let len = self.len + data.len();
+        if len > config.max_input_size {
+            return Err(CompressError::InputTooLarge {
+                size: len,
+                limit: config.max_input_size,
+            });
+        }
+        self.len = len;
+        self.crc.update(data);
+        for &b in data {
+            self.histogram[b as usize] += 1;
+        }
+        match &mut self.encoder {
+            Some(encoder) => encoder.push(data),
+            None => {
+                self.pending.extend_from_slice(data);
+                if self.whole || self.pending.len() < CHUNK_SIZE {
+                    return Ok(());
+                }
+                self.nested = frame::is_frame(&self.pending);
+                self.whole = self.method == CompressionMethod::Auto
+                    || codec_stream::needs_histogram(self.method)
+                    || config.dictionary != DictionarySelection::None
+                    || (self.nested && config.nested_frames != NestedFramePolicy::Warn);
+                if self.whole {
+                    return Ok(());
+                }
+                let mut encoder = codec_stream::StreamEncoder::new(self.method, config, None, 0)?;
+                encoder.push(&std::mem::take(&mut self.pending))?;
+                self.encoder = Some(encoder);
+                Ok(())
+            }
+        }
+    }
+
+    fn finish(self) -> Result<CompressedOutput, CompressError> {
+        let Some(encoder) = self.encoder else {
+            return self.compressor.compress(&self.pending, self.method);
+        };
+        let mut output = self.compressor.finish_output(
+            self.method,
+            self.len,
+            encoder.finish()?,
+            analysis::entropy_from_histogram(&self.histogram),
+            Some(self.crc.finalize()),
+            None,
+        )?;
+        output.metadata.block_count = (self.len / self.compressor.config.lz4_block_size).max(1);
+        output.metadata.nested_frame = self.nested;
+        Ok(output)
+    }
+}
+
+impl Compressor {
+    /// Compress bytes as an iterator yields them (see [`iter`](crate::iter))
+    pub fn compress_from_iter(
+        &self,
+        bytes: impl IntoIterator<Item = u8>,
+        method: CompressionMethod,
+    ) -> Result<CompressedOutput, CompressError> {
+        let mut encoder = ChunkEncoder::new(self, method);
+        let mut chunk = Vec::with_capacity(CHUNK_SIZE);
+        for byte in bytes {
+            chunk.push(byte);
+            if chunk.len() == CHUNK_SIZE {
+                encoder.push(&chunk)?;
+                chunk.clear();
+            }
+        }
+        encoder.push(&chunk)?;
+        encoder.finish()
+    }
+
+    /// Compress the concatenation of buffers as an iterator yields them
+    pub fn compress_chunks<I>(&self, chunks: I, method: CompressionMethod) -> Result<CompressedOutput, CompressError>
+    where
+        I: IntoIterator,
+        I::Item: AsRef<[u8]>,
+    {
+        let mut encoder = ChunkEncoder::new(self, method);
+        for chunk in chunks {
+            encoder.push(chunk.as_ref())?;
+        }
+        encoder.finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::CompressionConfig;
+
+    fn records() -> impl Iterator<Item = String> {
+        (0..6000).map(|i| format!("{{\"seq\":{},\"kind\":\"tick\",\"value\":{}}}\n", i, i * 31 % 1000))
+    }
+
+    #[test]
+    fn test_iter_matches_collected_input() {
+        let compressor = Compressor::default();
+        let collected: Vec<u8> = records().flat_map(String::into_bytes).collect();
+        assert!(collected.len() > 2 * CHUNK_SIZE);
+        for method in [CompressionMethod::Lz4Semantic, CompressionMethod::Lzss, CompressionMethod::Huffman, CompressionMethod::Auto] {
+            let expected = compressor.compress(&collected, method).unwrap().to_frame();
+            let chunks = compressor.compress_chunks(records(), method).unwrap();
+            let bytes = compressor.compress_from_iter(records().flat_map(String::into_bytes), method).unwrap();
+            assert_eq!(chunks.to_frame(), expected, "{:?}", method);
+            assert_eq!(bytes.to_frame(), expected, "{:?}", method);
+        }
+        let small = compressor.compress_from_iter(b"short".iter().copied(), CompressionMethod::Stored).unwrap();
+        assert_eq!(compressor.decompress(&small).unwrap(), b"short");
+    }
+
+    #[test]
+    fn test_iter_limits_and_empty_input() {
+        let compressor = Compressor::default();
+        assert!(matches!(
+            compressor.compress_chunks(Vec::<Vec<u8>>::new(), CompressionMethod::Lzss),
+            Err(CompressError::EmptyInput)
+        ));
+        let limited = Compressor::new(CompressionConfig { max_input_size: 1000, ..CompressionConfig::default() });
+        let endless = std::iter::repeat(b'x');
+        assert!(matches!(
+            limited.compress_from_iter(endless, CompressionMethod::Lz4Semantic),
+            Err(CompressError::InputTooLarge { .. })
+        ));
+    }
+}
@@ -0,0 +1,270 @@
+//! Sorted key streams: front coding plus suffix compression
+//!
+//! Sorted index keys share long prefixes with their predecessor, which a
+//! general-purpose codec only partly exploits. [`compress_sorted_keys`]
+//! stores each key as the length of the prefix it shares with the previous
+//! key plus the remaining suffix. Lengths and suffix bytes go to separate
+//! streams, each compressed on its own, so the suffix coder sees only key
+//! bytes. Every [`RESTART_INTERVAL`] keys a key is stored whole, and the
+//! stream offsets of those restart points let [`SortedKeys::seek`]
+//! binary-search without decoding every key in front of the target.
+//!
+//! Layout:
+//!
+//! ```text
+//! [SGKY][version u8][count varint][lengths frame len varint]
+//! [frame of the length stream][frame of the suffix stream]
+//! length stream: ([shared varint][suffix_len varint])*
+//!                ([length offset u32 LE][suffix offset u32 LE])*
+//!                [restart count u32 LE]
+//! ```
+
+use crate::error::CompressError;
+use crate::{varint, Compressor};
+
+/// Magic bytes opening a sorted key stream
+pub const KEYS_MAGIC: [u8; 4] = *b"SGKY";
+
+/// Sorted key stream format version
+pub const KEYS_VERSION: u8 = 1;
+
+/// Keys between restart points
+pub const RESTART_INTERVAL: usize = 16;
+
+/// Front-code and compress `keys`, which must be in ascending order
+pub fn compress_sorted_keys<K: AsRef<[u8]>>(compressor: &Compressor, keys: &[K]) -> Result<Vec<u8>, CompressError> {
+    let mut lengths = Vec::new();
+    let mut suffixes = Vec::new();
+    let mut restarts = Vec::new();
+    let mut previous: &[u8] = &[];
+    for (i, key) in keys.iter().enumerate() {
+        let key = key.as_ref();
+        if i > 0 && key < previous {
+            return Err(CompressError::KeysError(format!("key {} is out of order", i)));
+        }
+        let shared = if i % RESTART_INTERVAL == 0 {
+            restarts.push((lengths.len() as u32, suffixes.len() as u32));
+            0
+        } else {
+            key.iter().zip(previous).take_while(|(a, b)| a == b).count()
+        };
+        varint::write_u64(&mut lengths, shared as u64);
+        varint::write_u64(&mut lengths, (key.len() - shared) as u64);
+        suffixes.extend_from_slice(&key[shared..]);
+        previous = key;
+    }
+    for (length_offset, suffix_offset) in &restarts {
+        lengths.extend_from_slice(&length_offset.to_le_bytes());
+        lengths.extend_from_slice(&suffix_offset.to_le_bytes());
+    }
+    lengths.extend_from_slice(&(restarts.len() as u32).to_le_bytes());
+
+    let lengths = compressor.compress_adaptive(&lengths)?.to_frame();
+    let mut output = Vec::with_capacity(lengths.len() + suffixes.len() / 2 + 16);
+    output.extend_from_slice(&KEYS_MAGIC);
+    output.push(KEYS_VERSION);
+    varint::write_u64(&mut output, keys.len() as u64);
+    varint::write_u64(&mut output, lengths.len() as u64);
+    output.extend_from_slice(&lengths);
+    if suffixes.is_empty() {
+        // Every key is empty; frames cannot hold empty input
+        return Ok(output);
+    }
+    output.extend_from_slice(&compressor.compress_adaptive(&suffixes)?.to_frame());
+    Ok(output)
+}
+
+/// Decoded view of a sorted key stream
+pub struct SortedKeys {
+    lengths: Vec<u8>,
+    suffixes: Vec<u8>,
+    /// End of the length entries, where the restart table begins
+    lengths_end: usize,
+    restarts: Vec<(usize, usize)>,
+    len: usize,
+}
+
+impl SortedKeys {
+    /// Unpack a stream written by [`compress_sorted_keys`]
+    pub fn from_bytes(compressor: &Compressor, data: &[u8]) -> Result<Self, CompressError> {
+        if data.len() < 5 || data[..4] != KEYS_MAGIC {
+            return Err(CompressError::KeysError("not a sorted key stream".into()));
+        }
+        if data[4] != KEYS_VERSION {
+            return Err(CompressError::KeysError(format!("unsupported version {}", data[4])));
+        }
+        let truncated = || CompressError::KeysError("truncated header".into());
+        let mut pos = 5;
+        let len = varint::read_usize(data, &mut pos).ok_or_else(truncated)?;
+        let lengths_len = varint::read_usize(data, &mut pos).ok_or_else(truncated)?;
+        let lengths_end = pos.checked_add(lengths_len).filter(|&end| end <= data.len()).ok_or_else(truncated)?;
+        let lengths = compressor.decompress_frame(&data[pos..lengths_end])?;
+        let suffixes = if lengths_end == data.len() {
+            Vec::new()
+        } else {
+            compressor.decompress_frame(&data[lengths_end..])?
+        };
+
+        let corrupt = || CompressError::KeysError("corrupt restart table".into());
+        let count_at = lengths.len().checked_sub(4).ok_or_else(corrupt)?;
+        let count = u32::from_le_bytes(lengths[count_at..].try_into().unwrap()) as usize;
+        let entries_end = count
+            .checked_mul(8)
+            .and_then(|table| count_at.checked_sub(table))
+            .ok_or_else(corrupt)?;
+        let restarts = lengths[entries_end..count_at]
+            .chunks_exact(8)
+            .map(|c| {
+                (
+                    u32::from_le_bytes(c[..4].try_into().unwrap()) as usize,
+                    u32::from_le_bytes(c[4..].try_into().unwrap()) as usize,
+                )
+            })
+            .collect::<Vec<_>>();
+        if count != len.div_ceil(RESTART_INTERVAL)
+            || restarts.iter().any(|&(l, s)| l > entries_end || s > suffixes.len())
+        {
+            return Err(corrupt());
+        }
+        Ok(Self {
+            lengths,
+            suffixes,
+            lengths_end: entries_end,
+            restarts,
+            len,
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Every key, in order
+    pub fn iter(&self) -> KeyIter<'_> {
+        self.iter_from_restart(0)
+    }
+
+    /// Keys from the first one not less than `target`, in order
+    pub fn seek(&self, target: &[u8]) -> KeyIter<'_> {
+        // Last restart whose key is <= target; restart keys are stored whole
+        let (mut low, mut high) = (0, self.restarts.len());
+        while low < high {
+            let mid = (low + high) / 2;
+            match self.iter_from_restart(mid).next() {
+                Some(Ok(key)) if key.as_slice() <= target => low = mid + 1,
+                _ => high = mid,
+            }
+        }
+        let restart = low.saturating_sub(1);
+        let mut iter = self.iter_from_restart(restart);
+        loop {
+            let before = iter.clone();
+            match iter.next() {
+                Some(Ok(key)) if key.as_slice() < target => continue,
+                Some(_) => return before,
+                None => return iter,
+            }
+        }
+    }
+
+    fn iter_from_restart(&self, restart: usize) -> KeyIter<'_> {
+        let (length_pos, suffix_pos) = self
+            .restarts
+            .get(restart)
+            .copied()
+            .unwrap_or((self.lengths_end, self.suffixes.len()));
+        KeyIter {
+            lengths: &self.lengths[..self.lengths_end],
+            suffixes: &self.suffixes,
+            length_pos,
+            suffix_pos,
+            remaining: self.len.saturating_sub(restart * RESTART_INTERVAL),
+            key: Vec::new(),
+        }
+    }
+}
+
+/// Iterator over keys of a [`SortedKeys`]
+#[derive(Clone)]
+pub struct KeyIter<'a> {
+    lengths: &'a [u8],
+    suffixes: &'a [u8],
+    length_pos: usize,
+    suffix_pos: usize,
+    remaining: usize,
+    key: Vec<u8>,
+}
+
+impl Iterator for KeyIter<'_> {
+    type Item = Result<Vec<u8>, CompressError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+        self.remaining -= 1;
+        let corrupt = || CompressError::KeysError("truncated entry".into());
+        let entry = (|| {
+            let shared = varint::read_usize(self.lengths, &mut self.length_pos).ok_or_else(corrupt)?;
+            let suffix_len = varint::read_usize(self.lengths, &mut self.length_pos).ok_or_else(corrupt)?;
+            if shared > self.key.len() {
+                return Err(CompressError::KeysError("shared prefix longer than previous key".into()));
+            }
+            let end = self.suffix_pos.checked_add(suffix_len).ok_or_else(corrupt)?;
+            let suffix = self.suffixes.get(self.suffix_pos..end).ok_or_else(corrupt)?;
+            self.key.truncate(shared);
+            self.key.extend_from_slice(suffix);
+            self.suffix_pos = end;
+            Ok(self.key.clone())
+        })();
+        if entry.is_err() {
+            self.remaining = 0;
+        }
+        Some(entry)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::CompressionMethod;
+
+    fn index_keys() -> Vec<Vec<u8>> {
+        let mut keys: Vec<Vec<u8>> = (0..500)
+            .map(|i| format!("tenant/{:03}/document/{:06}", i % 7, i * 37).into_bytes())
+            .collect();
+        keys.sort();
+        keys
+    }
+
+    #[test]
+    fn test_keys_roundtrip_and_ratio() {
+        let compressor = Compressor::default();
+        let keys = index_keys();
+        let packed = compress_sorted_keys(&compressor, &keys).unwrap();
+        let generic = compressor.compress(&keys.concat(), CompressionMethod::Auto).unwrap();
+        assert!(packed.len() < generic.total_encoded_size());
+
+        let decoded = SortedKeys::from_bytes(&compressor, &packed).unwrap();
+        assert_eq!(decoded.len(), keys.len());
+        let all: Vec<Vec<u8>> = decoded.iter().collect::<Result<_, _>>().unwrap();
+        assert_eq!(all, keys);
+        assert!(compress_sorted_keys(&compressor, &[b"b".to_vec(), b"a".to_vec()]).is_err());
+    }
+
+    #[test]
+    fn test_seek() {
+        let compressor = Compressor::default();
+        let keys = index_keys();
+        let decoded = SortedKeys::from_bytes(&compressor, &compress_sorted_keys(&compressor, &keys).unwrap()).unwrap();
+        for target in [&keys[0][..], &keys[123], b"tenant/003/", b"a", b"zzz"] {
+            let expected: Vec<Vec<u8>> = keys.iter().filter(|k| k.as_slice() >= target).cloned().collect();
+            let found: Vec<Vec<u8>> = decoded.seek(target).collect::<Result<_, _>>().unwrap();
+            assert_eq!(found, expected, "{:?}", String::from_utf8_lossy(target));
+        }
+    }
+}
@@ -8,17 +8,140 @@
 //!
 //! Chooses the optimal strategy based on content analysis.
 
-pub mod config;
-pub mod error;
-pub mod huffman;
-pub mod lz4_wrapper;
-pub mod entropy;
-pub mod semantic;
-pub mod ryzanstein_integration;
-
-use crate::config::CompressionConfig;
+#![cfg_attr(all(not(feature = "std"), not(test)), no_std)]
+
+pub mod embedded;
+
+/// Declares the modules that need the `std` feature
+macro_rules! full_modules {
+    ($($(#[$attr:meta])* $vis:vis mod $name:ident;)*) => {
+        $($(#[$attr])* #[cfg(feature = "std")] $vis mod $name;)*
+    };
+}
+
+full_modules! {
+    pub mod analysis;
+    pub mod config;
+    pub mod error;
+    pub mod fingerprint;
+    pub mod huffman;
+    pub mod lz4_wrapper;
+    pub mod lzss;
+    pub mod entropy;
+    pub mod semantic;
+    pub mod semantic_lz;
+    pub mod session;
+    pub mod log_dedupe;
+    pub mod stored;
+    pub mod tokens;
+    pub mod tensor;
+    pub mod memory_image;
+    pub mod pcm;
+    pub mod tuning;
+    pub mod usage;
+    pub mod value;
+    pub mod code;
+    pub mod raster;
+    pub mod columnar;
+    pub mod corpus;
+    pub mod dictionary;
+    pub mod encrypt;
+    pub mod digest;
+    pub mod ryzanstein_integration;
+    pub mod similarity;
+    pub mod chunker;
+    pub mod store;
+    pub mod storage;
+    pub mod snapshot;
+    pub mod archive;
+    pub mod batch;
+    #[cfg(feature = "bytes")]
+    pub mod buf;
+    pub mod cache;
+    pub mod capabilities;
+    pub mod atomic;
+    pub mod bitmap;
+    pub mod block_cache;
+    pub mod incremental;
+    pub mod iter;
+    pub mod keys;
+    pub mod manifest;
+    pub mod memory;
+    pub mod multiplex;
+    pub mod pages;
+    pub mod paged;
+    pub mod params;
+    pub mod pipe;
+    pub mod realtime;
+    pub mod stream;
+    pub mod text;
+    pub mod append_log;
+    mod codec_stream;
+    pub mod frame;
+    pub mod frames;
+    pub mod hardware;
+    pub mod history;
+    pub mod negotiation;
+    pub mod scratch;
+    pub mod sidecar;
+    pub mod small;
+    pub mod varint;
+    pub mod warm;
+    #[cfg(any(test, feature = "testing"))]
+    pub mod testing;
+}
+
+#[cfg(feature = "std")]
+use crate::config::{CompressOptions, CompressionConfig};
+#[cfg(feature = "std")]
 use crate::error::CompressError;
 
+#[cfg(feature = "std")]
+/// splitmix64: advance `state` and return its next output. The one
+/// generator behind seeded tables, synthetic corpora and test data.
+pub(crate) const fn splitmix64(state: &mut u64) -> u64 {
+    *state = state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    let mut z = *state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
+
+#[cfg(feature = "std")]
+/// Cap on buffer preallocation driven by sizes read from untrusted input
+pub(crate) const MAX_PREALLOC: usize = 16 * 1024 * 1024;
+
+#[cfg(feature = "std")]
+/// Share of repeated bytes from which `EntropyCoding` replaces a skipped
+/// `Huffman` instead of `Stored`
+const HUFFMAN_FALLBACK_RUN_FRACTION: f64 = 0.25;
+
+#[cfg(feature = "std")]
+/// Order-0 entropy in bits per byte from which a histogram counts as near
+/// uniform for [`huffman_precheck`]
+const HUFFMAN_NEAR_UNIFORM_BITS: f64 = 7.5;
+
+#[cfg(feature = "std")]
+/// Method to code with instead of `Huffman` when the histogram is near
+/// uniform and predicts it saves less than `min_gain` of the input (see
+/// [`CompressionConfig::huffman_min_gain`]). Skewed histograms keep
+/// `Huffman` even where the code table outweighs the gain, as on short
+/// messages.
+fn huffman_precheck(method: CompressionMethod, histogram: &[u64; 256], run_fraction: f64, min_gain: f64) -> CompressionMethod {
+    let len: u64 = histogram.iter().sum();
+    if method != CompressionMethod::Huffman
+        || analysis::entropy_from_histogram(histogram) < HUFFMAN_NEAR_UNIFORM_BITS
+        || huffman::expected_gain(histogram) >= min_gain * len as f64
+    {
+        method
+    } else if run_fraction >= HUFFMAN_FALLBACK_RUN_FRACTION {
+        CompressionMethod::EntropyCoding
+    } else {
+        CompressionMethod::Stored
+    }
+}
+
+#[cfg(feature = "std")]
 /// Compression method selection
 #[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
 pub enum CompressionMethod {
@@ -26,28 +149,198 @@ pub enum CompressionMethod {
     Lz4Semantic,
     EntropyCoding,
     SemanticDedupe,
+    /// Newline-aware templating of repeated log lines
+    LogDedupe,
+    /// Input kept as-is; chosen for data predicted to be incompressible
+    Stored,
+    /// Sliding-window LZ77 with Huffman-coded tokens
+    Lzss,
+    /// Block dedup followed by the LZ backend over the deduplicated stream
+    SemanticLz,
     Auto,
 }
 
+#[cfg(feature = "std")]
+impl CompressionMethod {
+    /// Stable numeric identifier used in on-disk formats
+    pub fn id(self) -> u8 {
+        match self {
+            CompressionMethod::Huffman => 1,
+            CompressionMethod::Lz4Semantic => 2,
+            CompressionMethod::EntropyCoding => 3,
+            CompressionMethod::SemanticDedupe => 4,
+            CompressionMethod::LogDedupe => 5,
+            CompressionMethod::Stored => 6,
+            CompressionMethod::Lzss => 7,
+            CompressionMethod::SemanticLz => 8,
+            CompressionMethod::Auto => 0xFF,
+        }
+    }
+
+    /// Inverse of [`CompressionMethod::id`]; `Auto` is never stored
+    pub fn from_id(id: u8) -> Option<Self> {
+        match id {
+            1 => Some(CompressionMethod::Huffman),
+            2 => Some(CompressionMethod::Lz4Semantic),
+            3 => Some(CompressionMethod::EntropyCoding),
+            4 => Some(CompressionMethod::SemanticDedupe),
+            5 => Some(CompressionMethod::LogDedupe),
+            6 => Some(CompressionMethod::Stored),
+            7 => Some(CompressionMethod::Lzss),
+            8 => Some(CompressionMethod::SemanticLz),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(feature = "std")]
 /// Compressed output container
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 pub struct CompressedOutput {
     pub method: CompressionMethod,
     pub original_size: usize,
+    /// Length of the codec payload alone
     pub compressed_size: usize,
     pub data: Vec<u8>,
+    /// [`CompressedOutput::total_encoded_size`] over `original_size`
     pub ratio: f64,
     pub metadata: CompressionMetadata,
+    /// CRC-32 of the original data
+    #[serde(default)]
+    pub checksum: Option<u32>,
+    /// Static dictionary the input was coded with before the codec ran
+    #[serde(default)]
+    pub dictionary_id: Option<u32>,
+    /// Original data is UTF-8 text (see [`Compressor::compress_str`])
+    #[serde(default)]
+    pub utf8: bool,
+    /// Zero bytes padding the frame (see [`CompressedOutput::pad_to`])
+    #[serde(default)]
+    pub padding: Option<u32>,
+    /// Frame header carries `metadata.params` (see [`crate::params`])
+    #[serde(default)]
+    pub params_in_frame: bool,
 }
 
+#[cfg(feature = "std")]
+impl CompressedOutput {
+    /// Decompressed data one block at a time, for consumers that process it
+    /// incrementally instead of holding the whole original in memory. The
+    /// size and checksum are verified once the last block has been yielded;
+    /// a mismatch is reported as a final `Err`. Dictionary-coded outputs are
+    /// decoded whole (with the built-in dictionaries) and yielded as one block.
+    pub fn blocks(&self) -> Result<Blocks<'_>, CompressError> {
+        let inner: codec_stream::BlockIter<'_> = if self.dictionary_id.is_some() {
+            Box::new(std::iter::once(Compressor::default().decompress(self)))
+        } else {
+            codec_stream::decode_blocks(self.method, &self.data, self.original_size)?
+        };
+        Ok(Blocks {
+            inner,
+            expected_size: self.original_size,
+            expected_checksum: self.checksum,
+            produced: 0,
+            hasher: crc32fast::Hasher::new(),
+            done: false,
+        })
+    }
+}
+
+#[cfg(feature = "std")]
+/// Iterator returned by [`CompressedOutput::blocks`]
+pub struct Blocks<'a> {
+    inner: codec_stream::BlockIter<'a>,
+    expected_size: usize,
+    expected_checksum: Option<u32>,
+    produced: usize,
+    hasher: crc32fast::Hasher,
+    done: bool,
+}
+
+#[cfg(feature = "std")]
+impl Iterator for Blocks<'_> {
+    type Item = Result<Vec<u8>, CompressError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        match self.inner.next() {
+            Some(Ok(block)) => {
+                self.produced += block.len();
+                self.hasher.update(&block);
+                Some(Ok(block))
+            }
+            Some(Err(e)) => {
+                self.done = true;
+                Some(Err(e))
+            }
+            None => {
+                self.done = true;
+                if self.produced != self.expected_size {
+                    return Some(Err(CompressError::SizeMismatch {
+                        expected: self.expected_size,
+                        actual: self.produced,
+                    }));
+                }
+                let actual = std::mem::take(&mut self.hasher).finalize();
+                match self.expected_checksum {
+                    Some(expected) if expected != actual => {
+                        Some(Err(CompressError::ChecksumMismatch { expected, actual }))
+                    }
+                    _ => None,
+                }
+            }
+        }
+    }
+}
+
+#[cfg(feature = "std")]
 /// Metadata about the compression process
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 pub struct CompressionMetadata {
     pub entropy_bits: f64,
+    /// Blocks replaced by references (semantic dedup only)
     pub semantic_dedup_count: usize,
     pub block_count: usize,
+    #[serde(default)]
+    pub overhead: Overhead,
+    /// Clustering and savings breakdown when the method is semantic dedup
+    #[serde(default)]
+    pub semantic: Option<semantic::SemanticReport>,
+    /// The input was itself a frame (see [`config::NestedFramePolicy`])
+    #[serde(default)]
+    pub nested_frame: bool,
+    /// Memory the compression took. It describes the run rather than the
+    /// data, so it is not serialized and is zero for outputs read back.
+    #[serde(skip)]
+    pub memory: memory::MemoryStats,
+    /// Codec parameters in effect; `None` for outputs read from frames that
+    /// do not record them
+    #[serde(default)]
+    pub params: Option<params::CodecParams>,
+}
+
+#[cfg(feature = "std")]
+/// Bytes spent on structure rather than coded content
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct Overhead {
+    /// Frame header around the payload
+    pub container: usize,
+    /// Code tables and block indexes shipped inside the payload
+    pub tables: usize,
+    /// Per-block length headers inside the payload
+    pub block_headers: usize,
 }
 
+#[cfg(feature = "std")]
+impl Overhead {
+    pub fn total(&self) -> usize {
+        self.container + self.tables + self.block_headers
+    }
+}
+
+#[cfg(feature = "std")]
 /// Compression statistics
 #[derive(Debug, Clone)]
 pub struct CompressionStats {
@@ -57,82 +350,797 @@ pub struct CompressionStats {
     pub best_method_counts: std::collections::HashMap<String, usize>,
 }
 
+#[cfg(feature = "std")]
+/// Outcome of decoding a single block during [`Compressor::verify`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BlockStatus {
+    pub index: usize,
+    /// Offset of the block in the decompressed output
+    pub offset: usize,
+    pub size: usize,
+    /// Decode error, if the block could not be decoded
+    pub error: Option<String>,
+}
+
+#[cfg(feature = "std")]
+/// Result of an integrity check performed by [`Compressor::verify`]
+#[derive(Debug, Clone)]
+pub struct VerifyReport {
+    pub method: CompressionMethod,
+    pub expected_size: usize,
+    pub actual_size: usize,
+    pub expected_checksum: Option<u32>,
+    pub actual_checksum: u32,
+    pub blocks: Vec<BlockStatus>,
+}
+
+#[cfg(feature = "std")]
+impl VerifyReport {
+    /// Every block decoded, and size and checksum (when recorded) match
+    pub fn is_ok(&self) -> bool {
+        self.blocks.iter().all(|b| b.error.is_none())
+            && self.expected_size == self.actual_size
+            && self.expected_checksum.is_none_or(|c| c == self.actual_checksum)
+    }
+}
+
+#[cfg(feature = "std")]
 /// The main compressor engine
 pub struct Compressor {
     config: CompressionConfig,
+    dictionaries: dictionary::DictionaryRegistry,
+    history: Option<std::sync::Arc<std::sync::Mutex<history::MethodHistory>>>,
+    usage_hook: Option<std::sync::Arc<dyn usage::UsageHook>>,
+    tenant: Option<String>,
+    key_provider: Option<std::sync::Arc<dyn encrypt::KeyProvider>>,
+    block_cache: Option<std::sync::Arc<block_cache::BlockCache>>,
+    profile: hardware::HardwareProfile,
+    /// Settings `profile` filled in
+    auto: hardware::AutoSettings,
+}
+
+#[cfg(feature = "std")]
+impl Default for Compressor {
+    /// Create a compressor with default configuration
+    fn default() -> Self {
+        Self::new(CompressionConfig::default())
+    }
 }
 
+#[cfg(feature = "std")]
 impl Compressor {
-    /// Create a new compressor with the given configuration
-    pub fn new(config: CompressionConfig) -> Self {
-        Self { config }
+    /// Create a new compressor with the given configuration, filling in
+    /// defaults suited to the machine (see [`hardware`])
+    pub fn new(mut config: CompressionConfig) -> Self {
+        let profile = hardware::HardwareProfile::for_config(&config);
+        let auto = profile.apply_defaults(&mut config);
+        Self {
+            config,
+            dictionaries: dictionary::DictionaryRegistry::default(),
+            history: None,
+            usage_hook: None,
+            tenant: None,
+            key_provider: None,
+            block_cache: None,
+            profile,
+            auto,
+        }
     }
 
-    /// Create a compressor with default configuration
-    pub fn default() -> Self {
-        Self::new(CompressionConfig::default())
+    /// Remember which method [`Compressor::compress_adaptive`] picks for each
+    /// content profile and reuse it for similar inputs (see [`history`])
+    pub fn with_history(mut self, history: history::MethodHistory) -> Self {
+        self.history = Some(std::sync::Arc::new(std::sync::Mutex::new(history)));
+        self
+    }
+
+    /// Snapshot of the adaptive history, e.g. to save it
+    pub fn history(&self) -> Option<history::MethodHistory> {
+        self.history.as_ref().map(|h| h.lock().unwrap().clone())
+    }
+
+    /// Resolve dictionary ids against `registry` instead of the built-ins alone
+    pub fn with_dictionaries(mut self, registry: dictionary::DictionaryRegistry) -> Self {
+        self.dictionaries = registry;
+        self
     }
 
-    /// Compress data using the specified method
+    pub fn dictionaries(&self) -> &dictionary::DictionaryRegistry {
+        &self.dictionaries
+    }
+
+    /// Compress data using the specified method; inputs over
+    /// `max_input_size` are rejected (see [`Compressor::compress_chunked`])
     pub fn compress(&self, data: &[u8], method: CompressionMethod) -> Result<CompressedOutput, CompressError> {
+        self.compress_using(data, method, &self.config, true)
+    }
+
+    /// Compress with per-call overrides of the configured settings
+    pub fn compress_with(&self, data: &[u8], options: &CompressOptions) -> Result<CompressedOutput, CompressError> {
+        let config = options.apply(&self.config);
+        self.compress_using(data, options.method, &config, options.checksum.unwrap_or(true))
+    }
+
+    /// Compress data split across several buffers without first copying it
+    /// into one. Slices are fed to the codec in order; the result is the
+    /// same as compressing their concatenation. `Auto` and dictionary coding
+    /// inspect the input as a whole, so with either the slices are gathered.
+    pub fn compress_vectored(
+        &self,
+        slices: &[std::io::IoSlice<'_>],
+        method: CompressionMethod,
+    ) -> Result<CompressedOutput, CompressError> {
+        let head: Vec<u8> = slices.iter().flat_map(|s| s.iter().copied()).take(frame::MAX_HEADER_LEN).collect();
+        let nested = frame::is_frame(&head);
+        if method == CompressionMethod::Auto
+            || self.config.dictionary != dictionary::DictionarySelection::None
+            || (nested && self.config.nested_frames != config::NestedFramePolicy::Warn)
+        {
+            let gathered: Vec<u8> = slices.iter().flat_map(|s| s.iter().copied()).collect();
+            return self.compress(&gathered, method);
+        }
+        let total_len: usize = slices.iter().map(|s| s.len()).sum();
+        self.metered(
+            usage::Operation::Compress,
+            method,
+            total_len,
+            || self.compress_slices(slices, method, nested),
+            |output| (output.total_encoded_size(), output.method, output.metadata.memory),
+        )
+    }
+
+    /// Streaming path of [`Compressor::compress_vectored`]
+    fn compress_slices(
+        &self,
+        slices: &[std::io::IoSlice<'_>],
+        method: CompressionMethod,
+        nested: bool,
+    ) -> Result<CompressedOutput, CompressError> {
+        let total_len: usize = slices.iter().map(|s| s.len()).sum();
+        if total_len == 0 {
+            return Err(CompressError::EmptyInput);
+        }
+        if total_len > self.config.max_input_size {
+            return Err(CompressError::InputTooLarge {
+                size: total_len,
+                limit: self.config.max_input_size,
+            });
+        }
+
+        let mut histogram = [0u64; 256];
+        let mut hasher = crc32fast::Hasher::new();
+        let mut previous = None;
+        let mut repeats = 0;
+        for slice in slices {
+            for &b in slice.iter() {
+                histogram[b as usize] += 1;
+                repeats += (previous == Some(b)) as usize;
+                previous = Some(b);
+            }
+            hasher.update(slice);
+        }
+        let run_fraction = repeats as f64 / (total_len - 1).max(1) as f64;
+        let method = huffman_precheck(method, &histogram, run_fraction, self.config.huffman_min_gain);
+        let mut encoder = codec_stream::StreamEncoder::new(method, &self.config, Some(&histogram), total_len)?;
+        for slice in slices {
+            encoder.push(slice)?;
+        }
+        let mut output = self.finish_output(
+            method,
+            total_len,
+            encoder.finish()?,
+            analysis::entropy_from_histogram(&histogram),
+            Some(hasher.finalize()),
+            None,
+        )?;
+        output.metadata.block_count = (total_len / self.config.lz4_block_size).max(1);
+        output.metadata.nested_frame = nested;
+        Ok(output)
+    }
+
+    fn compress_using(
+        &self,
+        data: &[u8],
+        method: CompressionMethod,
+        config: &CompressionConfig,
+        checksum: bool,
+    ) -> Result<CompressedOutput, CompressError> {
+        self.ensure_unencrypted("frames")?;
+        self.compress_metered(data, method, config, checksum)
+    }
+
+    /// [`Compressor::compress_using`] for callers that encrypt the output
+    /// themselves
+    fn compress_metered(
+        &self,
+        data: &[u8],
+        method: CompressionMethod,
+        config: &CompressionConfig,
+        checksum: bool,
+    ) -> Result<CompressedOutput, CompressError> {
+        self.metered(
+            usage::Operation::Compress,
+            method,
+            data.len(),
+            || self.compress_unmetered(data, method, config, checksum),
+            |output| (output.total_encoded_size(), output.method, output.metadata.memory),
+        )
+    }
+
+    /// [`Compressor::compress_using`] without usage reporting
+    fn compress_unmetered(
+        &self,
+        data: &[u8],
+        method: CompressionMethod,
+        config: &CompressionConfig,
+        checksum: bool,
+    ) -> Result<CompressedOutput, CompressError> {
         if data.is_empty() {
             return Err(CompressError::EmptyInput);
         }
+        if data.len() > config.max_input_size {
+            return Err(CompressError::InputTooLarge {
+                size: data.len(),
+                limit: config.max_input_size,
+            });
+        }
 
+        let nested = frame::is_frame(data);
+        let method = match config.nested_frames {
+            _ if !nested => method,
+            config::NestedFramePolicy::Warn => method,
+            config::NestedFramePolicy::Store => CompressionMethod::Stored,
+            config::NestedFramePolicy::Reject => return Err(CompressError::AlreadyCompressed),
+        };
         let method = if method == CompressionMethod::Auto {
-            self.select_method(data)
+            self.select_method(data, config)
+        } else {
+            method
+        };
+
+        let dictionary = self.select_dictionary(data, config)?;
+        let coded = dictionary.as_ref().map(|d| d.encode(data));
+        let input = coded.as_deref().unwrap_or(data);
+        let method = if method == CompressionMethod::Huffman {
+            huffman_precheck(method, &huffman::byte_frequencies(input), analysis::run_fraction(input), config.huffman_min_gain)
         } else {
             method
         };
 
         let compressed = match method {
-            CompressionMethod::Huffman => huffman::compress(data)?,
-            CompressionMethod::Lz4Semantic => lz4_wrapper::compress(data, self.config.lz4_block_size)?,
-            CompressionMethod::EntropyCoding => entropy::compress(data)?,
-            CompressionMethod::SemanticDedupe => semantic::compress(data, self.config.dedup_threshold)?,
+            CompressionMethod::Huffman => huffman::compress(input)?,
+            CompressionMethod::Lz4Semantic => lz4_wrapper::compress_with_level(input, config.lz4_block_size, config.level)?,
+            CompressionMethod::EntropyCoding => entropy::compress(input)?,
+            CompressionMethod::SemanticDedupe => {
+                let mut encoder = semantic::StreamEncoder::with_table_limit(
+                    config.dedup_threshold,
+                    config.semantic_block_size,
+                    config.dedup_table_bytes,
+                );
+                encoder.push(input);
+                encoder.finish()
+            }
+            CompressionMethod::LogDedupe => log_dedupe::compress(input)?,
+            CompressionMethod::Stored => stored::compress(input)?,
+            CompressionMethod::Lzss => lzss::compress_with_level(input, &config.lzss, config.level)?,
+            CompressionMethod::SemanticLz => {
+                semantic_lz::compress(input, config.semantic_block_size, config.lz4_block_size, config.level)?
+            }
             CompressionMethod::Auto => unreachable!(),
         };
+        let compressed = match &coded {
+            Some(coded) => dictionary::wrap_payload(coded.len(), &compressed),
+            None => compressed,
+        };
+
+        let mut output = self.build_output(
+            method,
+            data.len(),
+            compressed,
+            self.compute_entropy(data),
+            checksum.then(|| crc32fast::hash(data)),
+            dictionary.map(|d| d.id),
+        )?;
+        output.metadata.block_count = (data.len() / config.lz4_block_size).max(1);
+        output.metadata.nested_frame = nested;
+        // Per-call options may differ from the configured parameters
+        output.metadata.params = Some(params::CodecParams::for_method(method, config, output.dictionary_id));
+        Ok(output)
+    }
+
+    /// Dictionary requested by `config` for `data`, if any
+    fn select_dictionary(
+        &self,
+        data: &[u8],
+        config: &CompressionConfig,
+    ) -> Result<Option<&dictionary::Dictionary>, CompressError> {
+        Ok(match config.dictionary {
+            dictionary::DictionarySelection::None => None,
+            dictionary::DictionarySelection::Auto => self.dictionaries.detect(data),
+            dictionary::DictionarySelection::Id(id) => Some(self.dictionaries.resolve(id)?),
+        })
+    }
+
+    /// Wrap a codec payload, accounting for every byte it will take on the
+    /// wire. Frames are not encrypted, so this fails when a key provider is
+    /// set rather than hand back plaintext.
+    fn finish_output(
+        &self,
+        method: CompressionMethod,
+        original_size: usize,
+        compressed: Vec<u8>,
+        entropy_bits: f64,
+        checksum: Option<u32>,
+        dictionary_id: Option<u32>,
+    ) -> Result<CompressedOutput, CompressError> {
+        self.ensure_unencrypted("frames")?;
+        self.build_output(method, original_size, compressed, entropy_bits, checksum, dictionary_id)
+    }
 
-        let ratio = if data.is_empty() {
-            1.0
+    /// [`Compressor::finish_output`] without the encryption check
+    fn build_output(
+        &self,
+        method: CompressionMethod,
+        original_size: usize,
+        compressed: Vec<u8>,
+        entropy_bits: f64,
+        checksum: Option<u32>,
+        dictionary_id: Option<u32>,
+    ) -> Result<CompressedOutput, CompressError> {
+        let codec_payload = if dictionary_id.is_some() {
+            dictionary::unwrap_payload(&compressed)?.1
         } else {
-            compressed.len() as f64 / data.len() as f64
+            &compressed
         };
-
-        Ok(CompressedOutput {
+        let (tables, block_headers) = codec_stream::payload_overhead(method, codec_payload)?;
+        let tables = tables + (compressed.len() - codec_payload.len());
+        let semantic = codec_stream::semantic_report(method, codec_payload)?;
+        let peak_output = compressed.capacity();
+        let mut output = CompressedOutput {
             method,
-            original_size: data.len(),
+            original_size,
             compressed_size: compressed.len(),
             data: compressed,
-            ratio,
+            ratio: 0.0,
             metadata: CompressionMetadata {
-                entropy_bits: self.compute_entropy(data),
-                semantic_dedup_count: 0,
-                block_count: (data.len() / self.config.lz4_block_size).max(1),
+                entropy_bits,
+                semantic_dedup_count: semantic.as_ref().map_or(0, |r| r.duplicate_blocks),
+                block_count: (original_size / self.config.lz4_block_size).max(1),
+                overhead: Overhead::default(),
+                semantic,
+                nested_frame: false,
+                memory: memory::MemoryStats {
+                    peak_scratch: self.estimated_scratch(usage::Operation::Compress, method, original_size),
+                    peak_output,
+                    tables,
+                },
+                params: Some(params::CodecParams::for_method(method, &self.config, dictionary_id)),
             },
-        })
+            checksum,
+            dictionary_id,
+            utf8: false,
+            padding: None,
+            params_in_frame: self.config.record_params,
+        };
+        output.metadata.overhead = Overhead {
+            container: output.total_encoded_size() - output.data.len(),
+            tables,
+            block_headers,
+        };
+        output.pad_to(self.config.frame_padding);
+        Ok(output)
+    }
+
+    /// Compress input of any size into a frame, splitting it into
+    /// independently compressed segments of at most `max_input_size` bytes.
+    ///
+    /// Input under the limit yields an ordinary single-segment frame. Either
+    /// way, [`Compressor::decompress_frame`] restores the original.
+    ///
+    /// With a [`block_alignment`](CompressionConfig::block_alignment), the
+    /// segments are written as back-to-back frames, each padded to a multiple
+    /// of the alignment, since a segmented frame's header would offset them.
+    /// The alignment should be a multiple of any `frame_padding`.
+    pub fn compress_chunked(&self, data: &[u8], method: CompressionMethod) -> Result<Vec<u8>, CompressError> {
+        if self.config.block_alignment > 1 {
+            let mut frames = Vec::new();
+            for chunk in data.chunks(self.config.max_input_size.max(1)) {
+                let mut output = self.compress(chunk, method)?;
+                output.pad_to(self.config.block_alignment);
+                output.append_frame(&mut frames);
+            }
+            if frames.is_empty() {
+                return Err(CompressError::EmptyInput);
+            }
+            return Ok(frames);
+        }
+        if data.len() <= self.config.max_input_size {
+            return Ok(self.compress(data, method)?.to_frame());
+        }
+        let mut segments = Vec::new();
+        let mut first_method = None;
+        for chunk in data.chunks(self.config.max_input_size.max(1)) {
+            let output = self.compress(chunk, method)?;
+            first_method.get_or_insert(output.method);
+            segments.push(output.to_frame());
+        }
+        Ok(frame::write_segmented(
+            &segments,
+            first_method.unwrap_or(method),
+            data.len() as u64,
+            crc32fast::hash(data),
+        ))
+    }
+
+    /// Compress `data` as with [`Compressor::compress_chunked`] and write the
+    /// frame to `path` via [`atomic::AtomicFile`], so a crash leaves either
+    /// the previous file or the complete new frame. Returns the frame size.
+    pub fn compress_to_file(
+        &self,
+        data: &[u8],
+        method: CompressionMethod,
+        path: impl AsRef<std::path::Path>,
+    ) -> Result<u64, CompressError> {
+        let frame = self.compress_chunked(data, method)?;
+        atomic::write_atomic(path.as_ref(), &frame)?;
+        Ok(frame.len() as u64)
+    }
+
+    /// Decode a frame from [`CompressedOutput::to_frame`] or
+    /// [`Compressor::compress_chunked`], verifying sizes and checksums.
+    ///
+    /// Several frames written back to back decode to the concatenation of
+    /// their outputs. Bytes after the last frame are handled as the
+    /// [`trailing_data`](CompressionConfig::trailing_data) policy says, with
+    /// `ReturnRemainder` failing as `Error` does.
+    pub fn decompress_frame(&self, frame: &[u8]) -> Result<Vec<u8>, CompressError> {
+        self.decompress_members(&self.frame_members(frame)?)
+    }
+
+    /// [`Compressor::decompress_frame`] for input that may continue past its
+    /// frames: returns the decoded data and, under
+    /// [`TrailingDataPolicy::ReturnRemainder`](config::TrailingDataPolicy::ReturnRemainder),
+    /// the bytes after the last frame (empty under `Ignore`)
+    pub fn decompress_with_remainder<'a>(&self, data: &'a [u8]) -> Result<(Vec<u8>, &'a [u8]), CompressError> {
+        let (frames, remainder) = frame::split_with_policy(data, self.config.trailing_data)?;
+        Ok((self.decompress_members(&frames)?, remainder))
+    }
+
+    /// Complete frames of a decode input, for callers with no way to return
+    /// a remainder
+    pub(crate) fn frame_members<'a>(&self, data: &'a [u8]) -> Result<Vec<&'a [u8]>, CompressError> {
+        Ok(frame::split_with_policy(data, self.strict_trailing_data())?.0)
+    }
+
+    /// The trailing data policy with `ReturnRemainder` as `Error`
+    pub(crate) fn strict_trailing_data(&self) -> config::TrailingDataPolicy {
+        match self.config.trailing_data {
+            config::TrailingDataPolicy::ReturnRemainder => config::TrailingDataPolicy::Error,
+            policy => policy,
+        }
+    }
+
+    fn decompress_members(&self, frames: &[&[u8]]) -> Result<Vec<u8>, CompressError> {
+        if let [single] = frames {
+            return self.decompress_member(single);
+        }
+        let mut data = Vec::new();
+        for member in frames {
+            data.extend_from_slice(&self.decompress_member(member)?);
+        }
+        Ok(data)
+    }
+
+    /// Decode back-to-back frames from `reader` into `writer`, holding one
+    /// frame at a time; returns the number of bytes written. Bytes after
+    /// the last frame are handled as in [`Compressor::decompress_frame`].
+    pub fn decompress_stream<R: std::io::Read, W: std::io::Write>(
+        &self,
+        reader: R,
+        writer: W,
+    ) -> Result<u64, CompressError> {
+        Ok(self.decode_stream(reader, writer, self.strict_trailing_data())?.0)
+    }
+
+    /// [`Compressor::decompress_stream`] returning, under
+    /// [`TrailingDataPolicy::ReturnRemainder`](config::TrailingDataPolicy::ReturnRemainder),
+    /// the bytes read past the last frame. Decoding stops there: whatever
+    /// follows them is left unread in `reader`.
+    pub fn decompress_stream_with_remainder<R: std::io::Read, W: std::io::Write>(
+        &self,
+        reader: R,
+        writer: W,
+    ) -> Result<(u64, Vec<u8>), CompressError> {
+        self.decode_stream(reader, writer, self.config.trailing_data)
+    }
+
+    fn decode_stream<R: std::io::Read, W: std::io::Write>(
+        &self,
+        mut reader: R,
+        mut writer: W,
+        policy: config::TrailingDataPolicy,
+    ) -> Result<(u64, Vec<u8>), CompressError> {
+        let (mut read, mut written) = (0u64, 0u64);
+        loop {
+            let frame = match frame::read_member(&mut reader)? {
+                frame::Member::Frame(frame) => frame,
+                frame::Member::End => return Ok((written, Vec::new())),
+                frame::Member::Trailing(_, cause) if read == 0 => return Err(cause),
+                frame::Member::Trailing(tail, cause) => {
+                    return Ok((written, frame::trailing(policy, tail, read, cause)?));
+                }
+            };
+            let data = self.decompress_member(&frame)?;
+            writer.write_all(&data)?;
+            read += frame.len() as u64;
+            written += data.len() as u64;
+        }
+    }
+
+    /// Decode exactly one frame, segmented or not, through the block cache
+    /// if one is installed
+    fn decompress_member(&self, frame: &[u8]) -> Result<Vec<u8>, CompressError> {
+        let header = frame::FrameHeader::parse(frame)?;
+        if let Some(cache) = self.block_cache.as_ref().filter(|_| header.flags & frame::FLAG_SEGMENTED == 0) {
+            let hash = store::BlockHash::of(frame);
+            if let Some(block) = cache.get(&hash) {
+                return Ok(block.to_vec());
+            }
+            let data = self.decode_member(frame, &header)?;
+            cache.insert(hash, data.as_slice().into());
+            return Ok(data);
+        }
+        self.decode_member(frame, &header)
+    }
+
+    /// [`Compressor::decompress_member`] bypassing the block cache for this frame
+    pub(crate) fn decode_member(&self, frame: &[u8], header: &frame::FrameHeader) -> Result<Vec<u8>, CompressError> {
+        let data = if header.flags & frame::FLAG_SEGMENTED != 0 {
+            let payload = frame::payload(frame, header)?;
+            let mut data = Vec::with_capacity((header.original_size as usize).min(MAX_PREALLOC));
+            for segment in frame::segments(payload)? {
+                data.extend_from_slice(&self.decompress_member(segment)?);
+            }
+            data
+        } else {
+            self.decompress(&CompressedOutput::from_frame(frame)?)?
+        };
+        if data.len() as u64 != header.original_size {
+            if self.config.lenient_sizes {
+                return Ok(data);
+            }
+            return Err(CompressError::SizeMismatch {
+                expected: header.original_size as usize,
+                actual: data.len(),
+            });
+        }
+        if let Some(expected) = header.checksum {
+            let actual = crc32fast::hash(&data);
+            if actual != expected {
+                return Err(CompressError::ChecksumMismatch { expected, actual });
+            }
+        }
+        Ok(data)
     }
 
     /// Decompress data
     pub fn decompress(&self, output: &CompressedOutput) -> Result<Vec<u8>, CompressError> {
-        match output.method {
-            CompressionMethod::Huffman => huffman::decompress(&output.data, output.original_size),
-            CompressionMethod::Lz4Semantic => lz4_wrapper::decompress(&output.data, output.original_size),
-            CompressionMethod::EntropyCoding => entropy::decompress(&output.data, output.original_size),
-            CompressionMethod::SemanticDedupe => semantic::decompress(&output.data, output.original_size),
+        self.metered(
+            usage::Operation::Decompress,
+            output.method,
+            output.total_encoded_size(),
+            || self.decompress_unmetered(output),
+            |data| (data.len(), output.method, self.decode_memory(output, data)),
+        )
+    }
+
+    /// [`Compressor::decompress`] without usage reporting
+    fn decompress_unmetered(&self, output: &CompressedOutput) -> Result<Vec<u8>, CompressError> {
+        let Some(id) = output.dictionary_id else {
+            return self.decompress_payload(output.method, &output.data, output.original_size);
+        };
+        let dictionary = self.dictionaries.resolve(id)?;
+        let (coded_len, payload) = dictionary::unwrap_payload(&output.data)?;
+        self.check_size(output.original_size, dictionary.decode(&self.decompress_payload(output.method, payload, coded_len)?)?)
+    }
+
+    /// Decode a raw codec payload produced by `method`, which must decode to
+    /// `original_size` bytes unless sizes are lenient
+    pub(crate) fn decompress_payload(
+        &self,
+        method: CompressionMethod,
+        data: &[u8],
+        original_size: usize,
+    ) -> Result<Vec<u8>, CompressError> {
+        let data = match method {
+            CompressionMethod::Huffman => huffman::decompress(data, original_size),
+            CompressionMethod::Lz4Semantic => lz4_wrapper::decompress(data, original_size),
+            CompressionMethod::EntropyCoding => entropy::decompress(data, original_size),
+            CompressionMethod::SemanticDedupe => semantic::decompress(data, original_size),
+            CompressionMethod::LogDedupe => log_dedupe::decompress(data, original_size),
+            CompressionMethod::Stored => stored::decompress(data, original_size),
+            CompressionMethod::Lzss => lzss::decompress(data, original_size),
+            CompressionMethod::SemanticLz => semantic_lz::decompress(data, original_size),
             CompressionMethod::Auto => Err(CompressError::InvalidMethod),
+        }?;
+        self.check_size(original_size, data)
+    }
+
+    /// `data`, if it has the `expected` length or sizes are lenient (see
+    /// [`CompressionConfig::lenient_sizes`])
+    fn check_size(&self, expected: usize, data: Vec<u8>) -> Result<Vec<u8>, CompressError> {
+        if data.len() != expected && !self.config.lenient_sizes {
+            return Err(CompressError::SizeMismatch {
+                expected,
+                actual: data.len(),
+            });
         }
+        Ok(data)
+    }
+
+    /// Re-encode `output` with `target` without materializing the original
+    /// data, except where the target encoder holds its whole input.
+    ///
+    /// The source is decoded one block at a time and fed straight into the
+    /// target encoder. Targets that need a global histogram (Huffman) decode the
+    /// source twice rather than buffer it. The `Lzss` and `LogDedupe`
+    /// encoders buffer everything they are fed (matches may reach anywhere
+    /// back in the input, and templating needs whole lines), so with those
+    /// targets the original is held in full. Dictionary-coded sources are
+    /// decoded whole and recompressed.
+    pub fn transcode(
+        &self,
+        output: &CompressedOutput,
+        target: CompressionMethod,
+    ) -> Result<CompressedOutput, CompressError> {
+        if target == CompressionMethod::Auto {
+            return Err(CompressError::InvalidMethod);
+        }
+        if output.dictionary_id.is_some() {
+            return self.compress(&self.decompress(output)?, target);
+        }
+
+        let source_blocks = || codec_stream::decode_blocks(output.method, &output.data, output.original_size);
+
+        let mut histogram = [0u64; 256];
+        let mut counted = false;
+        if codec_stream::needs_histogram(target) {
+            for block in source_blocks()? {
+                for &b in &block? {
+                    histogram[b as usize] += 1;
+                }
+            }
+            counted = true;
+        }
+
+        let mut encoder = codec_stream::StreamEncoder::new(target, &self.config, Some(&histogram), output.original_size)?;
+        let mut hasher = crc32fast::Hasher::new();
+        let mut produced = 0usize;
+        for block in source_blocks()? {
+            let block = block?;
+            if !counted {
+                for &b in &block {
+                    histogram[b as usize] += 1;
+                }
+            }
+            hasher.update(&block);
+            produced += block.len();
+            encoder.push(&block)?;
+        }
+        if produced != output.original_size {
+            return Err(CompressError::SizeMismatch {
+                expected: output.original_size,
+                actual: produced,
+            });
+        }
+        let checksum = hasher.finalize();
+        if output.checksum.is_some_and(|c| c != checksum) {
+            return Err(CompressError::ChecksumMismatch {
+                expected: output.checksum.unwrap_or_default(),
+                actual: checksum,
+            });
+        }
+        let compressed = encoder.finish()?;
+
+        self.finish_output(
+            target,
+            output.original_size,
+            compressed,
+            analysis::entropy_from_histogram(&histogram),
+            Some(checksum),
+            None,
+        )
+    }
+
+    /// Describe a serialized frame (see [`frame`]) without decompressing it
+    pub fn inspect(&self, frame: &[u8]) -> Result<frame::FrameInfo, CompressError> {
+        frame::inspect(frame)
+    }
+
+    /// Check that `output` decodes to the declared size and checksum without
+    /// keeping the decoded data around. Corruption is reported in the returned
+    /// [`VerifyReport`] rather than as an error.
+    pub fn verify(&self, output: &CompressedOutput) -> Result<VerifyReport, CompressError> {
+        let mut report = VerifyReport {
+            method: output.method,
+            expected_size: output.original_size,
+            actual_size: 0,
+            expected_checksum: output.checksum,
+            actual_checksum: 0,
+            blocks: Vec::new(),
+        };
+        let mut hasher = crc32fast::Hasher::new();
+
+        let blocks = if output.dictionary_id.is_some() {
+            Ok(Box::new(std::iter::once(self.decompress(output))) as codec_stream::BlockIter)
+        } else {
+            codec_stream::decode_blocks(output.method, &output.data, output.original_size)
+        };
+        let blocks = match blocks {
+            Ok(blocks) => blocks,
+            Err(CompressError::InvalidMethod) => return Err(CompressError::InvalidMethod),
+            Err(e) => {
+                report.blocks.push(BlockStatus {
+                    index: 0,
+                    offset: 0,
+                    size: 0,
+                    error: Some(e.to_string()),
+                });
+                report.actual_checksum = hasher.finalize();
+                return Ok(report);
+            }
+        };
+
+        for (index, block) in blocks.enumerate() {
+            let offset = report.actual_size;
+            match block {
+                Ok(block) => {
+                    hasher.update(&block);
+                    report.actual_size += block.len();
+                    report.blocks.push(BlockStatus {
+                        index,
+                        offset,
+                        size: block.len(),
+                        error: None,
+                    });
+                }
+                Err(e) => {
+                    report.blocks.push(BlockStatus {
+                        index,
+                        offset,
+                        size: 0,
+                        error: Some(e.to_string()),
+                    });
+                    break;
+                }
+            }
+        }
+        report.actual_checksum = hasher.finalize();
+        Ok(report)
     }
 
     /// Compress data using adaptive method selection.
-    /// Tries multiple algorithms and returns the best result.
+    /// Tries multiple algorithms and returns the one with the lowest
+    /// [`config::Objective`] cost.
     pub fn compress_adaptive(&self, data: &[u8]) -> Result<CompressedOutput, CompressError> {
         if data.is_empty() {
             return Err(CompressError::EmptyInput);
         }
 
+        if analysis::is_incompressible(data, self.config.incompressible_ratio) {
+            return self.compress(data, CompressionMethod::Stored);
+        }
+
         let entropy = self.compute_entropy(data);
-        let has_repeated_blocks = self.detect_block_repetition(data);
+        let key = history::ProfileKey::of(data, entropy);
+        let history = self.history.as_ref().filter(|_| !self.config.deterministic);
+        let remembered = history.and_then(|h| h.lock().unwrap().lookup(&key));
+        if let Some(method) = remembered {
+            if let Ok(result) = self.compress(data, method) {
+                return Ok(result);
+            }
+        }
+        let has_repeated_blocks = analysis::has_repeated_blocks_in(data, self.config.repetition_window);
 
         // Build candidate list based on data characteristics
         let mut candidates = Vec::new();
@@ -154,50 +1162,82 @@ impl Compressor {
             candidates.push(CompressionMethod::Huffman);
         }
 
-        // Try each candidate and pick the best ratio
-        let mut best: Option<CompressedOutput> = None;
+        // Try each candidate and keep the cheapest under the objective
+        let mut best: Option<(f64, CompressedOutput)> = None;
         for method in candidates {
+            let started = std::time::Instant::now();
             if let Ok(result) = self.compress(data, method) {
-                if best.as_ref().map_or(true, |b| result.ratio < b.ratio) {
-                    best = Some(result);
+                let seconds_per_mib = if self.config.deterministic {
+                    0.0
+                } else {
+                    started.elapsed().as_secs_f64() * (1024.0 * 1024.0) / data.len() as f64
+                };
+                let memory_per_byte = self.estimated_memory(result.method, data.len()) as f64 / data.len() as f64;
+                let cost = self.config.objective.cost(result.ratio, seconds_per_mib, memory_per_byte);
+                if best.as_ref().is_none_or(|(c, _)| cost < *c) {
+                    best = Some((cost, result));
                 }
             }
         }
 
-        best.ok_or(CompressError::EmptyInput)
+        let (_, best) = best.ok_or(CompressError::EmptyInput)?;
+        if let Some(history) = history {
+            history.lock().unwrap().record(key, best.method);
+        }
+        Ok(best)
     }
 
-    /// Detect if data has repeated 64-byte blocks (indicator for semantic dedup)
-    fn detect_block_repetition(&self, data: &[u8]) -> bool {
-        if data.len() < 128 {
-            return false;
-        }
-        let block_size = 64;
-        let mut seen = std::collections::HashSet::new();
-        let mut duplicates = 0;
-        let total_blocks = data.len() / block_size;
+    /// Rough working memory, in bytes, `method` needs to compress `len` bytes,
+    /// not counting the input itself: scratch (see
+    /// [`Compressor::estimated_scratch`]) plus the output
+    pub fn estimated_memory(&self, method: CompressionMethod, len: usize) -> usize {
+        self.estimated_scratch(usage::Operation::Compress, method, len) + len
+    }
 
-        for chunk in data.chunks(block_size) {
-            if chunk.len() == block_size {
-                let hash = {
-                    let mut h: u64 = 0xcbf29ce484222325;
-                    for &b in chunk {
-                        h ^= b as u64;
-                        h = h.wrapping_mul(0x100000001b3);
-                    }
-                    h
-                };
-                if !seen.insert(hash) {
-                    duplicates += 1;
-                }
-            }
+    /// Predict the ratio [`Compressor::compress`] would report for `data`,
+    /// compressing only [`analysis::ESTIMATE_SAMPLES`] evenly spaced windows
+    /// of large inputs (inputs that small are simply compressed).
+    ///
+    /// Sampling tracks LZ and entropy coders closely on homogeneous data.
+    /// For `SemanticDedupe` and `SemanticLz` it only sees repeats within a
+    /// window, so the estimate is pessimistic when duplicates are far apart.
+    pub fn estimate_ratio(&self, data: &[u8], method: CompressionMethod) -> Result<f64, CompressError> {
+        let config = &self.config;
+        let window = analysis::ESTIMATE_WINDOW.max(config.lz4_block_size).min(config.max_input_size).max(1);
+        if data.len() <= window * analysis::ESTIMATE_SAMPLES {
+            return Ok(self.compress_unmetered(data, method, config, true)?.ratio);
         }
-
-        total_blocks > 0 && (duplicates as f64 / total_blocks as f64) > 0.1
+        let method = if method == CompressionMethod::Auto {
+            self.select_method(data, config)
+        } else {
+            method
+        };
+        let stride = (data.len() - window) / (analysis::ESTIMATE_SAMPLES - 1);
+        let mut payload = 0;
+        for i in 0..analysis::ESTIMATE_SAMPLES {
+            payload += self.compress_unmetered(&data[i * stride..i * stride + window], method, config, false)?.data.len();
+        }
+        let scaled = payload as f64 * data.len() as f64 / (window * analysis::ESTIMATE_SAMPLES) as f64;
+        // Header with checksum, as compress writes it
+        let header = frame::FrameHeader {
+            version: frame::FORMAT_VERSION,
+            method,
+            flags: frame::FLAG_CHECKSUM,
+            original_size: data.len() as u64,
+            payload_len: scaled as u64,
+            checksum: Some(0),
+            dictionary_id: None,
+            padding: None,
+            params: None,
+        };
+        Ok((scaled + header.encoded_len() as f64) / data.len() as f64)
     }
 
     /// Automatically select the best compression method based on data analysis
-    fn select_method(&self, data: &[u8]) -> CompressionMethod {
+    fn select_method(&self, data: &[u8], config: &CompressionConfig) -> CompressionMethod {
+        if analysis::is_incompressible(data, config.incompressible_ratio) {
+            return CompressionMethod::Stored;
+        }
         let entropy = self.compute_entropy(data);
         if entropy < 3.0 {
             CompressionMethod::Huffman
@@ -210,25 +1250,11 @@ impl Compressor {
 
     /// Compute Shannon entropy of data in bits per byte
     fn compute_entropy(&self, data: &[u8]) -> f64 {
-        if data.is_empty() {
-            return 0.0;
-        }
-        let mut freq = [0u64; 256];
-        for &b in data {
-            freq[b as usize] += 1;
-        }
-        let len = data.len() as f64;
-        let mut entropy = 0.0;
-        for &f in &freq {
-            if f > 0 {
-                let p = f as f64 / len;
-                entropy -= p * p.log2();
-            }
-        }
-        entropy
+        analysis::entropy(data)
     }
 }
 
+#[cfg(feature = "std")]
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -243,6 +1269,44 @@ mod tests {
         assert_eq!(result.method, CompressionMethod::Huffman);
     }
 
+    #[test]
+    fn test_huffman_skipped_on_uniform_data() {
+        let compressor = Compressor::default();
+        let mut state = 11u64;
+        let mut random = || splitmix64(&mut state) as u8;
+        let noise: Vec<u8> = (0..20_000).map(|_| random()).collect();
+        let runs: Vec<u8> = (0..5_000).flat_map(|_| [random(); 4]).collect();
+        assert!(huffman::expected_gain(&huffman::byte_frequencies(&noise)) < 0.0);
+
+        let stored = compressor.compress(&noise, CompressionMethod::Huffman).unwrap();
+        assert_eq!(stored.method, CompressionMethod::Stored);
+        assert_eq!(compressor.decompress(&stored).unwrap(), noise);
+        let rle = compressor.compress(&runs, CompressionMethod::Huffman).unwrap();
+        assert_eq!(rle.method, CompressionMethod::EntropyCoding);
+        assert_eq!(compressor.decompress(&rle).unwrap(), runs);
+        let slices = [std::io::IoSlice::new(&noise[..7000]), std::io::IoSlice::new(&noise[7000..])];
+        assert_eq!(compressor.compress_vectored(&slices, CompressionMethod::Huffman).unwrap().to_frame(), stored.to_frame());
+
+        let forced = Compressor::new(CompressionConfig { huffman_min_gain: f64::NEG_INFINITY, ..CompressionConfig::default() });
+        assert_eq!(forced.compress(&noise, CompressionMethod::Huffman).unwrap().method, CompressionMethod::Huffman);
+        let text = b"skewed text keeps its Huffman coding ".repeat(100);
+        assert_eq!(compressor.compress(&text, CompressionMethod::Huffman).unwrap().method, CompressionMethod::Huffman);
+    }
+
+    #[test]
+    fn test_huffman_kept_on_short_skewed_input() {
+        // The code table outweighs the gain here, but the histogram is far
+        // from uniform, so the caller's choice stands
+        let compressor = Compressor::default();
+        let data = b"aaaabbbcc aaaabbbcc";
+        assert!(huffman::expected_gain(&huffman::byte_frequencies(data)) < 0.0);
+        let result = compressor.compress(data, CompressionMethod::Huffman).unwrap();
+        assert_eq!(result.method, CompressionMethod::Huffman);
+        assert_eq!(compressor.decompress(&result).unwrap(), data);
+        let slices = [std::io::IoSlice::new(&data[..7]), std::io::IoSlice::new(&data[7..])];
+        assert_eq!(compressor.compress_vectored(&slices, CompressionMethod::Huffman).unwrap().method, CompressionMethod::Huffman);
+    }
+
     #[test]
     fn test_compress_lz4() {
         let compressor = Compressor::default();
@@ -275,6 +1339,49 @@ mod tests {
         assert_eq!(result.method, CompressionMethod::Huffman);
     }
 
+    #[test]
+    fn test_incompressible_input_is_stored() {
+        let compressor = Compressor::default();
+        let mut state = 0x2545_F491_4F6C_DD1Du64;
+        let random: Vec<u8> = (0..20_000)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                (state >> 32) as u8
+            })
+            .collect();
+        for out in [
+            compressor.compress(&random, CompressionMethod::Auto).unwrap(),
+            compressor.compress_adaptive(&random).unwrap(),
+        ] {
+            assert_eq!(out.method, CompressionMethod::Stored);
+            assert_eq!(out.compressed_size, random.len());
+            assert_eq!(compressor.decompress_frame(&out.to_frame()).unwrap(), random);
+        }
+    }
+
+    #[test]
+    fn test_adaptive_objective_weights() {
+        let data = "objective weighting ".repeat(2000);
+        let smallest = Compressor::default().compress_adaptive(data.as_bytes()).unwrap();
+
+        let frugal = Compressor::new(CompressionConfig {
+            objective: config::Objective {
+                ratio_weight: 1.0,
+                cpu_weight: 0.0,
+                memory_weight: 10.0,
+            },
+            ..Default::default()
+        });
+        let out = frugal.compress_adaptive(data.as_bytes()).unwrap();
+        assert!(
+            frugal.estimated_memory(out.method, data.len()) <= frugal.estimated_memory(smallest.method, data.len())
+        );
+        assert_eq!(frugal.decompress(&out).unwrap(), data.as_bytes());
+        assert_eq!(config::Objective::default().cost(0.5, 100.0, 100.0), 0.5);
+    }
+
     #[test]
     fn test_entropy_computation() {
         let compressor = Compressor::default();
@@ -283,6 +1390,83 @@ mod tests {
         assert!(entropy < 0.01, "uniform data should have ~0 entropy");
     }
 
+    #[test]
+    fn test_transcode_between_methods() {
+        let compressor = Compressor::default();
+        let data = "transcode me please ".repeat(200);
+        let source = compressor.compress(data.as_bytes(), CompressionMethod::Huffman).unwrap();
+        for target in [
+            CompressionMethod::Lz4Semantic,
+            CompressionMethod::EntropyCoding,
+            CompressionMethod::SemanticDedupe,
+            CompressionMethod::Huffman,
+        ] {
+            let out = compressor.transcode(&source, target).unwrap();
+            assert_eq!(out.method, target);
+            assert_eq!(compressor.decompress(&out).unwrap(), data.as_bytes());
+            let direct = compressor.compress(data.as_bytes(), target).unwrap();
+            assert!((out.metadata.entropy_bits - direct.metadata.entropy_bits).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn test_transcode_rejects_auto() {
+        let compressor = Compressor::default();
+        let source = compressor.compress(b"abc", CompressionMethod::EntropyCoding).unwrap();
+        assert!(compressor.transcode(&source, CompressionMethod::Auto).is_err());
+    }
+
+    #[test]
+    fn test_verify_intact_output() {
+        let compressor = Compressor::default();
+        let data = vec![3u8; 200_000];
+        let out = compressor.compress(&data, CompressionMethod::Lz4Semantic).unwrap();
+        let report = compressor.verify(&out).unwrap();
+        assert!(report.is_ok());
+        assert_eq!(report.actual_size, data.len());
+        assert_eq!(report.blocks.len(), 4);
+    }
+
+    #[test]
+    fn test_verify_detects_corruption() {
+        let compressor = Compressor::default();
+        let data = b"verify me verify me verify me";
+        let mut out = compressor.compress(data, CompressionMethod::EntropyCoding).unwrap();
+        out.data[2] ^= 0x01;
+        let report = compressor.verify(&out).unwrap();
+        assert!(!report.is_ok());
+        assert_ne!(Some(report.actual_checksum), report.expected_checksum);
+
+        out.data.pop();
+        let report = compressor.verify(&out).unwrap();
+        assert!(report.blocks.iter().any(|b| b.error.is_some()));
+    }
+
+    #[test]
+    fn test_declared_size_enforced_unless_lenient() {
+        let strict = Compressor::default();
+        let lenient = Compressor::new(CompressionConfig {
+            lenient_sizes: true,
+            ..CompressionConfig::default()
+        });
+        let data = b"GET /health 200\nGET /health 200\nPOST /login 401\n".repeat(30);
+        for method in [CompressionMethod::LogDedupe, CompressionMethod::Lzss, CompressionMethod::Lz4Semantic] {
+            let mut out = strict.compress(&data, method).unwrap();
+            out.original_size += 7;
+            assert!(matches!(
+                strict.decompress(&out),
+                Err(CompressError::SizeMismatch { expected, actual }) if expected == data.len() + 7 && actual == data.len()
+            ));
+            assert_eq!(lenient.decompress(&out).unwrap(), data);
+        }
+
+        // A frame whose header understates the size, checksum and all
+        let mut frame = strict.compress(&data, CompressionMethod::LogDedupe).unwrap().to_frame();
+        frame[7..15].copy_from_slice(&(data.len() as u64 - 1).to_le_bytes());
+        assert!(matches!(strict.decompress_frame(&frame), Err(CompressError::SizeMismatch { .. })));
+        assert_eq!(lenient.decompress_frame(&frame).unwrap(), data);
+    }
+
     #[test]
     fn test_compression_ratio() {
         let compressor = Compressor::default();
@@ -290,4 +1474,294 @@ mod tests {
         let result = compressor.compress(data.as_bytes(), CompressionMethod::Huffman).unwrap();
         assert!(result.ratio < 1.0, "repetitive data should compress well");
     }
+
+    #[test]
+    fn test_overhead_breakdown() {
+        let config = CompressionConfig {
+            lz4_block_size: 1000,
+            ..Default::default()
+        };
+        let compressor = Compressor::new(config);
+        let data = "overhead accounting ".repeat(150);
+
+        let lz4 = compressor.compress(data.as_bytes(), CompressionMethod::Lz4Semantic).unwrap();
+        assert_eq!(lz4.metadata.overhead.block_headers, 1 + 3 * (2 + 1));
+        assert_eq!(lz4.metadata.overhead.tables, 0);
+
+        let huffman = compressor.compress(data.as_bytes(), CompressionMethod::Huffman).unwrap();
+        assert!(huffman.metadata.overhead.tables > 0);
+        assert_eq!(
+            huffman.total_encoded_size(),
+            huffman.data.len() + huffman.metadata.overhead.container
+        );
+        assert!(huffman.metadata.overhead.total() < huffman.total_encoded_size());
+    }
+
+    #[test]
+    fn test_compress_chunked_over_limit() {
+        let config = CompressionConfig {
+            max_input_size: 1000,
+            ..Default::default()
+        };
+        let compressor = Compressor::new(config);
+        let data = "segment me please ".repeat(200);
+        assert!(matches!(
+            compressor.compress(data.as_bytes(), CompressionMethod::Huffman),
+            Err(CompressError::InputTooLarge { size: 3600, limit: 1000 })
+        ));
+
+        let framed = compressor.compress_chunked(data.as_bytes(), CompressionMethod::Auto).unwrap();
+        let info = compressor.inspect(&framed).unwrap();
+        assert_eq!(info.segment_count, 4);
+        assert_eq!(info.estimated_decompressed_size, 3600);
+        assert_eq!(info.frame_size, framed.len() as u64);
+        assert!(CompressedOutput::from_frame(&framed).is_err());
+        assert_eq!(compressor.decompress_frame(&framed).unwrap(), data.as_bytes());
+
+        let single = compressor.compress_chunked(b"small", CompressionMethod::Huffman).unwrap();
+        assert_eq!(compressor.inspect(&single).unwrap().segment_count, 1);
+        assert_eq!(compressor.decompress_frame(&single).unwrap(), b"small");
+    }
+
+    #[test]
+    fn test_concatenated_frames_decode() {
+        let compressor = Compressor::default();
+        let first = compressor.compress(b"first segment, ", CompressionMethod::Huffman).unwrap().to_frame();
+        let second = compressor.compress(&[7u8; 500], CompressionMethod::EntropyCoding).unwrap().to_frame();
+        let mut appended = first.clone();
+        appended.extend_from_slice(&second);
+        appended.extend_from_slice(&first);
+
+        let mut expected = b"first segment, ".to_vec();
+        expected.extend_from_slice(&[7u8; 500]);
+        expected.extend_from_slice(b"first segment, ");
+        assert_eq!(frame::split_frames(&appended).unwrap().len(), 3);
+        assert_eq!(compressor.decompress_frame(&appended).unwrap(), expected);
+
+        let mut streamed = Vec::new();
+        let written = compressor.decompress_stream(&appended[..], &mut streamed).unwrap();
+        assert_eq!(written, expected.len() as u64);
+        assert_eq!(streamed, expected);
+
+        assert!(compressor.decompress_frame(&appended[..appended.len() - 1]).is_err());
+        assert!(compressor.decompress_stream(&appended[..appended.len() - 1], &mut Vec::new()).is_err());
+    }
+
+    #[test]
+    fn test_compress_vectored_matches_contiguous() {
+        use std::io::IoSlice;
+        let compressor = Compressor::default();
+        let head = b"GET /index.html HTTP/1.1\r\nHost: example.com\r\n".repeat(20);
+        let body = b"payload bytes from a ring buffer ".repeat(40);
+        let slices = [IoSlice::new(&head), IoSlice::new(b""), IoSlice::new(&body)];
+        let whole = [head.as_slice(), body.as_slice()].concat();
+        for method in crate::testing::all_methods() {
+            let vectored = compressor.compress_vectored(&slices, method).unwrap();
+            assert_eq!(compressor.decompress(&vectored).unwrap(), whole, "{:?}", method);
+            assert_eq!(vectored.checksum, Some(crc32fast::hash(&whole)));
+        }
+        let auto = compressor.compress_vectored(&slices, CompressionMethod::Auto).unwrap();
+        assert_eq!(compressor.decompress(&auto).unwrap(), whole);
+        assert!(compressor.compress_vectored(&[IoSlice::new(b"")], CompressionMethod::Huffman).is_err());
+    }
+
+    #[test]
+    fn test_nested_frame_policy() {
+        use crate::config::NestedFramePolicy;
+        let data = b"compressed once, then handed to the compressor again ".repeat(30);
+        let frame = Compressor::default().compress(&data, CompressionMethod::Huffman).unwrap().to_frame();
+        let policy = |nested_frames| {
+            Compressor::new(CompressionConfig {
+                nested_frames,
+                ..Default::default()
+            })
+        };
+
+        let warned = policy(NestedFramePolicy::Warn).compress(&frame, CompressionMethod::Lzss).unwrap();
+        assert!(warned.metadata.nested_frame);
+        assert_eq!(warned.method, CompressionMethod::Lzss);
+        let (head, tail) = frame.split_at(10);
+        let vectored = policy(NestedFramePolicy::Store)
+            .compress_vectored(&[std::io::IoSlice::new(head), std::io::IoSlice::new(tail)], CompressionMethod::Lzss)
+            .unwrap();
+        assert!(vectored.metadata.nested_frame);
+        assert_eq!(vectored.method, CompressionMethod::Stored);
+        assert_eq!(Compressor::default().decompress(&vectored).unwrap(), frame);
+        assert!(matches!(
+            policy(NestedFramePolicy::Reject).compress(&frame, CompressionMethod::Auto),
+            Err(CompressError::AlreadyCompressed)
+        ));
+        assert!(!policy(NestedFramePolicy::Reject).compress(&data, CompressionMethod::Auto).unwrap().metadata.nested_frame);
+    }
+
+    #[test]
+    fn test_blocks_iterator() {
+        let compressor = Compressor::default();
+        let data: Vec<u8> = (0..300_000u32).map(|i| (i % 251) as u8).collect();
+        for method in [CompressionMethod::Lz4Semantic, CompressionMethod::Huffman, CompressionMethod::SemanticLz] {
+            let out = compressor.compress(&data, method).unwrap();
+            let blocks: Vec<Vec<u8>> = out.blocks().unwrap().collect::<Result<_, _>>().unwrap();
+            assert!(blocks.len() > 1, "{:?}", method);
+            assert_eq!(blocks.concat(), data);
+        }
+
+        let mut out = compressor.compress(&data, CompressionMethod::Lz4Semantic).unwrap();
+        out.checksum = out.checksum.map(|c| c ^ 1);
+        let last = out.blocks().unwrap().last().unwrap();
+        assert!(matches!(last, Err(CompressError::ChecksumMismatch { .. })));
+    }
+
+    #[test]
+    fn test_compress_with_overrides() {
+        let compressor = Compressor::default();
+        let data = "override per call ".repeat(500);
+        let options = config::CompressOptions {
+            method: CompressionMethod::Lz4Semantic,
+            block_size: Some(1000),
+            checksum: Some(false),
+            ..Default::default()
+        };
+        let out = compressor.compress_with(data.as_bytes(), &options).unwrap();
+        assert_eq!(out.method, CompressionMethod::Lz4Semantic);
+        assert_eq!(out.checksum, None);
+        assert_eq!(compressor.inspect(&out.to_frame()).unwrap().block_count, 9);
+        assert_eq!(compressor.decompress_frame(&out.to_frame()).unwrap(), data.as_bytes());
+
+        let json = br#"{"id": 1, "name": "a", "status": "ok", "value": 2}"#;
+        let options = config::CompressOptions {
+            method: CompressionMethod::Huffman,
+            dictionary: Some(dictionary::DictionarySelection::Id(dictionary::JSON_KEYS)),
+            ..Default::default()
+        };
+        let out = compressor.compress_with(json, &options).unwrap();
+        assert_eq!(out.dictionary_id, Some(dictionary::JSON_KEYS));
+        assert!(out.checksum.is_some());
+        assert_eq!(compressor.decompress(&out).unwrap(), json);
+        // The compressor's own settings are untouched
+        assert_eq!(compressor.compress(json, CompressionMethod::Huffman).unwrap().dictionary_id, None);
+    }
+
+    #[test]
+    fn test_compress_to_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("data.sgma");
+        let compressor = Compressor::default();
+        let data = b"durable frame ".repeat(100);
+        let written = compressor.compress_to_file(&data, CompressionMethod::Auto, &path).unwrap();
+        let frame = std::fs::read(&path).unwrap();
+        assert_eq!(frame.len() as u64, written);
+        assert_eq!(compressor.decompress_frame(&frame).unwrap(), data);
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn test_static_dictionary_frame() {
+        let json = br#"{"id": 12, "name": "sensor", "status": "ok", "value": 3.5, "timestamp": null}"#;
+        let plain = Compressor::default().compress(json, CompressionMethod::Huffman).unwrap();
+        let compressor = Compressor::new(CompressionConfig {
+            dictionary: dictionary::DictionarySelection::Auto,
+            ..Default::default()
+        });
+        let out = compressor.compress(json, CompressionMethod::Huffman).unwrap();
+        assert_eq!(out.dictionary_id, Some(dictionary::JSON_KEYS));
+        assert!(out.total_encoded_size() < plain.total_encoded_size());
+
+        let frame = out.to_frame();
+        assert_eq!(compressor.inspect(&frame).unwrap().dictionary_id, Some(dictionary::JSON_KEYS));
+        assert_eq!(Compressor::default().decompress_frame(&frame).unwrap(), json);
+        assert!(compressor.verify(&out).unwrap().is_ok());
+        let transcoded = compressor.transcode(&out, CompressionMethod::Lz4Semantic).unwrap();
+        assert_eq!(compressor.decompress(&transcoded).unwrap(), json);
+
+        let forced = Compressor::new(CompressionConfig {
+            dictionary: dictionary::DictionarySelection::Id(99),
+            ..Default::default()
+        });
+        assert!(matches!(
+            forced.compress(json, CompressionMethod::Huffman),
+            Err(CompressError::UnknownDictionary(99))
+        ));
+    }
+
+    #[test]
+    fn test_registered_dictionary_resolved_on_decompress() {
+        let mut registry = dictionary::DictionaryRegistry::new();
+        let id = registry
+            .register("metrics", vec![b"cpu_usage_percent{host=".to_vec(), b"memory_rss_bytes{host=".to_vec()])
+            .unwrap();
+        let producer = Compressor::new(CompressionConfig {
+            dictionary: dictionary::DictionarySelection::Id(id),
+            ..Default::default()
+        })
+        .with_dictionaries(registry.clone());
+        let data = b"cpu_usage_percent{host=\"a\"} 12\nmemory_rss_bytes{host=\"a\"} 4096\n";
+        let frame = producer.compress(data, CompressionMethod::EntropyCoding).unwrap().to_frame();
+
+        assert!(matches!(
+            Compressor::default().decompress_frame(&frame),
+            Err(CompressError::UnknownDictionary(found)) if found == id
+        ));
+        let consumer = Compressor::default().with_dictionaries(registry);
+        assert_eq!(consumer.decompress_frame(&frame).unwrap(), data);
+    }
+
+    #[test]
+    fn test_semantic_metadata_report() {
+        let compressor = Compressor::default();
+        let data = [vec![b'x'; 64 * 4], vec![b'y'; 64]].concat();
+        let out = compressor.compress(&data, CompressionMethod::SemanticDedupe).unwrap();
+        assert_eq!(out.metadata.semantic_dedup_count, 3);
+        let report = out.metadata.semantic.as_ref().unwrap();
+        assert_eq!(report.clusters, 1);
+        assert_eq!(report.exact_bytes_saved, 64 * 3);
+        assert_eq!(report.embedding_calls, 0);
+
+        let huffman = compressor.compress(&data, CompressionMethod::Huffman).unwrap();
+        assert!(huffman.metadata.semantic.is_none());
+        assert_eq!(huffman.metadata.semantic_dedup_count, 0);
+    }
+
+    #[test]
+    fn test_estimate_ratio_tracks_actual() {
+        let compressor = Compressor::default();
+        let data = corpus::synthesize(&corpus::CorpusSpec {
+            len: 3 * 1024 * 1024,
+            duplicate_rate: 0.0,
+            near_duplicate_rate: 0.0,
+            entropy: 4.5,
+            ..Default::default()
+        })
+        .data;
+        for method in [CompressionMethod::Lz4Semantic, CompressionMethod::Huffman, CompressionMethod::Auto] {
+            let estimate = compressor.estimate_ratio(&data, method).unwrap();
+            let actual = compressor.compress(&data, method).unwrap().ratio;
+            assert!((estimate - actual).abs() / actual < 0.03, "{:?}: {} vs {}", method, estimate, actual);
+        }
+        let small = b"short input, compressed outright".repeat(10);
+        let exact = compressor.compress(&small, CompressionMethod::Lzss).unwrap().ratio;
+        assert_eq!(compressor.estimate_ratio(&small, CompressionMethod::Lzss).unwrap(), exact);
+    }
+
+    #[test]
+    fn test_deterministic_mode_gives_identical_bytes() {
+        let data: Vec<u8> = (0..6000u32).map(|i| b"aaaabbc"[(i * 7 % 11 % 7) as usize]).collect();
+        let config = CompressionConfig {
+            deterministic: true,
+            objective: config::Objective {
+                cpu_weight: 1.0,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let compressor = Compressor::new(config.clone()).with_history(history::MethodHistory::default());
+        let first = compressor.compress_adaptive(&data).unwrap().to_frame();
+        for _ in 0..3 {
+            assert_eq!(compressor.compress_adaptive(&data).unwrap().to_frame(), first);
+            assert_eq!(Compressor::new(config.clone()).compress_adaptive(&data).unwrap().to_frame(), first);
+        }
+        assert!(compressor.history().unwrap().records.is_empty());
+        // Huffman tables no longer follow hash map order
+        let huffman = |c: &Compressor| c.compress(&data, CompressionMethod::Huffman).unwrap().data;
+        assert_eq!(huffman(&Compressor::default()), huffman(&Compressor::default()));
+    }
 }
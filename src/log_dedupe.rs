@@ -0,0 +1,276 @@
+//! Line-based dedup for log files
+//!
+//! Each line is split on spaces into fields. Fields containing a digit
+//! (timestamps, ids, addresses, durations) are variable; the rest form the
+//! line's template. Every distinct template is stored once, and the line
+//! stream becomes template ids plus the variable field values. The three
+//! streams are compressed separately since each is far more uniform than the
+//! interleaved original.
+//!
+//! Layout: `[line_count:varint]` then, for the template dictionary, the
+//! template-id stream and the variable stream: `[raw_len:varint][len:varint][deflate blocks]`
+
+use crate::error::CompressError;
+use crate::{lz4_wrapper, varint};
+use std::collections::HashMap;
+
+/// Block size used for each section's deflate blocks
+const SECTION_BLOCK_SIZE: usize = 64 * 1024;
+
+const FIELD_CONST: u8 = 0;
+const FIELD_VAR: u8 = 1;
+
+fn is_variable(field: &[u8]) -> bool {
+    field.iter().any(u8::is_ascii_digit)
+}
+
+/// Compress newline-separated text
+pub fn compress(data: &[u8]) -> Result<Vec<u8>, CompressError> {
+    let mut encoder = StreamEncoder::new();
+    encoder.push(data);
+    encoder.finish()
+}
+
+/// Decompress log-dedupe data
+pub fn decompress(data: &[u8], original_size: usize) -> Result<Vec<u8>, CompressError> {
+    let mut output = Vec::with_capacity(original_size.min(crate::MAX_PREALLOC));
+    for block in BlockDecoder::new(data, usize::MAX)? {
+        output.extend_from_slice(&block?);
+    }
+    Ok(output)
+}
+
+/// Buffers input and templates it on `finish`; templating needs whole lines
+#[derive(Debug, Default)]
+pub struct StreamEncoder {
+    buffer: Vec<u8>,
+}
+
+impl StreamEncoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, data: &[u8]) {
+        self.buffer.extend_from_slice(data);
+    }
+
+    pub fn finish(self) -> Result<Vec<u8>, CompressError> {
+        let mut templates: HashMap<Vec<u8>, u64> = HashMap::new();
+        let mut dictionary = Vec::new();
+        let mut ids = Vec::new();
+        let mut vars = Vec::new();
+        let mut line_count = 0u64;
+
+        for line in self.buffer.split(|&b| b == b'\n') {
+            line_count += 1;
+            let mut template = Vec::new();
+            let fields: Vec<&[u8]> = line.split(|&b| b == b' ').collect();
+            varint::write_u64(&mut template, fields.len() as u64);
+            for field in fields {
+                if is_variable(field) {
+                    template.push(FIELD_VAR);
+                    varint::write_u64(&mut vars, field.len() as u64);
+                    vars.extend_from_slice(field);
+                } else {
+                    template.push(FIELD_CONST);
+                    varint::write_u64(&mut template, field.len() as u64);
+                    template.extend_from_slice(field);
+                }
+            }
+            let next_id = templates.len() as u64;
+            let id = *templates.entry(template).or_insert_with_key(|t| {
+                dictionary.extend_from_slice(t);
+                next_id
+            });
+            varint::write_u64(&mut ids, id);
+        }
+
+        let mut output = Vec::new();
+        varint::write_u64(&mut output, line_count);
+        for section in [&dictionary, &ids, &vars] {
+            let packed = lz4_wrapper::compress(section, SECTION_BLOCK_SIZE)?;
+            varint::write_u64(&mut output, section.len() as u64);
+            varint::write_u64(&mut output, packed.len() as u64);
+            output.extend_from_slice(&packed);
+        }
+        Ok(output)
+    }
+}
+
+/// Rebuilds lines, yielding blocks of roughly `block_len` bytes
+pub struct BlockDecoder {
+    templates: Vec<Vec<Option<Vec<u8>>>>,
+    ids: Vec<u8>,
+    ids_pos: usize,
+    vars: Vec<u8>,
+    vars_pos: usize,
+    remaining_lines: usize,
+    block_len: usize,
+}
+
+fn err(msg: &str) -> CompressError {
+    CompressError::LogDedupeError(msg.into())
+}
+
+impl BlockDecoder {
+    pub fn new(data: &[u8], block_len: usize) -> Result<Self, CompressError> {
+        let mut pos = 0;
+        let line_count = varint::read_usize(data, &mut pos).ok_or_else(|| err("data too short"))?;
+        let mut sections = Vec::with_capacity(3);
+        for _ in 0..3 {
+            let raw_len = varint::read_usize(data, &mut pos).ok_or_else(|| err("truncated section"))?;
+            let len = varint::read_usize(data, &mut pos).ok_or_else(|| err("truncated section"))?;
+            let packed = data
+                .get(pos..pos.saturating_add(len))
+                .ok_or_else(|| err("truncated section"))?;
+            pos += len;
+            let section = lz4_wrapper::decompress(packed, raw_len)?;
+            if section.len() != raw_len {
+                return Err(err("section size mismatch"));
+            }
+            sections.push(section);
+        }
+        if pos != data.len() {
+            return Err(err("trailing bytes"));
+        }
+        let vars = sections.pop().unwrap_or_default();
+        let ids = sections.pop().unwrap_or_default();
+        let dictionary = sections.pop().unwrap_or_default();
+
+        let mut templates = Vec::new();
+        let mut pos = 0;
+        while pos < dictionary.len() {
+            let fields = varint::read_usize(&dictionary, &mut pos).ok_or_else(|| err("truncated template"))?;
+            let mut template = Vec::with_capacity(fields.min(dictionary.len()));
+            for _ in 0..fields {
+                let kind = *dictionary.get(pos).ok_or_else(|| err("truncated template"))?;
+                pos += 1;
+                template.push(match kind {
+                    FIELD_VAR => None,
+                    FIELD_CONST => {
+                        let len = varint::read_usize(&dictionary, &mut pos).ok_or_else(|| err("truncated template"))?;
+                        let field = dictionary
+                            .get(pos..pos.saturating_add(len))
+                            .ok_or_else(|| err("truncated template"))?;
+                        pos += len;
+                        Some(field.to_vec())
+                    }
+                    _ => return Err(err("unknown field kind")),
+                });
+            }
+            templates.push(template);
+        }
+
+        Ok(Self {
+            templates,
+            ids,
+            ids_pos: 0,
+            vars,
+            vars_pos: 0,
+            remaining_lines: line_count,
+            block_len: block_len.max(1),
+        })
+    }
+
+    fn next_line(&mut self, out: &mut Vec<u8>) -> Result<(), CompressError> {
+        let id = varint::read_usize(&self.ids, &mut self.ids_pos).ok_or_else(|| err("truncated line ids"))?;
+        let template = self.templates.get(id).ok_or_else(|| err("unknown template"))?;
+        for (i, field) in template.iter().enumerate() {
+            if i > 0 {
+                out.push(b' ');
+            }
+            match field {
+                Some(constant) => out.extend_from_slice(constant),
+                None => {
+                    let len = varint::read_usize(&self.vars, &mut self.vars_pos)
+                        .ok_or_else(|| err("truncated variables"))?;
+                    let value = self
+                        .vars
+                        .get(self.vars_pos..self.vars_pos.saturating_add(len))
+                        .ok_or_else(|| err("truncated variables"))?;
+                    out.extend_from_slice(value);
+                    self.vars_pos += len;
+                }
+            }
+        }
+        self.remaining_lines -= 1;
+        if self.remaining_lines > 0 {
+            out.push(b'\n');
+        }
+        Ok(())
+    }
+}
+
+impl Iterator for BlockDecoder {
+    type Item = Result<Vec<u8>, CompressError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining_lines == 0 {
+            return None;
+        }
+        let mut block = Vec::new();
+        while self.remaining_lines > 0 && block.len() < self.block_len {
+            if let Err(e) = self.next_line(&mut block) {
+                self.remaining_lines = 0;
+                return Some(Err(e));
+            }
+        }
+        Some(Ok(block))
+    }
+}
+
+/// Number of distinct templates in a payload
+pub fn template_count(data: &[u8]) -> Result<usize, CompressError> {
+    Ok(BlockDecoder::new(data, usize::MAX)?.templates.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn access_log(lines: usize) -> String {
+        (0..lines)
+            .map(|i| {
+                format!(
+                    "2024-03-{:02}T12:{:02}:{:02}Z INFO request handled path=/api/v1/items/{} status={} took={}ms\n",
+                    i % 28 + 1,
+                    i % 60,
+                    (i * 7) % 60,
+                    i * 13,
+                    if i % 10 == 0 { 404 } else { 200 },
+                    i % 250
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_log_dedupe_roundtrip() {
+        for data in [access_log(200), "no trailing newline\nsecond  line 42".to_string(), String::new()] {
+            let compressed = compress(data.as_bytes()).unwrap();
+            assert_eq!(decompress(&compressed, data.len()).unwrap(), data.as_bytes());
+        }
+    }
+
+    #[test]
+    fn test_log_dedupe_templates_lines() {
+        let data = access_log(500);
+        let compressed = compress(data.as_bytes()).unwrap();
+        assert_eq!(template_count(&compressed).unwrap(), 2);
+        let fixed_chunks = crate::semantic::compress(data.as_bytes(), 0.95).unwrap();
+        assert!(compressed.len() * 4 < fixed_chunks.len());
+    }
+
+    #[test]
+    fn test_block_decoder_splits_on_lines() {
+        let data = access_log(100);
+        let compressed = compress(data.as_bytes()).unwrap();
+        let blocks: Vec<Vec<u8>> = BlockDecoder::new(&compressed, 1000)
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert!(blocks.len() > 1);
+        assert_eq!(blocks.concat(), data.as_bytes());
+    }
+}
@@ -1,69 +1,227 @@
 //! LZ4 wrapper for block-level compression with semantic awareness
 
+use crate::config::CompressionLevel;
 use crate::error::CompressError;
 
 /// Compress data using LZ4-style block compression
 pub fn compress(data: &[u8], block_size: usize) -> Result<Vec<u8>, CompressError> {
+    compress_with_level(data, block_size, CompressionLevel::Fast)
+}
+
+/// [`compress`] with lazier, deeper match search at higher levels
+pub fn compress_with_level(data: &[u8], block_size: usize, level: CompressionLevel) -> Result<Vec<u8>, CompressError> {
     // Simple LZ4-like compression: store block headers + compressed blocks
-    let mut output = Vec::new();
-    let num_blocks = (data.len() + block_size - 1) / block_size;
-    output.extend_from_slice(&(num_blocks as u32).to_le_bytes());
+    let mut encoder = StreamEncoder::with_level(block_size, level);
+    encoder.push(data)?;
+    encoder.finish()
+}
 
-    for chunk in data.chunks(block_size) {
-        // Use flate2 for actual compression of each block
-        let compressed = lz4_compress_block(chunk)?;
-        output.extend_from_slice(&(chunk.len() as u32).to_le_bytes());
-        output.extend_from_slice(&(compressed.len() as u32).to_le_bytes());
-        output.extend_from_slice(&compressed);
+/// Decompress LZ4-compressed data
+pub fn decompress(data: &[u8], original_size: usize) -> Result<Vec<u8>, CompressError> {
+    let mut output = Vec::with_capacity(original_size.min(crate::MAX_PREALLOC));
+    for block in BlockDecoder::new(data)? {
+        output.extend_from_slice(&block?);
     }
-
     Ok(output)
 }
 
-/// Decompress LZ4-compressed data
-pub fn decompress(data: &[u8], original_size: usize) -> Result<Vec<u8>, CompressError> {
-    if data.len() < 4 {
-        return Err(CompressError::Lz4Error("data too short".into()));
+/// Incremental encoder producing the same layout as [`compress`]
+pub struct StreamEncoder {
+    block_size: usize,
+    level: CompressionLevel,
+    buffers: EncoderBuffers,
+    num_blocks: u64,
+}
+
+/// Working buffers of a [`StreamEncoder`], kept between encoders by
+/// [`crate::scratch::ScratchBuffers`]
+#[derive(Debug, Default)]
+pub(crate) struct EncoderBuffers {
+    /// Input of the block being filled
+    pending: Vec<u8>,
+    /// Encoded blocks so far, headers included
+    blocks: Vec<u8>,
+    /// Deflate output of one block
+    block: Vec<u8>,
+}
+
+impl EncoderBuffers {
+    pub(crate) fn capacity(&self) -> usize {
+        self.pending.capacity() + self.blocks.capacity() + self.block.capacity()
     }
+}
 
-    let mut pos = 0;
-    let num_blocks =
-        u32::from_le_bytes([data[pos], data[pos + 1], data[pos + 2], data[pos + 3]]) as usize;
-    pos += 4;
+impl StreamEncoder {
+    pub fn new(block_size: usize) -> Self {
+        Self::with_level(block_size, CompressionLevel::Fast)
+    }
 
-    let mut output = Vec::with_capacity(original_size);
+    pub fn with_level(block_size: usize, level: CompressionLevel) -> Self {
+        Self::with_buffers(block_size, level, EncoderBuffers::default())
+    }
 
-    for _ in 0..num_blocks {
-        if pos + 8 > data.len() {
-            return Err(CompressError::Lz4Error("truncated block header".into()));
+    /// Encoder reusing `buffers` from an earlier one
+    pub(crate) fn with_buffers(block_size: usize, level: CompressionLevel, mut buffers: EncoderBuffers) -> Self {
+        buffers.pending.clear();
+        buffers.blocks.clear();
+        Self {
+            block_size: block_size.max(1),
+            level,
+            buffers,
+            num_blocks: 0,
+        }
+    }
+
+    /// Feed more input; full blocks are compressed as soon as they are complete
+    pub fn push(&mut self, mut data: &[u8]) -> Result<(), CompressError> {
+        while !data.is_empty() {
+            if self.buffers.pending.is_empty() && data.len() >= self.block_size {
+                let (block, rest) = data.split_at(self.block_size);
+                self.emit(block)?;
+                data = rest;
+                continue;
+            }
+            let take = (self.block_size - self.buffers.pending.len()).min(data.len());
+            self.buffers.pending.extend_from_slice(&data[..take]);
+            data = &data[take..];
+            if self.buffers.pending.len() == self.block_size {
+                self.emit_pending()?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Compress the trailing partial block and return the encoded stream
+    pub fn finish(self) -> Result<Vec<u8>, CompressError> {
+        let mut output = Vec::with_capacity(10 + self.buffers.blocks.len() + self.buffers.pending.len());
+        self.finish_into(&mut output)?;
+        Ok(output)
+    }
+
+    /// [`StreamEncoder::finish`] appending to `output`; hands back the
+    /// working buffers for reuse
+    pub(crate) fn finish_into(mut self, output: &mut Vec<u8>) -> Result<EncoderBuffers, CompressError> {
+        if !self.buffers.pending.is_empty() {
+            self.emit_pending()?;
+        }
+        crate::varint::write_u64(output, self.num_blocks);
+        output.extend_from_slice(&self.buffers.blocks);
+        Ok(self.buffers)
+    }
+
+    fn emit_pending(&mut self) -> Result<(), CompressError> {
+        let pending = std::mem::take(&mut self.buffers.pending);
+        let result = self.emit(&pending);
+        self.buffers.pending = pending;
+        self.buffers.pending.clear();
+        result
+    }
+
+    fn emit(&mut self, chunk: &[u8]) -> Result<(), CompressError> {
+        let EncoderBuffers { blocks, block, .. } = &mut self.buffers;
+        block.clear();
+        lz4_compress_block_into(chunk, self.level, block)?;
+        crate::varint::write_u64(blocks, chunk.len() as u64);
+        crate::varint::write_u64(blocks, block.len() as u64);
+        blocks.extend_from_slice(block);
+        self.num_blocks += 1;
+        Ok(())
+    }
+}
+
+/// Decodes one block at a time without materializing the whole output
+pub struct BlockDecoder<'a> {
+    data: &'a [u8],
+    pos: usize,
+    remaining: usize,
+}
+
+impl<'a> BlockDecoder<'a> {
+    pub fn new(data: &'a [u8]) -> Result<Self, CompressError> {
+        let mut pos = 0;
+        let num_blocks = read_len(data, &mut pos, "data too short")?;
+        Ok(Self {
+            data,
+            pos,
+            remaining: num_blocks,
+        })
+    }
+
+    fn next_block(&mut self) -> Result<Vec<u8>, CompressError> {
+        let (_orig_len, comp_len) = read_block_header(self.data, &mut self.pos)?;
+        let pos = self.pos;
+        let block = lz4_decompress_block(&self.data[pos..pos + comp_len])?;
+        self.pos = pos + comp_len;
+        Ok(block)
+    }
+}
+
+fn read_len(data: &[u8], pos: &mut usize, what: &str) -> Result<usize, CompressError> {
+    crate::varint::read_usize(data, pos).ok_or_else(|| CompressError::Lz4Error(what.into()))
+}
+
+/// Read `(orig_len, comp_len)` and check the compressed bytes are present
+fn read_block_header(data: &[u8], pos: &mut usize) -> Result<(usize, usize), CompressError> {
+    let orig_len = read_len(data, pos, "truncated block header")?;
+    let comp_len = read_len(data, pos, "truncated block header")?;
+    if comp_len > data.len() - *pos {
+        return Err(CompressError::Lz4Error("truncated block data".into()));
+    }
+    Ok((orig_len, comp_len))
+}
+
+impl Iterator for BlockDecoder<'_> {
+    type Item = Result<Vec<u8>, CompressError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
         }
-        let _orig_len =
-            u32::from_le_bytes([data[pos], data[pos + 1], data[pos + 2], data[pos + 3]]) as usize;
-        pos += 4;
-        let comp_len =
-            u32::from_le_bytes([data[pos], data[pos + 1], data[pos + 2], data[pos + 3]]) as usize;
-        pos += 4;
-
-        if pos + comp_len > data.len() {
-            return Err(CompressError::Lz4Error("truncated block data".into()));
+        self.remaining -= 1;
+        let block = self.next_block();
+        if block.is_err() {
+            self.remaining = 0;
         }
-        let block = lz4_decompress_block(&data[pos..pos + comp_len])?;
-        output.extend_from_slice(&block);
+        Some(block)
+    }
+}
+
+/// Decompressed size of every block, read from the block headers only
+pub fn block_sizes(data: &[u8]) -> Result<Vec<usize>, CompressError> {
+    let mut pos = 0;
+    let num_blocks = read_len(data, &mut pos, "data too short")?;
+    let mut sizes = Vec::with_capacity(num_blocks.min(data.len() / 2));
+    for _ in 0..num_blocks {
+        let (orig_len, comp_len) = read_block_header(data, &mut pos)?;
+        sizes.push(orig_len);
         pos += comp_len;
     }
+    Ok(sizes)
+}
 
-    Ok(output)
+/// Bytes spent on the block count and per-block length headers
+pub fn header_len(data: &[u8]) -> Result<usize, CompressError> {
+    let mut pos = 0;
+    let num_blocks = read_len(data, &mut pos, "data too short")?;
+    let mut payload = 0;
+    for _ in 0..num_blocks {
+        let (_, comp_len) = read_block_header(data, &mut pos)?;
+        pos += comp_len;
+        payload += comp_len;
+    }
+    Ok(pos - payload)
 }
 
-fn lz4_compress_block(data: &[u8]) -> Result<Vec<u8>, CompressError> {
+fn lz4_compress_block_into(data: &[u8], level: CompressionLevel, output: &mut Vec<u8>) -> Result<(), CompressError> {
     use std::io::Write;
-    let mut encoder = flate2::write::DeflateEncoder::new(Vec::new(), flate2::Compression::fast());
+    let mut encoder = flate2::write::DeflateEncoder::new(output, level.deflate());
     encoder
         .write_all(data)
         .map_err(|e| CompressError::Lz4Error(e.to_string()))?;
     encoder
         .finish()
-        .map_err(|e| CompressError::Lz4Error(e.to_string()))
+        .map_err(|e| CompressError::Lz4Error(e.to_string()))?;
+    Ok(())
 }
 
 fn lz4_decompress_block(data: &[u8]) -> Result<Vec<u8>, CompressError> {
@@ -96,6 +254,36 @@ mod tests {
         assert_eq!(decompressed, data);
     }
 
+    #[test]
+    fn test_stream_encoder_matches_compress() {
+        let data: Vec<u8> = (0..1000u32).map(|i| (i % 7) as u8).collect();
+        let mut encoder = StreamEncoder::new(128);
+        for piece in data.chunks(37) {
+            encoder.push(piece).unwrap();
+        }
+        assert_eq!(encoder.finish().unwrap(), compress(&data, 128).unwrap());
+    }
+
+    #[test]
+    fn test_block_decoder_yields_blocks() {
+        let data = vec![9u8; 300];
+        let compressed = compress(&data, 128).unwrap();
+        let sizes: Vec<usize> = BlockDecoder::new(&compressed)
+            .unwrap()
+            .map(|b| b.unwrap().len())
+            .collect();
+        assert_eq!(sizes, vec![128, 128, 44]);
+    }
+
+    #[test]
+    fn test_higher_level_roundtrips_smaller() {
+        let data = "the quick brown fox jumps over the lazy dog; the lazy dog sleeps. ".repeat(400);
+        let fast = compress(data.as_bytes(), 4096).unwrap();
+        let max = compress_with_level(data.as_bytes(), 4096, CompressionLevel::Max).unwrap();
+        assert!(max.len() <= fast.len());
+        assert_eq!(decompress(&max, data.len()).unwrap(), data.as_bytes());
+    }
+
     #[test]
     fn test_lz4_small_data() {
         let data = b"hi";
@@ -103,4 +291,15 @@ mod tests {
         let decompressed = decompress(&compressed, data.len()).unwrap();
         assert_eq!(decompressed, data);
     }
+
+    #[cfg(target_pointer_width = "64")]
+    #[test]
+    fn test_block_header_beyond_u32() {
+        let mut data = Vec::new();
+        crate::varint::write_u64(&mut data, 1);
+        crate::varint::write_u64(&mut data, u32::MAX as u64 + 1);
+        crate::varint::write_u64(&mut data, 0);
+        assert_eq!(block_sizes(&data).unwrap(), vec![u32::MAX as usize + 1]);
+        assert_eq!(header_len(&data).unwrap(), data.len());
+    }
 }
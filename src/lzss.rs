@@ -0,0 +1,529 @@
+//! LZSS: LZ77 matching against a sliding window, with Huffman-coded tokens
+//!
+//! Input becomes a sequence of literals and `(offset, length)` back-references
+//! into the previous `window_size` bytes. Tokens are split into four streams
+//! (match flags, literals, lengths, offsets), each Huffman coded on its own
+//! since their statistics have nothing in common.
+//!
+//! Layout: `[window_size:varint][min_match:varint][token_count:varint]`, then
+//! for the flag, literal, length and offset streams:
+//! `[raw_len:varint][len:varint][huffman payload]`. Lengths are stored as
+//! `length - min_match` and offsets as `offset - 1`, both varints. No match
+//! is longer than [`MAX_MATCH`].
+
+use crate::config::{CompressionLevel, LzssConfig, DEFAULT_HASH_BITS};
+use crate::error::CompressError;
+use crate::{huffman, varint};
+use std::time::Instant;
+
+/// Match candidates examined per position
+const MAX_CHAIN: usize = 64;
+/// Match candidates examined per position at [`CompressionLevel::Max`]
+const MAX_CHAIN_DEEP: usize = 1024;
+/// Accepted [`LzssConfig::hash_bits`]
+const HASH_BITS_RANGE: std::ops::RangeInclusive<u32> = 10..=20;
+/// Positions coded between deadline checks of [`compress_bounded`]
+const DEADLINE_CHECK_INTERVAL: usize = 1024;
+const NO_POS: usize = usize::MAX;
+/// Longest match the format allows; decoders reject longer ones
+pub const MAX_MATCH: usize = 64 * 1024;
+
+fn err(msg: &str) -> CompressError {
+    CompressError::LzssError(msg.into())
+}
+
+fn hash3(data: &[u8], hash_bits: u32) -> usize {
+    let v = (data[0] as u32) << 16 | (data[1] as u32) << 8 | data[2] as u32;
+    (v.wrapping_mul(2654435761) >> (32 - hash_bits)) as usize
+}
+
+/// Compress with the given window and match lengths
+pub fn compress(data: &[u8], config: &LzssConfig) -> Result<Vec<u8>, CompressError> {
+    compress_with_level(data, config, CompressionLevel::Fast)
+}
+
+/// [`compress`] with lazy matching and deeper chains at higher levels
+pub fn compress_with_level(data: &[u8], config: &LzssConfig, level: CompressionLevel) -> Result<Vec<u8>, CompressError> {
+    let mut encoder = StreamEncoder::with_level(config, level)?;
+    encoder.push(data);
+    encoder.finish()
+}
+
+/// Decompress LZSS data; tokens expanding past `original_size` bytes are
+/// rejected before they are copied
+pub fn decompress(data: &[u8], original_size: usize) -> Result<Vec<u8>, CompressError> {
+    let mut output = Vec::with_capacity(original_size.min(crate::MAX_PREALLOC));
+    for block in BlockDecoder::new(data, original_size, usize::MAX)? {
+        output.extend_from_slice(&block?);
+    }
+    Ok(output)
+}
+
+/// Buffers input and tokenizes it on `finish`
+#[derive(Debug)]
+pub struct StreamEncoder {
+    config: LzssConfig,
+    level: CompressionLevel,
+    buffer: Vec<u8>,
+}
+
+impl StreamEncoder {
+    pub fn new(config: &LzssConfig) -> Result<Self, CompressError> {
+        Self::with_level(config, CompressionLevel::Fast)
+    }
+
+    pub fn with_level(config: &LzssConfig, level: CompressionLevel) -> Result<Self, CompressError> {
+        validate(config)?;
+        Ok(Self {
+            config: config.clone(),
+            level,
+            buffer: Vec::new(),
+        })
+    }
+
+    pub fn push(&mut self, data: &[u8]) {
+        self.buffer.extend_from_slice(data);
+    }
+
+    pub fn finish(self) -> Result<Vec<u8>, CompressError> {
+        let max_chain = if self.level == CompressionLevel::Max {
+            MAX_CHAIN_DEEP
+        } else {
+            MAX_CHAIN
+        };
+        let lazy = self.level > CompressionLevel::Fast;
+        Ok(encode(&self.buffer, &self.config, max_chain, lazy, None)?.expect("no deadline set"))
+    }
+}
+
+fn validate(config: &LzssConfig) -> Result<(), CompressError> {
+    if config.window_size == 0 || config.min_match < 3 || config.max_match < config.min_match {
+        return Err(err("window must be non-empty and 3 <= min_match <= max_match"));
+    }
+    if config.max_match > MAX_MATCH {
+        return Err(err("max_match above the format limit of 65536"));
+    }
+    if config.hash_bits != 0 && !HASH_BITS_RANGE.contains(&config.hash_bits) {
+        return Err(err("hash_bits must be 0 (default) or between 10 and 20"));
+    }
+    Ok(())
+}
+
+/// Greedy [`compress`] examining at most `max_chain` candidates per
+/// position; `None` once `deadline` has passed, which is checked every
+/// [`DEADLINE_CHECK_INTERVAL`] positions
+pub(crate) fn compress_bounded(
+    data: &[u8],
+    config: &LzssConfig,
+    max_chain: usize,
+    deadline: Instant,
+) -> Result<Option<Vec<u8>>, CompressError> {
+    validate(config)?;
+    encode(data, config, max_chain.max(1), false, Some(deadline))
+}
+
+fn encode(
+    data: &[u8],
+    config: &LzssConfig,
+    max_chain: usize,
+    lazy: bool,
+    deadline: Option<Instant>,
+) -> Result<Option<Vec<u8>>, CompressError> {
+    let LzssConfig {
+        window_size,
+        min_match,
+        max_match,
+        hash_bits,
+    } = *config;
+    let hash_bits = if hash_bits == 0 { DEFAULT_HASH_BITS } else { hash_bits };
+
+    let mut flags = Vec::new();
+    let mut literals = Vec::new();
+    let mut lengths = Vec::new();
+    let mut offsets = Vec::new();
+    let mut tokens = 0u64;
+    let mut flag_byte = 0u8;
+
+    let mut matcher = Matcher::new(data.len(), window_size, max_chain, hash_bits);
+    let mut pos = 0;
+    let mut next_check = DEADLINE_CHECK_INTERVAL;
+    while pos < data.len() {
+        if pos >= next_check {
+            if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                return Ok(None);
+            }
+            next_check = pos + DEADLINE_CHECK_INTERVAL;
+        }
+        let (len, offset) = matcher.longest_match(data, pos, window_size, max_match);
+        let mut is_match = len >= min_match;
+        if is_match && lazy && len < max_match {
+            // Defer to the next position if it starts a longer match
+            matcher.insert_until(data, pos + 1);
+            let (next_len, _) = matcher.longest_match(data, pos + 1, window_size, max_match);
+            is_match = next_len <= len;
+        }
+        if is_match {
+            varint::write_u64(&mut lengths, (len - min_match) as u64);
+            varint::write_u64(&mut offsets, (offset - 1) as u64);
+            pos += len;
+        } else {
+            literals.push(data[pos]);
+            pos += 1;
+        }
+        matcher.insert_until(data, pos);
+        flag_byte |= (is_match as u8) << (tokens % 8);
+        tokens += 1;
+        if tokens.is_multiple_of(8) {
+            flags.push(flag_byte);
+            flag_byte = 0;
+        }
+    }
+    if !tokens.is_multiple_of(8) {
+        flags.push(flag_byte);
+    }
+
+    let mut output = Vec::new();
+    varint::write_u64(&mut output, window_size as u64);
+    varint::write_u64(&mut output, min_match as u64);
+    varint::write_u64(&mut output, tokens);
+    for section in [&flags, &literals, &lengths, &offsets] {
+        let packed = if section.is_empty() {
+            Vec::new()
+        } else {
+            huffman::compress(section)?
+        };
+        varint::write_u64(&mut output, section.len() as u64);
+        varint::write_u64(&mut output, packed.len() as u64);
+        output.extend_from_slice(&packed);
+    }
+    Ok(Some(output))
+}
+
+/// Hash chains over 3-byte prefixes
+struct Matcher {
+    head: Vec<usize>,
+    /// Previous position with the same hash, a ring indexed by position;
+    /// longer than the window, so no slot a chain walk reaches is reused
+    prev: Vec<usize>,
+    prev_mask: usize,
+    max_chain: usize,
+    hash_bits: u32,
+    /// Positions below this are already in the chains
+    inserted: usize,
+}
+
+impl Matcher {
+    fn new(len: usize, window_size: usize, max_chain: usize, hash_bits: u32) -> Self {
+        let ring = window_size.saturating_add(1).min(len.max(1)).next_power_of_two();
+        Self {
+            head: vec![NO_POS; 1 << hash_bits],
+            prev: vec![NO_POS; ring],
+            prev_mask: ring - 1,
+            max_chain,
+            hash_bits,
+            inserted: 0,
+        }
+    }
+
+    /// Add every position before `end` not yet in the chains
+    fn insert_until(&mut self, data: &[u8], end: usize) {
+        let end = end.min(data.len().saturating_sub(2));
+        while self.inserted < end {
+            let pos = self.inserted;
+            let h = hash3(&data[pos..], self.hash_bits);
+            self.prev[pos & self.prev_mask] = self.head[h];
+            self.head[h] = pos;
+            self.inserted += 1;
+        }
+    }
+
+    /// Longest `(length, offset)` match for `pos` within the window
+    fn longest_match(&self, data: &[u8], pos: usize, window_size: usize, max_match: usize) -> (usize, usize) {
+        let mut best = (0, 0);
+        if pos + 3 > data.len() {
+            return best;
+        }
+        let limit = max_match.min(data.len() - pos);
+        let mut candidate = self.head[hash3(&data[pos..], self.hash_bits)];
+        let mut chain = 0;
+        while candidate != NO_POS && pos - candidate <= window_size && chain < self.max_chain {
+            let len = data[candidate..]
+                .iter()
+                .zip(&data[pos..pos + limit])
+                .take_while(|(a, b)| a == b)
+                .count();
+            if len > best.0 {
+                best = (len, pos - candidate);
+                if len == limit {
+                    break;
+                }
+            }
+            candidate = self.prev[candidate & self.prev_mask];
+            chain += 1;
+        }
+        best
+    }
+}
+
+/// Bytes of the stream header, section headers and Huffman code tables
+pub fn table_len(data: &[u8]) -> Result<usize, CompressError> {
+    let parsed = parse(data)?;
+    let mut len = parsed.header_len;
+    for (_, payload) in parsed.sections {
+        if !payload.is_empty() {
+            len += huffman::table_len(payload)?;
+        }
+    }
+    Ok(len)
+}
+
+struct Parsed<'a> {
+    window_size: usize,
+    min_match: usize,
+    tokens: usize,
+    /// `(raw_len, huffman payload)` of the flag, literal, length and offset streams
+    sections: Vec<(usize, &'a [u8])>,
+    /// Header bytes, section length headers included
+    header_len: usize,
+}
+
+fn parse(data: &[u8]) -> Result<Parsed<'_>, CompressError> {
+    let mut pos = 0;
+    let read = |pos: &mut usize| varint::read_usize(data, pos).ok_or_else(|| err("truncated header"));
+    let window_size = read(&mut pos)?;
+    let min_match = read(&mut pos)?;
+    let tokens = read(&mut pos)?;
+    let mut header_len = pos;
+    let mut sections = Vec::with_capacity(4);
+    for _ in 0..4 {
+        let start = pos;
+        let raw_len = read(&mut pos)?;
+        let len = read(&mut pos)?;
+        header_len += pos - start;
+        let payload = data.get(pos..pos.saturating_add(len)).ok_or_else(|| err("truncated section"))?;
+        pos += len;
+        sections.push((raw_len, payload));
+    }
+    if pos != data.len() {
+        return Err(err("trailing bytes"));
+    }
+    Ok(Parsed {
+        window_size,
+        min_match,
+        tokens,
+        sections,
+        header_len,
+    })
+}
+
+/// Replays tokens, yielding blocks of roughly `block_len` bytes; keeps only
+/// the last window of output as history
+pub struct BlockDecoder {
+    window_size: usize,
+    min_match: usize,
+    remaining_tokens: usize,
+    /// Output still allowed by the declared original size
+    remaining_output: usize,
+    token_index: usize,
+    flags: Vec<u8>,
+    literals: Vec<u8>,
+    literal_pos: usize,
+    lengths: Vec<u8>,
+    length_pos: usize,
+    offsets: Vec<u8>,
+    offset_pos: usize,
+    history: Vec<u8>,
+    block_len: usize,
+}
+
+impl BlockDecoder {
+    /// Decoder failing once the tokens expand past `original_size` bytes
+    pub fn new(data: &[u8], original_size: usize, block_len: usize) -> Result<Self, CompressError> {
+        let parsed = parse(data)?;
+        let mut streams = Vec::with_capacity(4);
+        for (raw_len, payload) in parsed.sections {
+            let stream = if raw_len == 0 {
+                Vec::new()
+            } else {
+                huffman::decompress(payload, raw_len)?
+            };
+            if stream.len() != raw_len {
+                return Err(err("section size mismatch"));
+            }
+            streams.push(stream);
+        }
+        let offsets = streams.pop().unwrap_or_default();
+        let lengths = streams.pop().unwrap_or_default();
+        let literals = streams.pop().unwrap_or_default();
+        let flags = streams.pop().unwrap_or_default();
+        if flags.len() != parsed.tokens.div_ceil(8) {
+            return Err(err("flag stream does not match token count"));
+        }
+        Ok(Self {
+            window_size: parsed.window_size,
+            min_match: parsed.min_match,
+            remaining_tokens: parsed.tokens,
+            remaining_output: original_size,
+            token_index: 0,
+            flags,
+            literals,
+            literal_pos: 0,
+            lengths,
+            length_pos: 0,
+            offsets,
+            offset_pos: 0,
+            history: Vec::new(),
+            block_len: block_len.max(1),
+        })
+    }
+
+    fn next_token(&mut self) -> Result<(), CompressError> {
+        let is_match = self.flags[self.token_index / 8] >> (self.token_index % 8) & 1 == 1;
+        self.token_index += 1;
+        self.remaining_tokens -= 1;
+        if !is_match {
+            if self.remaining_output == 0 {
+                return Err(err("output exceeds the declared size"));
+            }
+            self.remaining_output -= 1;
+            let b = *self.literals.get(self.literal_pos).ok_or_else(|| err("truncated literals"))?;
+            self.literal_pos += 1;
+            self.history.push(b);
+            return Ok(());
+        }
+        let len = varint::read_usize(&self.lengths, &mut self.length_pos)
+            .and_then(|l| l.checked_add(self.min_match))
+            .ok_or_else(|| err("truncated lengths"))?;
+        let offset = varint::read_usize(&self.offsets, &mut self.offset_pos)
+            .and_then(|o| o.checked_add(1))
+            .ok_or_else(|| err("truncated offsets"))?;
+        if len > MAX_MATCH || len > self.remaining_output {
+            return Err(err("match exceeds the declared size or the format's max match"));
+        }
+        if offset > self.history.len() || offset > self.window_size {
+            return Err(err("offset outside window"));
+        }
+        self.remaining_output -= len;
+        let start = self.history.len() - offset;
+        for i in 0..len {
+            let b = self.history[start + i];
+            self.history.push(b);
+        }
+        Ok(())
+    }
+}
+
+impl Iterator for BlockDecoder {
+    type Item = Result<Vec<u8>, CompressError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining_tokens == 0 {
+            return None;
+        }
+        // Drop history the next block can no longer reference
+        let keep_from = self.history.len().saturating_sub(self.window_size);
+        self.history.drain(..keep_from);
+        let block_start = self.history.len();
+        while self.remaining_tokens > 0 && self.history.len() - block_start < self.block_len {
+            if let Err(e) = self.next_token() {
+                self.remaining_tokens = 0;
+                return Some(Err(e));
+            }
+        }
+        Some(Ok(self.history[block_start..].to_vec()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lzss_roundtrip() {
+        let config = LzssConfig::default();
+        let inputs: [&[u8]; 4] = [
+            b"abcabcabcabcabcabc the quick brown fox abcabc",
+            &[0u8; 10_000],
+            b"a",
+            &(0..=255).collect::<Vec<u8>>(),
+        ];
+        for data in inputs {
+            let compressed = compress(data, &config).unwrap();
+            assert_eq!(decompress(&compressed, data.len()).unwrap(), data);
+        }
+    }
+
+    #[test]
+    fn test_lzss_beats_rle_on_text() {
+        let data = "fn main() { println!(\"hello, world\"); }\n".repeat(200);
+        let compressed = compress(data.as_bytes(), &LzssConfig::default()).unwrap();
+        assert!(compressed.len() * 10 < data.len());
+        assert!(compressed.len() < crate::entropy::compress(data.as_bytes()).unwrap().len());
+    }
+
+    #[test]
+    fn test_window_limits_offsets() {
+        let block: Vec<u8> = (0..200u32).map(|i| (i * 7 % 251) as u8).collect();
+        let data = [block.clone(), vec![b'x'; 1000], block].concat();
+        let small = LzssConfig {
+            window_size: 512,
+            ..Default::default()
+        };
+        let near = compress(&data, &small).unwrap();
+        let far = compress(&data, &LzssConfig::default()).unwrap();
+        assert!(far.len() < near.len());
+        assert_eq!(decompress(&near, data.len()).unwrap(), data);
+        assert!(StreamEncoder::new(&LzssConfig { min_match: 2, ..Default::default() }).is_err());
+    }
+
+    #[test]
+    fn test_lazy_matching_not_worse_than_greedy() {
+        let data = "abcd bcdefgh abcdefgh cdefghij abcdefghij ".repeat(300);
+        let config = LzssConfig::default();
+        let greedy = compress(data.as_bytes(), &config).unwrap();
+        for level in [CompressionLevel::Balanced, CompressionLevel::Max] {
+            let lazy = compress_with_level(data.as_bytes(), &config, level).unwrap();
+            assert!(lazy.len() <= greedy.len());
+            assert_eq!(decompress(&lazy, data.len()).unwrap(), data.as_bytes());
+        }
+    }
+
+    #[test]
+    fn test_block_decoder_keeps_window_history() {
+        let data = "window history spans blocks ".repeat(500);
+        let compressed = compress(data.as_bytes(), &LzssConfig::default()).unwrap();
+        let blocks: Vec<Vec<u8>> = BlockDecoder::new(&compressed, data.len(), 1000)
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert!(blocks.len() > 5);
+        assert_eq!(blocks.concat(), data.as_bytes());
+    }
+
+    #[test]
+    fn test_oversized_matches_rejected() {
+        let data = b"abcdefgh".repeat(64);
+        let config = LzssConfig::default();
+        let compressed = compress(&data, &config).unwrap();
+        assert!(decompress(&compressed, data.len() - 1).is_err());
+
+        // One literal, then a match claiming a length far past anything declared
+        let mut lengths = Vec::new();
+        varint::write_u64(&mut lengths, u32::MAX as u64);
+        let mut offsets = Vec::new();
+        varint::write_u64(&mut offsets, 0);
+        let mut forged = Vec::new();
+        for v in [32 * 1024, 4, 2] {
+            varint::write_u64(&mut forged, v);
+        }
+        for section in [vec![0b10u8], vec![b'a'], lengths, offsets] {
+            let packed = huffman::compress(&section).unwrap();
+            varint::write_u64(&mut forged, section.len() as u64);
+            varint::write_u64(&mut forged, packed.len() as u64);
+            forged.extend_from_slice(&packed);
+        }
+        assert!(decompress(&forged, 16).is_err());
+        assert!(decompress(&forged, usize::MAX).is_err());
+        assert!(StreamEncoder::new(&LzssConfig { max_match: MAX_MATCH + 1, ..config }).is_err());
+    }
+}
@@ -0,0 +1,107 @@
+//! `sigma-compress` command-line tool
+//!
+//! ```text
+//! sigma-compress list <archive-dir>
+//! sigma-compress --pipe [-d] [--method NAME] [--level fast|balanced|max] [--threads N]
+//! ```
+//!
+//! `--pipe` compresses stdin to back-to-back frames on stdout, or with `-d`
+//! decodes them back, so the tool slots into shell pipelines like gzip.
+
+use sigma_compress::archive::Archive;
+use sigma_compress::config::{CompressionConfig, CompressionLevel};
+use sigma_compress::error::CompressError;
+use sigma_compress::pipe::{self, PipeOptions};
+use sigma_compress::{CompressionMethod, Compressor};
+use std::process::ExitCode;
+
+const USAGE: &str = "usage: sigma-compress list <archive-dir>
+       sigma-compress --pipe [-d] [--method NAME] [--level fast|balanced|max] [--threads N]";
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let result = match args.iter().map(String::as_str).collect::<Vec<_>>().as_slice() {
+        ["list", archive] => list(archive),
+        ["--pipe", flags @ ..] => match parse_pipe_flags(flags) {
+            Some((decompress, level, options)) => run_pipe(decompress, level, &options),
+            None => {
+                eprintln!("{}", USAGE);
+                return ExitCode::from(2);
+            }
+        },
+        _ => {
+            eprintln!("{}", USAGE);
+            return ExitCode::from(2);
+        }
+    };
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        // The reader went away, e.g. `| head`; stop quietly as gzip does
+        Err(CompressError::IoError(e)) if e.kind() == std::io::ErrorKind::BrokenPipe => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("sigma-compress: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// Print one line per entry: size, stored size, method, mtime (seconds), CRC-32, path
+fn list(root: &str) -> Result<(), CompressError> {
+    let archive = Archive::open(root)?;
+    for info in archive.entries() {
+        let method = info.method.map_or_else(|| "mixed".to_string(), |m| format!("{:?}", m));
+        let mtime = info.modified_ns.map_or_else(|| "-".to_string(), |ns| (ns / 1_000_000_000).to_string());
+        let checksum = info.checksum.map_or_else(|| "-".to_string(), |c| format!("{:08x}", c));
+        println!(
+            "{:>12} {:>12} {:<14} {:>11} {} {}",
+            info.size, info.stored_size, method, mtime, checksum, info.path
+        );
+    }
+    Ok(())
+}
+
+/// `(decompress, level, options)` from the flags after `--pipe`
+fn parse_pipe_flags(flags: &[&str]) -> Option<(bool, CompressionLevel, PipeOptions)> {
+    let (mut decompress, mut level, mut options) = (false, CompressionLevel::default(), PipeOptions::default());
+    let mut flags = flags.iter();
+    while let Some(&flag) = flags.next() {
+        match flag {
+            "-d" | "--decompress" => decompress = true,
+            "--method" => options.method = parse_method(flags.next()?)?,
+            "--level" => {
+                level = match *flags.next()? {
+                    "fast" => CompressionLevel::Fast,
+                    "balanced" => CompressionLevel::Balanced,
+                    "max" => CompressionLevel::Max,
+                    _ => return None,
+                }
+            }
+            "--threads" => options.threads = flags.next()?.parse().ok()?,
+            _ => return None,
+        }
+    }
+    Some((decompress, level, options))
+}
+
+/// Method by name, ignoring case (`lz4` is short for `Lz4Semantic`)
+fn parse_method(name: &str) -> Option<CompressionMethod> {
+    use CompressionMethod::*;
+    let name = name.to_ascii_lowercase();
+    [Huffman, Lz4Semantic, EntropyCoding, SemanticDedupe, LogDedupe, Stored, Lzss, SemanticLz, Auto]
+        .into_iter()
+        .find(|m| format!("{:?}", m).to_ascii_lowercase() == name || (name == "lz4" && *m == Lz4Semantic))
+}
+
+fn run_pipe(decompress: bool, level: CompressionLevel, options: &PipeOptions) -> Result<(), CompressError> {
+    let compressor = Compressor::new(CompressionConfig {
+        level,
+        ..Default::default()
+    });
+    let (stdin, stdout) = (std::io::stdin().lock(), std::io::stdout().lock());
+    if decompress {
+        pipe::decompress_pipe(&compressor, stdin, stdout, options)?;
+    } else {
+        pipe::compress_pipe(&compressor, stdin, stdout, options)?;
+    }
+    Ok(())
+}
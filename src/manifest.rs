@@ -0,0 +1,199 @@
+//! Multi-part outputs described by a manifest
+//!
+//! [`Compressor::compress_parts`] splits input into independently decodable
+//! frames ("parts") that can be stored or served separately, e.g. from a CDN.
+//! The [`Manifest`] lists, per part, which input range it covers, its size and
+//! its BLAKE3 hash, so a client can fetch parts in any order, verify each on
+//! arrival and reassemble with [`Compressor::reassemble`].
+
+use crate::error::CompressError;
+use crate::store::BlockHash;
+use crate::{CompressionMethod, Compressor, MAX_PREALLOC};
+use serde::{Deserialize, Serialize};
+
+/// Manifest format version written by this build
+pub const MANIFEST_VERSION: u32 = 1;
+
+/// One part of a multi-part output
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ManifestPart {
+    /// Offset of the input range this part decodes to
+    pub offset: u64,
+    /// Length of that input range
+    pub original_size: u64,
+    /// Length of the part's frame
+    pub size: u64,
+    /// BLAKE3 hash of the part's frame
+    pub hash: BlockHash,
+}
+
+/// Description of a multi-part output
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Manifest {
+    pub version: u32,
+    pub method: CompressionMethod,
+    pub original_size: u64,
+    /// CRC-32 of the whole input
+    pub checksum: u32,
+    pub parts: Vec<ManifestPart>,
+}
+
+impl Manifest {
+    pub fn to_json(&self) -> Result<Vec<u8>, CompressError> {
+        serde_json::to_vec_pretty(self).map_err(|e| CompressError::SerializationError(e.to_string()))
+    }
+
+    pub fn from_json(data: &[u8]) -> Result<Self, CompressError> {
+        Self::checked(serde_json::from_slice(data).map_err(|e| CompressError::SerializationError(e.to_string()))?)
+    }
+
+    pub fn to_cbor(&self) -> Result<Vec<u8>, CompressError> {
+        let mut out = Vec::new();
+        ciborium::into_writer(self, &mut out).map_err(|e| CompressError::SerializationError(e.to_string()))?;
+        Ok(out)
+    }
+
+    pub fn from_cbor(data: &[u8]) -> Result<Self, CompressError> {
+        Self::checked(ciborium::from_reader(data).map_err(|e| CompressError::SerializationError(e.to_string()))?)
+    }
+
+    /// Total size of all parts
+    pub fn compressed_size(&self) -> u64 {
+        self.parts.iter().map(|p| p.size).sum()
+    }
+
+    fn checked(manifest: Self) -> Result<Self, CompressError> {
+        if manifest.version != MANIFEST_VERSION {
+            return Err(CompressError::ManifestError(format!(
+                "unsupported manifest version {}",
+                manifest.version
+            )));
+        }
+        Ok(manifest)
+    }
+}
+
+impl Compressor {
+    /// Compress `data` into independently decodable parts covering at most
+    /// `part_size` input bytes each (and never more than `max_input_size`)
+    pub fn compress_parts(
+        &self,
+        data: &[u8],
+        method: CompressionMethod,
+        part_size: usize,
+    ) -> Result<(Manifest, Vec<Vec<u8>>), CompressError> {
+        if data.is_empty() {
+            return Err(CompressError::EmptyInput);
+        }
+        let part_size = part_size.clamp(1, self.config.max_input_size.max(1));
+        let mut parts = Vec::new();
+        let mut frames = Vec::new();
+        let mut first_method = None;
+        for (i, chunk) in data.chunks(part_size).enumerate() {
+            let output = self.compress(chunk, method)?;
+            first_method.get_or_insert(output.method);
+            let frame = output.to_frame();
+            parts.push(ManifestPart {
+                offset: (i * part_size) as u64,
+                original_size: chunk.len() as u64,
+                size: frame.len() as u64,
+                hash: BlockHash::of(&frame),
+            });
+            frames.push(frame);
+        }
+        let manifest = Manifest {
+            version: MANIFEST_VERSION,
+            method: first_method.unwrap_or(method),
+            original_size: data.len() as u64,
+            checksum: crc32fast::hash(data),
+            parts,
+        };
+        Ok((manifest, frames))
+    }
+
+    /// Rebuild the input from its manifest and parts, given in manifest order.
+    /// Every part is checked against its hash before it is decoded.
+    pub fn reassemble<P: AsRef<[u8]>>(&self, manifest: &Manifest, parts: &[P]) -> Result<Vec<u8>, CompressError> {
+        if parts.len() != manifest.parts.len() {
+            return Err(CompressError::ManifestError(format!(
+                "expected {} parts, got {}",
+                manifest.parts.len(),
+                parts.len()
+            )));
+        }
+        let mut data = Vec::with_capacity((manifest.original_size as usize).min(MAX_PREALLOC));
+        for (i, (entry, part)) in manifest.parts.iter().zip(parts).enumerate() {
+            let part = part.as_ref();
+            if part.len() as u64 != entry.size || BlockHash::of(part) != entry.hash {
+                return Err(CompressError::ManifestError(format!("part {} does not match the manifest", i)));
+            }
+            if data.len() as u64 != entry.offset {
+                return Err(CompressError::ManifestError(format!("part {} is not contiguous", i)));
+            }
+            let decoded = self.decompress_frame(part)?;
+            if decoded.len() as u64 != entry.original_size {
+                return Err(CompressError::SizeMismatch {
+                    expected: entry.original_size as usize,
+                    actual: decoded.len(),
+                });
+            }
+            data.extend_from_slice(&decoded);
+        }
+        if data.len() as u64 != manifest.original_size {
+            return Err(CompressError::SizeMismatch {
+                expected: manifest.original_size as usize,
+                actual: data.len(),
+            });
+        }
+        let actual = crc32fast::hash(&data);
+        if actual != manifest.checksum {
+            return Err(CompressError::ChecksumMismatch {
+                expected: manifest.checksum,
+                actual,
+            });
+        }
+        Ok(data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parts_reassemble() {
+        let compressor = Compressor::default();
+        let data = "artifact distributed in parts ".repeat(1000);
+        let (manifest, parts) = compressor
+            .compress_parts(data.as_bytes(), CompressionMethod::Auto, 8000)
+            .unwrap();
+        assert_eq!(parts.len(), 4);
+        assert_eq!(manifest.parts[1].offset, 8000);
+        assert_eq!(manifest.compressed_size(), parts.iter().map(|p| p.len() as u64).sum::<u64>());
+
+        let from_json = Manifest::from_json(&manifest.to_json().unwrap()).unwrap();
+        let from_cbor = Manifest::from_cbor(&manifest.to_cbor().unwrap()).unwrap();
+        assert_eq!(from_json, manifest);
+        assert_eq!(from_cbor, manifest);
+        assert_eq!(compressor.reassemble(&from_cbor, &parts).unwrap(), data.as_bytes());
+    }
+
+    #[test]
+    fn test_reassemble_rejects_bad_parts() {
+        let compressor = Compressor::default();
+        let data: Vec<u8> = (0..5000u32).map(|i| (i / 100) as u8).collect();
+        let (manifest, mut parts) = compressor
+            .compress_parts(&data, CompressionMethod::EntropyCoding, 2000)
+            .unwrap();
+        assert!(compressor.reassemble(&manifest, &parts[..2]).is_err());
+        parts.swap(0, 1);
+        assert!(compressor.reassemble(&manifest, &parts).is_err());
+        parts.swap(0, 1);
+        let last = parts.last_mut().unwrap();
+        *last.last_mut().unwrap() ^= 1;
+        assert!(matches!(
+            compressor.reassemble(&manifest, &parts),
+            Err(CompressError::ManifestError(_))
+        ));
+    }
+}
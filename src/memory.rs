@@ -0,0 +1,125 @@
+//! Memory use of codec runs
+//!
+//! Sizing a container for a compression service needs the memory one
+//! operation takes, not just its input and output sizes. Every compression
+//! records [`MemoryStats`] in [`CompressionMetadata::memory`], and every
+//! metered run, compression or decompression, reports them to the
+//! [`UsageHook`](crate::usage::UsageHook); [`UsageLedger`] keeps the
+//! high-water mark per tenant in [`UsageTotals::peak_memory`].
+//!
+//! Output and table sizes are measured. Scratch memory (hash chains, block
+//! buffers, dedup maps) is allocated inside the codecs and is the estimate
+//! of [`Compressor::estimated_scratch`] for the method and input size, which
+//! bounds what the codec allocates for typical input.
+//!
+//! [`CompressionMetadata::memory`]: crate::CompressionMetadata::memory
+//! [`UsageLedger`]: crate::usage::UsageLedger
+//! [`UsageTotals::peak_memory`]: crate::usage::UsageTotals::peak_memory
+
+use crate::usage::Operation;
+use crate::{CompressedOutput, CompressionMethod, Compressor};
+use serde::{Deserialize, Serialize};
+
+/// Memory one operation held at its peak, in bytes; input not included
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MemoryStats {
+    /// Working buffers besides the output (estimated)
+    pub peak_scratch: usize,
+    /// Allocated size of the output buffer
+    pub peak_output: usize,
+    /// Code tables and block indexes within the output
+    pub tables: usize,
+}
+
+impl MemoryStats {
+    /// Scratch and output together; tables live inside the output
+    pub fn peak(&self) -> usize {
+        self.peak_scratch + self.peak_output
+    }
+
+    /// Field-wise maximum, for high-water marks over several operations
+    pub fn max(self, other: Self) -> Self {
+        Self {
+            peak_scratch: self.peak_scratch.max(other.peak_scratch),
+            peak_output: self.peak_output.max(other.peak_output),
+            tables: self.tables.max(other.tables),
+        }
+    }
+}
+
+impl Compressor {
+    /// Rough scratch memory, in bytes, `method` needs for `operation` on
+    /// `len` bytes of original data, not counting input or output
+    pub fn estimated_scratch(&self, operation: Operation, method: CompressionMethod, len: usize) -> usize {
+        let block_size = self.config.lz4_block_size.min(len);
+        let dedup_index = len / self.config.semantic_block_size.max(1) * 48;
+        match (operation, method) {
+            // Code tables
+            (_, CompressionMethod::Huffman) => 256 * 16,
+            (_, CompressionMethod::EntropyCoding | CompressionMethod::Stored) => 0,
+            // Deflate state plus one block in and out; bounds every method `Auto` picks
+            (Operation::Compress, CompressionMethod::Lz4Semantic | CompressionMethod::Auto) => 256 * 1024 + 2 * block_size,
+            (Operation::Decompress, CompressionMethod::Lz4Semantic | CompressionMethod::Auto) => 32 * 1024 + block_size,
+            // Unique blocks, refs and the block table, capped by `dedup_table_bytes`
+            (Operation::Compress, CompressionMethod::SemanticDedupe) => match self.config.dedup_table_bytes {
+                0 => len + dedup_index,
+                cap => len + dedup_index.min(cap),
+            },
+            (Operation::Decompress, CompressionMethod::SemanticDedupe) => dedup_index,
+            // The section streams and their packed forms
+            (_, CompressionMethod::LogDedupe) => 2 * len,
+            // Buffered input, one chain link per byte and the token streams
+            (Operation::Compress, CompressionMethod::Lzss) => (1 << self.config.lzss.hash_bits) * 8 + 9 * len,
+            (Operation::Decompress, CompressionMethod::Lzss) => len,
+            // Dedup state, then the dedup stream run through the LZ backend
+            (Operation::Compress, CompressionMethod::SemanticLz) => 2 * len + dedup_index + 256 * 1024,
+            (Operation::Decompress, CompressionMethod::SemanticLz) => len + dedup_index + 32 * 1024,
+        }
+    }
+
+    /// Stats of decoding `output` into `decoded`
+    pub(crate) fn decode_memory(&self, output: &CompressedOutput, decoded: &Vec<u8>) -> MemoryStats {
+        MemoryStats {
+            peak_scratch: self.estimated_scratch(Operation::Decompress, output.method, output.original_size),
+            peak_output: decoded.capacity(),
+            tables: output.metadata.overhead.tables,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::usage::UsageLedger;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_compression_reports_memory() {
+        let compressor = Compressor::default();
+        let data = b"memory is measured per operation; ".repeat(2000);
+        for method in CompressionMethod::CONCRETE {
+            let output = compressor.compress(&data, method).unwrap();
+            let memory = output.metadata.memory;
+            assert!(memory.peak_output >= output.data.len(), "{:?}", method);
+            assert_eq!(memory.tables, output.metadata.overhead.tables);
+            assert_eq!(memory.peak_scratch, compressor.estimated_scratch(Operation::Compress, method, data.len()));
+            assert_eq!(compressor.estimated_memory(method, data.len()), memory.peak_scratch + data.len());
+        }
+        let lzss = compressor.compress(&data, CompressionMethod::Lzss).unwrap().metadata.memory;
+        let stored = compressor.compress(&data, CompressionMethod::Stored).unwrap().metadata.memory;
+        assert!(lzss.peak_scratch > stored.peak_scratch && lzss.peak() > lzss.peak_scratch);
+    }
+
+    #[test]
+    fn test_ledger_tracks_high_water_mark() {
+        let ledger = Arc::new(UsageLedger::new());
+        let compressor = Compressor::default().with_usage_hook(ledger.clone());
+        let small = compressor.compress(&b"tiny input ".repeat(10), CompressionMethod::Huffman).unwrap();
+        let large = compressor.compress(&b"larger input ".repeat(5000), CompressionMethod::Lzss).unwrap();
+        compressor.decompress(&small).unwrap();
+        let peak = ledger.totals("").peak_memory;
+        assert_eq!(peak, small.metadata.memory.max(large.metadata.memory).max(peak));
+        assert_eq!(peak.peak_scratch, large.metadata.memory.peak_scratch);
+        assert!(peak.peak_output >= large.data.len());
+    }
+}
@@ -0,0 +1,299 @@
+//! Memory snapshot profile for VM and process images
+//!
+//! A memory image is a run of fixed-size pages, most of them zero, many
+//! identical to another page (shared libraries, page cache, copy-on-write
+//! clones) and many differing from another page in a few bytes (stacks,
+//! heaps between two snapshots of the same guest). Each page is coded as the
+//! cheapest of: zero, a copy of an earlier page, a patch against an earlier
+//! page, or its raw bytes. Patch bases are found by sampling a few windows of
+//! every page, so similar pages meet however far apart they are. The page
+//! stream is then LZ-compressed (see [`crate::lz4_wrapper`]).
+//!
+//! Layout:
+//! ```text
+//! [page_size:varint][len:varint][body_len:varint][lz body]
+//! body, per page: [kind:u8] then
+//!   KIND_ZERO   nothing
+//!   KIND_SAME   [page:varint]                          equal earlier page
+//!   KIND_PATCH  [page:varint][patches:varint]([skip:varint][len:varint][bytes])*
+//!   KIND_RAW    [bytes]                                the whole page
+//! ```
+//! The last page may be short. Patches replace byte ranges of the base page;
+//! `skip` counts the unchanged bytes since the previous patch.
+
+use crate::config::DEFAULT_LZ4_BLOCK_SIZE;
+use crate::error::CompressError;
+use crate::{lz4_wrapper, varint};
+use std::collections::HashMap;
+
+/// Page size of x86-64 and most ARM64 kernels
+pub const DEFAULT_PAGE_SIZE: usize = 4096;
+
+const KIND_ZERO: u8 = 0;
+const KIND_SAME: u8 = 1;
+const KIND_PATCH: u8 = 2;
+const KIND_RAW: u8 = 3;
+
+/// Windows sampled per page to find patch bases, and their length
+const SAMPLES: usize = 4;
+const SAMPLE_LEN: usize = 32;
+/// Unchanged bytes shorter than this are folded into the surrounding patch
+const MIN_SKIP: usize = 8;
+
+/// How the pages of an image were coded
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct PageCounts {
+    pub zero: usize,
+    /// Copies of an earlier page
+    pub duplicate: usize,
+    /// Patches against an earlier page
+    pub patched: usize,
+    pub raw: usize,
+}
+
+/// Compress a memory image of `page_size`-byte pages
+pub fn compress(data: &[u8], page_size: usize) -> Result<Vec<u8>, CompressError> {
+    Ok(encode(data, page_size)?.0)
+}
+
+/// [`compress`], also reporting how the pages were coded
+pub fn encode(data: &[u8], page_size: usize) -> Result<(Vec<u8>, PageCounts), CompressError> {
+    if page_size < SAMPLES * SAMPLE_LEN {
+        return Err(CompressError::MemoryImageError(format!("page size {} below {}", page_size, SAMPLES * SAMPLE_LEN)));
+    }
+    let pages: Vec<&[u8]> = data.chunks(page_size).collect();
+    let mut counts = PageCounts::default();
+    let mut body = Vec::with_capacity(data.len() / 4);
+    let mut seen: HashMap<&[u8], usize> = HashMap::new();
+    let mut sampled: HashMap<(usize, &[u8]), usize> = HashMap::new();
+    let mut patch = Vec::new();
+
+    for (i, &page) in pages.iter().enumerate() {
+        if page.iter().all(|&b| b == 0) {
+            body.push(KIND_ZERO);
+            counts.zero += 1;
+            continue;
+        }
+        if let Some(&earlier) = seen.get(page) {
+            body.push(KIND_SAME);
+            varint::write_u64(&mut body, earlier as u64);
+            counts.duplicate += 1;
+            continue;
+        }
+
+        // Patch against the most recent page sharing a sampled window, or
+        // the previous page, whichever patch is smallest
+        let windows = sample_windows(page, page_size);
+        let mut best: Option<(usize, Vec<u8>)> = None;
+        let candidates = windows.iter().filter_map(|w| sampled.get(w).copied()).chain(i.checked_sub(1));
+        for base in candidates {
+            if pages[base].len() != page.len() || best.as_ref().is_some_and(|(b, _)| *b == base) {
+                continue;
+            }
+            patch.clear();
+            write_patch(&mut patch, pages[base], page);
+            if best.as_ref().map_or(patch.len() < page.len() / 2, |(_, p)| patch.len() < p.len()) {
+                best = Some((base, patch.clone()));
+            }
+        }
+        match best {
+            Some((base, patch)) => {
+                body.push(KIND_PATCH);
+                varint::write_u64(&mut body, base as u64);
+                body.extend_from_slice(&patch);
+                counts.patched += 1;
+            }
+            None => {
+                body.push(KIND_RAW);
+                body.extend_from_slice(page);
+                counts.raw += 1;
+            }
+        }
+        seen.insert(page, i);
+        for window in windows {
+            sampled.insert(window, i);
+        }
+    }
+
+    let mut out = Vec::new();
+    varint::write_u64(&mut out, page_size as u64);
+    varint::write_u64(&mut out, data.len() as u64);
+    varint::write_u64(&mut out, body.len() as u64);
+    out.extend_from_slice(&lz4_wrapper::compress(&body, DEFAULT_LZ4_BLOCK_SIZE)?);
+    Ok((out, counts))
+}
+
+/// Decompress an image written by [`compress`]
+pub fn decompress(data: &[u8]) -> Result<Vec<u8>, CompressError> {
+    let err = |msg: &str| CompressError::MemoryImageError(msg.into());
+    let mut pos = 0;
+    let page_size = varint::read_usize(data, &mut pos).ok_or_else(|| err("truncated header"))?;
+    let len = varint::read_usize(data, &mut pos).ok_or_else(|| err("truncated header"))?;
+    let body_len = varint::read_usize(data, &mut pos).ok_or_else(|| err("truncated header"))?;
+    if page_size == 0 {
+        return Err(err("zero page size"));
+    }
+    // Every page takes at least its kind byte
+    if body_len < len.div_ceil(page_size) {
+        return Err(err("body shorter than its page count"));
+    }
+    let body = lz4_wrapper::decompress(&data[pos..], body_len)?;
+    if body.len() != body_len {
+        return Err(CompressError::SizeMismatch {
+            expected: body_len,
+            actual: body.len(),
+        });
+    }
+
+    let mut out = Vec::with_capacity(len.min(crate::MAX_PREALLOC));
+    let mut starts = Vec::with_capacity(len.div_ceil(page_size).min(crate::MAX_PREALLOC));
+    let mut pos = 0;
+    while out.len() < len {
+        let page_len = page_size.min(len - out.len());
+        let start = out.len();
+        let kind = *body.get(pos).ok_or_else(|| err("truncated body"))?;
+        pos += 1;
+        let base = |pos: &mut usize, out: &[u8]| -> Result<usize, CompressError> {
+            let base = varint::read_usize(&body, pos).ok_or_else(|| err("truncated page reference"))?;
+            match starts.get(base) {
+                Some(&at) if out.len() - at >= page_len => Ok(at),
+                _ => Err(err("page reference out of range")),
+            }
+        };
+        match kind {
+            KIND_ZERO => out.resize(start + page_len, 0),
+            KIND_SAME => {
+                let at = base(&mut pos, &out)?;
+                out.extend_from_within(at..at + page_len);
+            }
+            KIND_PATCH => {
+                let at = base(&mut pos, &out)?;
+                out.extend_from_within(at..at + page_len);
+                let patches = varint::read_usize(&body, &mut pos).ok_or_else(|| err("truncated patch"))?;
+                let mut offset = start;
+                for _ in 0..patches {
+                    let skip = varint::read_usize(&body, &mut pos).ok_or_else(|| err("truncated patch"))?;
+                    let n = varint::read_usize(&body, &mut pos).ok_or_else(|| err("truncated patch"))?;
+                    offset = offset.checked_add(skip).filter(|o| o.saturating_add(n) <= start + page_len).ok_or_else(|| err("patch past page end"))?;
+                    let bytes = body.get(pos..pos.saturating_add(n)).ok_or_else(|| err("truncated patch"))?;
+                    out[offset..offset + n].copy_from_slice(bytes);
+                    pos += n;
+                    offset += n;
+                }
+            }
+            KIND_RAW => {
+                let bytes = body.get(pos..pos + page_len).ok_or_else(|| err("truncated page"))?;
+                out.extend_from_slice(bytes);
+                pos += page_len;
+            }
+            other => return Err(err(&format!("unknown page kind {}", other))),
+        }
+        starts.push(start);
+    }
+    if pos != body.len() {
+        return Err(err("trailing bytes after last page"));
+    }
+    Ok(out)
+}
+
+/// Windows of `page` at evenly spaced offsets, keyed by their position
+fn sample_windows(page: &[u8], page_size: usize) -> Vec<(usize, &[u8])> {
+    (0..SAMPLES)
+        .map(|k| k * page_size / SAMPLES)
+        .filter_map(|offset| page.get(offset..offset + SAMPLE_LEN).map(|w| (offset, w)))
+        .filter(|(_, w)| w.iter().any(|&b| b != 0))
+        .collect()
+}
+
+/// Append the patches turning `base` into `page`, of the same length
+fn write_patch(out: &mut Vec<u8>, base: &[u8], page: &[u8]) {
+    let mut runs: Vec<(usize, usize)> = Vec::new();
+    let mut i = 0;
+    while i < page.len() {
+        if base[i] == page[i] {
+            i += 1;
+            continue;
+        }
+        let start = i;
+        while i < page.len() && base[i] != page[i] {
+            i += 1;
+        }
+        match runs.last_mut() {
+            Some((_, end)) if start - *end < MIN_SKIP => *end = i,
+            _ => runs.push((start, i)),
+        }
+    }
+    varint::write_u64(out, runs.len() as u64);
+    let mut previous = 0;
+    for (start, end) in runs {
+        varint::write_u64(out, (start - previous) as u64);
+        varint::write_u64(out, (end - start) as u64);
+        out.extend_from_slice(&page[start..end]);
+        previous = end;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::splitmix64;
+
+    /// Guest image: random pages, zero pages, copies and lightly dirtied
+    /// copies of earlier pages
+    fn image(pages: usize) -> Vec<u8> {
+        let mut state = 7;
+        let mut data: Vec<u8> = Vec::with_capacity(pages * DEFAULT_PAGE_SIZE);
+        for i in 0..pages {
+            let start = data.len();
+            match i % 4 {
+                0 => data.extend((0..DEFAULT_PAGE_SIZE).map(|_| splitmix64(&mut state) as u8)),
+                1 => data.resize(start + DEFAULT_PAGE_SIZE, 0),
+                2 => data.extend_from_within(0..DEFAULT_PAGE_SIZE),
+                _ => {
+                    let base = (splitmix64(&mut state) as usize % (i / 4 + 1)) * 4 * DEFAULT_PAGE_SIZE;
+                    data.extend_from_within(base..base + DEFAULT_PAGE_SIZE);
+                    for _ in 0..5 {
+                        let at = start + splitmix64(&mut state) as usize % DEFAULT_PAGE_SIZE;
+                        data[at] ^= 0x5A;
+                    }
+                }
+            }
+        }
+        data
+    }
+
+    #[test]
+    fn test_pages_deduped_and_patched() {
+        let mut data = image(64);
+        data.extend_from_slice(b"short tail page");
+        let (compressed, counts) = encode(&data, DEFAULT_PAGE_SIZE).unwrap();
+        assert_eq!(decompress(&compressed).unwrap(), data);
+        assert_eq!(counts.zero, 16);
+        assert_eq!(counts.duplicate, 16);
+        assert_eq!(counts.patched, 16);
+        assert_eq!(counts.raw, 17);
+        // Little more than the random pages themselves
+        assert!(compressed.len() < 17 * DEFAULT_PAGE_SIZE + 2048, "{}", compressed.len());
+        let lz = crate::Compressor::default().compress(&data, crate::CompressionMethod::Lz4Semantic).unwrap();
+        assert!(compressed.len() * 3 < lz.data.len() * 2);
+    }
+
+    #[test]
+    fn test_rejects_bad_images() {
+        let data = image(8);
+        let compressed = compress(&data, DEFAULT_PAGE_SIZE).unwrap();
+        assert!(decompress(&compressed[..compressed.len() - 1]).is_err());
+        assert!(compress(&data, 64).is_err());
+
+        // A reference to a page not yet decoded
+        let mut body = vec![KIND_SAME, 1];
+        body.extend_from_slice(&[KIND_ZERO]);
+        let mut forged = Vec::new();
+        for v in [DEFAULT_PAGE_SIZE, 2 * DEFAULT_PAGE_SIZE, body.len()] {
+            varint::write_u64(&mut forged, v as u64);
+        }
+        forged.extend_from_slice(&lz4_wrapper::compress(&body, DEFAULT_LZ4_BLOCK_SIZE).unwrap());
+        assert!(decompress(&forged).is_err());
+        assert_eq!(decompress(&compress(&[], DEFAULT_PAGE_SIZE).unwrap()).unwrap(), b"");
+    }
+}
@@ -0,0 +1,296 @@
+//! Several logical streams over one connection
+//!
+//! A connection carrying many logical streams (RPC channels, per-tenant log
+//! feeds) should not mix their statistics: one stream's table would fit the
+//! next stream's messages badly and be rebuilt on every switch. Each stream
+//! gets a context id, and every context keeps its own [`HuffmanSession`]
+//! model and, optionally, its own dictionary. Control messages create,
+//! reset and close contexts in band, so the [`ContextDecoder`] on the other
+//! end mirrors the encoder's state. Resetting drops a context's model, for
+//! example after a logical stream restarts.
+//!
+//! Messages must reach the decoder whole and in the order they were
+//! produced; framing them on the connection is up to the transport. Message
+//! layout:
+//!
+//! ```text
+//! [kind u8][context varint][body]
+//! create: [has dictionary u8][dictionary id u32 if present]
+//! reset, close: empty
+//! data: a HuffmanSession message of the context's (dictionary coded) input
+//! ```
+
+use crate::dictionary::Dictionary;
+use crate::error::CompressError;
+use crate::session::{HuffmanSession, SessionDecoder};
+use crate::{varint, Compressor};
+use std::collections::HashMap;
+
+const KIND_CREATE: u8 = 1;
+const KIND_RESET: u8 = 2;
+const KIND_CLOSE: u8 = 3;
+const KIND_DATA: u8 = 4;
+
+/// Open contexts a [`ContextDecoder`] accepts, bounding a peer's memory use
+pub const MAX_CONTEXTS: usize = 4096;
+
+/// What a message did
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ContextEvent {
+    Created { context: u64, dictionary: Option<u32> },
+    Reset(u64),
+    Closed(u64),
+    Data { context: u64, data: Vec<u8> },
+}
+
+struct EncoderContext {
+    session: HuffmanSession,
+    dictionary: Option<Dictionary>,
+}
+
+/// Encoder side of a multiplexed connection
+pub struct ContextEncoder {
+    compressor: Compressor,
+    contexts: HashMap<u64, EncoderContext>,
+}
+
+impl ContextEncoder {
+    /// Contexts resolve dictionaries through `compressor`'s registry
+    pub fn new(compressor: Compressor) -> Self {
+        Self {
+            compressor,
+            contexts: HashMap::new(),
+        }
+    }
+
+    /// Open `context`, coding its data behind `dictionary` if given
+    pub fn create(&mut self, context: u64, dictionary: Option<u32>) -> Result<Vec<u8>, CompressError> {
+        if self.contexts.contains_key(&context) {
+            return Err(CompressError::ContextError(format!("context {} already exists", context)));
+        }
+        self.compressor.ensure_unencrypted("multiplexed streams")?;
+        let resolved = match dictionary {
+            Some(id) => Some(self.compressor.dictionaries().resolve(id)?.clone()),
+            None => None,
+        };
+        let mut message = header(KIND_CREATE, context);
+        write_dictionary(&mut message, dictionary);
+        self.contexts.insert(
+            context,
+            EncoderContext {
+                session: HuffmanSession::new(),
+                dictionary: resolved,
+            },
+        );
+        Ok(message)
+    }
+
+    /// Code `data` with the model of `context`
+    pub fn compress(&mut self, context: u64, data: &[u8]) -> Result<Vec<u8>, CompressError> {
+        let state = self.contexts.get_mut(&context).ok_or_else(|| unknown(context))?;
+        let mut message = header(KIND_DATA, context);
+        let coded = match &state.dictionary {
+            Some(dictionary) => state.session.compress(&dictionary.encode(data))?,
+            None => state.session.compress(data)?,
+        };
+        message.extend_from_slice(&coded);
+        Ok(message)
+    }
+
+    /// Forget the model of `context`; its dictionary is kept
+    pub fn reset(&mut self, context: u64) -> Result<Vec<u8>, CompressError> {
+        let state = self.contexts.get_mut(&context).ok_or_else(|| unknown(context))?;
+        state.session = HuffmanSession::new();
+        Ok(header(KIND_RESET, context))
+    }
+
+    pub fn close(&mut self, context: u64) -> Result<Vec<u8>, CompressError> {
+        self.contexts.remove(&context).ok_or_else(|| unknown(context))?;
+        Ok(header(KIND_CLOSE, context))
+    }
+
+    /// Number of open contexts
+    pub fn len(&self) -> usize {
+        self.contexts.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.contexts.is_empty()
+    }
+}
+
+#[derive(Default)]
+struct DecoderContext {
+    session: SessionDecoder,
+    dictionary: Option<Dictionary>,
+}
+
+/// Decoder side of a multiplexed connection
+pub struct ContextDecoder {
+    compressor: Compressor,
+    contexts: HashMap<u64, DecoderContext>,
+}
+
+impl ContextDecoder {
+    /// Dictionaries named by create messages must be registered on `compressor`
+    pub fn new(compressor: Compressor) -> Self {
+        Self {
+            compressor,
+            contexts: HashMap::new(),
+        }
+    }
+
+    pub fn decode(&mut self, message: &[u8]) -> Result<ContextEvent, CompressError> {
+        let kind = *message.first().ok_or_else(|| CompressError::ContextError("empty message".into()))?;
+        let mut pos = 1;
+        let context =
+            varint::read_u64(message, &mut pos).ok_or_else(|| CompressError::ContextError("missing context id".into()))?;
+        let body = &message[pos..];
+        match kind {
+            KIND_CREATE => {
+                if self.contexts.contains_key(&context) {
+                    return Err(CompressError::ContextError(format!("context {} already exists", context)));
+                }
+                if self.contexts.len() >= MAX_CONTEXTS {
+                    return Err(CompressError::ContextError(format!("more than {} open contexts", MAX_CONTEXTS)));
+                }
+                let dictionary = read_dictionary(body)?;
+                let resolved = match dictionary {
+                    Some(id) => Some(self.compressor.dictionaries().resolve(id)?.clone()),
+                    None => None,
+                };
+                self.contexts.insert(
+                    context,
+                    DecoderContext {
+                        session: SessionDecoder::new(),
+                        dictionary: resolved,
+                    },
+                );
+                Ok(ContextEvent::Created { context, dictionary })
+            }
+            KIND_RESET => {
+                let state = self.contexts.get_mut(&context).ok_or_else(|| unknown(context))?;
+                state.session = SessionDecoder::new();
+                Ok(ContextEvent::Reset(context))
+            }
+            KIND_CLOSE => {
+                self.contexts.remove(&context).ok_or_else(|| unknown(context))?;
+                Ok(ContextEvent::Closed(context))
+            }
+            KIND_DATA => {
+                let state = self.contexts.get_mut(&context).ok_or_else(|| unknown(context))?;
+                let decoded = state.session.decompress(body)?;
+                let data = match &state.dictionary {
+                    Some(dictionary) => dictionary.decode(&decoded)?,
+                    None => decoded,
+                };
+                Ok(ContextEvent::Data { context, data })
+            }
+            other => Err(CompressError::ContextError(format!("unknown message kind {}", other))),
+        }
+    }
+
+    /// Number of open contexts
+    pub fn len(&self) -> usize {
+        self.contexts.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.contexts.is_empty()
+    }
+}
+
+fn header(kind: u8, context: u64) -> Vec<u8> {
+    let mut message = vec![kind];
+    varint::write_u64(&mut message, context);
+    message
+}
+
+fn unknown(context: u64) -> CompressError {
+    CompressError::ContextError(format!("context {} is not open", context))
+}
+
+fn write_dictionary(output: &mut Vec<u8>, dictionary: Option<u32>) {
+    match dictionary {
+        Some(id) => {
+            output.push(1);
+            output.extend_from_slice(&id.to_le_bytes());
+        }
+        None => output.push(0),
+    }
+}
+
+fn read_dictionary(body: &[u8]) -> Result<Option<u32>, CompressError> {
+    match body {
+        [0] => Ok(None),
+        [1, id @ ..] if id.len() == 4 => Ok(Some(u32::from_le_bytes(id.try_into().unwrap()))),
+        _ => Err(CompressError::ContextError("malformed create message".into())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dictionary;
+
+    #[test]
+    fn test_contexts_keep_separate_models() {
+        let mut encoder = ContextEncoder::new(Compressor::default());
+        let mut decoder = ContextDecoder::new(Compressor::default());
+        let json = encoder.create(1, Some(dictionary::JSON_KEYS)).unwrap();
+        assert_eq!(
+            decoder.decode(&json).unwrap(),
+            ContextEvent::Created { context: 1, dictionary: Some(dictionary::JSON_KEYS) }
+        );
+        decoder.decode(&encoder.create(2, None).unwrap()).unwrap();
+
+        let mut sizes = (0, 0);
+        for i in 0..40 {
+            let a = format!("{{\"id\":{},\"name\":\"item\",\"type\":\"view\"}}", i);
+            let b = format!("GGGGCCCCAAAATTTT{}", "ACGT".repeat(i % 5 + 3));
+            for (context, data) in [(1, a.as_bytes()), (2, b.as_bytes())] {
+                let message = encoder.compress(context, data).unwrap();
+                if i == 39 {
+                    sizes = (sizes.1, message.len());
+                }
+                let event = decoder.decode(&message).unwrap();
+                assert_eq!(event, ContextEvent::Data { context, data: data.to_vec() });
+            }
+        }
+        // Interleaving does not force table rebuilds: late messages are short
+        assert!(sizes.0 < 30 && sizes.1 < 20, "{:?}", sizes);
+
+        assert_eq!(decoder.decode(&encoder.reset(2).unwrap()).unwrap(), ContextEvent::Reset(2));
+        let message = encoder.compress(2, b"after reset").unwrap();
+        assert_eq!(decoder.decode(&message).unwrap(), ContextEvent::Data { context: 2, data: b"after reset".to_vec() });
+        assert_eq!(decoder.decode(&encoder.close(1).unwrap()).unwrap(), ContextEvent::Closed(1));
+        assert_eq!((encoder.len(), decoder.len()), (1, 1));
+    }
+
+    #[test]
+    fn test_context_errors() {
+        let mut encoder = ContextEncoder::new(Compressor::default());
+        assert!(encoder.compress(7, b"x").is_err());
+        assert!(encoder.create(7, Some(12345)).is_err());
+        let create = encoder.create(7, None).unwrap();
+        assert!(encoder.create(7, None).is_err());
+
+        let mut decoder = ContextDecoder::new(Compressor::default());
+        let data = encoder.compress(7, b"payload").unwrap();
+        assert!(matches!(decoder.decode(&data), Err(CompressError::ContextError(_))));
+        decoder.decode(&create).unwrap();
+        assert!(decoder.decode(&create).is_err());
+        assert!(decoder.decode(&[KIND_CREATE, 8, 1, 0]).is_err());
+        assert!(decoder.decode(&[9, 7]).is_err());
+        assert!(decoder.decode(&[]).is_err());
+        let create_plain = |context| {
+            let mut message = header(KIND_CREATE, context);
+            write_dictionary(&mut message, None);
+            message
+        };
+        for context in 100..100 + MAX_CONTEXTS as u64 - 1 {
+            decoder.decode(&create_plain(context)).unwrap();
+        }
+        assert!(decoder.decode(&create_plain(1)).is_err());
+    }
+}
@@ -0,0 +1,192 @@
+//! Agreeing on a mutually decodable configuration
+//!
+//! Services exchanging frames may run different builds: an older consumer
+//! may not know a newer method or frame version, and dictionaries trained on
+//! one side are not necessarily registered on the other. Before exchanging
+//! frames, each side sends [`MethodNegotiation::offer`] (the methods it
+//! decodes, its [`FormatVersion`] and its dictionary ids) and calls
+//! [`MethodNegotiation::accept`] with the peer's offer. Both get the same
+//! [`Agreement`], ordered by their own preference, or an error when no frame
+//! could be read by both.
+//!
+//! Offers serialize as JSON carrying [`NEGOTIATION_VERSION`].
+
+use crate::config::CompressOptions;
+use crate::dictionary::DictionarySelection;
+use crate::error::CompressError;
+use crate::frame::{self, FormatVersion};
+use crate::{CompressionMethod, Compressor};
+use serde::{Deserialize, Serialize};
+
+/// Offer format version written by this build
+pub const NEGOTIATION_VERSION: u32 = 1;
+
+/// What one side can decode
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MethodNegotiation {
+    pub version: u32,
+    /// Decodable methods, most preferred first
+    pub methods: Vec<CompressionMethod>,
+    pub format: FormatVersion,
+    /// Registered dictionary ids, built-ins included
+    pub dictionaries: Vec<u32>,
+}
+
+/// Methods and dictionaries both sides decode
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Agreement {
+    /// In the accepting side's order of preference
+    pub methods: Vec<CompressionMethod>,
+    pub dictionaries: Vec<u32>,
+    pub frame_version: u8,
+}
+
+impl MethodNegotiation {
+    /// Every method of this build and the dictionaries registered on `compressor`
+    pub fn offer(compressor: &Compressor) -> Self {
+        Self {
+            version: NEGOTIATION_VERSION,
+            methods: CompressionMethod::CONCRETE.to_vec(),
+            format: FormatVersion::current(),
+            dictionaries: compressor.dictionaries().ids(),
+        }
+    }
+
+    /// Offer only `methods`, in this order of preference
+    pub fn with_methods(mut self, methods: &[CompressionMethod]) -> Self {
+        self.methods = methods.iter().copied().filter(|m| *m != CompressionMethod::Auto).collect();
+        self
+    }
+
+    pub fn to_bytes(&self) -> Result<Vec<u8>, CompressError> {
+        serde_json::to_vec(self).map_err(|e| CompressError::SerializationError(e.to_string()))
+    }
+
+    /// Parse an offer from [`MethodNegotiation::to_bytes`]
+    pub fn from_bytes(data: &[u8]) -> Result<Self, CompressError> {
+        let offer: Self = serde_json::from_slice(data).map_err(|e| CompressError::SerializationError(e.to_string()))?;
+        if offer.version != NEGOTIATION_VERSION {
+            return Err(CompressError::NegotiationError(format!("unsupported offer version {}", offer.version)));
+        }
+        Ok(offer)
+    }
+
+    /// Intersect this side's offer with the `peer`'s
+    pub fn accept(&self, peer: &MethodNegotiation) -> Result<Agreement, CompressError> {
+        if self.format.frame != peer.format.frame {
+            return Err(CompressError::NegotiationError(format!(
+                "frame version {} here, {} on the peer",
+                self.format.frame, peer.format.frame
+            )));
+        }
+        let methods: Vec<CompressionMethod> =
+            self.methods.iter().copied().filter(|m| peer.methods.contains(m)).collect();
+        if methods.is_empty() {
+            return Err(CompressError::NegotiationError("no method decodable by both sides".to_string()));
+        }
+        let dictionaries = self.dictionaries.iter().copied().filter(|id| peer.dictionaries.contains(id)).collect();
+        Ok(Agreement {
+            methods,
+            dictionaries,
+            frame_version: self.format.frame,
+        })
+    }
+}
+
+impl Agreement {
+    pub fn allows(&self, method: CompressionMethod) -> bool {
+        self.methods.contains(&method)
+    }
+
+    /// Options producing frames the peer decodes: `Auto` when every method
+    /// is agreed, the most preferred agreed method otherwise, and `dictionary`
+    /// (or none) provided both sides have it
+    pub fn options(&self, dictionary: Option<u32>) -> Result<CompressOptions, CompressError> {
+        let method = if CompressionMethod::CONCRETE.iter().all(|m| self.allows(*m)) {
+            CompressionMethod::Auto
+        } else {
+            self.methods[0]
+        };
+        let dictionary = match dictionary {
+            Some(id) if !self.dictionaries.contains(&id) => {
+                return Err(CompressError::NegotiationError(format!("dictionary {} is not shared", id)))
+            }
+            Some(id) => DictionarySelection::Id(id),
+            None => DictionarySelection::None,
+        };
+        Ok(CompressOptions {
+            method,
+            dictionary: Some(dictionary),
+            ..CompressOptions::default()
+        })
+    }
+
+    /// Check that every frame in `data` uses the agreed version, methods and
+    /// dictionaries
+    pub fn check(&self, data: &[u8]) -> Result<(), CompressError> {
+        for member in frame::split_frames(data)? {
+            let header = frame::FrameHeader::parse(member)?;
+            if header.version != self.frame_version {
+                return Err(CompressError::NegotiationError(format!("frame version {} not agreed", header.version)));
+            }
+            if !self.allows(header.method) {
+                return Err(CompressError::NegotiationError(format!("method {:?} not agreed", header.method)));
+            }
+            if let Some(id) = header.dictionary_id.filter(|id| !self.dictionaries.contains(id)) {
+                return Err(CompressError::NegotiationError(format!("dictionary {} not agreed", id)));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dictionary::{self, DictionaryRegistry};
+
+    #[test]
+    fn test_agreement_restricts_methods_and_dictionaries() {
+        let samples: Vec<Vec<u8>> =
+            (0..8).map(|i| format!("{{\"tenant\":\"acme\",\"order\":{},\"status\":\"shipped\"}}\n", i).repeat(20).into_bytes()).collect();
+        let trained = dictionary::train("orders", &samples).unwrap();
+        let trained_id = trained.id;
+        let mut registry = DictionaryRegistry::new();
+        registry.insert(trained).unwrap();
+        let producer = Compressor::default().with_dictionaries(registry);
+        let consumer = MethodNegotiation::offer(&Compressor::default())
+            .with_methods(&[CompressionMethod::Lzss, CompressionMethod::Huffman, CompressionMethod::Stored]);
+
+        let wire = consumer.to_bytes().unwrap();
+        let agreement = MethodNegotiation::offer(&producer).accept(&MethodNegotiation::from_bytes(&wire).unwrap()).unwrap();
+        let reverse = consumer.accept(&MethodNegotiation::offer(&producer)).unwrap();
+        assert_eq!(agreement.methods, [CompressionMethod::Huffman, CompressionMethod::Stored, CompressionMethod::Lzss]);
+        assert_eq!(reverse.methods, consumer.methods);
+        assert_eq!(agreement.dictionaries, reverse.dictionaries);
+        assert!(!agreement.dictionaries.contains(&trained_id));
+        assert!(agreement.options(Some(trained_id)).is_err());
+
+        let options = agreement.options(None).unwrap();
+        let frame = producer.compress_with(&samples[0], &options).unwrap().to_frame();
+        agreement.check(&frame).unwrap();
+        assert_eq!(Compressor::default().decompress_frame(&frame).unwrap(), samples[0]);
+        let lz4 = producer.compress(&samples[0], CompressionMethod::Lz4Semantic).unwrap().to_frame();
+        assert!(agreement.check(&lz4).is_err());
+    }
+
+    #[test]
+    fn test_incompatible_offers_rejected() {
+        let ours = MethodNegotiation::offer(&Compressor::default());
+        let mut theirs = ours.clone();
+        theirs.format.frame += 1;
+        assert!(matches!(ours.accept(&theirs), Err(CompressError::NegotiationError(_))));
+        let stored_only = ours.clone().with_methods(&[CompressionMethod::Stored]);
+        let lz_only = ours.clone().with_methods(&[CompressionMethod::Lzss, CompressionMethod::Auto]);
+        assert_eq!(lz_only.methods, [CompressionMethod::Lzss]);
+        assert!(stored_only.accept(&lz_only).is_err());
+        assert_eq!(ours.accept(&ours).unwrap().options(None).unwrap().method, CompressionMethod::Auto);
+
+        theirs.version = NEGOTIATION_VERSION + 1;
+        assert!(MethodNegotiation::from_bytes(&theirs.to_bytes().unwrap()).is_err());
+    }
+}
@@ -0,0 +1,164 @@
+//! Output split into fixed-size, independently decodable pages
+//!
+//! Storage engines that manage space in fixed-size pages want compressed
+//! units that fill exactly one page each. [`Compressor::compress_paged`]
+//! packs as much input as fits into every page: each page holds one
+//! complete frame followed by zero padding, so any page decodes on its own
+//! with [`Compressor::decompress_page`]. Where [`crate::pages`] compresses
+//! fixed-size *input* pages, this fixes the size of the *output*.
+//!
+//! The input span per page is found by compressing growing prefixes until
+//! one no longer fits and bisecting between the last two, so compressing
+//! costs a logarithmic number of codec runs per page. Input that does not
+//! compress still makes progress: a `Stored` frame of one byte fits any page
+//! of at least [`MIN_PAGE_SIZE`].
+//!
+//! Layout of each page:
+//!
+//! ```text
+//! [frame][zero padding up to page_size]
+//! ```
+
+use crate::error::CompressError;
+use crate::{frame, CompressionMethod, Compressor};
+
+/// Smallest page size accepted; leaves room for any frame header
+pub const MIN_PAGE_SIZE: usize = 64;
+
+impl Compressor {
+    /// Compress `data` into pages of exactly `page_size` bytes, each holding
+    /// one frame of `method` (see [`paged`](crate::paged))
+    pub fn compress_paged(
+        &self,
+        data: &[u8],
+        page_size: usize,
+        method: CompressionMethod,
+    ) -> Result<Vec<u8>, CompressError> {
+        if page_size < MIN_PAGE_SIZE {
+            return Err(CompressError::PageError(format!("page size must be at least {}", MIN_PAGE_SIZE)));
+        }
+        if data.is_empty() {
+            return Err(CompressError::EmptyInput);
+        }
+        let mut output = Vec::new();
+        let mut pos = 0;
+        while pos < data.len() {
+            let (len, frame) = self.largest_fit(&data[pos..], page_size, method)?;
+            let page_start = output.len();
+            output.extend_from_slice(&frame);
+            output.resize(page_start + page_size, 0);
+            pos += len;
+        }
+        Ok(output)
+    }
+
+    /// Longest prefix of `data` whose frame fits `page_size`, with that frame
+    fn largest_fit(
+        &self,
+        data: &[u8],
+        page_size: usize,
+        method: CompressionMethod,
+    ) -> Result<(usize, Vec<u8>), CompressError> {
+        let encode = |len: usize| -> Result<Option<Vec<u8>>, CompressError> {
+            let frame = self.compress(&data[..len], method)?.to_frame();
+            Ok((frame.len() <= page_size).then_some(frame))
+        };
+        // Grow until a prefix no longer fits; `fit` fits and `overflow` does not
+        let mut fit = None;
+        let mut overflow = data.len() + 1;
+        let mut len = (page_size / 2).min(data.len());
+        loop {
+            match encode(len)? {
+                Some(frame) => {
+                    fit = Some((len, frame));
+                    if len == data.len() {
+                        break;
+                    }
+                    len = (len * 2).min(data.len());
+                }
+                None => {
+                    overflow = len;
+                    break;
+                }
+            }
+        }
+        let mut low = fit.as_ref().map_or(0, |(len, _)| *len);
+        while overflow - low > 1 {
+            let mid = low + (overflow - low) / 2;
+            match encode(mid)? {
+                Some(frame) => {
+                    low = mid;
+                    fit = Some((mid, frame));
+                }
+                None => overflow = mid,
+            }
+        }
+        fit.ok_or_else(|| CompressError::PageError("page too small for any input".into()))
+    }
+
+    /// Decode one page written by [`Compressor::compress_paged`]
+    pub fn decompress_page(&self, page: &[u8]) -> Result<Vec<u8>, CompressError> {
+        let header = frame::FrameHeader::parse(page)?;
+        let end = header
+            .frame_len()
+            .filter(|&end| end <= page.len())
+            .ok_or_else(|| CompressError::PageError("frame overruns its page".into()))?;
+        if page[end..].iter().any(|&b| b != 0) {
+            return Err(CompressError::PageError("non-zero page padding".into()));
+        }
+        self.decompress_frame(&page[..end])
+    }
+
+    /// Decode every page of `data`, which must be a whole number of pages
+    pub fn decompress_paged(&self, data: &[u8], page_size: usize) -> Result<Vec<u8>, CompressError> {
+        if page_size < MIN_PAGE_SIZE || !data.len().is_multiple_of(page_size) {
+            return Err(CompressError::PageError(format!(
+                "{} bytes are not a whole number of {} byte pages",
+                data.len(),
+                page_size
+            )));
+        }
+        let mut output = Vec::new();
+        for page in data.chunks(page_size) {
+            output.extend_from_slice(&self.decompress_page(page)?);
+        }
+        Ok(output)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pages_are_full_and_independent() {
+        let compressor = Compressor::default();
+        let data: Vec<u8> =
+            (0..5000).flat_map(|i| format!("row {} status=active region=eu-{}\n", i, i % 7).into_bytes()).collect();
+        let paged = compressor.compress_paged(&data, 4096, CompressionMethod::Lz4Semantic).unwrap();
+        assert_eq!(paged.len() % 4096, 0);
+        assert!(paged.len() < data.len() / 3, "{} of {}", paged.len(), data.len());
+        let mut joined = Vec::new();
+        for page in paged.chunks(4096) {
+            joined.extend_from_slice(&compressor.decompress_page(page).unwrap());
+        }
+        assert_eq!(joined, data);
+        assert_eq!(compressor.decompress_paged(&paged, 4096).unwrap(), data);
+        assert!(compressor.decompress_paged(&paged[..4000], 4096).is_err());
+    }
+
+    #[test]
+    fn test_incompressible_input_and_bad_pages() {
+        let compressor = Compressor::default();
+        let mut state = 7u64;
+        let data: Vec<u8> = (0..3000).map(|_| crate::splitmix64(&mut state) as u8).collect();
+        let paged = compressor.compress_paged(&data, MIN_PAGE_SIZE, CompressionMethod::Stored).unwrap();
+        assert_eq!(compressor.decompress_paged(&paged, MIN_PAGE_SIZE).unwrap(), data);
+        assert!(compressor.compress_paged(&data, MIN_PAGE_SIZE - 1, CompressionMethod::Stored).is_err());
+
+        let mut padded = compressor.compress_paged(b"short input", 256, CompressionMethod::Stored).unwrap();
+        assert_eq!(padded.len(), 256);
+        padded[255] = 1;
+        assert!(matches!(compressor.decompress_page(&padded), Err(CompressError::PageError(_))));
+    }
+}
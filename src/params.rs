@@ -0,0 +1,167 @@
+//! Codec parameters a frame was written with
+//!
+//! The same method produces different payloads under different block sizes,
+//! levels and dictionaries, and [`crate::config::CompressOptions`] can
+//! change them per call. Every compression records the values actually in
+//! effect as [`CodecParams`] in [`CompressionMetadata::params`]; with
+//! [`CompressionConfig::record_params`] they also go into the frame header
+//! (see [`crate::frame`]), so a reader of the frame alone knows them too:
+//! ```text
+//! [block_size:u32][level:u8][model_order:u8]
+//! ```
+//! A zero block size and an `0xFF` level or order mean the parameter does
+//! not apply to the method. The dictionary id is the header's own field.
+//!
+//! [`CompressionMetadata::params`]: crate::CompressionMetadata::params
+
+use crate::config::{CompressionConfig, CompressionLevel};
+use crate::error::CompressError;
+use crate::CompressionMethod;
+use serde::{Deserialize, Serialize};
+
+/// Encoded length of [`CodecParams`] in a frame header
+pub const ENCODED_LEN: usize = 4 + 1 + 1;
+
+/// Level or order byte of a parameter the method does not use
+const ABSENT: u8 = 0xFF;
+
+/// Parameters that shaped one codec payload; `None` where the method has
+/// no such parameter
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CodecParams {
+    /// Size of the blocks the codec split its input into
+    pub block_size: Option<u32>,
+    /// Match search effort of the LZ stage
+    pub level: Option<CompressionLevel>,
+    /// Bytes of context the entropy model conditions on; 0 for order-0
+    pub model_order: Option<u8>,
+    /// Static dictionary applied ahead of the codec
+    pub dictionary_id: Option<u32>,
+}
+
+impl CodecParams {
+    /// Parameters `method` takes from `config`
+    pub fn for_method(method: CompressionMethod, config: &CompressionConfig, dictionary_id: Option<u32>) -> Self {
+        let block_size = |size: usize| Some(u32::try_from(size).unwrap_or(u32::MAX));
+        let (block_size, level, model_order) = match method {
+            CompressionMethod::Huffman | CompressionMethod::EntropyCoding => (None, None, Some(0)),
+            CompressionMethod::Lz4Semantic => (block_size(config.lz4_block_size), Some(config.level), None),
+            CompressionMethod::Lzss => (None, Some(config.level), None),
+            CompressionMethod::SemanticDedupe => (block_size(config.semantic_block_size), None, None),
+            CompressionMethod::SemanticLz => (block_size(config.semantic_block_size), Some(config.level), None),
+            CompressionMethod::LogDedupe | CompressionMethod::Stored | CompressionMethod::Auto => (None, None, None),
+        };
+        Self {
+            block_size,
+            level,
+            model_order,
+            dictionary_id,
+        }
+    }
+
+    /// Append the header encoding; the dictionary id is written separately
+    pub fn write(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.block_size.unwrap_or(0).to_le_bytes());
+        out.push(self.level.map_or(ABSENT, level_id));
+        out.push(self.model_order.unwrap_or(ABSENT));
+    }
+
+    /// Parse the [`ENCODED_LEN`] bytes at the start of `data`
+    pub fn parse(data: &[u8], dictionary_id: Option<u32>) -> Result<Self, CompressError> {
+        let data = data
+            .get(..ENCODED_LEN)
+            .ok_or_else(|| CompressError::FrameError("truncated header".into()))?;
+        let block_size = u32::from_le_bytes(data[..4].try_into().unwrap());
+        let level = match data[4] {
+            ABSENT => None,
+            id => Some(level_from_id(id).ok_or_else(|| CompressError::FrameError(format!("unknown level id {}", id)))?),
+        };
+        Ok(Self {
+            block_size: (block_size != 0).then_some(block_size),
+            level,
+            model_order: (data[5] != ABSENT).then_some(data[5]),
+            dictionary_id,
+        })
+    }
+}
+
+fn level_id(level: CompressionLevel) -> u8 {
+    match level {
+        CompressionLevel::Fast => 0,
+        CompressionLevel::Balanced => 1,
+        CompressionLevel::Max => 2,
+    }
+}
+
+fn level_from_id(id: u8) -> Option<CompressionLevel> {
+    match id {
+        0 => Some(CompressionLevel::Fast),
+        1 => Some(CompressionLevel::Balanced),
+        2 => Some(CompressionLevel::Max),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::CompressOptions;
+    use crate::{frame, CompressedOutput, Compressor};
+
+    #[test]
+    fn test_params_recorded_in_header() {
+        let compressor = Compressor::new(CompressionConfig {
+            record_params: true,
+            ..CompressionConfig::default()
+        });
+        let data = b"params travel with the frame; ".repeat(200);
+        let options = CompressOptions {
+            method: CompressionMethod::Lz4Semantic,
+            level: Some(CompressionLevel::Max),
+            block_size: Some(4096),
+            ..CompressOptions::default()
+        };
+        let output = compressor.compress_with(&data, &options).unwrap();
+        let expected = CodecParams {
+            block_size: Some(4096),
+            level: Some(CompressionLevel::Max),
+            model_order: None,
+            dictionary_id: None,
+        };
+        assert_eq!(output.metadata.params, Some(expected));
+
+        let frame = output.to_frame();
+        assert_eq!(frame.len(), output.total_encoded_size());
+        assert_eq!(frame::FrameHeader::parse(&frame).unwrap().params, Some(expected));
+        assert_eq!(frame::inspect(&frame).unwrap().params, Some(expected));
+        assert_eq!(CompressedOutput::from_frame(&frame).unwrap().metadata.params, Some(expected));
+        assert_eq!(compressor.decompress_frame(&frame).unwrap(), data);
+
+        // Still readable by the heapless decoder
+        let huffman = compressor.compress(&data, CompressionMethod::Huffman).unwrap();
+        assert_eq!(huffman.metadata.params.unwrap().model_order, Some(0));
+        let mut buffer = vec![0u8; data.len()];
+        assert_eq!(crate::embedded::decode_frame(&huffman.to_frame(), &mut buffer).unwrap(), data.len());
+        assert_eq!(buffer, data);
+    }
+
+    #[test]
+    fn test_params_kept_out_of_header_by_default() {
+        let compressor = Compressor::default();
+        let data = b"dedup blocks of a configured size ".repeat(100);
+        let output = compressor.compress(&data, CompressionMethod::SemanticDedupe).unwrap();
+        let params = output.metadata.params.unwrap();
+        assert_eq!(params.block_size, Some(compressor.config.semantic_block_size as u32));
+        assert_eq!(params.level, None);
+
+        let frame = output.to_frame();
+        assert_eq!(frame::FrameHeader::parse(&frame).unwrap().params, None);
+        assert_eq!(CompressedOutput::from_frame(&frame).unwrap().metadata.params, None);
+
+        let mut encoded = Vec::new();
+        params.write(&mut encoded);
+        assert_eq!(encoded.len(), ENCODED_LEN);
+        assert_eq!(CodecParams::parse(&encoded, None).unwrap(), params);
+        assert!(CodecParams::parse(&[0, 0, 0, 0, 7, ABSENT], None).is_err());
+    }
+}
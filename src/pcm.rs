@@ -0,0 +1,282 @@
+//! PCM audio profile for voice notes and other raw sample buffers
+//!
+//! Interleaved little-endian 16- or 24-bit samples are split into one
+//! stream per channel, each sample is replaced by its difference from the
+//! previous one in its channel, and the zigzagged differences are bit-packed
+//! in blocks at the width of the largest in the block. Speech is smooth and
+//! often quiet, so most blocks pack into a few bits per sample and silence
+//! into none; the coding is exact, so the buffer comes back bit for bit.
+//!
+//! Layout:
+//! ```text
+//! [format:u8][channels:varint][frames:varint]
+//! per channel, per block of BLOCK_SAMPLES: [width:u8][packed residuals]
+//! ```
+//! A channel's last block may be short. Packed residuals are LSB-first and
+//! padded to a whole byte.
+
+use crate::error::CompressError;
+use crate::varint;
+
+/// Samples per bit-packing block; each block costs one width byte
+pub const BLOCK_SAMPLES: usize = 256;
+
+/// Sample encoding, signed little-endian
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum SampleFormat {
+    S16,
+    S24,
+}
+
+impl SampleFormat {
+    /// Bytes per sample
+    pub fn size(self) -> usize {
+        match self {
+            SampleFormat::S16 => 2,
+            SampleFormat::S24 => 3,
+        }
+    }
+
+    fn id(self) -> u8 {
+        match self {
+            SampleFormat::S16 => 1,
+            SampleFormat::S24 => 2,
+        }
+    }
+
+    fn from_id(id: u8) -> Option<Self> {
+        match id {
+            1 => Some(SampleFormat::S16),
+            2 => Some(SampleFormat::S24),
+            _ => None,
+        }
+    }
+
+    fn read(self, bytes: &[u8]) -> i32 {
+        match self {
+            SampleFormat::S16 => i16::from_le_bytes([bytes[0], bytes[1]]) as i32,
+            // Place the 24 bits at the top so the shift back sign-extends
+            SampleFormat::S24 => i32::from_le_bytes([0, bytes[0], bytes[1], bytes[2]]) >> 8,
+        }
+    }
+
+    fn write(self, sample: i32, out: &mut Vec<u8>) {
+        out.extend_from_slice(&sample.to_le_bytes()[..self.size()]);
+    }
+
+    /// Widest zigzagged difference of two samples
+    fn max_width(self) -> u8 {
+        (self.size() * 8 + 1) as u8
+    }
+}
+
+/// Sample format and channel count supplied by the caller
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct PcmLayout {
+    pub format: SampleFormat,
+    pub channels: u16,
+}
+
+impl PcmLayout {
+    pub fn new(format: SampleFormat, channels: u16) -> Self {
+        Self { format, channels }
+    }
+
+    /// Bytes per frame, one sample of every channel
+    pub fn frame_size(&self) -> usize {
+        self.format.size() * self.channels as usize
+    }
+}
+
+fn zigzag(value: i32) -> u32 {
+    ((value << 1) ^ (value >> 31)) as u32
+}
+
+fn unzigzag(value: u32) -> i32 {
+    (value >> 1) as i32 ^ -((value & 1) as i32)
+}
+
+/// Compress a PCM buffer laid out as `layout`
+pub fn compress(data: &[u8], layout: &PcmLayout) -> Result<Vec<u8>, CompressError> {
+    let frame_size = layout.frame_size();
+    if frame_size == 0 || !data.len().is_multiple_of(frame_size) {
+        return Err(CompressError::PcmError(format!(
+            "{} bytes are not whole frames of {} {:?} channels",
+            data.len(),
+            layout.channels,
+            layout.format
+        )));
+    }
+    let frames = data.len() / frame_size;
+    let mut out = Vec::with_capacity(data.len() / 2 + 16);
+    out.push(layout.format.id());
+    varint::write_u64(&mut out, layout.channels as u64);
+    varint::write_u64(&mut out, frames as u64);
+
+    let size = layout.format.size();
+    let mut residuals = Vec::with_capacity(BLOCK_SAMPLES);
+    for channel in 0..layout.channels as usize {
+        let mut prev = 0i32;
+        let mut samples = data
+            .chunks_exact(frame_size)
+            .map(|frame| layout.format.read(&frame[channel * size..]));
+        loop {
+            residuals.clear();
+            for sample in samples.by_ref().take(BLOCK_SAMPLES) {
+                residuals.push(zigzag(sample - prev));
+                prev = sample;
+            }
+            if residuals.is_empty() {
+                break;
+            }
+            pack_block(&residuals, &mut out);
+        }
+    }
+    Ok(out)
+}
+
+fn pack_block(residuals: &[u32], out: &mut Vec<u8>) {
+    let width = 32 - residuals.iter().fold(0, |acc, &r| acc | r).leading_zeros();
+    out.push(width as u8);
+    let mut acc = 0u64;
+    let mut bits = 0;
+    for &r in residuals {
+        acc |= (r as u64) << bits;
+        bits += width;
+        while bits >= 8 {
+            out.push(acc as u8);
+            acc >>= 8;
+            bits -= 8;
+        }
+    }
+    if bits > 0 {
+        out.push(acc as u8);
+    }
+}
+
+/// Decompress a buffer, returning the samples and the layout they were
+/// compressed with
+pub fn decompress(data: &[u8]) -> Result<(Vec<u8>, PcmLayout), CompressError> {
+    let err = |msg: &str| CompressError::PcmError(msg.into());
+    let format = data
+        .first()
+        .and_then(|&id| SampleFormat::from_id(id))
+        .ok_or_else(|| err("unknown sample format"))?;
+    let mut pos = 1;
+    let channels = varint::read_usize(data, &mut pos).ok_or_else(|| err("truncated header"))?;
+    let frames = varint::read_usize(data, &mut pos).ok_or_else(|| err("truncated header"))?;
+    let channels = u16::try_from(channels).ok().filter(|&c| c > 0).ok_or_else(|| err("bad channel count"))?;
+    let layout = PcmLayout::new(format, channels);
+    let total = frames
+        .checked_mul(layout.frame_size())
+        .ok_or_else(|| err("frame count overflows"))?;
+
+    let mut planes: Vec<Vec<i32>> = Vec::with_capacity(channels as usize);
+    for _ in 0..channels {
+        let mut plane = Vec::with_capacity(frames.min(crate::MAX_PREALLOC));
+        let mut prev = 0i32;
+        while plane.len() < frames {
+            let count = BLOCK_SAMPLES.min(frames - plane.len());
+            let width = *data.get(pos).ok_or_else(|| err("truncated block"))? as u32;
+            pos += 1;
+            if width > format.max_width() as u32 {
+                return Err(err(&format!("block width {} exceeds {}", width, format.max_width())));
+            }
+            let packed_len = (count * width as usize).div_ceil(8);
+            let packed = data
+                .get(pos..pos + packed_len)
+                .ok_or_else(|| err("truncated block"))?;
+            pos += packed_len;
+
+            let mask = (1u64 << width) - 1;
+            let mut acc = 0u64;
+            let mut bits = 0;
+            let mut bytes = packed.iter();
+            for _ in 0..count {
+                while bits < width {
+                    acc |= (*bytes.next().unwrap() as u64) << bits;
+                    bits += 8;
+                }
+                prev = prev.wrapping_add(unzigzag((acc & mask) as u32));
+                acc >>= width;
+                bits -= width;
+                plane.push(prev);
+            }
+        }
+        planes.push(plane);
+    }
+    if pos != data.len() {
+        return Err(err("trailing bytes after the last block"));
+    }
+
+    let mut out = Vec::with_capacity(total.min(crate::MAX_PREALLOC));
+    for frame in 0..frames {
+        for plane in &planes {
+            format.write(plane[frame], &mut out);
+        }
+    }
+    Ok((out, layout))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::splitmix64;
+    use crate::{CompressionMethod, Compressor};
+
+    #[test]
+    fn test_voice_note_roundtrip_and_gain() {
+        // Two seconds of 16 kHz stereo: a wobbling tone with hiss, then silence
+        let mut state = 5;
+        let mut data = Vec::new();
+        for i in 0..32_000 {
+            let t = i as f64 / 16_000.0;
+            let voiced = i < 24_000;
+            for channel in 0..2 {
+                let tone = (t * 220.0 * std::f64::consts::TAU + channel as f64).sin()
+                    * (t * 3.0 * std::f64::consts::TAU).sin()
+                    * 6000.0;
+                let hiss = (splitmix64(&mut state) % 16) as f64 - 8.0;
+                let sample = if voiced { (tone + hiss) as i16 } else { 0 };
+                data.extend_from_slice(&sample.to_le_bytes());
+            }
+        }
+        let layout = PcmLayout::new(SampleFormat::S16, 2);
+        let compressed = compress(&data, &layout).unwrap();
+        let (restored, restored_layout) = decompress(&compressed).unwrap();
+        assert_eq!(restored, data);
+        assert_eq!(restored_layout, layout);
+
+        let generic = Compressor::default()
+            .compress(&data, CompressionMethod::Lz4Semantic)
+            .unwrap()
+            .to_frame();
+        assert!(compressed.len() * 4 < generic.len() * 3, "{} vs {}", compressed.len(), generic.len());
+    }
+
+    #[test]
+    fn test_24_bit_extremes_and_rejects() {
+        // Full-scale swings give the widest residuals a 24-bit block can hold
+        let mut data = Vec::new();
+        for i in 0..1000 {
+            let sample: i32 = match i % 3 {
+                0 => 0x7F_FFFF,
+                1 => -0x80_0000,
+                _ => i * 37 - 18_000,
+            };
+            SampleFormat::S24.write(sample, &mut data);
+        }
+        let layout = PcmLayout::new(SampleFormat::S24, 1);
+        let compressed = compress(&data, &layout).unwrap();
+        assert_eq!(compressed[4], SampleFormat::S24.max_width());
+        assert_eq!(decompress(&compressed).unwrap(), (data.clone(), layout));
+
+        assert!(compress(&data[..999], &layout).is_ok());
+        assert!(compress(&data[..1000], &layout).is_err());
+        assert!(compress(&data, &PcmLayout::new(SampleFormat::S16, 0)).is_err());
+        let mut bad = compressed.clone();
+        bad[4] = 26;
+        assert!(decompress(&bad).is_err());
+        assert!(decompress(&compressed[..compressed.len() - 1]).is_err());
+    }
+}
@@ -0,0 +1,176 @@
+//! Framed stdin-to-stdout compression for shell pipelines
+//!
+//! Like gzip, pipe mode never needs the whole input in memory: the input is
+//! cut into [`PipeOptions::chunk_size`] pieces and each becomes an ordinary
+//! frame written back to back, which [`Compressor::decompress_frame`] and
+//! [`Compressor::decompress_stream`] already decode as one stream. With more
+//! than one thread, a batch of `threads` chunks (or frames, when decoding)
+//! is processed in parallel and written in input order.
+
+use crate::error::CompressError;
+use crate::{frame, CompressionMethod, Compressor};
+use std::io::{Read, Write};
+use std::thread;
+
+/// Input compressed into each frame by default
+pub const DEFAULT_CHUNK_SIZE: usize = 4 * 1024 * 1024;
+
+/// Settings for [`compress_pipe`] and [`decompress_pipe`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct PipeOptions {
+    pub method: CompressionMethod,
+    /// Worker threads; 0 uses the compressor's
+    /// [`HardwareProfile::threads`](crate::hardware::HardwareProfile::threads)
+    pub threads: usize,
+    pub chunk_size: usize,
+}
+
+impl Default for PipeOptions {
+    fn default() -> Self {
+        Self {
+            method: CompressionMethod::Auto,
+            threads: 1,
+            chunk_size: DEFAULT_CHUNK_SIZE,
+        }
+    }
+}
+
+impl PipeOptions {
+    fn threads(&self, compressor: &Compressor) -> usize {
+        match self.threads {
+            0 => compressor.profile().threads,
+            n => n,
+        }
+    }
+}
+
+/// Bytes moved by a pipe run
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PipeStats {
+    pub bytes_in: u64,
+    pub bytes_out: u64,
+}
+
+/// Compress `reader` into back-to-back frames on `writer`
+pub fn compress_pipe<R: Read, W: Write>(
+    compressor: &Compressor,
+    mut reader: R,
+    mut writer: W,
+    options: &PipeOptions,
+) -> Result<PipeStats, CompressError> {
+    let (threads, mut stats) = (options.threads(compressor), PipeStats::default());
+    loop {
+        let mut chunks = Vec::with_capacity(threads);
+        while chunks.len() < threads {
+            let mut chunk = Vec::new();
+            reader.by_ref().take(options.chunk_size.max(1) as u64).read_to_end(&mut chunk)?;
+            if chunk.is_empty() {
+                break;
+            }
+            stats.bytes_in += chunk.len() as u64;
+            chunks.push(chunk);
+        }
+        if chunks.is_empty() {
+            break;
+        }
+        for frame in run_batch(&chunks, |chunk| Ok(compressor.compress(chunk, options.method)?.to_frame()))? {
+            writer.write_all(&frame)?;
+            stats.bytes_out += frame.len() as u64;
+        }
+    }
+    writer.flush()?;
+    Ok(stats)
+}
+
+/// Decode back-to-back frames from `reader` onto `writer`. Bytes after the
+/// last frame are handled as in [`Compressor::decompress_frame`].
+pub fn decompress_pipe<R: Read, W: Write>(
+    compressor: &Compressor,
+    mut reader: R,
+    mut writer: W,
+    options: &PipeOptions,
+) -> Result<PipeStats, CompressError> {
+    let (threads, mut stats) = (options.threads(compressor), PipeStats::default());
+    let mut done = false;
+    while !done {
+        let mut frames = Vec::with_capacity(threads);
+        while frames.len() < threads && !done {
+            match frame::read_member(&mut reader)? {
+                frame::Member::Frame(frame) => {
+                    stats.bytes_in += frame.len() as u64;
+                    frames.push(frame);
+                }
+                frame::Member::End => done = true,
+                frame::Member::Trailing(_, cause) if stats.bytes_in == 0 => return Err(cause),
+                frame::Member::Trailing(tail, cause) => {
+                    frame::trailing(compressor.strict_trailing_data(), tail, stats.bytes_in, cause)?;
+                    done = true;
+                }
+            }
+        }
+        for data in run_batch(&frames, |frame| compressor.decompress_frame(frame))? {
+            writer.write_all(&data)?;
+            stats.bytes_out += data.len() as u64;
+        }
+    }
+    writer.flush()?;
+    Ok(stats)
+}
+
+/// Apply `work` to every item, one thread per item when there are several,
+/// keeping results in input order
+fn run_batch<F>(items: &[Vec<u8>], work: F) -> Result<Vec<Vec<u8>>, CompressError>
+where
+    F: Fn(&[u8]) -> Result<Vec<u8>, CompressError> + Sync,
+{
+    if let [single] = items {
+        return Ok(vec![work(single)?]);
+    }
+    thread::scope(|scope| {
+        let handles: Vec<_> = items.iter().map(|item| scope.spawn(|| work(item))).collect();
+        handles
+            .into_iter()
+            .map(|handle| handle.join().expect("pipe worker panicked"))
+            .collect()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pipe_roundtrip_multithreaded() {
+        let compressor = Compressor::default();
+        let input: Vec<u8> = (0..30_000).flat_map(|i| format!("line {} of the pipe\n", i % 311).into_bytes()).collect();
+        let options = PipeOptions {
+            method: CompressionMethod::Lz4Semantic,
+            threads: 3,
+            chunk_size: 64 * 1024,
+        };
+        let mut framed = Vec::new();
+        let stats = compress_pipe(&compressor, &input[..], &mut framed, &options).unwrap();
+        assert_eq!(stats.bytes_in, input.len() as u64);
+        assert_eq!(stats.bytes_out, framed.len() as u64);
+        assert!(framed.len() < input.len() / 4);
+        // Ordinary back-to-back frames
+        assert_eq!(compressor.decompress_frame(&framed).unwrap(), input);
+
+        let mut restored = Vec::new();
+        decompress_pipe(&compressor, &framed[..], &mut restored, &options).unwrap();
+        assert_eq!(restored, input);
+    }
+
+    #[test]
+    fn test_pipe_empty_and_truncated() {
+        let compressor = Compressor::default();
+        let options = PipeOptions::default();
+        let mut framed = Vec::new();
+        compress_pipe(&compressor, &b""[..], &mut framed, &options).unwrap();
+        assert!(framed.is_empty());
+
+        compress_pipe(&compressor, &b"short input"[..], &mut framed, &options).unwrap();
+        let mut restored = Vec::new();
+        assert!(decompress_pipe(&compressor, &framed[..framed.len() - 1], &mut restored, &options).is_err());
+    }
+}
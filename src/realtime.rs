@@ -0,0 +1,137 @@
+//! Bounded per-block latency for ingestion paths
+//!
+//! A latency-critical ingestion path cannot wait on a match search that
+//! happens to hit a pathological input. [`Compressor::compress_realtime`]
+//! cuts input into [`RealtimeBudget::block_size`] blocks and gives each a
+//! fixed time budget. Blocks are coded with greedy LZSS examining at most
+//! [`RealtimeBudget::max_chain`] candidates per position, and the clock is
+//! checked as the search goes; a block whose budget runs out, or that does
+//! not shrink, is written `Stored` instead. Work past the budget is thus
+//! limited to one check interval of capped search plus a copy of the block.
+//!
+//! Each block becomes its own frame, written back to back, so
+//! [`Compressor::decompress_frame`] restores the input and a reader can
+//! start on a block before the next is written. Dictionaries are not used.
+
+use crate::error::CompressError;
+use crate::{lzss, stored, CompressionMethod, Compressor};
+use std::time::{Duration, Instant};
+
+/// Limits of [`Compressor::compress_realtime`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RealtimeBudget {
+    /// Input per block, and so per frame
+    pub block_size: usize,
+    /// Time each block may spend in match search and entropy coding
+    pub max_block_time: Duration,
+    /// Match candidates examined per position
+    pub max_chain: usize,
+}
+
+impl Default for RealtimeBudget {
+    fn default() -> Self {
+        Self {
+            block_size: 64 * 1024,
+            max_block_time: Duration::from_millis(1),
+            max_chain: 8,
+        }
+    }
+}
+
+/// What a [`Compressor::compress_realtime`] call did
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RealtimeStats {
+    pub blocks: u64,
+    /// Blocks written `Stored` because their budget ran out
+    pub deadline_misses: u64,
+    /// Longest time spent on one block, fallback included
+    pub worst_block_time: Duration,
+}
+
+impl Compressor {
+    /// Compress `data` into back-to-back frames, spending at most about
+    /// `budget.max_block_time` on each block (see [`realtime`](crate::realtime))
+    pub fn compress_realtime(
+        &self,
+        data: &[u8],
+        budget: &RealtimeBudget,
+    ) -> Result<(Vec<u8>, RealtimeStats), CompressError> {
+        if data.is_empty() {
+            return Err(CompressError::EmptyInput);
+        }
+        let mut frames = Vec::with_capacity(data.len() / 2 + 64);
+        let mut stats = RealtimeStats::default();
+        for block in data.chunks(budget.block_size.max(1)) {
+            let start = Instant::now();
+            let deadline = start + budget.max_block_time;
+            let coded = lzss::compress_bounded(block, &self.config.lzss, budget.max_chain, deadline)?;
+            let missed = coded.is_none() || Instant::now() >= deadline;
+            let (method, payload) = match coded {
+                Some(coded) if !missed && coded.len() < block.len() => (CompressionMethod::Lzss, coded),
+                _ => (CompressionMethod::Stored, stored::compress(block)?),
+            };
+            let mut output = self.finish_output(
+                method,
+                block.len(),
+                payload,
+                self.compute_entropy(block),
+                Some(crc32fast::hash(block)),
+                None,
+            )?;
+            // The chain budget, not the configured level, bounded the search
+            if let Some(params) = &mut output.metadata.params {
+                params.level = None;
+            }
+            frames.extend_from_slice(&output.to_frame());
+            stats.blocks += 1;
+            stats.deadline_misses += missed as u64;
+            stats.worst_block_time = stats.worst_block_time.max(start.elapsed());
+        }
+        Ok((frames, stats))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::frame;
+
+    fn input() -> Vec<u8> {
+        (0..4000).flat_map(|i| format!("ts={} sensor={} value={}\n", 1_700_000_000 + i, i % 16, i % 97).into_bytes()).collect()
+    }
+
+    #[test]
+    fn test_realtime_compresses_within_budget() {
+        let compressor = Compressor::default();
+        let data = input();
+        let budget = RealtimeBudget {
+            block_size: 16 * 1024,
+            max_block_time: Duration::from_secs(5),
+            ..RealtimeBudget::default()
+        };
+        let (frames, stats) = compressor.compress_realtime(&data, &budget).unwrap();
+        assert_eq!(stats.blocks as usize, data.len().div_ceil(budget.block_size));
+        assert_eq!(stats.deadline_misses, 0);
+        assert!(frames.len() < data.len() / 3, "{} of {}", frames.len(), data.len());
+        let members = frame::split_frames(&frames).unwrap();
+        assert!(members.iter().all(|m| frame::FrameHeader::parse(m).unwrap().method == CompressionMethod::Lzss));
+        assert_eq!(compressor.decompress_frame(&frames).unwrap(), data);
+    }
+
+    #[test]
+    fn test_exhausted_budget_falls_back_to_stored() {
+        let compressor = Compressor::default();
+        let data = input();
+        let budget = RealtimeBudget {
+            max_block_time: Duration::ZERO,
+            ..RealtimeBudget::default()
+        };
+        let (frames, stats) = compressor.compress_realtime(&data, &budget).unwrap();
+        assert_eq!(stats.deadline_misses, stats.blocks);
+        for member in frame::split_frames(&frames).unwrap() {
+            assert_eq!(frame::FrameHeader::parse(member).unwrap().method, CompressionMethod::Stored);
+        }
+        assert_eq!(compressor.decompress_frame(&frames).unwrap(), data);
+        assert!(compressor.compress_realtime(b"", &budget).is_err());
+    }
+}
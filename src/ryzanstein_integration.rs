@@ -1,44 +1,267 @@
 //! Ryzanstein integration for semantic compression
 //!
 //! Uses Ryzanstein embeddings to identify semantically similar blocks
-//! for enhanced deduplication.
+//! for enhanced deduplication. Requests are retried with exponential backoff,
+//! and a circuit breaker switches to local hash-based embeddings while the
+//! server keeps failing so a flaky server cannot stall compression.
 
+use crate::config::{ClientPolicy, CompressionConfig, LshConfig};
 use crate::error::CompressError;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+pub use crate::similarity::{SemanticBlock, Similarity, DEFAULT_SHINGLE_LEN};
+
+/// Assign each block a group id: a block joins the first earlier group whose
+/// first member scores at least `threshold` against it, or starts a new group.
+///
+/// Compares every block against every group; see
+/// [`crate::semantic::group_similar`] for the indexed version.
+pub fn group_by_similarity(blocks: &[SemanticBlock], similarity: Similarity, threshold: f64) -> Vec<usize> {
+    let mut representatives: Vec<usize> = Vec::new();
+    let mut groups = Vec::with_capacity(blocks.len());
+    for (i, block) in blocks.iter().enumerate() {
+        let group = match representatives
+            .iter()
+            .position(|&r| similarity.score(&blocks[r], block) >= threshold)
+        {
+            Some(group) => group,
+            None => {
+                representatives.push(i);
+                representatives.len() - 1
+            }
+        };
+        groups.push(group);
+    }
+    groups
+}
 
 /// Client for Ryzanstein semantic services
 pub struct RyzansteinCompressClient {
     base_url: String,
+    embedding_dim: usize,
+    lsh: LshConfig,
+    policy: ClientPolicy,
+    http: reqwest::Client,
+    breaker: Mutex<Breaker>,
+    limiter: Mutex<RateLimiter>,
+}
+
+/// Spaces requests evenly at a fixed rate
+#[derive(Debug)]
+struct RateLimiter {
+    interval: Option<Duration>,
+    next_slot: Instant,
+}
+
+impl RateLimiter {
+    fn new(per_sec: f64) -> Self {
+        Self {
+            interval: (per_sec > 0.0).then(|| Duration::from_secs_f64(1.0 / per_sec)),
+            next_slot: Instant::now(),
+        }
+    }
+
+    /// Claim the next slot, returning how long to wait for it
+    fn reserve(&mut self, now: Instant) -> Duration {
+        let Some(interval) = self.interval else {
+            return Duration::ZERO;
+        };
+        let slot = self.next_slot.max(now);
+        self.next_slot = slot + interval;
+        slot - now
+    }
+}
+
+#[derive(Debug, Default)]
+struct Breaker {
+    consecutive_failures: u32,
+    open_until: Option<Instant>,
+}
+
+#[derive(serde::Serialize)]
+struct EmbeddingRequest<'a> {
+    input: &'a [String],
+}
+
+#[derive(serde::Deserialize)]
+struct EmbeddingResponse {
+    data: Vec<EmbeddingData>,
+}
+
+#[derive(serde::Deserialize)]
+struct EmbeddingData {
+    embedding: Vec<f32>,
 }
 
 impl RyzansteinCompressClient {
     pub fn new(base_url: &str) -> Self {
+        Self::with_policy(base_url, ClientPolicy::default())
+    }
+
+    pub fn with_policy(base_url: &str, policy: ClientPolicy) -> Self {
         Self {
             base_url: base_url.to_string(),
+            embedding_dim: 128,
+            lsh: LshConfig::default(),
+            http: reqwest::Client::new(),
+            breaker: Mutex::new(Breaker::default()),
+            limiter: Mutex::new(RateLimiter::new(policy.max_requests_per_sec)),
+            policy,
         }
     }
 
-    /// Get semantic embeddings for code blocks
+    /// Client for the service and embedding dimension named in `config`
+    pub fn from_config(config: &CompressionConfig, policy: ClientPolicy) -> Self {
+        let mut client = Self::with_policy(&config.ryzanstein_url, policy).with_embedding_dim(config.embedding_dim);
+        client.lsh = config.lsh.clone();
+        client
+    }
+
+    /// Expect (and fall back to) embeddings of `dim` components
+    pub fn with_embedding_dim(mut self, dim: usize) -> Self {
+        self.embedding_dim = dim.max(1);
+        self
+    }
+
+    pub fn embedding_dim(&self) -> usize {
+        self.embedding_dim
+    }
+
+    /// Embed `blocks` and group them with [`crate::semantic::group_similar`]
+    pub async fn group_similar(
+        &self,
+        blocks: &[String],
+        similarity: Similarity,
+        threshold: f64,
+    ) -> Result<Vec<usize>, CompressError> {
+        let embeddings = match similarity {
+            Similarity::Jaccard { .. } => vec![Vec::new(); blocks.len()],
+            _ => self.get_embeddings(blocks).await?,
+        };
+        let items: Vec<SemanticBlock> = blocks
+            .iter()
+            .zip(&embeddings)
+            .map(|(text, embedding)| SemanticBlock { text, embedding })
+            .collect();
+        Ok(crate::semantic::group_similar(&items, similarity, threshold, &self.lsh))
+    }
+
+    /// Base URL of the Ryzanstein service this client talks to
+    pub fn base_url(&self) -> &str {
+        &self.base_url
+    }
+
+    /// Whether the circuit breaker is currently skipping the server
+    pub fn circuit_open(&self) -> bool {
+        let breaker = self.breaker.lock().unwrap();
+        breaker.open_until.is_some_and(|until| Instant::now() < until)
+    }
+
+    /// Get semantic embeddings for code blocks, sent in batches of
+    /// `batch_size` at no more than `max_requests_per_sec`.
+    ///
+    /// Batches that fail after all retries, or that arrive while the circuit
+    /// is open, get hash-based pseudo-embeddings instead.
     pub async fn get_embeddings(&self, blocks: &[String]) -> Result<Vec<Vec<f32>>, CompressError> {
-        // In production, calls Ryzanstein /v1/embeddings
-        // Fallback: hash-based pseudo-embeddings
-        Ok(blocks.iter().map(|b| self.fallback_embed(b)).collect())
+        let mut embeddings = Vec::with_capacity(blocks.len());
+        for batch in blocks.chunks(self.policy.batch_size.max(1)) {
+            embeddings.extend(self.embed_batch(batch).await);
+        }
+        Ok(embeddings)
     }
 
-    /// Compute similarity between two embedding vectors
-    pub fn cosine_similarity(a: &[f32], b: &[f32]) -> f64 {
-        if a.len() != b.len() || a.is_empty() {
-            return 0.0;
+    async fn embed_batch(&self, batch: &[String]) -> Vec<Vec<f32>> {
+        if !self.circuit_open() {
+            match self.fetch_with_retry(batch).await {
+                Ok(embeddings) => {
+                    self.record(true);
+                    return embeddings;
+                }
+                Err(e) => {
+                    tracing::warn!("ryzanstein embeddings failed, using local fallback: {}", e);
+                    self.record(false);
+                }
+            }
+        }
+        batch.iter().map(|b| self.fallback_embed(b)).collect()
+    }
+
+    async fn fetch_with_retry(&self, blocks: &[String]) -> Result<Vec<Vec<f32>>, CompressError> {
+        let mut attempt = 0;
+        loop {
+            match self.fetch(blocks).await {
+                Ok(embeddings) => return Ok(embeddings),
+                Err(e) if attempt >= self.policy.max_retries => return Err(e),
+                Err(_) => {
+                    tokio::time::sleep(self.backoff(attempt)).await;
+                    attempt += 1;
+                }
+            }
+        }
+    }
+
+    async fn fetch(&self, blocks: &[String]) -> Result<Vec<Vec<f32>>, CompressError> {
+        let wait = self.limiter.lock().unwrap().reserve(Instant::now());
+        tokio::time::sleep(wait).await;
+        let url = format!("{}/v1/embeddings", self.base_url.trim_end_matches('/'));
+        let response = self
+            .http
+            .post(url)
+            .timeout(Duration::from_millis(self.policy.request_timeout_ms))
+            .json(&EmbeddingRequest { input: blocks })
+            .send()
+            .await
+            .and_then(|r| r.error_for_status())
+            .map_err(|e| CompressError::RyzansteinError(e.to_string()))?;
+        let body: EmbeddingResponse = response
+            .json()
+            .await
+            .map_err(|e| CompressError::RyzansteinError(e.to_string()))?;
+        if body.data.len() != blocks.len() {
+            return Err(CompressError::RyzansteinError(format!(
+                "expected {} embeddings, got {}",
+                blocks.len(),
+                body.data.len()
+            )));
+        }
+        if let Some(d) = body.data.iter().find(|d| d.embedding.len() != self.embedding_dim) {
+            return Err(CompressError::RyzansteinError(format!(
+                "expected {}-dimensional embeddings, got {}",
+                self.embedding_dim,
+                d.embedding.len()
+            )));
+        }
+        Ok(body.data.into_iter().map(|d| d.embedding).collect())
+    }
+
+    /// Delay before retry number `attempt` (0-based)
+    fn backoff(&self, attempt: u32) -> Duration {
+        let ms = self
+            .policy
+            .initial_backoff_ms
+            .saturating_mul(1u64 << attempt.min(32))
+            .min(self.policy.max_backoff_ms);
+        Duration::from_millis(ms)
+    }
+
+    fn record(&self, success: bool) {
+        let mut breaker = self.breaker.lock().unwrap();
+        if success {
+            *breaker = Breaker::default();
+            return;
         }
-        let dot: f64 = a.iter().zip(b).map(|(x, y)| (*x as f64) * (*y as f64)).sum();
-        let mag_a: f64 = a.iter().map(|x| (*x as f64).powi(2)).sum::<f64>().sqrt();
-        let mag_b: f64 = b.iter().map(|x| (*x as f64).powi(2)).sum::<f64>().sqrt();
-        if mag_a * mag_b < 1e-10 {
-            0.0
-        } else {
-            dot / (mag_a * mag_b)
+        breaker.consecutive_failures += 1;
+        if breaker.consecutive_failures >= self.policy.breaker_threshold.max(1) {
+            breaker.open_until = Some(Instant::now() + Duration::from_millis(self.policy.breaker_cooldown_ms));
         }
     }
 
+    /// Compute similarity between two embedding vectors
+    pub fn cosine_similarity(a: &[f32], b: &[f32]) -> f64 {
+        crate::similarity::cosine(a, b)
+    }
+
     /// Health check for Ryzanstein connectivity
     pub async fn health_check(&self) -> Result<bool, CompressError> {
         // Mock: always healthy in development
@@ -46,9 +269,10 @@ impl RyzansteinCompressClient {
     }
 
     fn fallback_embed(&self, text: &str) -> Vec<f32> {
-        let mut embedding = vec![0.0f32; 128];
+        let dim = self.embedding_dim;
+        let mut embedding = vec![0.0f32; dim];
         for (i, byte) in text.bytes().enumerate() {
-            embedding[i % 128] += (byte as f32) / 255.0;
+            embedding[i % dim] += (byte as f32) / 255.0;
         }
         let norm: f32 = embedding.iter().map(|x| x * x).sum::<f32>().sqrt();
         if norm > 0.0 {
@@ -103,4 +327,116 @@ mod tests {
         assert_eq!(embeddings.len(), 2);
         assert_eq!(embeddings[0].len(), 128);
     }
+
+    #[test]
+    fn test_backoff_doubles_up_to_cap() {
+        let policy = ClientPolicy {
+            initial_backoff_ms: 100,
+            max_backoff_ms: 350,
+            ..Default::default()
+        };
+        let client = RyzansteinCompressClient::with_policy("http://localhost:8000", policy);
+        let delays: Vec<u64> = (0..4).map(|a| client.backoff(a).as_millis() as u64).collect();
+        assert_eq!(delays, vec![100, 200, 350, 350]);
+    }
+
+    #[tokio::test]
+    async fn test_breaker_opens_and_falls_back() {
+        let policy = ClientPolicy {
+            max_retries: 0,
+            request_timeout_ms: 500,
+            breaker_threshold: 2,
+            ..Default::default()
+        };
+        let client = RyzansteinCompressClient::with_policy("http://127.0.0.1:9", policy);
+        let blocks = vec!["fn main()".to_string()];
+        for _ in 0..2 {
+            let embeddings = client.get_embeddings(&blocks).await.unwrap();
+            assert_eq!(embeddings[0], client.fallback_embed("fn main()"));
+        }
+        assert!(client.circuit_open());
+    }
+
+    #[test]
+    fn test_rate_limiter_spaces_requests() {
+        let mut limiter = RateLimiter::new(10.0);
+        let now = Instant::now();
+        let waits: Vec<u128> = (0..3).map(|_| limiter.reserve(now).as_millis()).collect();
+        assert_eq!(waits, vec![0, 100, 200]);
+        assert_eq!(RateLimiter::new(0.0).reserve(now), Duration::ZERO);
+    }
+
+    #[tokio::test]
+    async fn test_embeddings_are_batched() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::sync::Arc;
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let requests = Arc::new(AtomicUsize::new(0));
+        let counter = requests.clone();
+        tokio::spawn(async move {
+            loop {
+                let (mut socket, _) = listener.accept().await.unwrap();
+                counter.fetch_add(1, Ordering::SeqCst);
+                let mut raw = Vec::new();
+                let mut buf = [0u8; 1024];
+                let body = loop {
+                    let n = socket.read(&mut buf).await.unwrap();
+                    assert!(n > 0, "connection closed before the request body");
+                    raw.extend_from_slice(&buf[..n]);
+                    let text = String::from_utf8_lossy(&raw).to_string();
+                    if let Some(split) = text.find("\r\n\r\n") {
+                        let len: usize = text
+                            .lines()
+                            .find_map(|l| l.to_ascii_lowercase().strip_prefix("content-length:").map(|v| v.trim().parse().unwrap()))
+                            .unwrap_or(0);
+                        if raw.len() >= split + 4 + len {
+                            break raw[split + 4..split + 4 + len].to_vec();
+                        }
+                    }
+                };
+                let request: serde_json::Value = serde_json::from_slice(&body).unwrap();
+                let n = request["input"].as_array().unwrap().len();
+                let data: Vec<_> = (0..n).map(|_| serde_json::json!({ "embedding": [1.0, 0.0] })).collect();
+                let response = serde_json::json!({ "data": data }).to_string();
+                let reply = format!(
+                    "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    response.len(),
+                    response
+                );
+                socket.write_all(reply.as_bytes()).await.unwrap();
+            }
+        });
+
+        let policy = ClientPolicy {
+            batch_size: 2,
+            max_requests_per_sec: 0.0,
+            ..Default::default()
+        };
+        let client = RyzansteinCompressClient::with_policy(&url, policy).with_embedding_dim(2);
+        let blocks: Vec<String> = (0..5).map(|i| format!("block {}", i)).collect();
+        let embeddings = client.get_embeddings(&blocks).await.unwrap();
+        assert_eq!(embeddings.len(), 5);
+        assert!(embeddings.iter().all(|e| e == &vec![1.0, 0.0]));
+        assert_eq!(requests.load(Ordering::SeqCst), 3);
+    }
+
+    #[test]
+    fn test_group_by_similarity_and_dimension() {
+        let client = RyzansteinCompressClient::new("http://localhost:8000").with_embedding_dim(16);
+        assert_eq!(client.fallback_embed("sixteen").len(), 16);
+
+        let texts = ["let total = a + b;", "let total = a + c;", "<html><body>", "let total = a + b;"];
+        let embeddings: Vec<Vec<f32>> = texts.iter().map(|t| client.fallback_embed(t)).collect();
+        let blocks: Vec<SemanticBlock> = texts
+            .iter()
+            .zip(&embeddings)
+            .map(|(text, embedding)| SemanticBlock { text, embedding })
+            .collect();
+        assert_eq!(group_by_similarity(&blocks, Similarity::Cosine, 0.99), vec![0, 0, 1, 0]);
+        let groups = group_by_similarity(&blocks, Similarity::Jaccard { shingle_len: 4 }, 0.99);
+        assert_eq!(groups, vec![0, 1, 2, 0]);
+    }
 }
@@ -0,0 +1,165 @@
+//! Caller-owned scratch space for repeated compression
+//!
+//! Each `compress` call allocates the payload, the codec's block buffers and,
+//! for deduplication, a table of unique blocks, then frees them again. In a
+//! long-running service these short-lived buffers of varying size fragment
+//! the heap. A [`ScratchBuffers`] owned by the caller (typically one per
+//! worker thread) keeps them between calls:
+//! [`Compressor::compress_with_scratch`] encodes through it, and
+//! [`ScratchBuffers::recycle`] takes back the payload of an output the
+//! caller is done with, so steady-state compression stops allocating.
+//!
+//! `Huffman`, `Lz4Semantic` and `SemanticDedupe` run entirely in the scratch
+//! buffers and produce the same payloads as [`Compressor::compress`]. Other
+//! methods, `Auto`, dictionary coding and inputs diverted by the nested-frame
+//! policy go through [`Compressor::compress`] unchanged.
+
+use crate::config::NestedFramePolicy;
+use crate::dictionary::DictionarySelection;
+use crate::error::CompressError;
+use crate::lz4_wrapper::EncoderBuffers;
+use crate::semantic::DedupScratch;
+use crate::{frame, huffman, lz4_wrapper, semantic, usage, CompressedOutput, CompressionMethod, Compressor};
+
+/// Reusable buffers for [`Compressor::compress_with_scratch`]
+#[derive(Debug, Default)]
+pub struct ScratchBuffers {
+    /// Payload of the next output
+    output: Vec<u8>,
+    lz: EncoderBuffers,
+    dedup: DedupScratch,
+}
+
+impl ScratchBuffers {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Return the payload allocation of an output that is no longer needed
+    pub fn recycle(&mut self, output: CompressedOutput) {
+        if output.data.capacity() > self.output.capacity() {
+            self.output = output.data;
+        }
+    }
+
+    /// Bytes currently held, for pool accounting
+    pub fn capacity(&self) -> usize {
+        self.output.capacity() + self.lz.capacity() + self.dedup.capacity()
+    }
+}
+
+impl Compressor {
+    /// [`Compressor::compress`] using `scratch` for the payload and codec
+    /// working memory
+    pub fn compress_with_scratch(
+        &self,
+        data: &[u8],
+        method: CompressionMethod,
+        scratch: &mut ScratchBuffers,
+    ) -> Result<CompressedOutput, CompressError> {
+        let config = &self.config;
+        let supported = matches!(
+            method,
+            CompressionMethod::Huffman | CompressionMethod::Lz4Semantic | CompressionMethod::SemanticDedupe
+        );
+        let nested = frame::is_frame(data);
+        if !supported
+            || config.dictionary != DictionarySelection::None
+            || (nested && config.nested_frames != NestedFramePolicy::Warn)
+        {
+            return self.compress(data, method);
+        }
+        self.metered(
+            usage::Operation::Compress,
+            method,
+            data.len(),
+            || {
+                if data.is_empty() {
+                    return Err(CompressError::EmptyInput);
+                }
+                if data.len() > config.max_input_size {
+                    return Err(CompressError::InputTooLarge {
+                        size: data.len(),
+                        limit: config.max_input_size,
+                    });
+                }
+                let mut payload = std::mem::take(&mut scratch.output);
+                payload.clear();
+                let payload = match method {
+                    CompressionMethod::Huffman => {
+                        let mut encoder =
+                            huffman::StreamEncoder::with_output(&huffman::byte_frequencies(data), data.len(), payload)?;
+                        encoder.push(data)?;
+                        encoder.finish()
+                    }
+                    CompressionMethod::Lz4Semantic => {
+                        let buffers = std::mem::take(&mut scratch.lz);
+                        let mut encoder = lz4_wrapper::StreamEncoder::with_buffers(config.lz4_block_size, config.level, buffers);
+                        encoder.push(data)?;
+                        scratch.lz = encoder.finish_into(&mut payload)?;
+                        payload
+                    }
+                    _ => {
+                        semantic::compress_blocks_into(data, config.semantic_block_size, &mut scratch.dedup, &mut payload);
+                        payload
+                    }
+                };
+                let mut output = self.finish_output(
+                    method,
+                    data.len(),
+                    payload,
+                    self.compute_entropy(data),
+                    Some(crc32fast::hash(data)),
+                    None,
+                )?;
+                output.metadata.block_count = (data.len() / config.lz4_block_size).max(1);
+                output.metadata.nested_frame = nested;
+                Ok(output)
+            },
+            |output| (output.total_encoded_size(), output.method, output.metadata.memory),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scratch_matches_compress() {
+        let compressor = Compressor::default();
+        let mut scratch = ScratchBuffers::new();
+        let data: Vec<u8> = (0..4000).flat_map(|i| format!("row {} status=ok\n", i % 50).into_bytes()).collect();
+        for method in [CompressionMethod::Huffman, CompressionMethod::Lz4Semantic, CompressionMethod::SemanticDedupe] {
+            let reused = compressor.compress_with_scratch(&data, method, &mut scratch).unwrap();
+            let fresh = compressor.compress(&data, method).unwrap();
+            assert_eq!(reused.compressed_size, fresh.compressed_size);
+            assert_eq!(reused.checksum, fresh.checksum);
+            assert_eq!(reused.metadata.semantic, fresh.metadata.semantic);
+            if method != CompressionMethod::Huffman {
+                // Huffman code tables are written in hash-map order
+                assert_eq!(reused.data, fresh.data);
+            }
+            assert_eq!(compressor.decompress(&reused).unwrap(), data);
+            scratch.recycle(reused);
+        }
+        let other = compressor.compress_with_scratch(&data, CompressionMethod::Lzss, &mut scratch).unwrap();
+        assert_eq!(compressor.decompress(&other).unwrap(), data);
+        assert!(compressor.compress_with_scratch(b"", CompressionMethod::Huffman, &mut scratch).is_err());
+    }
+
+    #[test]
+    fn test_recycled_payload_is_reused() {
+        let compressor = Compressor::default();
+        let mut scratch = ScratchBuffers::new();
+        let data = b"steady state payload ".repeat(300);
+        let first = compressor.compress_with_scratch(&data, CompressionMethod::Lz4Semantic, &mut scratch).unwrap();
+        let pointer = first.data.as_ptr();
+        scratch.recycle(first);
+        let held = scratch.capacity();
+        assert!(held > 0);
+        let second = compressor.compress_with_scratch(&data, CompressionMethod::Lz4Semantic, &mut scratch).unwrap();
+        assert_eq!(second.data.as_ptr(), pointer);
+        assert_eq!(compressor.decompress(&second).unwrap(), data);
+    }
+}
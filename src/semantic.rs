@@ -1,95 +1,718 @@
 //! Semantic deduplication via content hashing and similarity grouping
 //!
 //! Groups similar content blocks and stores them once with references.
+//!
+//! Payload layout:
+//! ```text
+//! [0x00][version:u8][ref_coding:u8]
+//! [num_unique:varint]([block_len:varint][block_data])*
+//! [num_refs:varint][ref:width bytes LE]*                      plain refs
+//! [num_refs:varint][num_runs:varint]([ref:width bytes LE][count:varint])*
+//!                                                             with RUNS_FLAG
+//! ```
+//! The low bits of `ref_coding` give the width, the narrowest of 1, 2 or 4
+//! bytes that indexes every unique block; `RUNS_FLAG` codes refs as runs of
+//! the same block, written when that is smaller, so a block repeated
+//! thousands of times in a row takes a few bytes. Version 2 payloads have
+//! no runs. Legacy payloads have no `[0x00][version][ref_coding]` prefix
+//! and 4-byte refs; a legacy block table never starts with a zero count
+//! unless it is empty, so all of them decode.
+//!
+//! The streaming encoder finds repeats through a table of the unique blocks
+//! seen so far. Left unbounded it grows with the input; with a memory cap
+//! (see [`StreamEncoder::with_table_limit`]) it keeps at most that many
+//! bytes of entries and evicts cold blocks in CLOCK order, so a block seen
+//! again after its eviction is stored again. The payload format is the same
+//! either way, and [`report`] counts such repeat copies as
+//! [`SemanticReport::missed_duplicates`].
 
+use crate::config::LshConfig;
 use crate::error::CompressError;
-use std::collections::HashMap;
+use crate::ryzanstein_integration::RyzansteinCompressClient;
+use crate::similarity::{SemanticBlock, Similarity};
+use std::collections::{HashMap, HashSet};
+use std::hash::{BuildHasher, BuildHasherDefault, DefaultHasher};
 
-/// Compress via semantic deduplication (content-addressable blocks)
-pub fn compress(data: &[u8], _threshold: f64) -> Result<Vec<u8>, CompressError> {
-    let block_size = 64;
-    let mut blocks: Vec<&[u8]> = Vec::new();
-    let mut unique_blocks: HashMap<Vec<u8>, u32> = HashMap::new();
-    let mut block_refs: Vec<u32> = Vec::new();
+/// Default size of the dedup unit
+pub const BLOCK_SIZE: usize = 64;
 
-    for chunk in data.chunks(block_size) {
-        blocks.push(chunk);
-        let key = chunk.to_vec();
-        let idx = unique_blocks.len() as u32;
-        let block_idx = *unique_blocks.entry(key).or_insert(idx);
-        block_refs.push(block_idx);
+/// Leading byte of versioned payloads
+const VERSION_MARKER: u8 = 0x00;
+/// Payload layout written by this build; legacy payloads are version 1
+pub const FORMAT_VERSION: u8 = 3;
+/// Version with narrow refs but no runs
+const FORMAT_VERSION_NO_RUNS: u8 = 2;
+/// Set in the `ref_coding` byte when refs are coded as runs
+const RUNS_FLAG: u8 = 0x80;
+/// Width of refs in legacy payloads
+const LEGACY_REF_WIDTH: usize = 4;
+
+/// Narrowest ref width that indexes `unique` blocks
+fn ref_width(unique: usize) -> usize {
+    match unique {
+        0..=0x100 => 1,
+        0x101..=0x1_0000 => 2,
+        _ => 4,
     }
+}
 
-    // Format: [num_unique:u32][block_len:u32,block_data...][num_refs:u32][refs...]
-    let mut output = Vec::new();
-    let num_unique = unique_blocks.len() as u32;
-    output.extend_from_slice(&num_unique.to_le_bytes());
+/// How a payload's refs are written
+#[derive(Debug, Clone, Copy)]
+struct RefCoding {
+    width: usize,
+    runs: bool,
+}
 
-    // Sort unique blocks by index so they can be looked up
-    let mut sorted: Vec<(Vec<u8>, u32)> = unique_blocks.into_iter().collect();
-    sorted.sort_by_key(|&(_, idx)| idx);
+impl RefCoding {
+    /// Narrowest width for `unique` blocks, with runs when they are smaller
+    fn choose(refs: &[u32], unique: usize) -> Self {
+        let width = ref_width(unique);
+        let runs = ref_runs(refs);
+        let run_bytes = crate::varint::encoded_len(runs.len() as u64)
+            + runs.iter().map(|&(_, count)| width + crate::varint::encoded_len(count)).sum::<usize>();
+        Self {
+            width,
+            runs: run_bytes < refs.len() * width,
+        }
+    }
+
+    fn byte(&self) -> u8 {
+        self.width as u8 | if self.runs { RUNS_FLAG } else { 0 }
+    }
+}
 
-    for (block, _) in &sorted {
-        output.extend_from_slice(&(block.len() as u32).to_le_bytes());
-        output.extend_from_slice(block);
+/// `(ref, count)` for each stretch of equal refs
+fn ref_runs(refs: &[u32]) -> Vec<(u32, u64)> {
+    let mut runs: Vec<(u32, u64)> = Vec::new();
+    for &r in refs {
+        match runs.last_mut() {
+            Some((last, count)) if *last == r => *count += 1,
+            _ => runs.push((r, 1)),
+        }
     }
+    runs
+}
+
+/// Write the version header and block-table count
+fn write_header(output: &mut Vec<u8>, coding: RefCoding, unique: usize) {
+    output.extend_from_slice(&[VERSION_MARKER, FORMAT_VERSION, coding.byte()]);
+    crate::varint::write_u64(output, unique as u64);
+}
 
-    let num_refs = block_refs.len() as u32;
-    output.extend_from_slice(&num_refs.to_le_bytes());
-    for r in &block_refs {
-        output.extend_from_slice(&r.to_le_bytes());
+/// Write the ref count and the refs, plain or as runs
+fn write_refs(output: &mut Vec<u8>, coding: RefCoding, refs: &[u32]) {
+    crate::varint::write_u64(output, refs.len() as u64);
+    if coding.runs {
+        let runs = ref_runs(refs);
+        crate::varint::write_u64(output, runs.len() as u64);
+        for (r, count) in runs {
+            output.extend_from_slice(&r.to_le_bytes()[..coding.width]);
+            crate::varint::write_u64(output, count);
+        }
+    } else {
+        output.reserve(refs.len() * coding.width);
+        for r in refs {
+            output.extend_from_slice(&r.to_le_bytes()[..coding.width]);
+        }
     }
+}
+
+/// Memory one entry of the bounded block table takes, map slot included
+pub const TABLE_ENTRY_BYTES: usize = 48;
+
+/// Compress via semantic deduplication (content-addressable blocks)
+pub fn compress(data: &[u8], threshold: f64) -> Result<Vec<u8>, CompressError> {
+    compress_blocks(data, threshold, BLOCK_SIZE)
+}
 
+/// [`compress`] with `block_size`-byte dedup units; block lengths are stored
+/// in the payload, so decoding needs no matching setting
+pub fn compress_blocks(data: &[u8], threshold: f64, block_size: usize) -> Result<Vec<u8>, CompressError> {
+    let mut encoder = StreamEncoder::with_block_size(threshold, block_size);
+    encoder.push(data);
+    Ok(encoder.finish())
+}
+
+/// Decompress semantically-deduplicated data; refs declaring more than
+/// `original_size` bytes in total are rejected before they are expanded
+pub fn decompress(data: &[u8], original_size: usize) -> Result<Vec<u8>, CompressError> {
+    let mut output = Vec::with_capacity(original_size.min(crate::MAX_PREALLOC));
+    for block in BlockDecoder::bounded(data, original_size, usize::MAX)? {
+        output.extend_from_slice(&block?);
+    }
     Ok(output)
 }
 
-/// Decompress semantically-deduplicated data
-pub fn decompress(data: &[u8], _original_size: usize) -> Result<Vec<u8>, CompressError> {
-    if data.len() < 4 {
-        return Err(CompressError::SemanticError("data too short".into()));
+/// Size of the block behind every reference, read without copying block
+/// data; as in [`decompress`], at most `original_size` bytes' worth
+pub fn block_sizes(data: &[u8], original_size: usize) -> Result<Vec<usize>, CompressError> {
+    let mut decoder = BlockDecoder::bounded(data, original_size, usize::MAX)?;
+    let mut sizes = Vec::with_capacity(decoder.remaining_refs.min(original_size));
+    while decoder.remaining_refs > 0 {
+        let (idx, count) = decoder.next_run()?;
+        sizes.extend(std::iter::repeat_n(decoder.blocks[idx].len(), count as usize));
+    }
+    Ok(sizes)
+}
+
+/// Bytes the refs of `data` expand to, summed over runs without expanding them
+pub fn decoded_len(data: &[u8]) -> Result<u64, CompressError> {
+    let mut decoder = BlockDecoder::new(data, usize::MAX)?;
+    let mut total = 0u64;
+    while decoder.remaining_refs > 0 {
+        let (idx, count) = decoder.next_run()?;
+        total = (decoder.blocks[idx].len() as u64)
+            .checked_mul(count)
+            .and_then(|bytes| total.checked_add(bytes))
+            .ok_or_else(|| CompressError::SemanticError("decoded size overflows u64".into()))?;
+    }
+    Ok(total)
+}
+
+/// What a dedup payload achieved, recorded in
+/// [`crate::CompressionMetadata::semantic`]
+#[derive(Debug, Clone, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct SemanticReport {
+    /// Blocks the input was split into
+    pub blocks: usize,
+    pub unique_blocks: usize,
+    /// Blocks replaced by a reference to an earlier identical block
+    pub duplicate_blocks: usize,
+    /// Distinct blocks that occur more than once
+    pub clusters: usize,
+    /// Input bytes not stored thanks to exact dedup
+    pub exact_bytes_saved: usize,
+    /// Input bytes not stored thanks to similarity-based delta coding
+    pub similarity_bytes_saved: usize,
+    /// Requests made to the embedding service
+    pub embedding_calls: usize,
+    /// Unique blocks stored again because a bounded block table had evicted
+    /// their earlier copy
+    #[serde(default)]
+    pub missed_duplicates: usize,
+    /// Bytes those repeat copies take
+    #[serde(default)]
+    pub missed_bytes: usize,
+}
+
+/// Dedup statistics of a payload, read from its block table and refs
+pub fn report(data: &[u8]) -> Result<SemanticReport, CompressError> {
+    let mut decoder = BlockDecoder::new(data, usize::MAX)?;
+    let mut uses = vec![0usize; decoder.blocks.len()];
+    let mut referenced_bytes = 0usize;
+    while decoder.remaining_refs > 0 {
+        let (idx, count) = decoder.next_run()?;
+        uses[idx] += count as usize;
+        referenced_bytes = referenced_bytes.saturating_add(decoder.blocks[idx].len().saturating_mul(count as usize));
+    }
+    let blocks = uses.iter().sum::<usize>();
+    let unique_blocks = uses.iter().filter(|&&n| n > 0).count();
+    let stored_bytes: usize = decoder.blocks.iter().map(|b| b.len()).sum();
+    let mut seen = HashSet::with_capacity(decoder.blocks.len());
+    let missed: Vec<&[u8]> = decoder.blocks.iter().copied().filter(|b| !seen.insert(*b)).collect();
+    Ok(SemanticReport {
+        blocks,
+        unique_blocks,
+        duplicate_blocks: blocks - unique_blocks,
+        clusters: uses.iter().filter(|&&n| n > 1).count(),
+        exact_bytes_saved: referenced_bytes.saturating_sub(stored_bytes),
+        similarity_bytes_saved: 0,
+        embedding_calls: 0,
+        missed_duplicates: missed.len(),
+        missed_bytes: missed.iter().map(|b| b.len()).sum(),
+    })
+}
+
+/// Bytes spent on counts and length prefixes rather than block contents or refs
+pub fn index_len(data: &[u8]) -> Result<usize, CompressError> {
+    let decoder = BlockDecoder::new(data, usize::MAX)?;
+    Ok(decoder.pos - decoder.blocks.iter().map(|b| b.len()).sum::<usize>())
+}
+
+/// Unique block the encoder can still reference
+#[derive(Debug, Clone, Copy)]
+struct Slot {
+    hash: u64,
+    index: u32,
+    /// Position of the block data in the encoder's block table
+    offset: usize,
+    len: usize,
+    /// Referenced since the clock hand last passed
+    referenced: bool,
+}
+
+/// Block hash to unique block, holding at most `max_entries` blocks and
+/// evicting in CLOCK order
+#[derive(Debug)]
+struct BlockTable {
+    map: HashMap<u64, usize, BuildHasherDefault<DefaultHasher>>,
+    slots: Vec<Slot>,
+    max_entries: usize,
+    hand: usize,
+    evictions: u64,
+}
+
+impl BlockTable {
+    fn new(max_entries: usize) -> Self {
+        Self {
+            map: HashMap::default(),
+            slots: Vec::new(),
+            max_entries: max_entries.max(1),
+            hand: 0,
+            evictions: 0,
+        }
+    }
+
+    fn get(&mut self, hash: u64) -> Option<Slot> {
+        let slot = &mut self.slots[*self.map.get(&hash)?];
+        slot.referenced = true;
+        Some(*slot)
+    }
+
+    fn insert(&mut self, slot: Slot) {
+        if self.slots.len() < self.max_entries {
+            self.map.insert(slot.hash, self.slots.len());
+            self.slots.push(slot);
+            return;
+        }
+        while self.slots[self.hand].referenced {
+            self.slots[self.hand].referenced = false;
+            self.hand = (self.hand + 1) % self.slots.len();
+        }
+        self.map.remove(&self.slots[self.hand].hash);
+        self.map.insert(slot.hash, self.hand);
+        self.slots[self.hand] = slot;
+        self.hand = (self.hand + 1) % self.slots.len();
+        self.evictions += 1;
+    }
+}
+
+/// Incremental dedup encoder; input is re-chunked on block-size boundaries
+/// regardless of how it is split across `push` calls
+pub struct StreamEncoder {
+    table: BlockTable,
+    /// Block table of the payload: `[block_len:varint][block_data]` per block
+    blocks: Vec<u8>,
+    unique_count: u32,
+    block_refs: Vec<u32>,
+    pending: Vec<u8>,
+    block_size: usize,
+}
+
+impl StreamEncoder {
+    pub fn new(threshold: f64) -> Self {
+        Self::with_block_size(threshold, BLOCK_SIZE)
     }
-    let mut pos = 0;
-    let num_unique =
-        u32::from_le_bytes([data[pos], data[pos + 1], data[pos + 2], data[pos + 3]]) as usize;
-    pos += 4;
 
-    let mut blocks: Vec<Vec<u8>> = Vec::with_capacity(num_unique);
-    for _ in 0..num_unique {
-        if pos + 4 > data.len() {
-            return Err(CompressError::SemanticError("truncated".into()));
+    pub fn with_block_size(threshold: f64, block_size: usize) -> Self {
+        Self::with_table_limit(threshold, block_size, 0)
+    }
+
+    /// Encoder whose block table takes at most about `max_table_bytes`
+    /// (at least one entry); 0 leaves it unbounded
+    pub fn with_table_limit(_threshold: f64, block_size: usize, max_table_bytes: usize) -> Self {
+        let block_size = block_size.max(1);
+        let max_entries = match max_table_bytes {
+            0 => usize::MAX,
+            bytes => bytes / TABLE_ENTRY_BYTES,
+        };
+        Self {
+            table: BlockTable::new(max_entries),
+            blocks: Vec::new(),
+            unique_count: 0,
+            block_refs: Vec::new(),
+            pending: Vec::with_capacity(block_size),
+            block_size,
         }
-        let blen =
-            u32::from_le_bytes([data[pos], data[pos + 1], data[pos + 2], data[pos + 3]]) as usize;
-        pos += 4;
-        if pos + blen > data.len() {
-            return Err(CompressError::SemanticError("truncated block".into()));
+    }
+
+    /// Blocks evicted from the table so far
+    pub fn evictions(&self) -> u64 {
+        self.table.evictions
+    }
+
+    pub fn push(&mut self, mut data: &[u8]) {
+        if !self.pending.is_empty() {
+            let take = (self.block_size - self.pending.len()).min(data.len());
+            self.pending.extend_from_slice(&data[..take]);
+            data = &data[take..];
+            if self.pending.len() < self.block_size {
+                return;
+            }
+            let block = std::mem::take(&mut self.pending);
+            self.add_block(&block);
         }
-        blocks.push(data[pos..pos + blen].to_vec());
-        pos += blen;
+        let mut chunks = data.chunks_exact(self.block_size);
+        for chunk in &mut chunks {
+            self.add_block(chunk);
+        }
+        self.pending.extend_from_slice(chunks.remainder());
+    }
+
+    pub fn finish(mut self) -> Vec<u8> {
+        if !self.pending.is_empty() {
+            let block = std::mem::take(&mut self.pending);
+            self.add_block(&block);
+        }
+
+        let unique = self.unique_count as usize;
+        let coding = RefCoding::choose(&self.block_refs, unique);
+        let mut output = Vec::with_capacity(self.blocks.len() + coding.width * self.block_refs.len() + 24);
+        write_header(&mut output, coding, unique);
+        output.extend_from_slice(&self.blocks);
+        write_refs(&mut output, coding, &self.block_refs);
+        output
     }
 
-    if pos + 4 > data.len() {
-        return Err(CompressError::SemanticError("missing refs".into()));
+    fn add_block(&mut self, chunk: &[u8]) {
+        let hash = self.table.map.hasher().hash_one(chunk);
+        if let Some(slot) = self.table.get(hash) {
+            if self.blocks[slot.offset..slot.offset + slot.len] == *chunk {
+                self.block_refs.push(slot.index);
+                return;
+            }
+        }
+        let index = self.unique_count;
+        self.unique_count += 1;
+        crate::varint::write_u64(&mut self.blocks, chunk.len() as u64);
+        let offset = self.blocks.len();
+        self.blocks.extend_from_slice(chunk);
+        self.block_refs.push(index);
+        // A colliding block keeps the entry of the first
+        if !self.table.map.contains_key(&hash) {
+            self.table.insert(Slot {
+                hash,
+                index,
+                offset,
+                len: chunk.len(),
+                referenced: false,
+            });
+        }
+    }
+}
+
+/// Reusable dedup tables for [`compress_blocks_into`]
+#[derive(Debug, Default)]
+pub(crate) struct DedupScratch {
+    state: std::hash::RandomState,
+    /// Block hash to unique block index
+    index: HashMap<u64, u32>,
+    /// `(offset, len)` of each unique block in the input
+    uniques: Vec<(usize, usize)>,
+    refs: Vec<u32>,
+}
+
+impl DedupScratch {
+    pub(crate) fn capacity(&self) -> usize {
+        self.index.capacity() * 12 + self.uniques.capacity() * 16 + self.refs.capacity() * 4
+    }
+}
+
+/// [`compress_blocks`] appending to `output`, with tables kept in `scratch`
+/// between calls. Unique blocks are referenced in place instead of copied;
+/// a hash collision costs at most a missed duplicate.
+pub(crate) fn compress_blocks_into(data: &[u8], block_size: usize, scratch: &mut DedupScratch, output: &mut Vec<u8>) {
+    use std::hash::BuildHasher;
+    let DedupScratch { state, index, uniques, refs } = scratch;
+    index.clear();
+    uniques.clear();
+    refs.clear();
+    for (i, block) in data.chunks(block_size.max(1)).enumerate() {
+        let offset = i * block_size.max(1);
+        let next = uniques.len() as u32;
+        let idx = *index.entry(state.hash_one(block)).or_insert(next);
+        let (start, len) = uniques.get(idx as usize).copied().unwrap_or((offset, block.len()));
+        if idx == next || data[start..start + len] != *block {
+            refs.push(next);
+            uniques.push((offset, block.len()));
+        } else {
+            refs.push(idx);
+        }
+    }
+
+    let coding = RefCoding::choose(refs, uniques.len());
+    write_header(output, coding, uniques.len());
+    for &(start, len) in uniques.iter() {
+        crate::varint::write_u64(output, len as u64);
+        output.extend_from_slice(&data[start..start + len]);
+    }
+    write_refs(output, coding, refs);
+}
+
+/// Resolves references into blocks of roughly `block_len` bytes
+pub struct BlockDecoder<'a> {
+    blocks: Vec<&'a [u8]>,
+    data: &'a [u8],
+    pos: usize,
+    /// Refs not yet read from the payload
+    remaining_refs: usize,
+    /// Runs not yet read; `None` when refs are plain
+    remaining_runs: Option<usize>,
+    ref_width: usize,
+    /// Block index and repeats left of the run being expanded
+    run: (usize, u64),
+    block_len: usize,
+}
+
+impl<'a> BlockDecoder<'a> {
+    pub fn new(data: &'a [u8], block_len: usize) -> Result<Self, CompressError> {
+        let read_len = |pos: &mut usize, what: &str| {
+            crate::varint::read_usize(data, pos).ok_or_else(|| CompressError::SemanticError(what.into()))
+        };
+        let (mut pos, coding) = match data {
+            [VERSION_MARKER, FORMAT_VERSION, coding, ..] => (3, *coding),
+            [VERSION_MARKER, FORMAT_VERSION_NO_RUNS, coding, ..] if coding & RUNS_FLAG == 0 => (3, *coding),
+            [VERSION_MARKER, version, _, ..] => {
+                return Err(CompressError::SemanticError(format!("unsupported payload version {}", version)))
+            }
+            _ => (0, LEGACY_REF_WIDTH as u8),
+        };
+        let ref_width = (coding & !RUNS_FLAG) as usize;
+        if !matches!(ref_width, 1 | 2 | 4) {
+            return Err(CompressError::SemanticError(format!("invalid ref width {}", ref_width)));
+        }
+        let num_unique = read_len(&mut pos, "data too short")?;
+
+        let mut blocks = Vec::with_capacity(num_unique.min(data.len()));
+        for _ in 0..num_unique {
+            let blen = read_len(&mut pos, "truncated")?;
+            if blen > data.len() - pos {
+                return Err(CompressError::SemanticError("truncated block".into()));
+            }
+            // Encoders never store one, and refs to it would expand to nothing
+            if blen == 0 {
+                return Err(CompressError::SemanticError("empty block".into()));
+            }
+            blocks.push(&data[pos..pos + blen]);
+            pos += blen;
+        }
+
+        let num_refs = read_len(&mut pos, "missing refs")?;
+        let remaining_runs = match coding & RUNS_FLAG {
+            0 => None,
+            _ => Some(read_len(&mut pos, "missing run count")?),
+        };
+
+        Ok(Self {
+            blocks,
+            data,
+            pos,
+            remaining_refs: num_refs,
+            remaining_runs,
+            ref_width,
+            run: (0, 0),
+            block_len: block_len.max(1),
+        })
     }
-    let num_refs =
-        u32::from_le_bytes([data[pos], data[pos + 1], data[pos + 2], data[pos + 3]]) as usize;
-    pos += 4;
 
-    let mut output = Vec::new();
-    for _ in 0..num_refs {
-        if pos + 4 > data.len() {
-            return Err(CompressError::SemanticError("truncated ref".into()));
+    /// [`BlockDecoder::new`] for a payload that must decode to at most
+    /// `original_size` bytes, checked up front since runs can claim any size
+    pub fn bounded(data: &'a [u8], original_size: usize, block_len: usize) -> Result<Self, CompressError> {
+        if decoded_len(data)? > original_size as u64 {
+            return Err(CompressError::SemanticError("refs exceed the original size".into()));
+        }
+        let decoder = Self::new(data, block_len)?;
+        // Every block holds at least a byte, so no more refs than bytes
+        if decoder.remaining_refs > original_size {
+            return Err(CompressError::SemanticError("more refs than original bytes".into()));
         }
-        let idx =
-            u32::from_le_bytes([data[pos], data[pos + 1], data[pos + 2], data[pos + 3]]) as usize;
-        pos += 4;
-        if idx >= blocks.len() {
+        Ok(decoder)
+    }
+
+    /// Block index of the ref at `pos`
+    fn read_ref(&self, pos: usize) -> Result<usize, CompressError> {
+        let bytes = self
+            .data
+            .get(pos..pos + self.ref_width)
+            .ok_or_else(|| CompressError::SemanticError("truncated ref".into()))?;
+        let mut idx = [0u8; 4];
+        idx[..bytes.len()].copy_from_slice(bytes);
+        Ok(u32::from_le_bytes(idx) as usize)
+    }
+
+    /// Next `(block index, count)` read from the payload; a plain ref is a
+    /// run of one. Only call while refs remain.
+    fn next_run(&mut self) -> Result<(usize, u64), CompressError> {
+        let idx = self.read_ref(self.pos)?;
+        self.pos += self.ref_width;
+        if idx >= self.blocks.len() {
             return Err(CompressError::SemanticError("invalid ref".into()));
         }
-        output.extend_from_slice(&blocks[idx]);
+        let count = match &mut self.remaining_runs {
+            None => 1,
+            Some(0) => return Err(CompressError::SemanticError("runs fall short of the ref count".into())),
+            Some(runs) => {
+                *runs -= 1;
+                crate::varint::read_u64(self.data, &mut self.pos)
+                    .filter(|&count| count > 0 && count <= self.remaining_refs as u64)
+                    .ok_or_else(|| CompressError::SemanticError("invalid run length".into()))?
+            }
+        };
+        self.remaining_refs -= count as usize;
+        Ok((idx, count))
     }
 
-    Ok(output)
+    fn next_ref(&mut self) -> Result<&'a [u8], CompressError> {
+        if self.run.1 == 0 {
+            self.run = self.next_run()?;
+        }
+        self.run.1 -= 1;
+        Ok(self.blocks[self.run.0])
+    }
+
+    fn has_refs(&self) -> bool {
+        self.remaining_refs > 0 || self.run.1 > 0
+    }
+}
+
+impl Iterator for BlockDecoder<'_> {
+    type Item = Result<Vec<u8>, CompressError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if !self.has_refs() {
+            return None;
+        }
+        let mut output = Vec::new();
+        while self.has_refs() && output.len() < self.block_len {
+            match self.next_ref() {
+                Ok(block) => output.extend_from_slice(block),
+                Err(e) => {
+                    self.remaining_refs = 0;
+                    self.run.1 = 0;
+                    return Some(Err(e));
+                }
+            }
+        }
+        Some(Ok(output))
+    }
+}
+
+/// Random-hyperplane LSH index over embeddings for approximate cosine
+/// nearest-neighbor search
+pub struct LshIndex {
+    /// `num_tables * bits_per_table` hyperplanes of `dim` components each
+    planes: Vec<Vec<f32>>,
+    tables: Vec<HashMap<u64, Vec<usize>>>,
+    vectors: Vec<Vec<f32>>,
+    bits_per_table: usize,
+    max_candidates: usize,
+}
+
+impl LshIndex {
+    pub fn new(dim: usize, config: &LshConfig) -> Self {
+        let bits_per_table = config.bits_per_table.clamp(1, 64);
+        let num_tables = config.num_tables.max(1);
+        let mut state = config.seed;
+        let mut next = move || (crate::splitmix64(&mut state) >> 11) as f64 / (1u64 << 53) as f64;
+        let planes = (0..num_tables * bits_per_table)
+            .map(|_| {
+                (0..dim)
+                    .map(|_| {
+                        // Box-Muller: gaussian components make hyperplane directions uniform
+                        let (u1, u2) = (next().max(f64::MIN_POSITIVE), next());
+                        ((-2.0 * u1.ln()).sqrt() * (std::f64::consts::TAU * u2).cos()) as f32
+                    })
+                    .collect()
+            })
+            .collect();
+        Self {
+            planes,
+            tables: vec![HashMap::new(); num_tables],
+            vectors: Vec::new(),
+            bits_per_table,
+            max_candidates: config.max_candidates.max(1),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.vectors.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.vectors.is_empty()
+    }
+
+    /// Add an embedding, returning its id (ids count up from 0)
+    pub fn insert(&mut self, embedding: &[f32]) -> usize {
+        let id = self.vectors.len();
+        for table in 0..self.tables.len() {
+            let key = self.bucket(table, embedding);
+            self.tables[table].entry(key).or_default().push(id);
+        }
+        self.vectors.push(embedding.to_vec());
+        id
+    }
+
+    /// Ids sharing a bucket with `embedding` in any table, at most `max_candidates`
+    pub fn candidates(&self, embedding: &[f32]) -> Vec<usize> {
+        let mut seen = HashSet::new();
+        let mut out = Vec::new();
+        for table in 0..self.tables.len() {
+            let key = self.bucket(table, embedding);
+            for &id in self.tables[table].get(&key).into_iter().flatten() {
+                if seen.insert(id) {
+                    out.push(id);
+                    if out.len() == self.max_candidates {
+                        return out;
+                    }
+                }
+            }
+        }
+        out
+    }
+
+    /// Up to `k` approximate nearest neighbors by cosine similarity, best first
+    pub fn query(&self, embedding: &[f32], k: usize) -> Vec<(usize, f64)> {
+        let mut scored: Vec<(usize, f64)> = self
+            .candidates(embedding)
+            .into_iter()
+            .map(|id| (id, RyzansteinCompressClient::cosine_similarity(&self.vectors[id], embedding)))
+            .collect();
+        scored.sort_by(|a, b| b.1.total_cmp(&a.1).then(a.0.cmp(&b.0)));
+        scored.truncate(k);
+        scored
+    }
+
+    fn bucket(&self, table: usize, embedding: &[f32]) -> u64 {
+        let planes = &self.planes[table * self.bits_per_table..(table + 1) * self.bits_per_table];
+        planes.iter().enumerate().fold(0u64, |key, (bit, plane)| {
+            let dot: f32 = plane.iter().zip(embedding).map(|(p, e)| p * e).sum();
+            key | ((dot >= 0.0) as u64) << bit
+        })
+    }
+}
+
+/// Like [`crate::ryzanstein_integration::group_by_similarity`], but finds
+/// candidate groups through an [`LshIndex`] over group representatives instead
+/// of comparing against all of them.
+///
+/// The index buckets by cosine direction; candidates are re-scored with
+/// `similarity`. Jaccard ignores embeddings, so it falls back to the
+/// exhaustive comparison.
+pub fn group_similar(blocks: &[SemanticBlock], similarity: Similarity, threshold: f64, config: &LshConfig) -> Vec<usize> {
+    if matches!(similarity, Similarity::Jaccard { .. }) {
+        return crate::ryzanstein_integration::group_by_similarity(blocks, similarity, threshold);
+    }
+    let dim = blocks.first().map_or(0, |b| b.embedding.len());
+    let mut index = LshIndex::new(dim, config);
+    let mut representatives = Vec::new();
+    let mut groups = Vec::with_capacity(blocks.len());
+    for (i, block) in blocks.iter().enumerate() {
+        let mut candidates = index.candidates(block.embedding);
+        candidates.sort_unstable();
+        let group = match candidates
+            .into_iter()
+            .find(|&g| similarity.score(&blocks[representatives[g]], block) >= threshold)
+        {
+            Some(group) => group,
+            None => {
+                representatives.push(i);
+                index.insert(block.embedding)
+            }
+        };
+        groups.push(group);
+    }
+    groups
 }
 
 #[cfg(test)]
@@ -114,6 +737,28 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_stream_encoder_rechunks() {
+        let data: Vec<u8> = (0..500u32).map(|i| (i % 128) as u8).collect();
+        let mut encoder = StreamEncoder::new(0.95);
+        for piece in data.chunks(23) {
+            encoder.push(piece);
+        }
+        assert_eq!(encoder.finish(), compress(&data, 0.95).unwrap());
+    }
+
+    #[test]
+    fn test_block_decoder_streams() {
+        let data = "semantic block ".repeat(100);
+        let compressed = compress(data.as_bytes(), 0.95).unwrap();
+        let blocks: Vec<Vec<u8>> = BlockDecoder::new(&compressed, 256)
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert!(blocks.len() > 1);
+        assert_eq!(blocks.concat(), data.as_bytes());
+    }
+
     #[test]
     fn test_semantic_unique_data() {
         let data: Vec<u8> = (0..200).collect();
@@ -121,4 +766,174 @@ mod tests {
         let decompressed = decompress(&compressed, data.len()).unwrap();
         assert_eq!(decompressed, data);
     }
+
+    #[test]
+    fn test_bounded_table_keeps_hot_blocks() {
+        let block = |tag: u8| vec![tag; BLOCK_SIZE];
+        // A hot block between every cold one, then the cold ones again
+        let mut data = Vec::new();
+        for _ in 0..2 {
+            for cold in 1..=20 {
+                data.extend(block(0));
+                data.extend(block(cold));
+            }
+        }
+        let mut bounded = StreamEncoder::with_table_limit(0.95, BLOCK_SIZE, 4 * TABLE_ENTRY_BYTES);
+        bounded.push(&data);
+        assert!(bounded.evictions() > 0);
+        let payload = bounded.finish();
+        assert_eq!(decompress(&payload, data.len()).unwrap(), data);
+        let hot_copies = BlockDecoder::new(&payload, usize::MAX).unwrap().blocks.iter().filter(|b| **b == &block(0)[..]).count();
+        assert_eq!(hot_copies, 1);
+
+        let unbounded = compress_blocks(&data, 0.95, BLOCK_SIZE).unwrap();
+        let (lost, full) = (report(&payload).unwrap(), report(&unbounded).unwrap());
+        assert_eq!((full.missed_duplicates, full.missed_bytes), (0, 0));
+        assert!(lost.missed_duplicates > 0);
+        assert_eq!(lost.missed_bytes, lost.missed_duplicates * BLOCK_SIZE);
+        assert_eq!(lost.unique_blocks - full.unique_blocks, lost.missed_duplicates);
+    }
+
+    #[test]
+    fn test_refs_narrow_to_unique_count() {
+        let blocks = |count: u32| -> Vec<u8> { (0..count).flat_map(|i| format!("{:063}\n", i).into_bytes()).collect() };
+        for (unique, width) in [(200, 1), (300, 2), (70_000, 4)] {
+            let data = blocks(unique).repeat(2);
+            let payload = compress(&data, 0.95).unwrap();
+            assert_eq!(payload[..3], [VERSION_MARKER, FORMAT_VERSION, width]);
+            assert_eq!(decompress(&payload, data.len()).unwrap(), data);
+            assert_eq!(report(&payload).unwrap().duplicate_blocks, unique as usize);
+            assert_eq!(index_len(&payload).unwrap() + data.len() / 2 + 2 * unique as usize * width as usize, payload.len());
+        }
+
+        // Legacy payloads: no version header, 4-byte refs
+        let mut legacy = Vec::new();
+        crate::varint::write_u64(&mut legacy, 2);
+        for block in [&b"first"[..], b"second"] {
+            crate::varint::write_u64(&mut legacy, block.len() as u64);
+            legacy.extend_from_slice(block);
+        }
+        crate::varint::write_u64(&mut legacy, 3);
+        for r in [1u32, 0, 1] {
+            legacy.extend_from_slice(&r.to_le_bytes());
+        }
+        assert_eq!(decompress(&legacy, 17).unwrap(), b"secondfirstsecond");
+        assert_eq!(block_sizes(&legacy, 17).unwrap(), vec![6, 5, 6]);
+        assert!(decompress(&[VERSION_MARKER, FORMAT_VERSION + 1, 1, 0, 0], 0).is_err());
+    }
+
+    #[test]
+    fn test_repeated_blocks_coded_as_runs() {
+        let data = [vec![7u8; 64 * 4096], vec![1u8; 64], vec![7u8; 64 * 10]].concat();
+        let payload = compress(&data, 0.95).unwrap();
+        assert_eq!(payload[..3], [VERSION_MARKER, FORMAT_VERSION, 1 | RUNS_FLAG]);
+        // Two unique blocks and three runs
+        assert!(payload.len() < 2 * 64 + 20, "{} bytes", payload.len());
+        assert_eq!(decompress(&payload, data.len()).unwrap(), data);
+        assert_eq!(decoded_len(&payload).unwrap(), data.len() as u64);
+        assert_eq!(block_sizes(&payload, data.len()).unwrap().len(), 4096 + 1 + 10);
+        assert_eq!(report(&payload).unwrap().duplicate_blocks, 4096 + 1 + 10 - 2);
+
+        // Distinct refs stay plain, which is smaller
+        let distinct: Vec<u8> = (0..=255u8).flat_map(|b| [b; 64]).collect();
+        assert_eq!(compress(&distinct, 0.95).unwrap()[2], 1);
+        // Version 2 payloads, with narrow but plain refs, still decode
+        assert_eq!(decompress(&[VERSION_MARKER, 2, 1, 1, 1, b'a', 2, 0, 0], 2).unwrap(), b"aa");
+
+        // A run may not expand past the original size
+        let mut bomb = vec![VERSION_MARKER, FORMAT_VERSION, 1 | RUNS_FLAG, 1, 1, b'x'];
+        crate::varint::write_u64(&mut bomb, u64::MAX >> 8);
+        bomb.extend_from_slice(&[1, 0]);
+        crate::varint::write_u64(&mut bomb, u64::MAX >> 8);
+        assert!(decompress(&bomb, 1 << 20).is_err());
+        assert!(block_sizes(&bomb, 1 << 20).is_err());
+        assert!(BlockDecoder::bounded(&bomb, 1 << 20, 4096).is_err());
+
+        // Nor may refs to an empty block, which add no bytes to check
+        let mut empty = vec![VERSION_MARKER, FORMAT_VERSION, 1 | RUNS_FLAG, 1, 0];
+        crate::varint::write_u64(&mut empty, 1 << 62);
+        empty.extend_from_slice(&[1, 0]);
+        crate::varint::write_u64(&mut empty, 1 << 62);
+        assert!(decompress(&empty, 1 << 20).is_err());
+        assert!(block_sizes(&empty, 1 << 20).is_err());
+        assert!(decoded_len(&empty).is_err());
+    }
+
+    #[test]
+    fn test_compressor_applies_table_cap() {
+        use crate::{config::CompressionConfig, CompressionMethod, Compressor};
+        let data: Vec<u8> = (0..3).flat_map(|_| (0..400u32).flat_map(|i| format!("{:063}\n", i).into_bytes())).collect();
+        let capped = Compressor::new(CompressionConfig {
+            dedup_table_bytes: 100 * TABLE_ENTRY_BYTES,
+            ..CompressionConfig::default()
+        });
+        let output = capped.compress(&data, CompressionMethod::SemanticDedupe).unwrap();
+        let semantic = output.metadata.semantic.clone().unwrap();
+        assert_eq!(semantic.missed_duplicates, 800);
+        assert_eq!(capped.decompress(&output).unwrap(), data);
+        let full = Compressor::default().compress(&data, CompressionMethod::SemanticDedupe).unwrap();
+        assert_eq!(full.metadata.semantic.unwrap().duplicate_blocks, 800);
+        assert!(capped.estimated_scratch(crate::usage::Operation::Compress, CompressionMethod::SemanticDedupe, 1 << 30) < (1 << 30) + (1 << 20));
+    }
+
+    #[cfg(target_pointer_width = "64")]
+    #[test]
+    fn test_ref_count_beyond_u32() {
+        let mut data = Vec::new();
+        crate::varint::write_u64(&mut data, 1);
+        crate::varint::write_u64(&mut data, 1);
+        data.push(b'x');
+        crate::varint::write_u64(&mut data, u32::MAX as u64 + 1);
+        let decoder = BlockDecoder::new(&data, usize::MAX).unwrap();
+        assert_eq!(decoder.remaining_refs, u32::MAX as usize + 1);
+        assert_eq!(index_len(&data).unwrap(), data.len() - 1);
+        assert!(block_sizes(&data, usize::MAX).is_err());
+    }
+
+    fn embedding(seed: u32, dim: usize) -> Vec<f32> {
+        (0..dim as u32)
+            .map(|i| ((seed.wrapping_mul(2654435761) ^ i.wrapping_mul(40503)) % 1000) as f32 / 500.0 - 1.0)
+            .collect()
+    }
+
+    #[test]
+    fn test_lsh_finds_near_duplicates() {
+        let config = LshConfig::default();
+        let mut index = LshIndex::new(32, &config);
+        for seed in 0..500 {
+            index.insert(&embedding(seed, 32));
+        }
+        let mut probe = embedding(123, 32);
+        probe[0] += 0.01;
+        let hits = index.query(&probe, 3);
+        assert_eq!(hits[0].0, 123);
+        assert!(hits[0].1 > 0.99);
+        assert_eq!(index.len(), 500);
+    }
+
+    #[test]
+    fn test_group_similar_matches_exhaustive() {
+        let vectors: Vec<Vec<f32>> = (0..200).map(|i| embedding(i % 20, 16)).collect();
+        let blocks: Vec<SemanticBlock> = vectors
+            .iter()
+            .map(|embedding| SemanticBlock { text: "", embedding })
+            .collect();
+        let indexed = group_similar(&blocks, Similarity::Cosine, 0.999, &LshConfig::default());
+        let exhaustive = crate::ryzanstein_integration::group_by_similarity(&blocks, Similarity::Cosine, 0.999);
+        assert_eq!(indexed, exhaustive);
+        assert_eq!(indexed.iter().max(), Some(&19));
+    }
+
+    #[test]
+    fn test_report_counts_duplicates() {
+        let mut data = vec![1u8; 64 * 3];
+        data.extend(vec![2u8; 64 * 2]);
+        data.extend(vec![3u8; 10]);
+        let report = report(&compress(&data, 0.95).unwrap()).unwrap();
+        assert_eq!(report.blocks, 6);
+        assert_eq!(report.unique_blocks, 3);
+        assert_eq!(report.duplicate_blocks, 3);
+        assert_eq!(report.clusters, 2);
+        assert_eq!(report.exact_bytes_saved, 64 * 3);
+    }
 }
@@ -0,0 +1,164 @@
+//! Two-level compression: block dedup, then LZ over what remains
+//!
+//! The input first goes through [`semantic`] dedup, which stores each
+//! distinct block once; the resulting stream (unique blocks plus refs) is
+//! then compressed by the [`lz4_wrapper`] backend to catch repeats finer than
+//! a block. Payload layout:
+//!
+//! ```text
+//! [dedup_len varint][LZ payload of the dedup stream]
+//! ```
+
+use crate::config::CompressionLevel;
+use crate::error::CompressError;
+use crate::semantic::{self, SemanticReport};
+use crate::{lz4_wrapper, varint};
+
+/// Dedup `data` in `block_size` units and LZ-compress the result
+pub fn compress(
+    data: &[u8],
+    block_size: usize,
+    lz_block_size: usize,
+    level: CompressionLevel,
+) -> Result<Vec<u8>, CompressError> {
+    let mut encoder = StreamEncoder::new(block_size, lz_block_size, level);
+    encoder.push(data);
+    encoder.finish()
+}
+
+pub fn decompress(data: &[u8], original_size: usize) -> Result<Vec<u8>, CompressError> {
+    let dedup = dedup_stream(data)?;
+    let output = semantic::decompress(&dedup, original_size)?;
+    if output.len() != original_size {
+        return Err(CompressError::SizeMismatch {
+            expected: original_size,
+            actual: output.len(),
+        });
+    }
+    Ok(output)
+}
+
+/// Recover the intermediate dedup stream
+fn dedup_stream(data: &[u8]) -> Result<Vec<u8>, CompressError> {
+    let mut pos = 0;
+    let dedup_len = varint::read_usize(data, &mut pos)
+        .ok_or_else(|| CompressError::SemanticError("missing dedup stream length".into()))?;
+    let dedup = lz4_wrapper::decompress(&data[pos..], dedup_len)?;
+    if dedup.len() != dedup_len {
+        return Err(CompressError::SizeMismatch {
+            expected: dedup_len,
+            actual: dedup.len(),
+        });
+    }
+    Ok(dedup)
+}
+
+/// Size of the block behind every dedup reference, at most
+/// `original_size` bytes' worth
+pub fn block_sizes(data: &[u8], original_size: usize) -> Result<Vec<usize>, CompressError> {
+    semantic::block_sizes(&dedup_stream(data)?, original_size)
+}
+
+/// Dedup statistics of the inner stream
+pub fn report(data: &[u8]) -> Result<SemanticReport, CompressError> {
+    semantic::report(&dedup_stream(data)?)
+}
+
+/// Bytes spent on the length prefix and the LZ block headers
+pub fn header_len(data: &[u8]) -> Result<usize, CompressError> {
+    let mut pos = 0;
+    varint::read_usize(data, &mut pos)
+        .ok_or_else(|| CompressError::SemanticError("missing dedup stream length".into()))?;
+    Ok(pos + lz4_wrapper::header_len(&data[pos..])?)
+}
+
+/// Incremental encoder; dedup runs as input arrives, the LZ pass at the end
+pub struct StreamEncoder {
+    dedup: semantic::StreamEncoder,
+    lz_block_size: usize,
+    level: CompressionLevel,
+}
+
+impl StreamEncoder {
+    pub fn new(block_size: usize, lz_block_size: usize, level: CompressionLevel) -> Self {
+        Self {
+            dedup: semantic::StreamEncoder::with_block_size(0.0, block_size),
+            lz_block_size,
+            level,
+        }
+    }
+
+    pub fn push(&mut self, data: &[u8]) {
+        self.dedup.push(data);
+    }
+
+    pub fn finish(self) -> Result<Vec<u8>, CompressError> {
+        let dedup = self.dedup.finish();
+        let mut output = Vec::new();
+        varint::write_u64(&mut output, dedup.len() as u64);
+        output.extend_from_slice(&lz4_wrapper::compress_with_level(&dedup, self.lz_block_size, self.level)?);
+        Ok(output)
+    }
+}
+
+/// Yields decoded data in blocks of at most `block_len` bytes. Refs may
+/// point anywhere in the dedup stream, so the payload is decoded up front.
+pub struct BlockDecoder {
+    data: Vec<u8>,
+    pos: usize,
+    block_len: usize,
+}
+
+impl BlockDecoder {
+    pub fn new(data: &[u8], original_size: usize, block_len: usize) -> Result<Self, CompressError> {
+        let dedup = dedup_stream(data)?;
+        Ok(Self {
+            data: semantic::decompress(&dedup, original_size)?,
+            pos: 0,
+            block_len: block_len.max(1),
+        })
+    }
+}
+
+impl Iterator for BlockDecoder {
+    type Item = Result<Vec<u8>, CompressError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.pos >= self.data.len() {
+            return None;
+        }
+        let end = (self.pos + self.block_len).min(self.data.len());
+        let block = self.data[self.pos..end].to_vec();
+        self.pos = end;
+        Some(Ok(block))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_roundtrip_beats_single_stage() {
+        let mut data = Vec::new();
+        for i in 0..200 {
+            data.extend_from_slice(b"fn handler(request: Request) -> Response { respond(request) }\n");
+            data.extend_from_slice(format!("// call site {}\n", i % 7).as_bytes());
+        }
+        let two_level = compress(&data, 64, 64 * 1024, CompressionLevel::Fast).unwrap();
+        assert_eq!(decompress(&two_level, data.len()).unwrap(), data);
+        let dedup_only = semantic::compress_blocks(&data, 0.0, 64).unwrap();
+        assert!(two_level.len() < dedup_only.len());
+        assert!(report(&two_level).unwrap().duplicate_blocks > 0);
+    }
+
+    #[test]
+    fn test_block_decoder() {
+        let data = b"block by block ".repeat(1000);
+        let payload = compress(&data, 32, 4096, CompressionLevel::Fast).unwrap();
+        let blocks: Vec<Vec<u8>> = BlockDecoder::new(&payload, data.len(), 4000).unwrap().collect::<Result<_, _>>().unwrap();
+        assert!(blocks.len() > 1);
+        assert_eq!(blocks.concat(), data);
+        assert!(decompress(&payload[..payload.len() - 3], data.len()).is_err());
+    }
+}
@@ -0,0 +1,244 @@
+//! Huffman coding of message streams with table reuse
+//!
+//! Homogeneous streams (RPC payloads, log records) send many short messages
+//! with nearly the same byte distribution, and a per-message code table can
+//! cost more than the coded bits. A [`HuffmanSession`] keeps the model built
+//! for an earlier message and reuses it while new messages fit it: a
+//! chi-square test compares each message's histogram with the model's
+//! counts, and only when it drifts (or a byte the model cannot code shows
+//! up) is a new table built and sent. New tables fold the message into the
+//! previous counts at half weight, so the model's alphabet grows to cover
+//! the stream. [`SessionDecoder`] mirrors the state.
+//!
+//! Message layout:
+//!
+//! ```text
+//! [flags u8][table if flags & 1][len varint][coded bits]
+//! table: [symbols varint] ([symbol u8][count varint])*
+//! ```
+
+use crate::error::CompressError;
+use crate::huffman::HuffmanModel;
+use crate::{analysis, varint};
+
+/// Flag: a new table precedes the coded bits
+const FLAG_TABLE: u8 = 0x01;
+
+/// Default z-score above which a histogram no longer fits the model
+pub const DEFAULT_TOLERANCE: f64 = 2.33;
+
+/// Messages coded by a session so far
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SessionStats {
+    pub messages: u64,
+    /// Messages that carried a new table
+    pub tables_sent: u64,
+}
+
+/// Encoder side of a table-reusing Huffman stream
+pub struct HuffmanSession {
+    model: Option<(HuffmanModel, [u64; 256])>,
+    tolerance: f64,
+    stats: SessionStats,
+}
+
+impl Default for HuffmanSession {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl HuffmanSession {
+    pub fn new() -> Self {
+        Self {
+            model: None,
+            tolerance: DEFAULT_TOLERANCE,
+            stats: SessionStats::default(),
+        }
+    }
+
+    /// Accept histograms whose chi-square statistic is within `z` standard
+    /// deviations of its expectation; lower values rebuild tables sooner
+    pub fn with_tolerance(mut self, z: f64) -> Self {
+        self.tolerance = z;
+        self
+    }
+
+    pub fn stats(&self) -> SessionStats {
+        self.stats
+    }
+
+    /// Code one message, sending a table only if the cached one does not fit
+    pub fn compress(&mut self, data: &[u8]) -> Result<Vec<u8>, CompressError> {
+        let histogram = analysis::byte_histogram(data);
+        let mut output = Vec::with_capacity(data.len() / 2 + 16);
+        let reuse = data.is_empty()
+            || self
+                .model
+                .as_ref()
+                .is_some_and(|(_, counts)| fits(&histogram, counts, self.tolerance));
+        if reuse {
+            output.push(0);
+        } else {
+            let mut counts = histogram;
+            if let Some((_, previous)) = &self.model {
+                for (count, &old) in counts.iter_mut().zip(previous) {
+                    *count += old.div_ceil(2);
+                }
+            }
+            let model = HuffmanModel::from_frequencies(&counts)
+                .ok_or_else(|| CompressError::HuffmanError("empty histogram".into()))?;
+            output.push(FLAG_TABLE);
+            write_table(&counts, &mut output);
+            self.model = Some((model, counts));
+            self.stats.tables_sent += 1;
+        }
+        varint::write_u64(&mut output, data.len() as u64);
+        if let Some((model, _)) = &self.model {
+            output.extend_from_slice(&model.encode(data)?);
+        }
+        self.stats.messages += 1;
+        Ok(output)
+    }
+}
+
+/// Decoder side of a [`HuffmanSession`] stream; messages must be decoded in
+/// the order they were produced
+#[derive(Default)]
+pub struct SessionDecoder {
+    model: Option<HuffmanModel>,
+}
+
+impl SessionDecoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn decompress(&mut self, message: &[u8]) -> Result<Vec<u8>, CompressError> {
+        let flags = *message
+            .first()
+            .ok_or_else(|| CompressError::HuffmanError("empty message".into()))?;
+        let mut pos = 1;
+        if flags & FLAG_TABLE != 0 {
+            let histogram = read_table(message, &mut pos)?;
+            self.model = Some(
+                HuffmanModel::from_frequencies(&histogram)
+                    .ok_or_else(|| CompressError::HuffmanError("empty table".into()))?,
+            );
+        }
+        let len = varint::read_usize(message, &mut pos)
+            .ok_or_else(|| CompressError::HuffmanError("missing message length".into()))?;
+        if len == 0 {
+            return Ok(Vec::new());
+        }
+        // Every code is at least a bit long
+        if len.div_ceil(8) > message.len() - pos {
+            return Err(CompressError::HuffmanError(format!(
+                "{} symbols cannot fit in {} bytes",
+                len,
+                message.len() - pos
+            )));
+        }
+        let model = self
+            .model
+            .as_ref()
+            .ok_or_else(|| CompressError::HuffmanError("message refers to a table never sent".into()))?;
+        model.decode(&message[pos..], len)
+    }
+}
+
+/// Whether `histogram` is plausibly drawn from the distribution of `counts`
+fn fits(histogram: &[u64; 256], counts: &[u64; 256], tolerance: f64) -> bool {
+    let n: u64 = histogram.iter().sum();
+    let total: u64 = counts.iter().sum();
+    let mut chi_square = 0.0;
+    let mut categories = 0usize;
+    for (&observed, &count) in histogram.iter().zip(counts) {
+        if count == 0 {
+            if observed > 0 {
+                return false;
+            }
+            continue;
+        }
+        categories += 1;
+        let expected = n as f64 * count as f64 / total as f64;
+        let diff = observed as f64 - expected;
+        chi_square += diff * diff / expected;
+    }
+    // Normal approximation of the chi-square distribution
+    let df = categories.saturating_sub(1).max(1) as f64;
+    chi_square <= df + tolerance * (2.0 * df).sqrt()
+}
+
+fn write_table(histogram: &[u64; 256], output: &mut Vec<u8>) {
+    let symbols = histogram.iter().filter(|&&c| c > 0).count();
+    varint::write_u64(output, symbols as u64);
+    for (symbol, &count) in histogram.iter().enumerate() {
+        if count > 0 {
+            output.push(symbol as u8);
+            varint::write_u64(output, count);
+        }
+    }
+}
+
+fn read_table(data: &[u8], pos: &mut usize) -> Result<[u64; 256], CompressError> {
+    let truncated = || CompressError::HuffmanError("truncated table".into());
+    let symbols = varint::read_usize(data, pos).ok_or_else(truncated)?;
+    if symbols > 256 {
+        return Err(CompressError::HuffmanError("too many table symbols".into()));
+    }
+    let mut histogram = [0u64; 256];
+    for _ in 0..symbols {
+        let symbol = *data.get(*pos).ok_or_else(truncated)?;
+        *pos += 1;
+        histogram[symbol as usize] = varint::read_u64(data, pos).ok_or_else(truncated)?;
+    }
+    Ok(histogram)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_homogeneous_stream_reuses_table() {
+        let mut session = HuffmanSession::new();
+        let mut decoder = SessionDecoder::new();
+        let mut sizes = Vec::new();
+        for i in 0..50u64 {
+            let message = format!(
+                "{{\"user\":\"u{}\",\"action\":\"view\",\"page\":{}}}",
+                i * 7919 % 100_000,
+                i % 10
+            );
+            let coded = session.compress(message.as_bytes()).unwrap();
+            sizes.push(coded.len());
+            assert_eq!(decoder.decompress(&coded).unwrap(), message.as_bytes());
+        }
+        let stats = session.stats();
+        assert_eq!(stats.messages, 50);
+        assert!(stats.tables_sent < 10, "{:?}", stats);
+        assert!(sizes[49] < sizes[0]);
+    }
+
+    #[test]
+    fn test_drift_sends_new_table() {
+        let mut session = HuffmanSession::new();
+        let mut decoder = SessionDecoder::new();
+        for message in [&b"aaaaabbbbbaaaaabbbbb"[..], b"", b"zzzzzzzzzzyyyyyyyyyy", b"aabbaabbaabbaabbaabb"] {
+            let coded = session.compress(message).unwrap();
+            assert_eq!(decoder.decompress(&coded).unwrap(), message);
+        }
+        assert_eq!(session.stats().tables_sent, 3);
+        assert!(SessionDecoder::new().decompress(&[0, 3, 0xFF]).is_err());
+
+        // A declared length beyond one symbol per bit is refused up front
+        let coded = session.compress(b"aabbaabb").unwrap();
+        assert_eq!(decoder.decompress(&coded).unwrap(), b"aabbaabb");
+        let mut huge = vec![0];
+        crate::varint::write_u64(&mut huge, 1 << 61);
+        huge.push(0);
+        let err = decoder.decompress(&huge).unwrap_err();
+        assert!(err.to_string().contains("cannot fit"), "{}", err);
+    }
+}
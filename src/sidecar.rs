@@ -0,0 +1,109 @@
+//! Queryable metadata stored next to compressed blobs
+//!
+//! Object stores index small metadata documents far more cheaply than they
+//! can open payloads. A [`Sidecar`] carries everything about an output that
+//! such an index needs (method, sizes, checksum, dictionary, and optionally
+//! the input's [`ContentProfile`]) and encodes to compact CBOR.
+
+use crate::analysis::ContentProfile;
+use crate::error::CompressError;
+use crate::{CompressedOutput, CompressionMethod};
+use serde::{Deserialize, Serialize};
+
+/// Sidecar format version written by this build
+pub const SIDECAR_VERSION: u32 = 1;
+
+/// Metadata describing one compressed output
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Sidecar {
+    pub version: u32,
+    pub method: CompressionMethod,
+    pub original_size: u64,
+    /// Codec payload bytes
+    pub compressed_size: u64,
+    /// Frame bytes, header included
+    pub encoded_size: u64,
+    pub ratio: f64,
+    /// CRC-32 of the original data
+    pub checksum: Option<u32>,
+    pub dictionary_id: Option<u32>,
+    pub entropy_bits: f64,
+    pub block_count: u64,
+    pub semantic_dedup_count: u64,
+    /// Profile of the input, when the producer had it at hand
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub profile: Option<ContentProfile>,
+}
+
+impl Sidecar {
+    /// Describe `output`
+    pub fn of(output: &CompressedOutput) -> Self {
+        Self {
+            version: SIDECAR_VERSION,
+            method: output.method,
+            original_size: output.original_size as u64,
+            compressed_size: output.compressed_size as u64,
+            encoded_size: output.total_encoded_size() as u64,
+            ratio: output.ratio,
+            checksum: output.checksum,
+            dictionary_id: output.dictionary_id,
+            entropy_bits: output.metadata.entropy_bits,
+            block_count: output.metadata.block_count as u64,
+            semantic_dedup_count: output.metadata.semantic_dedup_count as u64,
+            profile: None,
+        }
+    }
+
+    /// Attach the input's content profile
+    pub fn with_profile(mut self, profile: ContentProfile) -> Self {
+        self.profile = Some(profile);
+        self
+    }
+
+    /// Encode as CBOR
+    pub fn to_cbor(&self) -> Result<Vec<u8>, CompressError> {
+        let mut out = Vec::new();
+        ciborium::into_writer(self, &mut out).map_err(|e| CompressError::SerializationError(e.to_string()))?;
+        Ok(out)
+    }
+
+    /// Decode a sidecar written by [`Sidecar::to_cbor`]
+    pub fn from_cbor(data: &[u8]) -> Result<Self, CompressError> {
+        ciborium::from_reader(data).map_err(|e| CompressError::SerializationError(e.to_string()))
+    }
+}
+
+impl CompressedOutput {
+    /// CBOR [`Sidecar`] describing this output, without a content profile
+    pub fn metadata_sidecar(&self) -> Result<Vec<u8>, CompressError> {
+        Sidecar::of(self).to_cbor()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Compressor;
+
+    #[test]
+    fn test_sidecar_roundtrip() {
+        let data = b"sidecar metadata for an object store ".repeat(50);
+        let out = Compressor::default().compress(&data, CompressionMethod::Huffman).unwrap();
+        let raw = out.metadata_sidecar().unwrap();
+        let sidecar = Sidecar::from_cbor(&raw).unwrap();
+        assert_eq!(sidecar, Sidecar::of(&out));
+        assert_eq!(sidecar.original_size, data.len() as u64);
+        assert_eq!(sidecar.checksum, out.checksum);
+        assert!(raw.len() < serde_json::to_vec(&sidecar).unwrap().len());
+    }
+
+    #[test]
+    fn test_sidecar_with_profile() {
+        let data = vec![0u8; 1000];
+        let out = Compressor::default().compress(&data, CompressionMethod::EntropyCoding).unwrap();
+        let sidecar = Sidecar::of(&out).with_profile(ContentProfile::of(&data));
+        let decoded = Sidecar::from_cbor(&sidecar.to_cbor().unwrap()).unwrap();
+        assert_eq!(decoded.profile.unwrap().bytes.distinct_bytes, 1);
+        assert!(Sidecar::from_cbor(b"not cbor").is_err());
+    }
+}
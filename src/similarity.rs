@@ -0,0 +1,107 @@
+//! Block similarity metrics
+//!
+//! [`Similarity`] is part of [`crate::config::CompressionConfig`] and is used
+//! by both the local grouping in [`crate::semantic`] and the
+//! [`crate::ryzanstein_integration`] client, which re-exports it.
+
+/// Shingle length used by [`Similarity::Jaccard`] unless configured otherwise
+pub const DEFAULT_SHINGLE_LEN: usize = 4;
+
+/// How alike two blocks are; every metric scores higher for more similar blocks
+#[derive(Debug, Clone, Copy, PartialEq, Default, serde::Serialize, serde::Deserialize)]
+pub enum Similarity {
+    /// Cosine of the angle between embeddings, in [-1, 1]
+    #[default]
+    Cosine,
+    /// Raw dot product of embeddings
+    Dot,
+    /// `1 / (1 + distance)` between embeddings, in (0, 1]
+    Euclidean,
+    /// Jaccard index of the blocks' byte shingles, in [0, 1]; ignores embeddings
+    Jaccard { shingle_len: usize },
+}
+
+/// A block as seen by [`Similarity::score`]
+#[derive(Debug, Clone, Copy)]
+pub struct SemanticBlock<'a> {
+    pub text: &'a str,
+    pub embedding: &'a [f32],
+}
+
+impl Similarity {
+    /// Score two blocks; embeddings of different lengths score as dissimilar
+    pub fn score(&self, a: &SemanticBlock, b: &SemanticBlock) -> f64 {
+        match *self {
+            Similarity::Cosine => cosine(a.embedding, b.embedding),
+            Similarity::Dot => {
+                if a.embedding.len() != b.embedding.len() {
+                    return 0.0;
+                }
+                a.embedding.iter().zip(b.embedding).map(|(x, y)| *x as f64 * *y as f64).sum()
+            }
+            Similarity::Euclidean => {
+                if a.embedding.len() != b.embedding.len() {
+                    return 0.0;
+                }
+                let dist: f64 = a
+                    .embedding
+                    .iter()
+                    .zip(b.embedding)
+                    .map(|(x, y)| (*x as f64 - *y as f64).powi(2))
+                    .sum::<f64>()
+                    .sqrt();
+                1.0 / (1.0 + dist)
+            }
+            Similarity::Jaccard { shingle_len } => jaccard(a.text.as_bytes(), b.text.as_bytes(), shingle_len),
+        }
+    }
+}
+
+fn jaccard(a: &[u8], b: &[u8], shingle_len: usize) -> f64 {
+    let shingles = |data: &[u8]| -> std::collections::HashSet<Vec<u8>> {
+        let k = shingle_len.max(1).min(data.len().max(1));
+        data.windows(k).map(|w| w.to_vec()).collect()
+    };
+    let (sa, sb) = (shingles(a), shingles(b));
+    let union = sa.union(&sb).count();
+    if union == 0 {
+        return 1.0;
+    }
+    sa.intersection(&sb).count() as f64 / union as f64
+}
+
+/// Cosine of the angle between `a` and `b`; 0 for mismatched lengths or
+/// zero vectors
+pub(crate) fn cosine(a: &[f32], b: &[f32]) -> f64 {
+    if a.len() != b.len() || a.is_empty() {
+        return 0.0;
+    }
+    let dot: f64 = a.iter().zip(b).map(|(x, y)| (*x as f64) * (*y as f64)).sum();
+    let mag_a: f64 = a.iter().map(|x| (*x as f64).powi(2)).sum::<f64>().sqrt();
+    let mag_b: f64 = b.iter().map(|x| (*x as f64).powi(2)).sum::<f64>().sqrt();
+    if mag_a * mag_b < 1e-10 {
+        0.0
+    } else {
+        dot / (mag_a * mag_b)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_similarity_metrics() {
+        let block = |text, embedding| SemanticBlock { text, embedding };
+        let a = block("fn add(a, b)", &[3.0, 4.0][..]);
+        let b = block("fn add(x, y)", &[3.0, 4.0][..]);
+        let c = block("SELECT * FROM t", &[0.0, 1.0][..]);
+        assert!((Similarity::Cosine.score(&a, &b) - 1.0).abs() < 1e-9);
+        assert!((Similarity::Dot.score(&a, &c) - 4.0).abs() < 1e-9);
+        assert!((Similarity::Euclidean.score(&a, &b) - 1.0).abs() < 1e-9);
+        assert!((Similarity::Euclidean.score(&a, &c) - 1.0 / (1.0 + 18f64.sqrt())).abs() < 1e-9);
+        let jaccard = Similarity::Jaccard { shingle_len: 3 };
+        assert!(jaccard.score(&a, &b) > jaccard.score(&a, &c));
+        assert_eq!(jaccard.score(&c, &c), 1.0);
+    }
+}
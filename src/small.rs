@@ -0,0 +1,240 @@
+//! Fast path for tiny messages
+//!
+//! Inputs of at most [`MAX_SMALL_INPUT`] bytes spend more time setting up a
+//! regular codec (code tables, hash chains, frame header) than coding. This
+//! path keeps everything on the stack: the output is a fixed-capacity
+//! [`SmallFrame`] whose capacity is a const generic, and the only table is
+//! a 256-entry match finder. Layout:
+//!
+//! ```text
+//! [0xB0 | mode][len u8] body
+//! ```
+//!
+//! Mode 0 stores the input as is. Mode 1 is byte-aligned LZ: a token below
+//! 0x80 is followed by `token + 1` literals, and a token `0x80 | n` copies
+//! `n + 3` bytes from `offset` (one byte) back. LZ is used only when it is
+//! shorter than storing, so output never exceeds input plus two bytes.
+
+use crate::error::CompressError;
+
+/// Largest input the small path accepts
+pub const MAX_SMALL_INPUT: usize = 255;
+
+/// Bytes in front of every small frame
+pub const HEADER_LEN: usize = 2;
+
+const MAGIC: u8 = 0xB0;
+const MODE_STORED: u8 = 0;
+const MODE_LZ: u8 = 1;
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 0x7F + MIN_MATCH;
+const MAX_LITERALS: usize = 0x80;
+
+/// Encoded (or decoded) bytes in a stack buffer of capacity `N`
+#[derive(Clone, Copy)]
+pub struct SmallFrame<const N: usize> {
+    buf: [u8; N],
+    len: usize,
+}
+
+impl<const N: usize> SmallFrame<N> {
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.buf[..self.len]
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+impl<const N: usize> std::fmt::Debug for SmallFrame<N> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("SmallFrame").field(&self.as_bytes()).finish()
+    }
+}
+
+/// Whether `data` starts like a small frame
+pub fn is_small_frame(data: &[u8]) -> bool {
+    data.len() >= HEADER_LEN && data[0] & 0xF0 == MAGIC && data[0] & 0x0F <= MODE_LZ
+}
+
+/// Compress `data` into a frame of capacity `N`, which must hold the stored
+/// form (`data.len() + HEADER_LEN` bytes)
+pub fn compress<const N: usize>(data: &[u8]) -> Result<SmallFrame<N>, CompressError> {
+    if data.len() > MAX_SMALL_INPUT {
+        return Err(CompressError::InputTooLarge {
+            size: data.len(),
+            limit: MAX_SMALL_INPUT,
+        });
+    }
+    if N < data.len() + HEADER_LEN {
+        return Err(CompressError::SmallError(format!(
+            "capacity {} cannot hold {} bytes",
+            N,
+            data.len()
+        )));
+    }
+    let mut frame = SmallFrame { buf: [0; N], len: 0 };
+    frame.buf[1] = data.len() as u8;
+    match encode_lz(data, &mut frame.buf[HEADER_LEN..]) {
+        Some(body) => {
+            frame.buf[0] = MAGIC | MODE_LZ;
+            frame.len = HEADER_LEN + body;
+        }
+        None => {
+            frame.buf[0] = MAGIC | MODE_STORED;
+            frame.buf[HEADER_LEN..HEADER_LEN + data.len()].copy_from_slice(data);
+            frame.len = HEADER_LEN + data.len();
+        }
+    }
+    Ok(frame)
+}
+
+/// Decode a small frame onto the stack
+pub fn decompress<const N: usize>(frame: &[u8]) -> Result<SmallFrame<N>, CompressError> {
+    if !is_small_frame(frame) {
+        return Err(CompressError::SmallError("not a small frame".into()));
+    }
+    let len = frame[1] as usize;
+    if len > N {
+        return Err(CompressError::SmallError(format!("capacity {} cannot hold {} bytes", N, len)));
+    }
+    let mut out = SmallFrame { buf: [0; N], len };
+    let body = &frame[HEADER_LEN..];
+    if frame[0] & 0x0F == MODE_STORED {
+        if body.len() != len {
+            return Err(CompressError::SizeMismatch {
+                expected: len,
+                actual: body.len(),
+            });
+        }
+        out.buf[..len].copy_from_slice(body);
+        return Ok(out);
+    }
+    decode_lz(body, &mut out.buf[..len])?;
+    Ok(out)
+}
+
+fn hash3(data: &[u8]) -> usize {
+    let v = u32::from(data[0]) | u32::from(data[1]) << 8 | u32::from(data[2]) << 16;
+    (v.wrapping_mul(0x9E37_79B1) >> 24) as usize
+}
+
+/// LZ-encode into `out`; `None` unless the result is shorter than `data`
+fn encode_lz(data: &[u8], out: &mut [u8]) -> Option<usize> {
+    let limit = out.len().min(data.len().checked_sub(1)?);
+    // Last position + 1 of each 3-byte hash; 0 means empty
+    let mut table = [0u8; 256];
+    let mut o = 0;
+    let mut literal_start = 0;
+    let mut i = 0;
+    while i + MIN_MATCH <= data.len() {
+        let h = hash3(&data[i..]);
+        let candidate = table[h] as usize;
+        table[h] = (i + 1) as u8;
+        if candidate > 0 {
+            let from = candidate - 1;
+            let mut len = 0;
+            while i + len < data.len() && len < MAX_MATCH && data[from + len] == data[i + len] {
+                len += 1;
+            }
+            if len >= MIN_MATCH {
+                o = emit_literals(&data[literal_start..i], out, o, limit)?;
+                if o + 2 > limit {
+                    return None;
+                }
+                out[o] = 0x80 | (len - MIN_MATCH) as u8;
+                out[o + 1] = (i - from) as u8;
+                o += 2;
+                i += len;
+                literal_start = i;
+                continue;
+            }
+        }
+        i += 1;
+    }
+    emit_literals(&data[literal_start..], out, o, limit)
+}
+
+fn emit_literals(literals: &[u8], out: &mut [u8], mut o: usize, limit: usize) -> Option<usize> {
+    for run in literals.chunks(MAX_LITERALS) {
+        if o + 1 + run.len() > limit {
+            return None;
+        }
+        out[o] = (run.len() - 1) as u8;
+        out[o + 1..o + 1 + run.len()].copy_from_slice(run);
+        o += 1 + run.len();
+    }
+    Some(o)
+}
+
+fn decode_lz(body: &[u8], out: &mut [u8]) -> Result<(), CompressError> {
+    let truncated = || CompressError::SmallError("truncated body".into());
+    let mut pos = 0;
+    let mut o = 0;
+    while o < out.len() {
+        let token = *body.get(pos).ok_or_else(truncated)? as usize;
+        pos += 1;
+        if token < 0x80 {
+            let run = token + 1;
+            let literals = body.get(pos..pos + run).ok_or_else(truncated)?;
+            out.get_mut(o..o + run)
+                .ok_or_else(|| CompressError::SmallError("literals overrun output".into()))?
+                .copy_from_slice(literals);
+            pos += run;
+            o += run;
+        } else {
+            let len = (token & 0x7F) + MIN_MATCH;
+            let offset = *body.get(pos).ok_or_else(truncated)? as usize;
+            pos += 1;
+            if offset == 0 || offset > o || o + len > out.len() {
+                return Err(CompressError::SmallError("invalid match".into()));
+            }
+            for k in 0..len {
+                out[o + k] = out[o + k - offset];
+            }
+            o += len;
+        }
+    }
+    if pos != body.len() {
+        return Err(CompressError::SmallError("trailing bytes after body".into()));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_small_roundtrip() {
+        for data in [
+            &b""[..],
+            b"x",
+            b"{\"id\":1,\"id\":1,\"id\":1,\"ok\":true}",
+            &[7u8; 255],
+            b"no repeats at all here",
+        ] {
+            let frame = compress::<257>(data).unwrap();
+            assert!(frame.len() <= data.len() + HEADER_LEN);
+            assert!(is_small_frame(frame.as_bytes()));
+            assert_eq!(decompress::<255>(frame.as_bytes()).unwrap().as_bytes(), data);
+        }
+        assert!(compress::<257>(&[7u8; 255]).unwrap().len() < 10);
+    }
+
+    #[test]
+    fn test_small_limits() {
+        assert!(compress::<300>(&[0u8; 256]).is_err());
+        assert!(compress::<8>(b"too long for eight").is_err());
+        let frame = compress::<64>(b"abcabcabcabcabcabc").unwrap();
+        assert!(decompress::<4>(frame.as_bytes()).is_err());
+        let bytes = frame.as_bytes();
+        assert!(decompress::<64>(&bytes[..bytes.len() - 1]).is_err());
+        assert!(decompress::<64>(b"SGMA").is_err());
+    }
+}
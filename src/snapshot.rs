@@ -0,0 +1,348 @@
+//! Directory snapshots backed by the block store
+//!
+//! A snapshot walks a directory tree, splits every file into content-defined
+//! chunks and stores each unique chunk once in a [`BlockStore`]. The returned
+//! [`SnapshotManifest`] lists the chunk hashes per file and is all that is
+//! needed (together with the store) to restore the tree.
+
+use crate::chunker;
+use crate::error::CompressError;
+use crate::storage::Storage;
+use crate::store::{BlockHash, BlockStore};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+/// Current manifest format version
+pub const MANIFEST_VERSION: u32 = 1;
+
+/// A single file captured in a snapshot
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SnapshotEntry {
+    /// Path relative to the snapshot root, `/`-separated
+    pub path: String,
+    pub size: u64,
+    /// Modification time in nanoseconds since the Unix epoch, when available
+    pub modified_ns: Option<u64>,
+    pub chunks: Vec<BlockHash>,
+}
+
+/// Counters describing how much work a snapshot did
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SnapshotStats {
+    pub files: usize,
+    pub total_bytes: u64,
+    /// Chunks written to the store by this snapshot
+    pub new_chunks: usize,
+    /// Chunks that were already present in the store
+    pub reused_chunks: usize,
+    /// Files carried over from the parent without being re-read
+    pub unchanged_files: usize,
+}
+
+/// Description of a directory tree in terms of stored blocks
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SnapshotManifest {
+    pub version: u32,
+    /// Id of the manifest this one was derived from
+    pub parent: Option<BlockHash>,
+    pub entries: Vec<SnapshotEntry>,
+    pub stats: SnapshotStats,
+}
+
+impl SnapshotManifest {
+    /// Content id of the manifest, derived from its entries
+    pub fn id(&self) -> BlockHash {
+        let raw = serde_json::to_vec(&self.entries).unwrap_or_default();
+        BlockHash::of(&raw)
+    }
+
+    /// Write the manifest as JSON
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), CompressError> {
+        let raw = serde_json::to_vec_pretty(self).map_err(|e| CompressError::SerializationError(e.to_string()))?;
+        fs::write(path, raw)?;
+        Ok(())
+    }
+
+    /// Read a manifest previously written with [`SnapshotManifest::save`]
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, CompressError> {
+        let raw = fs::read(path)?;
+        let manifest: Self =
+            serde_json::from_slice(&raw).map_err(|e| CompressError::SerializationError(e.to_string()))?;
+        if manifest.version != MANIFEST_VERSION {
+            return Err(CompressError::SnapshotError(format!(
+                "unsupported manifest version {}",
+                manifest.version
+            )));
+        }
+        Ok(manifest)
+    }
+}
+
+/// Snapshot creation and restore
+pub struct Snapshot;
+
+impl Snapshot {
+    /// Capture every regular file under `dir` into `store`
+    pub fn create<S: Storage>(dir: impl AsRef<Path>, store: &mut BlockStore<S>) -> Result<SnapshotManifest, CompressError> {
+        Self::build(dir.as_ref(), None, store)
+    }
+
+    /// Capture `dir`, re-reading only files whose size or mtime differ from `parent`
+    pub fn create_incremental<S: Storage>(
+        dir: impl AsRef<Path>,
+        parent: &SnapshotManifest,
+        store: &mut BlockStore<S>,
+    ) -> Result<SnapshotManifest, CompressError> {
+        Self::build(dir.as_ref(), Some(parent), store)
+    }
+
+    /// Recreate the files described by `manifest` under `target`
+    pub fn restore<S: Storage>(
+        manifest: &SnapshotManifest,
+        store: &BlockStore<S>,
+        target: impl AsRef<Path>,
+    ) -> Result<(), CompressError> {
+        let target = target.as_ref();
+        fs::create_dir_all(target)?;
+        for entry in &manifest.entries {
+            let dest = target.join(sanitize(&entry.path)?);
+            if let Some(parent) = dest.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            let mut contents = Vec::with_capacity((entry.size as usize).min(crate::MAX_PREALLOC));
+            for hash in &entry.chunks {
+                contents.extend_from_slice(&store.get(hash)?);
+            }
+            if contents.len() as u64 != entry.size {
+                return Err(CompressError::SizeMismatch {
+                    expected: entry.size as usize,
+                    actual: contents.len(),
+                });
+            }
+            fs::write(&dest, contents)?;
+        }
+        Ok(())
+    }
+
+    /// Drop the store references held by `manifest` so [`BlockStore::gc`] can reclaim
+    /// blocks no other snapshot uses
+    pub fn release<S: Storage>(manifest: &SnapshotManifest, store: &mut BlockStore<S>) -> Result<(), CompressError> {
+        for entry in &manifest.entries {
+            for hash in &entry.chunks {
+                store.release(hash);
+            }
+        }
+        store.flush()
+    }
+
+    fn build<S: Storage>(
+        dir: &Path,
+        parent: Option<&SnapshotManifest>,
+        store: &mut BlockStore<S>,
+    ) -> Result<SnapshotManifest, CompressError> {
+        let previous: HashMap<&str, &SnapshotEntry> = parent
+            .map(|p| p.entries.iter().map(|e| (e.path.as_str(), e)).collect())
+            .unwrap_or_default();
+
+        let mut files = Vec::new();
+        walk(dir, dir, &mut files)?;
+
+        let mut stats = SnapshotStats::default();
+        let mut entries = Vec::with_capacity(files.len());
+        for (rel, path) in files {
+            let meta = fs::metadata(&path)?;
+            let modified_ns = meta
+                .modified()
+                .ok()
+                .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+                .map(|d| d.as_nanos() as u64);
+
+            stats.files += 1;
+            stats.total_bytes += meta.len();
+
+            if let Some(prev) = previous.get(rel.as_str()) {
+                let unchanged = prev.size == meta.len()
+                    && prev.modified_ns.is_some()
+                    && prev.modified_ns == modified_ns
+                    && prev.chunks.iter().all(|h| store.contains(h));
+                if unchanged {
+                    for hash in &prev.chunks {
+                        store.retain(hash);
+                    }
+                    stats.unchanged_files += 1;
+                    stats.reused_chunks += prev.chunks.len();
+                    entries.push((*prev).clone());
+                    continue;
+                }
+            }
+
+            let data = fs::read(&path)?;
+            let mut chunks = Vec::new();
+            for piece in chunker::chunk(&data) {
+                let existed = store.contains(&BlockHash::of(piece));
+                chunks.push(store.put(piece)?);
+                if existed {
+                    stats.reused_chunks += 1;
+                } else {
+                    stats.new_chunks += 1;
+                }
+            }
+            entries.push(SnapshotEntry {
+                path: rel,
+                size: data.len() as u64,
+                modified_ns,
+                chunks,
+            });
+        }
+
+        store.flush()?;
+        Ok(SnapshotManifest {
+            version: MANIFEST_VERSION,
+            parent: parent.map(|p| p.id()),
+            entries,
+            stats,
+        })
+    }
+}
+
+/// Collect regular files below `dir` in sorted order, skipping symlinks
+fn walk(root: &Path, dir: &Path, out: &mut Vec<(String, PathBuf)>) -> Result<(), CompressError> {
+    let mut children: Vec<_> = fs::read_dir(dir)?.collect::<Result<_, _>>()?;
+    children.sort_by_key(|e| e.file_name());
+    for child in children {
+        let path = child.path();
+        let file_type = child.file_type()?;
+        if file_type.is_dir() {
+            walk(root, &path, out)?;
+        } else if file_type.is_file() {
+            let rel = path
+                .strip_prefix(root)
+                .map_err(|e| CompressError::SnapshotError(e.to_string()))?;
+            let rel = rel
+                .components()
+                .map(|c| c.as_os_str().to_string_lossy())
+                .collect::<Vec<_>>()
+                .join("/");
+            out.push((rel, path));
+        }
+    }
+    Ok(())
+}
+
+/// Reject manifest paths that would escape the restore target
+fn sanitize(rel: &str) -> Result<PathBuf, CompressError> {
+    let path = Path::new(rel);
+    if path.components().all(|c| matches!(c, Component::Normal(_))) {
+        Ok(path.to_path_buf())
+    } else {
+        Err(CompressError::SnapshotError(format!("unsafe path in manifest: {}", rel)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn populate(dir: &Path) {
+        fs::create_dir_all(dir.join("src/nested")).unwrap();
+        fs::write(dir.join("README.md"), b"# readme\n".repeat(20)).unwrap();
+        fs::write(dir.join("src/lib.rs"), b"pub fn f() {}\n".repeat(500)).unwrap();
+        fs::write(dir.join("src/nested/copy.rs"), b"pub fn f() {}\n".repeat(500)).unwrap();
+    }
+
+    #[test]
+    fn test_snapshot_restore_roundtrip() {
+        let src = tempfile::tempdir().unwrap();
+        let store_dir = tempfile::tempdir().unwrap();
+        let out = tempfile::tempdir().unwrap();
+        populate(src.path());
+
+        let mut store = BlockStore::open(store_dir.path()).unwrap();
+        let manifest = Snapshot::create(src.path(), &mut store).unwrap();
+        assert_eq!(manifest.stats.files, 3);
+        assert!(manifest.stats.reused_chunks > 0, "duplicate file should dedup");
+
+        Snapshot::restore(&manifest, &store, out.path()).unwrap();
+        for rel in ["README.md", "src/lib.rs", "src/nested/copy.rs"] {
+            assert_eq!(
+                fs::read(src.path().join(rel)).unwrap(),
+                fs::read(out.path().join(rel)).unwrap()
+            );
+        }
+    }
+
+    #[test]
+    fn test_incremental_skips_unchanged() {
+        let src = tempfile::tempdir().unwrap();
+        let store_dir = tempfile::tempdir().unwrap();
+        populate(src.path());
+
+        let mut store = BlockStore::open(store_dir.path()).unwrap();
+        let first = Snapshot::create(src.path(), &mut store).unwrap();
+        fs::write(src.path().join("NEW.txt"), b"fresh file").unwrap();
+
+        let second = Snapshot::create_incremental(src.path(), &first, &mut store).unwrap();
+        assert_eq!(second.parent, Some(first.id()));
+        assert_eq!(second.stats.files, 4);
+        assert_eq!(second.stats.unchanged_files, 3);
+        assert_eq!(second.stats.new_chunks, 1);
+    }
+
+    #[test]
+    fn test_release_then_gc() {
+        let src = tempfile::tempdir().unwrap();
+        let store_dir = tempfile::tempdir().unwrap();
+        populate(src.path());
+        let mut store = BlockStore::open(store_dir.path()).unwrap();
+
+        let first = Snapshot::create(src.path(), &mut store).unwrap();
+        let second = Snapshot::create_incremental(src.path(), &first, &mut store).unwrap();
+        Snapshot::release(&first, &mut store).unwrap();
+        assert_eq!(store.gc().unwrap().blocks_removed, 0, "second snapshot still holds blocks");
+
+        Snapshot::release(&second, &mut store).unwrap();
+        assert!(store.gc().unwrap().blocks_removed > 0);
+        assert!(store.is_empty());
+    }
+
+    #[test]
+    fn test_snapshot_on_dir_storage() {
+        let src = tempfile::tempdir().unwrap();
+        let store_dir = tempfile::tempdir().unwrap();
+        let out = tempfile::tempdir().unwrap();
+        populate(src.path());
+
+        let storage = crate::storage::DirStorage::open(store_dir.path()).unwrap();
+        let mut store = BlockStore::with_storage(storage).unwrap();
+        let manifest = Snapshot::create(src.path(), &mut store).unwrap();
+        Snapshot::restore(&manifest, &store, out.path()).unwrap();
+        assert_eq!(
+            fs::read(src.path().join("src/lib.rs")).unwrap(),
+            fs::read(out.path().join("src/lib.rs")).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_manifest_save_load() {
+        let src = tempfile::tempdir().unwrap();
+        let store_dir = tempfile::tempdir().unwrap();
+        populate(src.path());
+        let mut store = BlockStore::open(store_dir.path()).unwrap();
+        let manifest = Snapshot::create(src.path(), &mut store).unwrap();
+
+        let path = store_dir.path().join("snap.json");
+        manifest.save(&path).unwrap();
+        let loaded = SnapshotManifest::load(&path).unwrap();
+        assert_eq!(loaded.id(), manifest.id());
+    }
+
+    #[test]
+    fn test_restore_rejects_escaping_paths() {
+        assert!(sanitize("../etc/passwd").is_err());
+        assert!(sanitize("/abs").is_err());
+        assert!(sanitize("ok/path.txt").is_ok());
+    }
+}
@@ -0,0 +1,801 @@
+//! Storage backends for the block store
+//!
+//! A [`Storage`] is a flat key/value space addressed by [`BlockHash`], plus a
+//! handful of named metadata objects the block store uses for its own
+//! bookkeeping. Values are opaque: compression and refcounting live in
+//! [`crate::store::BlockStore`], so any backend works with snapshots unchanged.
+
+use crate::atomic::write_atomic;
+use crate::error::CompressError;
+use crate::store::BlockHash;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+/// Key/value backend addressed by block hash
+pub trait Storage {
+    /// Fetch the value stored under `hash`
+    fn get(&self, hash: &BlockHash) -> Result<Option<Vec<u8>>, CompressError>;
+
+    /// Store `value` under `hash`, replacing any previous value
+    fn put(&mut self, hash: &BlockHash, value: &[u8]) -> Result<(), CompressError>;
+
+    /// Remove `hash`, returning whether it was present
+    fn delete(&mut self, hash: &BlockHash) -> Result<bool, CompressError>;
+
+    /// Every hash currently stored
+    fn list(&self) -> Result<Vec<BlockHash>, CompressError>;
+
+    /// Read a named metadata object
+    fn read_meta(&self, name: &str) -> Result<Option<Vec<u8>>, CompressError>;
+
+    /// Replace a named metadata object
+    fn write_meta(&mut self, name: &str, value: &[u8]) -> Result<(), CompressError>;
+
+    fn contains(&self, hash: &BlockHash) -> Result<bool, CompressError> {
+        Ok(self.get(hash)?.is_some())
+    }
+
+    /// Make previous writes durable
+    fn sync(&mut self) -> Result<(), CompressError> {
+        Ok(())
+    }
+}
+
+/// Volatile storage, mainly for tests and short-lived dedup sessions
+#[derive(Debug, Default)]
+pub struct MemoryStorage {
+    objects: HashMap<BlockHash, Vec<u8>>,
+    meta: HashMap<String, Vec<u8>>,
+}
+
+impl MemoryStorage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl Storage for MemoryStorage {
+    fn get(&self, hash: &BlockHash) -> Result<Option<Vec<u8>>, CompressError> {
+        Ok(self.objects.get(hash).cloned())
+    }
+
+    fn put(&mut self, hash: &BlockHash, value: &[u8]) -> Result<(), CompressError> {
+        self.objects.insert(*hash, value.to_vec());
+        Ok(())
+    }
+
+    fn delete(&mut self, hash: &BlockHash) -> Result<bool, CompressError> {
+        Ok(self.objects.remove(hash).is_some())
+    }
+
+    fn list(&self) -> Result<Vec<BlockHash>, CompressError> {
+        let mut hashes: Vec<_> = self.objects.keys().copied().collect();
+        hashes.sort_unstable();
+        Ok(hashes)
+    }
+
+    fn read_meta(&self, name: &str) -> Result<Option<Vec<u8>>, CompressError> {
+        Ok(self.meta.get(name).cloned())
+    }
+
+    fn write_meta(&mut self, name: &str, value: &[u8]) -> Result<(), CompressError> {
+        self.meta.insert(name.to_string(), value.to_vec());
+        Ok(())
+    }
+
+    fn contains(&self, hash: &BlockHash) -> Result<bool, CompressError> {
+        Ok(self.objects.contains_key(hash))
+    }
+}
+
+/// One file per object under `<root>/objects/<first byte>/<hash>`
+pub struct DirStorage {
+    root: PathBuf,
+}
+
+impl DirStorage {
+    pub fn open(root: impl AsRef<Path>) -> Result<Self, CompressError> {
+        let root = root.as_ref().to_path_buf();
+        fs::create_dir_all(root.join("objects"))?;
+        Ok(Self { root })
+    }
+
+    fn object_path(&self, hash: &BlockHash) -> PathBuf {
+        let hex = hash.to_hex();
+        self.root.join("objects").join(&hex[..2]).join(hex)
+    }
+}
+
+impl Storage for DirStorage {
+    fn get(&self, hash: &BlockHash) -> Result<Option<Vec<u8>>, CompressError> {
+        match fs::read(self.object_path(hash)) {
+            Ok(raw) => Ok(Some(raw)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn put(&mut self, hash: &BlockHash, value: &[u8]) -> Result<(), CompressError> {
+        let path = self.object_path(hash);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        write_atomic(&path, value)
+    }
+
+    fn delete(&mut self, hash: &BlockHash) -> Result<bool, CompressError> {
+        match fs::remove_file(self.object_path(hash)) {
+            Ok(()) => Ok(true),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(false),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn list(&self) -> Result<Vec<BlockHash>, CompressError> {
+        let mut hashes = Vec::new();
+        for fan in fs::read_dir(self.root.join("objects"))? {
+            let fan = fan?;
+            if !fan.file_type()?.is_dir() {
+                continue;
+            }
+            for object in fs::read_dir(fan.path())? {
+                if let Some(hash) = object?.file_name().to_str().and_then(BlockHash::from_hex) {
+                    hashes.push(hash);
+                }
+            }
+        }
+        hashes.sort_unstable();
+        Ok(hashes)
+    }
+
+    fn read_meta(&self, name: &str) -> Result<Option<Vec<u8>>, CompressError> {
+        match fs::read(self.root.join(name)) {
+            Ok(raw) => Ok(Some(raw)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn write_meta(&mut self, name: &str, value: &[u8]) -> Result<(), CompressError> {
+        write_atomic(&self.root.join(name), value)
+    }
+
+    fn contains(&self, hash: &BlockHash) -> Result<bool, CompressError> {
+        Ok(self.object_path(hash).exists())
+    }
+}
+
+/// Pack files are rolled over once they grow past this size
+const PACK_TARGET_SIZE: u64 = 64 * 1024 * 1024;
+
+/// Size of the fixed record header preceding each value in a pack file
+const RECORD_HEADER_LEN: u64 = 32 + 8;
+
+/// Location of a record inside a pack file
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct PackLocation {
+    pack: u32,
+    offset: u64,
+    length: u64,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct PackIndex {
+    current_pack: u32,
+    records: BTreeMap<BlockHash, PackLocation>,
+}
+
+/// A pack record that the index does not point at
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OrphanRecord {
+    pub pack: u32,
+    pub offset: u64,
+    pub length: u64,
+    pub hash: BlockHash,
+}
+
+/// Result of [`PackStorage::compact`]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CompactionStats {
+    pub packs_rewritten: usize,
+    /// Packs deleted outright because they held no live records
+    pub packs_removed: usize,
+    /// Live bytes copied into fresh packs
+    pub bytes_moved: u64,
+    /// Disk space freed
+    pub bytes_reclaimed: u64,
+}
+
+/// Append-only pack files in a local directory
+///
+/// Layout:
+/// ```text
+/// <root>/packs.json
+/// <root>/packs/000001.pack   [hash:32][value_len:u64][value]...
+/// ```
+/// Deleting only drops the index entry; the bytes stay in the pack until
+/// [`PackStorage::compact`] rewrites it.
+pub struct PackStorage {
+    root: PathBuf,
+    index: PackIndex,
+    writer: Option<(u32, File)>,
+    dirty: bool,
+}
+
+impl PackStorage {
+    /// Open the pack directory at `root`, creating it if it does not exist
+    pub fn open(root: impl AsRef<Path>) -> Result<Self, CompressError> {
+        let root = root.as_ref().to_path_buf();
+        fs::create_dir_all(root.join("packs"))?;
+
+        let index = match fs::read(root.join("packs.json")) {
+            Ok(raw) => serde_json::from_slice(&raw).map_err(|e| CompressError::StoreError(e.to_string()))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => PackIndex {
+                current_pack: 1,
+                records: BTreeMap::new(),
+            },
+            Err(e) => return Err(e.into()),
+        };
+
+        Ok(Self {
+            root,
+            index,
+            writer: None,
+            dirty: false,
+        })
+    }
+
+    /// Root directory of the pack store
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Scan pack files for records the index does not reference: values that were
+    /// deleted, or writes that never made it into a synced index
+    pub fn orphans(&self) -> Result<Vec<OrphanRecord>, CompressError> {
+        let mut orphans = Vec::new();
+        for pack in self.pack_ids()? {
+            let raw = fs::read(self.pack_path(pack))?;
+            let mut offset = 0u64;
+            while offset < raw.len() as u64 {
+                let (hash, length) = record_extent(&raw[offset as usize..])?;
+                let live = self
+                    .index
+                    .records
+                    .get(&hash)
+                    .is_some_and(|e| e.pack == pack && e.offset == offset);
+                if !live {
+                    orphans.push(OrphanRecord { pack, offset, length, hash });
+                }
+                offset += length;
+            }
+        }
+        Ok(orphans)
+    }
+
+    /// Rewrite pack files whose garbage fraction is at least `min_garbage_ratio`,
+    /// copying their live records into fresh packs and deleting the originals
+    pub fn compact(&mut self, min_garbage_ratio: f64) -> Result<CompactionStats, CompressError> {
+        let packs = self.pack_ids()?;
+        let mut live_bytes: BTreeMap<u32, u64> = BTreeMap::new();
+        for loc in self.index.records.values() {
+            *live_bytes.entry(loc.pack).or_default() += loc.length;
+        }
+
+        let mut victims = Vec::new();
+        for &pack in &packs {
+            let size = fs::metadata(self.pack_path(pack))?.len();
+            let live = live_bytes.get(&pack).copied().unwrap_or(0);
+            let garbage = size.saturating_sub(live);
+            if size > 0 && garbage > 0 && garbage as f64 / size as f64 >= min_garbage_ratio {
+                victims.push((pack, size));
+            }
+        }
+        if victims.is_empty() {
+            return Ok(CompactionStats::default());
+        }
+
+        // Live records move into a pack that is not being compacted
+        self.writer = None;
+        self.index.current_pack = packs.iter().max().copied().unwrap_or(0) + 1;
+
+        let mut stats = CompactionStats::default();
+        for &(pack, _) in &victims {
+            let moving: Vec<(BlockHash, PackLocation)> = self
+                .index
+                .records
+                .iter()
+                .filter(|(_, loc)| loc.pack == pack)
+                .map(|(h, loc)| (*h, loc.clone()))
+                .collect();
+            if moving.is_empty() {
+                stats.packs_removed += 1;
+                continue;
+            }
+            let raw = fs::read(self.pack_path(pack))?;
+            for (hash, loc) in moving {
+                let record = &raw[loc.offset as usize..(loc.offset + loc.length) as usize];
+                let (new_pack, new_offset) = self.append_record(record)?;
+                let slot = self.index.records.get_mut(&hash).expect("location collected above");
+                slot.pack = new_pack;
+                slot.offset = new_offset;
+                stats.bytes_moved += loc.length;
+            }
+            stats.packs_rewritten += 1;
+        }
+
+        // Index must point at the new copies before the old packs disappear
+        self.dirty = true;
+        self.sync()?;
+        for (pack, size) in victims {
+            fs::remove_file(self.pack_path(pack))?;
+            stats.bytes_reclaimed += size;
+        }
+        stats.bytes_reclaimed = stats.bytes_reclaimed.saturating_sub(stats.bytes_moved);
+        Ok(stats)
+    }
+
+    fn pack_ids(&self) -> Result<Vec<u32>, CompressError> {
+        let mut ids = Vec::new();
+        for entry in fs::read_dir(self.root.join("packs"))? {
+            let name = entry?.file_name();
+            if let Some(id) = name.to_str().and_then(|n| n.strip_suffix(".pack")).and_then(|n| n.parse().ok()) {
+                ids.push(id);
+            }
+        }
+        ids.sort_unstable();
+        Ok(ids)
+    }
+
+    fn pack_path(&self, pack: u32) -> PathBuf {
+        self.root.join("packs").join(format!("{:06}.pack", pack))
+    }
+
+    fn append_record(&mut self, record: &[u8]) -> Result<(u32, u64), CompressError> {
+        let pack = self.index.current_pack;
+        if self.writer.as_ref().map(|(p, _)| *p) != Some(pack) {
+            let file = OpenOptions::new().create(true).append(true).open(self.pack_path(pack))?;
+            self.writer = Some((pack, file));
+        }
+        let (_, file) = self.writer.as_mut().unwrap();
+        let offset = file.metadata()?.len();
+        file.write_all(record)?;
+
+        if offset + record.len() as u64 >= PACK_TARGET_SIZE {
+            file.sync_data()?;
+            self.writer = None;
+            self.index.current_pack += 1;
+        }
+        Ok((pack, offset))
+    }
+}
+
+impl Storage for PackStorage {
+    fn get(&self, hash: &BlockHash) -> Result<Option<Vec<u8>>, CompressError> {
+        let Some(loc) = self.index.records.get(hash) else {
+            return Ok(None);
+        };
+        let mut file = File::open(self.pack_path(loc.pack))?;
+        file.seek(SeekFrom::Start(loc.offset))?;
+        let mut record = vec![0u8; loc.length as usize];
+        file.read_exact(&mut record)?;
+
+        let (stored_hash, length) = record_extent(&record)?;
+        if stored_hash != *hash || length != loc.length {
+            return Err(CompressError::StoreError(format!("index points at wrong record for {}", hash)));
+        }
+        record.drain(..RECORD_HEADER_LEN as usize);
+        Ok(Some(record))
+    }
+
+    fn put(&mut self, hash: &BlockHash, value: &[u8]) -> Result<(), CompressError> {
+        let mut record = Vec::with_capacity(RECORD_HEADER_LEN as usize + value.len());
+        record.extend_from_slice(&hash.0);
+        record.extend_from_slice(&(value.len() as u64).to_le_bytes());
+        record.extend_from_slice(value);
+
+        let (pack, offset) = self.append_record(&record)?;
+        self.index.records.insert(
+            *hash,
+            PackLocation {
+                pack,
+                offset,
+                length: record.len() as u64,
+            },
+        );
+        self.dirty = true;
+        Ok(())
+    }
+
+    fn delete(&mut self, hash: &BlockHash) -> Result<bool, CompressError> {
+        let removed = self.index.records.remove(hash).is_some();
+        self.dirty |= removed;
+        Ok(removed)
+    }
+
+    fn list(&self) -> Result<Vec<BlockHash>, CompressError> {
+        Ok(self.index.records.keys().copied().collect())
+    }
+
+    fn read_meta(&self, name: &str) -> Result<Option<Vec<u8>>, CompressError> {
+        match fs::read(self.root.join(name)) {
+            Ok(raw) => Ok(Some(raw)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn write_meta(&mut self, name: &str, value: &[u8]) -> Result<(), CompressError> {
+        write_atomic(&self.root.join(name), value)
+    }
+
+    fn contains(&self, hash: &BlockHash) -> Result<bool, CompressError> {
+        Ok(self.index.records.contains_key(hash))
+    }
+
+    /// Pack data is synced before the index is replaced
+    fn sync(&mut self) -> Result<(), CompressError> {
+        if let Some((_, file)) = &self.writer {
+            file.sync_data()?;
+        }
+        if !self.dirty {
+            return Ok(());
+        }
+        let raw = serde_json::to_vec(&self.index).map_err(|e| CompressError::StoreError(e.to_string()))?;
+        write_atomic(&self.root.join("packs.json"), &raw)?;
+        self.dirty = false;
+        Ok(())
+    }
+}
+
+/// Hash and total length of the pack record at the start of `raw`
+fn record_extent(raw: &[u8]) -> Result<(BlockHash, u64), CompressError> {
+    if (raw.len() as u64) < RECORD_HEADER_LEN {
+        return Err(CompressError::StoreError("truncated pack record".into()));
+    }
+    let mut hash = [0u8; 32];
+    hash.copy_from_slice(&raw[..32]);
+    let value_len = u64::from_le_bytes(raw[32..40].try_into().unwrap());
+    let length = RECORD_HEADER_LEN + value_len;
+    if length > raw.len() as u64 {
+        return Err(CompressError::StoreError("truncated pack payload".into()));
+    }
+    Ok((BlockHash(hash), length))
+}
+
+#[cfg(feature = "s3")]
+pub use s3::{S3Config, S3Storage};
+
+#[cfg(feature = "s3")]
+mod s3 {
+    //! S3-compatible object storage using SigV4-signed path-style requests
+
+    use super::Storage;
+    use crate::error::CompressError;
+    use crate::store::BlockHash;
+    use hmac::{Hmac, Mac};
+    use sha2::{Digest, Sha256};
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    /// Connection settings for [`S3Storage`]
+    #[derive(Debug, Clone)]
+    pub struct S3Config {
+        /// e.g. `https://s3.eu-central-1.amazonaws.com` or a MinIO URL
+        pub endpoint: String,
+        pub bucket: String,
+        pub region: String,
+        /// Key prefix under which objects and metadata are written
+        pub prefix: String,
+        pub access_key: String,
+        pub secret_key: String,
+    }
+
+    /// Objects stored as `<prefix>objects/<hash>` in an S3 bucket
+    pub struct S3Storage {
+        config: S3Config,
+        client: reqwest::blocking::Client,
+    }
+
+    impl S3Storage {
+        pub fn new(config: S3Config) -> Self {
+            Self {
+                config,
+                client: reqwest::blocking::Client::new(),
+            }
+        }
+
+        fn object_key(&self, hash: &BlockHash) -> String {
+            format!("{}objects/{}", self.config.prefix, hash.to_hex())
+        }
+
+        fn meta_key(&self, name: &str) -> String {
+            format!("{}meta/{}", self.config.prefix, name)
+        }
+
+        fn send(
+            &self,
+            method: reqwest::Method,
+            key: &str,
+            query: &[(&str, &str)],
+            body: &[u8],
+        ) -> Result<reqwest::blocking::Response, CompressError> {
+            let host = self
+                .config
+                .endpoint
+                .split("://")
+                .nth(1)
+                .unwrap_or(&self.config.endpoint)
+                .trim_end_matches('/');
+            let path = format!("/{}/{}", self.config.bucket, uri_encode(key, false));
+
+            let mut query: Vec<(String, String)> =
+                query.iter().map(|(k, v)| (uri_encode(k, true), uri_encode(v, true))).collect();
+            query.sort();
+            let canonical_query = query.iter().map(|(k, v)| format!("{}={}", k, v)).collect::<Vec<_>>().join("&");
+
+            let (amz_date, date) = amz_timestamps(SystemTime::now());
+            let payload_hash = hex(&Sha256::digest(body));
+            let canonical_request = format!(
+                "{}\n{}\n{}\nhost:{}\nx-amz-content-sha256:{}\nx-amz-date:{}\n\nhost;x-amz-content-sha256;x-amz-date\n{}",
+                method, path, canonical_query, host, payload_hash, amz_date, payload_hash
+            );
+            let scope = format!("{}/{}/s3/aws4_request", date, self.config.region);
+            let string_to_sign = format!(
+                "AWS4-HMAC-SHA256\n{}\n{}\n{}",
+                amz_date,
+                scope,
+                hex(&Sha256::digest(canonical_request.as_bytes()))
+            );
+
+            let mut key_material = hmac(format!("AWS4{}", self.config.secret_key).as_bytes(), date.as_bytes());
+            for part in [self.config.region.as_str(), "s3", "aws4_request"] {
+                key_material = hmac(&key_material, part.as_bytes());
+            }
+            let signature = hex(&hmac(&key_material, string_to_sign.as_bytes()));
+            let authorization = format!(
+                "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders=host;x-amz-content-sha256;x-amz-date, Signature={}",
+                self.config.access_key, scope, signature
+            );
+
+            let mut url = format!("{}{}", self.config.endpoint.trim_end_matches('/'), path);
+            if !canonical_query.is_empty() {
+                url = format!("{}?{}", url, canonical_query);
+            }
+            self.client
+                .request(method, url)
+                .header("x-amz-date", amz_date)
+                .header("x-amz-content-sha256", payload_hash)
+                .header("authorization", authorization)
+                .body(body.to_vec())
+                .send()
+                .map_err(|e| CompressError::StoreError(e.to_string()))
+        }
+
+        fn get_key(&self, key: &str) -> Result<Option<Vec<u8>>, CompressError> {
+            let resp = self.send(reqwest::Method::GET, key, &[], &[])?;
+            match resp.status() {
+                reqwest::StatusCode::NOT_FOUND => Ok(None),
+                s if s.is_success() => Ok(Some(
+                    resp.bytes().map_err(|e| CompressError::StoreError(e.to_string()))?.to_vec(),
+                )),
+                s => Err(CompressError::StoreError(format!("s3 GET {} returned {}", key, s))),
+            }
+        }
+
+        fn put_key(&self, key: &str, value: &[u8]) -> Result<(), CompressError> {
+            let resp = self.send(reqwest::Method::PUT, key, &[], value)?;
+            if !resp.status().is_success() {
+                return Err(CompressError::StoreError(format!("s3 PUT {} returned {}", key, resp.status())));
+            }
+            Ok(())
+        }
+    }
+
+    impl Storage for S3Storage {
+        fn get(&self, hash: &BlockHash) -> Result<Option<Vec<u8>>, CompressError> {
+            self.get_key(&self.object_key(hash))
+        }
+
+        fn put(&mut self, hash: &BlockHash, value: &[u8]) -> Result<(), CompressError> {
+            self.put_key(&self.object_key(hash), value)
+        }
+
+        fn delete(&mut self, hash: &BlockHash) -> Result<bool, CompressError> {
+            let existed = self.contains(hash)?;
+            let resp = self.send(reqwest::Method::DELETE, &self.object_key(hash), &[], &[])?;
+            if !resp.status().is_success() {
+                return Err(CompressError::StoreError(format!("s3 DELETE returned {}", resp.status())));
+            }
+            Ok(existed)
+        }
+
+        fn list(&self) -> Result<Vec<BlockHash>, CompressError> {
+            let prefix = format!("{}objects/", self.config.prefix);
+            let mut hashes = Vec::new();
+            let mut token: Option<String> = None;
+            loop {
+                let mut query = vec![("list-type", "2"), ("prefix", prefix.as_str())];
+                if let Some(t) = &token {
+                    query.push(("continuation-token", t.as_str()));
+                }
+                let resp = self.send(reqwest::Method::GET, "", &query, &[])?;
+                if !resp.status().is_success() {
+                    return Err(CompressError::StoreError(format!("s3 LIST returned {}", resp.status())));
+                }
+                let body = resp.text().map_err(|e| CompressError::StoreError(e.to_string()))?;
+                for key in xml_values(&body, "Key") {
+                    if let Some(hash) = key.strip_prefix(&prefix).and_then(BlockHash::from_hex) {
+                        hashes.push(hash);
+                    }
+                }
+                token = xml_values(&body, "NextContinuationToken").into_iter().next();
+                if token.is_none() {
+                    break;
+                }
+            }
+            hashes.sort_unstable();
+            Ok(hashes)
+        }
+
+        fn read_meta(&self, name: &str) -> Result<Option<Vec<u8>>, CompressError> {
+            self.get_key(&self.meta_key(name))
+        }
+
+        fn write_meta(&mut self, name: &str, value: &[u8]) -> Result<(), CompressError> {
+            self.put_key(&self.meta_key(name), value)
+        }
+
+        fn contains(&self, hash: &BlockHash) -> Result<bool, CompressError> {
+            let resp = self.send(reqwest::Method::HEAD, &self.object_key(hash), &[], &[])?;
+            Ok(resp.status().is_success())
+        }
+    }
+
+    fn hmac(key: &[u8], data: &[u8]) -> Vec<u8> {
+        let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("hmac accepts any key length");
+        mac.update(data);
+        mac.finalize().into_bytes().to_vec()
+    }
+
+    fn hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|b| format!("{:02x}", b)).collect()
+    }
+
+    /// Percent-encode per SigV4 rules; `/` is kept in paths
+    fn uri_encode(s: &str, encode_slash: bool) -> String {
+        let mut out = String::with_capacity(s.len());
+        for b in s.bytes() {
+            match b {
+                b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => out.push(b as char),
+                b'/' if !encode_slash => out.push('/'),
+                _ => out.push_str(&format!("%{:02X}", b)),
+            }
+        }
+        out
+    }
+
+    /// `(YYYYMMDDTHHMMSSZ, YYYYMMDD)` for the given instant
+    fn amz_timestamps(now: SystemTime) -> (String, String) {
+        let secs = now.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+        let days = (secs / 86_400) as i64;
+        let rem = secs % 86_400;
+
+        // Civil-from-days (Howard Hinnant)
+        let z = days + 719_468;
+        let era = z.div_euclid(146_097);
+        let doe = z - era * 146_097;
+        let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+        let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+        let mp = (5 * doy + 2) / 153;
+        let day = doy - (153 * mp + 2) / 5 + 1;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 };
+        let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+        let date = format!("{:04}{:02}{:02}", year, month, day);
+        let stamp = format!("{}T{:02}{:02}{:02}Z", date, rem / 3600, (rem / 60) % 60, rem % 60);
+        (stamp, date)
+    }
+
+    fn xml_values(body: &str, tag: &str) -> Vec<String> {
+        let open = format!("<{}>", tag);
+        let close = format!("</{}>", tag);
+        let mut values = Vec::new();
+        let mut rest = body;
+        while let Some(start) = rest.find(&open) {
+            rest = &rest[start + open.len()..];
+            match rest.find(&close) {
+                Some(end) => {
+                    values.push(rest[..end].to_string());
+                    rest = &rest[end + close.len()..];
+                }
+                None => break,
+            }
+        }
+        values
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn test_amz_timestamps() {
+            let t = UNIX_EPOCH + std::time::Duration::from_secs(1_440_938_160);
+            assert_eq!(amz_timestamps(t), ("20150830T123600Z".to_string(), "20150830".to_string()));
+        }
+
+        #[test]
+        fn test_xml_values() {
+            let body = "<R><Key>a</Key><Key>b</Key><IsTruncated>false</IsTruncated></R>";
+            assert_eq!(xml_values(body, "Key"), vec!["a", "b"]);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn exercise(storage: &mut dyn Storage) {
+        let a = BlockHash::of(b"a");
+        let b = BlockHash::of(b"b");
+        storage.put(&a, b"value a").unwrap();
+        storage.put(&b, b"value b").unwrap();
+        assert_eq!(storage.get(&a).unwrap().as_deref(), Some(&b"value a"[..]));
+        assert!(storage.contains(&b).unwrap());
+
+        let mut expected = vec![a, b];
+        expected.sort_unstable();
+        assert_eq!(storage.list().unwrap(), expected);
+
+        assert!(storage.delete(&a).unwrap());
+        assert!(!storage.delete(&a).unwrap());
+        assert_eq!(storage.get(&a).unwrap(), None);
+
+        storage.write_meta("refs.json", b"{}").unwrap();
+        assert_eq!(storage.read_meta("refs.json").unwrap().as_deref(), Some(&b"{}"[..]));
+        assert_eq!(storage.read_meta("missing").unwrap(), None);
+        storage.sync().unwrap();
+    }
+
+    #[test]
+    fn test_memory_storage() {
+        exercise(&mut MemoryStorage::new());
+    }
+
+    #[test]
+    fn test_dir_storage() {
+        let dir = tempfile::tempdir().unwrap();
+        exercise(&mut DirStorage::open(dir.path()).unwrap());
+    }
+
+    #[test]
+    fn test_pack_storage() {
+        let dir = tempfile::tempdir().unwrap();
+        exercise(&mut PackStorage::open(dir.path()).unwrap());
+    }
+
+    #[test]
+    fn test_pack_compaction() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut packs = PackStorage::open(dir.path()).unwrap();
+        let keep = BlockHash::of(b"keep");
+        let drop = BlockHash::of(b"drop");
+        packs.put(&keep, &[1u8; 500]).unwrap();
+        packs.put(&drop, &[2u8; 500]).unwrap();
+        packs.delete(&drop).unwrap();
+
+        let orphans = packs.orphans().unwrap();
+        assert_eq!(orphans.len(), 1);
+        assert_eq!(orphans[0].hash, drop);
+
+        let stats = packs.compact(0.1).unwrap();
+        assert_eq!(stats.packs_rewritten, 1);
+        assert_eq!(stats.bytes_reclaimed, orphans[0].length);
+        assert!(packs.orphans().unwrap().is_empty());
+        assert_eq!(packs.get(&keep).unwrap(), Some(vec![1u8; 500]));
+    }
+}
@@ -0,0 +1,370 @@
+//! Content-addressed block store
+//!
+//! Blocks are keyed by the BLAKE3 hash of their uncompressed contents and
+//! compressed individually before being handed to a [`Storage`] backend. The
+//! store keeps a reference count per block so unused blocks can be collected.
+//!
+//! Stored value layout: `[method:u8][original_size:u64][payload]`
+
+use crate::error::CompressError;
+use crate::storage::{CompactionStats, OrphanRecord, PackStorage, Storage};
+use crate::{CompressionMethod, Compressor};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::Path;
+
+/// Metadata object holding the refcount table
+const REFS_META: &str = "refs.json";
+
+/// Size of the header preceding each compressed payload
+const VALUE_HEADER_LEN: usize = 1 + 8;
+
+/// BLAKE3 hash identifying a block by content
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct BlockHash(pub [u8; 32]);
+
+impl BlockHash {
+    /// Hash a block's uncompressed contents
+    pub fn of(data: &[u8]) -> Self {
+        Self(*blake3::hash(data).as_bytes())
+    }
+
+    /// Lowercase hex representation
+    pub fn to_hex(&self) -> String {
+        self.0.iter().map(|b| format!("{:02x}", b)).collect()
+    }
+
+    /// Parse a 64-character hex string
+    pub fn from_hex(s: &str) -> Option<Self> {
+        if s.len() != 64 || !s.is_ascii() {
+            return None;
+        }
+        let mut out = [0u8; 32];
+        for (i, byte) in out.iter_mut().enumerate() {
+            *byte = u8::from_str_radix(&s[i * 2..i * 2 + 2], 16).ok()?;
+        }
+        Some(Self(out))
+    }
+}
+
+impl std::fmt::Display for BlockHash {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.to_hex())
+    }
+}
+
+impl Serialize for BlockHash {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_hex())
+    }
+}
+
+impl<'de> Deserialize<'de> for BlockHash {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        BlockHash::from_hex(&s).ok_or_else(|| serde::de::Error::custom("invalid block hash"))
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct RefEntry {
+    /// Number of live references (e.g. snapshot chunks) to this block
+    refs: u64,
+    /// Size of the stored value
+    stored_len: u64,
+    /// Method the block was compressed with (absent in older stores)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    method: Option<CompressionMethod>,
+}
+
+/// Result of [`BlockStore::gc`]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct GcStats {
+    /// Blocks deleted because nothing references them
+    pub blocks_removed: usize,
+    /// Stored bytes those blocks occupied
+    pub bytes_released: u64,
+}
+
+/// Persistent, deduplicating store of compressed blocks
+pub struct BlockStore<S: Storage = PackStorage> {
+    storage: S,
+    refs: BTreeMap<BlockHash, RefEntry>,
+    compressor: Compressor,
+    dirty: bool,
+}
+
+impl BlockStore<PackStorage> {
+    /// Open a pack-file store at `root`, creating it if it does not exist
+    pub fn open(root: impl AsRef<Path>) -> Result<Self, CompressError> {
+        Self::with_storage(PackStorage::open(root)?)
+    }
+
+    /// Pack records no live block points at; see [`PackStorage::orphans`]
+    pub fn orphans(&self) -> Result<Vec<OrphanRecord>, CompressError> {
+        self.storage.orphans()
+    }
+
+    /// Rewrite fragmented pack files; see [`PackStorage::compact`]
+    pub fn compact(&mut self, min_garbage_ratio: f64) -> Result<CompactionStats, CompressError> {
+        self.storage.compact(min_garbage_ratio)
+    }
+}
+
+impl<S: Storage> BlockStore<S> {
+    /// Build a store on top of any storage backend
+    pub fn with_storage(storage: S) -> Result<Self, CompressError> {
+        let refs = match storage.read_meta(REFS_META)? {
+            Some(raw) => serde_json::from_slice(&raw).map_err(|e| CompressError::StoreError(e.to_string()))?,
+            None => BTreeMap::new(),
+        };
+        Ok(Self {
+            storage,
+            refs,
+            compressor: Compressor::default(),
+            dirty: false,
+        })
+    }
+
+    /// Underlying storage backend
+    pub fn storage(&self) -> &S {
+        &self.storage
+    }
+
+    pub fn storage_mut(&mut self) -> &mut S {
+        &mut self.storage
+    }
+
+    /// Number of distinct blocks held
+    pub fn len(&self) -> usize {
+        self.refs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.refs.is_empty()
+    }
+
+    /// Whether a block with this hash is present
+    pub fn contains(&self, hash: &BlockHash) -> bool {
+        self.refs.contains_key(hash)
+    }
+
+    /// Total stored (compressed) bytes of all blocks
+    pub fn stored_bytes(&self) -> u64 {
+        self.refs.values().map(|e| e.stored_len).sum()
+    }
+
+    /// Stored size and compression method of a block, without reading it
+    pub fn block_info(&self, hash: &BlockHash) -> Option<(u64, Option<CompressionMethod>)> {
+        self.refs.get(hash).map(|e| (e.stored_len, e.method))
+    }
+
+    /// Insert a block, returning its hash. Blocks already present are not rewritten;
+    /// either way the block gains one reference.
+    pub fn put(&mut self, data: &[u8]) -> Result<BlockHash, CompressError> {
+        let hash = BlockHash::of(data);
+        if self.retain(&hash) {
+            return Ok(hash);
+        }
+
+        let (method, original_size, payload) = if data.is_empty() {
+            (CompressionMethod::EntropyCoding, 0, Vec::new())
+        } else {
+            let out = self.compressor.compress_adaptive(data)?;
+            (out.method, out.original_size, out.data)
+        };
+
+        let mut value = Vec::with_capacity(VALUE_HEADER_LEN + payload.len());
+        value.push(method.id());
+        value.extend_from_slice(&(original_size as u64).to_le_bytes());
+        value.extend_from_slice(&payload);
+
+        self.storage.put(&hash, &value)?;
+        self.refs.insert(
+            hash,
+            RefEntry {
+                refs: 1,
+                stored_len: value.len() as u64,
+                method: Some(method),
+            },
+        );
+        self.dirty = true;
+        Ok(hash)
+    }
+
+    /// Add a reference to a block already in the store. Returns false if it is absent.
+    pub fn retain(&mut self, hash: &BlockHash) -> bool {
+        match self.refs.get_mut(hash) {
+            Some(entry) => {
+                entry.refs += 1;
+                self.dirty = true;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Drop a reference to a block. The block stays readable until the next [`BlockStore::gc`].
+    pub fn release(&mut self, hash: &BlockHash) {
+        if let Some(entry) = self.refs.get_mut(hash) {
+            entry.refs = entry.refs.saturating_sub(1);
+            self.dirty = true;
+        }
+    }
+
+    /// Current reference count of a block, if present
+    pub fn refcount(&self, hash: &BlockHash) -> Option<u64> {
+        self.refs.get(hash).map(|e| e.refs)
+    }
+
+    /// Delete every block whose reference count has dropped to zero
+    pub fn gc(&mut self) -> Result<GcStats, CompressError> {
+        let dead: Vec<BlockHash> = self
+            .refs
+            .iter()
+            .filter(|(_, e)| e.refs == 0)
+            .map(|(h, _)| *h)
+            .collect();
+
+        let mut stats = GcStats::default();
+        for hash in dead {
+            self.storage.delete(&hash)?;
+            if let Some(entry) = self.refs.remove(&hash) {
+                stats.blocks_removed += 1;
+                stats.bytes_released += entry.stored_len;
+            }
+        }
+        if stats.blocks_removed > 0 {
+            self.dirty = true;
+            self.flush()?;
+        }
+        Ok(stats)
+    }
+
+    /// Fetch and decompress a block, verifying its contents against the hash
+    pub fn get(&self, hash: &BlockHash) -> Result<Vec<u8>, CompressError> {
+        let value = self
+            .storage
+            .get(hash)?
+            .ok_or_else(|| CompressError::StoreError(format!("block {} not found", hash)))?;
+        if value.len() < VALUE_HEADER_LEN {
+            return Err(CompressError::StoreError(format!("block {} is truncated", hash)));
+        }
+        let method = CompressionMethod::from_id(value[0])
+            .ok_or_else(|| CompressError::StoreError(format!("block {} has unknown method", hash)))?;
+        let original_size = u64::from_le_bytes(value[1..9].try_into().unwrap()) as usize;
+
+        let data = if original_size == 0 {
+            Vec::new()
+        } else {
+            self.compressor
+                .decompress_payload(method, &value[VALUE_HEADER_LEN..], original_size)?
+        };
+        if BlockHash::of(&data) != *hash {
+            return Err(CompressError::StoreError(format!("block {} failed integrity check", hash)));
+        }
+        Ok(data)
+    }
+
+    /// Make block data durable, then persist the refcount table
+    pub fn flush(&mut self) -> Result<(), CompressError> {
+        self.storage.sync()?;
+        if !self.dirty {
+            return Ok(());
+        }
+        let raw = serde_json::to_vec(&self.refs).map_err(|e| CompressError::StoreError(e.to_string()))?;
+        self.storage.write_meta(REFS_META, &raw)?;
+        self.dirty = false;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_put_get_roundtrip() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut store = BlockStore::open(dir.path()).unwrap();
+        let data = b"block store contents ".repeat(40);
+        let hash = store.put(&data).unwrap();
+        assert_eq!(store.get(&hash).unwrap(), data);
+    }
+
+    #[test]
+    fn test_put_dedups() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut store = BlockStore::open(dir.path()).unwrap();
+        let a = store.put(b"same bytes").unwrap();
+        let b = store.put(b"same bytes").unwrap();
+        assert_eq!(a, b);
+        assert_eq!(store.len(), 1);
+    }
+
+    #[test]
+    fn test_reopen_after_flush() {
+        let dir = tempfile::tempdir().unwrap();
+        let hash = {
+            let mut store = BlockStore::open(dir.path()).unwrap();
+            let hash = store.put(b"persisted block").unwrap();
+            store.flush().unwrap();
+            hash
+        };
+        let store = BlockStore::open(dir.path()).unwrap();
+        assert_eq!(store.get(&hash).unwrap(), b"persisted block");
+    }
+
+    #[test]
+    fn test_gc_and_compact_reclaim_space() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut store = BlockStore::open(dir.path()).unwrap();
+        let keep = store.put(&b"keep me ".repeat(100)).unwrap();
+        let drop = store.put(&b"drop me!".repeat(100)).unwrap();
+        store.release(&drop);
+
+        let gc = store.gc().unwrap();
+        assert_eq!(gc.blocks_removed, 1);
+        assert!(!store.contains(&drop));
+
+        let orphans = store.orphans().unwrap();
+        assert_eq!(orphans.len(), 1);
+        assert_eq!(orphans[0].hash, drop);
+
+        let compaction = store.compact(0.1).unwrap();
+        assert_eq!(compaction.packs_rewritten, 1);
+        assert_eq!(compaction.bytes_reclaimed, orphans[0].length);
+        assert!(store.orphans().unwrap().is_empty());
+        assert_eq!(store.get(&keep).unwrap(), b"keep me ".repeat(100));
+    }
+
+    #[test]
+    fn test_refcounts() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut store = BlockStore::open(dir.path()).unwrap();
+        let hash = store.put(b"shared").unwrap();
+        store.put(b"shared").unwrap();
+        assert_eq!(store.refcount(&hash), Some(2));
+        store.release(&hash);
+        assert_eq!(store.gc().unwrap().blocks_removed, 0);
+        store.release(&hash);
+        assert_eq!(store.gc().unwrap().blocks_removed, 1);
+    }
+
+    #[test]
+    fn test_memory_backend() {
+        let mut store = BlockStore::with_storage(crate::storage::MemoryStorage::new()).unwrap();
+        let hash = store.put(b"in memory").unwrap();
+        assert_eq!(store.get(&hash).unwrap(), b"in memory");
+        store.release(&hash);
+        store.gc().unwrap();
+        assert!(store.storage().list().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_hash_hex_roundtrip() {
+        let hash = BlockHash::of(b"hex");
+        assert_eq!(BlockHash::from_hex(&hash.to_hex()), Some(hash));
+        assert_eq!(BlockHash::from_hex("zz"), None);
+    }
+}
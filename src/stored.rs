@@ -0,0 +1,83 @@
+//! Stored passthrough: the payload is the input itself
+//!
+//! Chosen when the incompressibility probe predicts no gain (encrypted or
+//! already-compressed data), so such input costs one copy instead of a full
+//! compression pass that would only make it bigger.
+
+use crate::error::CompressError;
+
+/// Copy `data` unchanged
+pub fn compress(data: &[u8]) -> Result<Vec<u8>, CompressError> {
+    Ok(data.to_vec())
+}
+
+/// Copy stored data back out
+pub fn decompress(data: &[u8], original_size: usize) -> Result<Vec<u8>, CompressError> {
+    if data.len() != original_size {
+        return Err(CompressError::SizeMismatch {
+            expected: original_size,
+            actual: data.len(),
+        });
+    }
+    Ok(data.to_vec())
+}
+
+/// Incremental encoder; simply accumulates input
+#[derive(Debug, Default)]
+pub struct StreamEncoder {
+    output: Vec<u8>,
+}
+
+impl StreamEncoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, data: &[u8]) {
+        self.output.extend_from_slice(data);
+    }
+
+    pub fn finish(self) -> Vec<u8> {
+        self.output
+    }
+}
+
+/// Yields stored data in blocks of at most `block_len` bytes
+pub struct BlockDecoder<'a> {
+    chunks: std::slice::Chunks<'a, u8>,
+}
+
+impl<'a> BlockDecoder<'a> {
+    pub fn new(data: &'a [u8], block_len: usize) -> Self {
+        Self {
+            chunks: data.chunks(block_len.max(1)),
+        }
+    }
+}
+
+impl Iterator for BlockDecoder<'_> {
+    type Item = Result<Vec<u8>, CompressError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.chunks.next().map(|chunk| Ok(chunk.to_vec()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stored_roundtrip() {
+        let data = b"stored as is".to_vec();
+        assert_eq!(decompress(&compress(&data).unwrap(), data.len()).unwrap(), data);
+        assert!(decompress(&data, data.len() + 1).is_err());
+    }
+
+    #[test]
+    fn test_block_decoder_chunks() {
+        let data = vec![9u8; 2500];
+        let blocks: Vec<Vec<u8>> = BlockDecoder::new(&data, 1000).collect::<Result<_, _>>().unwrap();
+        assert_eq!(blocks.iter().map(Vec::len).collect::<Vec<_>>(), vec![1000, 1000, 500]);
+    }
+}
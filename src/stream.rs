@@ -0,0 +1,563 @@
+//! Streaming compression into back-to-back frames
+//!
+//! [`FrameWriter`] buffers input into segments and writes each one as an
+//! independent frame as soon as it is full. The concatenated frames decode
+//! with [`Compressor::decompress_frame`] or [`Compressor::decompress_stream`].
+//!
+//! Because frames share no codec state, everything needed to continue an
+//! interrupted job fits in a [`StreamCheckpoint`]: how much input was
+//! accepted, how much output was written, and the partial segment not yet
+//! framed. Resuming truncates the output back to the checkpoint and carries
+//! on from that input position, so completed frames are never redone.
+//!
+//! Both can pace their output to a byte rate (`with_max_bytes_per_sec`), e.g.
+//! when writing straight into a replication link that must not be saturated.
+//!
+//! With the `tokio` feature, [`compress_stream`] does the same over an async
+//! `Stream` of input buffers, yielding one frame per item.
+//!
+//! Checkpoint layout (integers little-endian):
+//! ```text
+//! [magic:"SGMK"][version:u8][method:u8][segment_size:u64]
+//! [input_position:u64][output_position:u64][frames:u64][pending_len:varint][pending]
+//! ```
+
+use crate::atomic::write_atomic;
+use crate::error::CompressError;
+use crate::{varint, CompressionMethod, Compressor};
+use std::fs::{File, OpenOptions};
+use std::io::{self, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+/// Input bytes per frame unless overridden with [`FrameWriter::with_segment_size`]
+pub const DEFAULT_SEGMENT_SIZE: usize = 1 << 20;
+
+/// Magic bytes opening a serialized checkpoint
+pub const CHECKPOINT_MAGIC: [u8; 4] = *b"SGMK";
+
+/// Checkpoint format version written by this build
+pub const CHECKPOINT_VERSION: u8 = 1;
+
+const CHECKPOINT_HEADER_LEN: usize = 4 + 1 + 1 + 8 * 4;
+
+/// Spaces out frames so output averages at most `rate` bytes per second.
+///
+/// A frame may go out once everything sent before it would have taken its
+/// full time at `rate`; bursts are therefore limited to a single frame.
+#[derive(Debug)]
+pub(crate) struct Pacer {
+    rate: u64,
+    start: Option<Instant>,
+    sent: u64,
+}
+
+impl Pacer {
+    pub(crate) fn new(rate: u64) -> Self {
+        Self {
+            rate: rate.max(1),
+            start: None,
+            sent: 0,
+        }
+    }
+
+    /// Record a frame of `len` bytes and return how long to wait before sending it
+    pub(crate) fn reserve(&mut self, len: usize) -> Duration {
+        let start = *self.start.get_or_insert_with(Instant::now);
+        let due = start + Duration::from_secs_f64(self.sent as f64 / self.rate as f64);
+        self.sent += len as u64;
+        due.saturating_duration_since(Instant::now())
+    }
+}
+
+/// Everything needed to resume a [`FrameWriter`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StreamCheckpoint {
+    pub method: CompressionMethod,
+    pub segment_size: usize,
+    /// Input bytes accepted so far, including `pending`
+    pub input_position: u64,
+    /// Output bytes written so far; all of them are complete frames
+    pub output_position: u64,
+    /// Frames written so far
+    pub frames: u64,
+    /// Accepted input not yet compressed into a frame
+    pub pending: Vec<u8>,
+}
+
+impl StreamCheckpoint {
+    /// Serialize into the layout described in the module docs
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(CHECKPOINT_HEADER_LEN + 10 + self.pending.len());
+        out.extend_from_slice(&CHECKPOINT_MAGIC);
+        out.push(CHECKPOINT_VERSION);
+        out.push(self.method.id());
+        for value in [
+            self.segment_size as u64,
+            self.input_position,
+            self.output_position,
+            self.frames,
+        ] {
+            out.extend_from_slice(&value.to_le_bytes());
+        }
+        varint::write_u64(&mut out, self.pending.len() as u64);
+        out.extend_from_slice(&self.pending);
+        out
+    }
+
+    /// Parse a checkpoint written by [`StreamCheckpoint::to_bytes`]
+    pub fn from_bytes(data: &[u8]) -> Result<Self, CompressError> {
+        let invalid = |msg: &str| CompressError::StreamError(format!("invalid checkpoint: {}", msg));
+        if data.len() < CHECKPOINT_HEADER_LEN || data[..4] != CHECKPOINT_MAGIC {
+            return Err(invalid("bad magic"));
+        }
+        if data[4] != CHECKPOINT_VERSION {
+            return Err(invalid("unsupported version"));
+        }
+        let method = CompressionMethod::from_id(data[5]).ok_or_else(|| invalid("unknown method"))?;
+        let field = |i: usize| u64::from_le_bytes(data[6 + 8 * i..14 + 8 * i].try_into().unwrap());
+        let mut pos = CHECKPOINT_HEADER_LEN;
+        let pending_len = varint::read_usize(data, &mut pos).ok_or_else(|| invalid("truncated"))?;
+        let pending = data
+            .get(pos..)
+            .filter(|rest| rest.len() == pending_len)
+            .ok_or_else(|| invalid("pending length mismatch"))?;
+        Ok(Self {
+            method,
+            segment_size: usize::try_from(field(0)).map_err(|_| invalid("segment size"))?,
+            input_position: field(1),
+            output_position: field(2),
+            frames: field(3),
+            pending: pending.to_vec(),
+        })
+    }
+
+    /// Write the checkpoint atomically, replacing any previous one
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), CompressError> {
+        write_atomic(path.as_ref(), &self.to_bytes())
+    }
+
+    /// Read a checkpoint written by [`StreamCheckpoint::save`]
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, CompressError> {
+        Self::from_bytes(&std::fs::read(path)?)
+    }
+}
+
+/// Compresses a stream of input into back-to-back frames
+pub struct FrameWriter<W: Write> {
+    sink: W,
+    compressor: Compressor,
+    method: CompressionMethod,
+    segment_size: usize,
+    pending: Vec<u8>,
+    input_position: u64,
+    output_position: u64,
+    frames: u64,
+    pacer: Option<Pacer>,
+}
+
+impl<W: Write> FrameWriter<W> {
+    /// Write frames of `method` to `sink`
+    pub fn new(sink: W, compressor: Compressor, method: CompressionMethod) -> Self {
+        Self {
+            sink,
+            compressor,
+            method,
+            segment_size: DEFAULT_SEGMENT_SIZE,
+            pending: Vec::new(),
+            input_position: 0,
+            output_position: 0,
+            frames: 0,
+            pacer: None,
+        }
+    }
+
+    /// Input bytes per frame; larger segments compress better, smaller ones
+    /// make checkpoints cheaper
+    pub fn with_segment_size(mut self, segment_size: usize) -> Self {
+        self.segment_size = segment_size.max(1);
+        self
+    }
+
+    /// Block before each frame as needed to keep output under `rate` bytes per second
+    pub fn with_max_bytes_per_sec(mut self, rate: u64) -> Self {
+        self.pacer = Some(Pacer::new(rate));
+        self
+    }
+
+    /// Continue from `checkpoint`. `sink` must hold exactly the first
+    /// `checkpoint.output_position` bytes written before it was taken (see
+    /// [`FrameWriter::resume_file`]); feed input from `checkpoint.input_position` on.
+    pub fn resume(sink: W, compressor: Compressor, checkpoint: StreamCheckpoint) -> Self {
+        Self {
+            sink,
+            compressor,
+            method: checkpoint.method,
+            segment_size: checkpoint.segment_size.max(1),
+            pending: checkpoint.pending,
+            input_position: checkpoint.input_position,
+            output_position: checkpoint.output_position,
+            frames: checkpoint.frames,
+            pacer: None,
+        }
+    }
+
+    /// Accept more input, writing a frame for every full segment
+    pub fn push(&mut self, mut data: &[u8]) -> Result<(), CompressError> {
+        while !data.is_empty() {
+            let take = (self.segment_size - self.pending.len()).min(data.len());
+            self.pending.extend_from_slice(&data[..take]);
+            self.input_position += take as u64;
+            data = &data[take..];
+            if self.pending.len() == self.segment_size {
+                self.emit()?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Resumable state after the input accepted so far
+    pub fn checkpoint(&mut self) -> Result<StreamCheckpoint, CompressError> {
+        self.sink.flush()?;
+        Ok(StreamCheckpoint {
+            method: self.method,
+            segment_size: self.segment_size,
+            input_position: self.input_position,
+            output_position: self.output_position,
+            frames: self.frames,
+            pending: self.pending.clone(),
+        })
+    }
+
+    /// Input bytes accepted so far
+    pub fn input_position(&self) -> u64 {
+        self.input_position
+    }
+
+    /// Frames written so far
+    pub fn frames(&self) -> u64 {
+        self.frames
+    }
+
+    /// Frame the remaining input and return the sink
+    pub fn finish(mut self) -> Result<W, CompressError> {
+        if !self.pending.is_empty() {
+            self.emit()?;
+        }
+        self.sink.flush()?;
+        Ok(self.sink)
+    }
+
+    fn emit(&mut self) -> Result<(), CompressError> {
+        let frame = self.compressor.compress(&self.pending, self.method)?.to_frame();
+        if let Some(pacer) = &mut self.pacer {
+            std::thread::sleep(pacer.reserve(frame.len()));
+        }
+        self.sink.write_all(&frame)?;
+        self.output_position += frame.len() as u64;
+        self.frames += 1;
+        self.pending.clear();
+        Ok(())
+    }
+}
+
+impl FrameWriter<File> {
+    /// Reopen the output file of an interrupted job, dropping anything
+    /// written after `checkpoint`
+    pub fn resume_file(
+        path: impl AsRef<Path>,
+        compressor: Compressor,
+        checkpoint: StreamCheckpoint,
+    ) -> Result<Self, CompressError> {
+        let mut file = OpenOptions::new().write(true).open(path)?;
+        if file.metadata()?.len() < checkpoint.output_position {
+            return Err(CompressError::StreamError("output is shorter than the checkpoint".into()));
+        }
+        file.set_len(checkpoint.output_position)?;
+        file.seek(SeekFrom::End(0))?;
+        Ok(Self::resume(file, compressor, checkpoint))
+    }
+}
+
+impl<W: Write> Write for FrameWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.push(buf).map_err(io::Error::other)?;
+        Ok(buf.len())
+    }
+
+    /// Flushes the sink; a partial segment stays buffered until it fills or
+    /// [`FrameWriter::finish`] is called
+    fn flush(&mut self) -> io::Result<()> {
+        self.sink.flush()
+    }
+}
+
+#[cfg(feature = "tokio")]
+pub use self::async_stream::{compress_stream, Frame, FrameStream};
+
+#[cfg(feature = "tokio")]
+mod async_stream {
+    use super::{Pacer, DEFAULT_SEGMENT_SIZE};
+    use crate::error::CompressError;
+    use crate::{CompressionMethod, Compressor};
+    use bytes::Bytes;
+    use futures_core::Stream;
+    use std::future::Future;
+    use std::pin::Pin;
+    use std::task::{Context, Poll};
+
+    /// One complete serialized frame
+    pub type Frame = Bytes;
+
+    /// Compress an async stream of buffers into a stream of frames.
+    ///
+    /// Input is only pulled while the current segment is incomplete, so at
+    /// most one segment plus one input item is buffered and a slow consumer
+    /// slows the producer down. Each segment is compressed on the polling
+    /// task; keep segments small when that must not stall other work.
+    pub fn compress_stream<S>(input: S, compressor: Compressor, method: CompressionMethod) -> FrameStream<S>
+    where
+        S: Stream<Item = Bytes>,
+    {
+        FrameStream {
+            input: Box::pin(input),
+            compressor,
+            method,
+            segment_size: DEFAULT_SEGMENT_SIZE,
+            pending: Vec::new(),
+            input_done: false,
+            pacer: None,
+            delayed: None,
+        }
+    }
+
+    /// Stream returned by [`compress_stream`]
+    pub struct FrameStream<S> {
+        input: Pin<Box<S>>,
+        compressor: Compressor,
+        method: CompressionMethod,
+        segment_size: usize,
+        pending: Vec<u8>,
+        input_done: bool,
+        pacer: Option<Pacer>,
+        /// Frame held back by the pacer and the timer releasing it
+        delayed: Option<(Frame, Pin<Box<tokio::time::Sleep>>)>,
+    }
+
+    impl<S> FrameStream<S> {
+        /// Input bytes per frame
+        pub fn with_segment_size(mut self, segment_size: usize) -> Self {
+            self.segment_size = segment_size.max(1);
+            self
+        }
+
+        /// Hold each frame back as needed to keep output under `rate` bytes per second
+        pub fn with_max_bytes_per_sec(mut self, rate: u64) -> Self {
+            self.pacer = Some(Pacer::new(rate));
+            self
+        }
+
+        /// Input bytes pulled but not yet framed
+        pub fn buffered(&self) -> usize {
+            self.pending.len()
+        }
+
+        /// Compress the first `len` pending bytes; `None` if the pacer delays the frame
+        fn emit(&mut self, len: usize) -> Option<Result<Frame, CompressError>> {
+            let rest = self.pending.split_off(len);
+            let segment = std::mem::replace(&mut self.pending, rest);
+            let frame = match self.compressor.compress(&segment, self.method) {
+                Ok(output) => Bytes::from(output.to_frame()),
+                Err(e) => return Some(Err(e)),
+            };
+            match &mut self.pacer {
+                Some(pacer) => {
+                    let wait = pacer.reserve(frame.len());
+                    if wait.is_zero() {
+                        return Some(Ok(frame));
+                    }
+                    self.delayed = Some((frame, Box::pin(tokio::time::sleep(wait))));
+                    None
+                }
+                None => Some(Ok(frame)),
+            }
+        }
+    }
+
+    impl<S: Stream<Item = Bytes>> Stream for FrameStream<S> {
+        type Item = Result<Frame, CompressError>;
+
+        fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+            let this = self.get_mut();
+            loop {
+                if let Some((_, sleep)) = &mut this.delayed {
+                    if sleep.as_mut().poll(cx).is_pending() {
+                        return Poll::Pending;
+                    }
+                    let (frame, _) = this.delayed.take().unwrap();
+                    return Poll::Ready(Some(Ok(frame)));
+                }
+                if this.pending.len() >= this.segment_size || (this.input_done && !this.pending.is_empty()) {
+                    let len = this.pending.len().min(this.segment_size);
+                    match this.emit(len) {
+                        Some(item) => return Poll::Ready(Some(item)),
+                        None => continue,
+                    }
+                }
+                if this.input_done {
+                    return Poll::Ready(None);
+                }
+                match this.input.as_mut().poll_next(cx) {
+                    Poll::Ready(Some(chunk)) => this.pending.extend_from_slice(&chunk),
+                    Poll::Ready(None) => this.input_done = true,
+                    Poll::Pending => return Poll::Pending,
+                }
+            }
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use std::future::poll_fn;
+
+        /// Yields `chunks` in order, counting how many were pulled
+        struct Source {
+            chunks: std::vec::IntoIter<Bytes>,
+            pulled: std::sync::Arc<std::sync::atomic::AtomicUsize>,
+        }
+
+        impl Stream for Source {
+            type Item = Bytes;
+
+            fn poll_next(mut self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Option<Bytes>> {
+                let next = self.chunks.next();
+                if next.is_some() {
+                    self.pulled.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+                }
+                Poll::Ready(next)
+            }
+        }
+
+        #[tokio::test]
+        async fn test_stream_frames_with_bounded_buffering() {
+            let data: Vec<u8> = (0..40_000u32).map(|i| (i % 251) as u8).collect();
+            let pulled = std::sync::Arc::new(std::sync::atomic::AtomicUsize::new(0));
+            let source = Source {
+                chunks: data.chunks(1000).map(Bytes::copy_from_slice).collect::<Vec<_>>().into_iter(),
+                pulled: pulled.clone(),
+            };
+            let mut frames = compress_stream(source, Compressor::default(), CompressionMethod::Auto)
+                .with_segment_size(8000);
+
+            let first = poll_fn(|cx| Pin::new(&mut frames).poll_next(cx)).await.unwrap().unwrap();
+            assert_eq!(pulled.load(std::sync::atomic::Ordering::SeqCst), 8, "pulls stop at one segment");
+            assert_eq!(frames.buffered(), 0);
+
+            let mut out = first.to_vec();
+            while let Some(frame) = poll_fn(|cx| Pin::new(&mut frames).poll_next(cx)).await {
+                out.extend_from_slice(&frame.unwrap());
+            }
+            assert_eq!(Compressor::default().decompress_frame(&out).unwrap(), data);
+        }
+
+        #[tokio::test]
+        async fn test_stream_paced() {
+            let data = vec![3u8; 30_000];
+            let source = Source {
+                chunks: vec![Bytes::from(data.clone())].into_iter(),
+                pulled: Default::default(),
+            };
+            let mut frames = compress_stream(source, Compressor::default(), CompressionMethod::Stored)
+                .with_segment_size(10_000)
+                .with_max_bytes_per_sec(200_000);
+            let started = std::time::Instant::now();
+            let mut out = Vec::new();
+            while let Some(frame) = poll_fn(|cx| Pin::new(&mut frames).poll_next(cx)).await {
+                out.extend_from_slice(&frame.unwrap());
+            }
+            // The third frame waits for the first two to drain at the limit
+            assert!(started.elapsed() >= std::time::Duration::from_millis(95));
+            assert_eq!(Compressor::default().decompress_frame(&out).unwrap(), data);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn input() -> Vec<u8> {
+        (0..5000u32).flat_map(|i| format!("record {} value {}\n", i, i % 17).into_bytes()).collect()
+    }
+
+    #[test]
+    fn test_frames_decode_as_one_stream() {
+        let data = input();
+        let mut writer =
+            FrameWriter::new(Vec::new(), Compressor::default(), CompressionMethod::Auto).with_segment_size(10_000);
+        for piece in data.chunks(3333) {
+            writer.push(piece).unwrap();
+        }
+        assert_eq!(writer.frames() as usize, data.len() / 10_000);
+        let out = writer.finish().unwrap();
+        assert_eq!(Compressor::default().decompress_frame(&out).unwrap(), data);
+    }
+
+    #[test]
+    fn test_resume_after_interruption() {
+        let dir = tempfile::tempdir().unwrap();
+        let out_path = dir.path().join("out.sgma");
+        let ckpt_path = dir.path().join("job.ckpt");
+        let data = input();
+
+        let file = File::create(&out_path).unwrap();
+        let mut writer =
+            FrameWriter::new(file, Compressor::default(), CompressionMethod::Huffman).with_segment_size(8192);
+        writer.push(&data[..30_000]).unwrap();
+        writer.checkpoint().unwrap().save(&ckpt_path).unwrap();
+        // Work done after the checkpoint is lost when the job is preempted
+        writer.push(&data[30_000..50_000]).unwrap();
+        drop(writer);
+
+        let checkpoint = StreamCheckpoint::load(&ckpt_path).unwrap();
+        assert_eq!(checkpoint.input_position, 30_000);
+        assert_eq!(checkpoint.pending.len(), 30_000 % 8192);
+        let start = checkpoint.input_position as usize;
+        let mut writer = FrameWriter::resume_file(&out_path, Compressor::default(), checkpoint).unwrap();
+        writer.push(&data[start..]).unwrap();
+        writer.finish().unwrap();
+
+        let out = std::fs::read(&out_path).unwrap();
+        assert_eq!(Compressor::default().decompress_frame(&out).unwrap(), data);
+    }
+
+    #[test]
+    fn test_writer_paced_to_rate() {
+        let data = vec![1u8; 40_000];
+        let mut writer = FrameWriter::new(Vec::new(), Compressor::default(), CompressionMethod::Stored)
+            .with_segment_size(10_000)
+            .with_max_bytes_per_sec(200_000);
+        let started = Instant::now();
+        writer.push(&data).unwrap();
+        let out = writer.finish().unwrap();
+        // Four ~10 KB frames at 200 KB/s: the last may start after ~150 ms
+        assert!(started.elapsed() >= Duration::from_millis(140));
+        assert_eq!(Compressor::default().decompress_frame(&out).unwrap(), data);
+    }
+
+    #[test]
+    fn test_checkpoint_bytes_roundtrip() {
+        let checkpoint = StreamCheckpoint {
+            method: CompressionMethod::Lzss,
+            segment_size: 4096,
+            input_position: 10,
+            output_position: 0,
+            frames: 0,
+            pending: b"0123456789".to_vec(),
+        };
+        let raw = checkpoint.to_bytes();
+        assert_eq!(StreamCheckpoint::from_bytes(&raw).unwrap(), checkpoint);
+        assert!(StreamCheckpoint::from_bytes(&raw[..raw.len() - 1]).is_err());
+    }
+}
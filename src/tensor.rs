@@ -0,0 +1,222 @@
+//! Tensor blob profile for KV caches and checkpoints
+//!
+//! Contiguous little-endian f16/bf16/f32 elements are split into byte planes
+//! (all first bytes, then all second bytes, ...). Exponent/sign planes of
+//! model tensors are highly skewed while mantissa planes are close to noise,
+//! so each plane is coded separately with whichever of Huffman, RLE or raw
+//! storage is smallest.
+//!
+//! Layout:
+//! ```text
+//! [dtype:u8][rank:varint][dims:varint...]
+//! per plane: [codec:u8][len:varint][payload]
+//! ```
+
+use crate::error::CompressError;
+use crate::{entropy, huffman, varint};
+
+/// Element type of a tensor blob
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum DType {
+    F16,
+    BF16,
+    F32,
+}
+
+impl DType {
+    /// Bytes per element
+    pub fn size(self) -> usize {
+        match self {
+            DType::F16 | DType::BF16 => 2,
+            DType::F32 => 4,
+        }
+    }
+
+    fn id(self) -> u8 {
+        match self {
+            DType::F16 => 1,
+            DType::BF16 => 2,
+            DType::F32 => 3,
+        }
+    }
+
+    fn from_id(id: u8) -> Option<Self> {
+        match id {
+            1 => Some(DType::F16),
+            2 => Some(DType::BF16),
+            3 => Some(DType::F32),
+            _ => None,
+        }
+    }
+}
+
+/// Element type and shape supplied by the caller
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct TensorLayout {
+    pub dtype: DType,
+    pub shape: Vec<usize>,
+}
+
+impl TensorLayout {
+    pub fn new(dtype: DType, shape: &[usize]) -> Self {
+        Self {
+            dtype,
+            shape: shape.to_vec(),
+        }
+    }
+
+    /// Size of the blob this layout describes, `None` on overflow
+    pub fn byte_len(&self) -> Option<usize> {
+        self.shape
+            .iter()
+            .try_fold(self.dtype.size(), |acc, &d| acc.checked_mul(d))
+    }
+}
+
+const PLANE_STORED: u8 = 0;
+const PLANE_HUFFMAN: u8 = 1;
+const PLANE_RLE: u8 = 2;
+
+/// Compress a tensor blob laid out as `layout`
+pub fn compress(data: &[u8], layout: &TensorLayout) -> Result<Vec<u8>, CompressError> {
+    if layout.byte_len() != Some(data.len()) {
+        return Err(CompressError::TensorError(format!(
+            "{} bytes do not match shape {:?} of {:?}",
+            data.len(),
+            layout.shape,
+            layout.dtype
+        )));
+    }
+    let mut out = vec![layout.dtype.id()];
+    varint::write_u64(&mut out, layout.shape.len() as u64);
+    for &d in &layout.shape {
+        varint::write_u64(&mut out, d as u64);
+    }
+
+    let width = layout.dtype.size();
+    for plane_idx in 0..width {
+        let plane: Vec<u8> = data.iter().skip(plane_idx).step_by(width).copied().collect();
+        let (codec, payload) = encode_plane(&plane)?;
+        out.push(codec);
+        varint::write_u64(&mut out, payload.len() as u64);
+        out.extend_from_slice(&payload);
+    }
+    Ok(out)
+}
+
+/// Decompress a blob, returning the data and the layout it was compressed with
+pub fn decompress(data: &[u8]) -> Result<(Vec<u8>, TensorLayout), CompressError> {
+    let err = |msg: &str| CompressError::TensorError(msg.into());
+    let dtype = data
+        .first()
+        .and_then(|&id| DType::from_id(id))
+        .ok_or_else(|| err("unknown dtype"))?;
+    let mut pos = 1;
+    let rank = varint::read_usize(data, &mut pos).ok_or_else(|| err("truncated shape"))?;
+    let mut shape = Vec::with_capacity(rank.min(data.len()));
+    for _ in 0..rank {
+        shape.push(varint::read_usize(data, &mut pos).ok_or_else(|| err("truncated shape"))?);
+    }
+    let layout = TensorLayout { dtype, shape };
+    let total = layout.byte_len().ok_or_else(|| err("shape overflows"))?;
+    let width = dtype.size();
+    let elements = total / width;
+
+    let mut planes = Vec::with_capacity(width);
+    for _ in 0..width {
+        let codec = *data.get(pos).ok_or_else(|| err("truncated plane"))?;
+        pos += 1;
+        let len = varint::read_usize(data, &mut pos).ok_or_else(|| err("truncated plane"))?;
+        let payload = data
+            .get(pos..pos.saturating_add(len))
+            .ok_or_else(|| err("truncated plane"))?;
+        pos += len;
+        let plane = decode_plane(codec, payload, elements)?;
+        if plane.len() != elements {
+            return Err(CompressError::SizeMismatch {
+                expected: elements,
+                actual: plane.len(),
+            });
+        }
+        planes.push(plane);
+    }
+    if pos != data.len() {
+        return Err(err("trailing bytes"));
+    }
+
+    // Every plane decoded to `elements` bytes, so `total` is backed by real data
+    let mut out = vec![0u8; total];
+    for (plane_idx, plane) in planes.iter().enumerate() {
+        for (i, &b) in plane.iter().enumerate() {
+            out[i * width + plane_idx] = b;
+        }
+    }
+    Ok((out, layout))
+}
+
+fn encode_plane(plane: &[u8]) -> Result<(u8, Vec<u8>), CompressError> {
+    let mut best = (PLANE_STORED, plane.to_vec());
+    if plane.is_empty() {
+        return Ok(best);
+    }
+    for (codec, payload) in [
+        (PLANE_HUFFMAN, huffman::compress(plane)?),
+        (PLANE_RLE, entropy::compress(plane)?),
+    ] {
+        if payload.len() < best.1.len() {
+            best = (codec, payload);
+        }
+    }
+    Ok(best)
+}
+
+fn decode_plane(codec: u8, payload: &[u8], elements: usize) -> Result<Vec<u8>, CompressError> {
+    match codec {
+        PLANE_STORED => Ok(payload.to_vec()),
+        PLANE_HUFFMAN => huffman::decompress(payload, elements),
+        PLANE_RLE => entropy::decompress(payload, elements),
+        _ => Err(CompressError::TensorError(format!("unknown plane codec {}", codec))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// bf16 values of a smooth activation-like signal
+    fn bf16_blob(n: usize) -> Vec<u8> {
+        (0..n)
+            .flat_map(|i| {
+                let v = ((i as f32) * 0.01).sin() * 0.5;
+                ((v.to_bits() >> 16) as u16).to_le_bytes()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_tensor_roundtrip_and_gain() {
+        let data = bf16_blob(4096);
+        let layout = TensorLayout::new(DType::BF16, &[8, 512]);
+        let compressed = compress(&data, &layout).unwrap();
+        let (restored, restored_layout) = decompress(&compressed).unwrap();
+        assert_eq!(restored, data);
+        assert_eq!(restored_layout, layout);
+        assert!(compressed.len() < crate::huffman::compress(&data).unwrap().len());
+    }
+
+    #[test]
+    fn test_tensor_f32_roundtrip() {
+        let data: Vec<u8> = (0..300).flat_map(|i| (i as f32 * 1.5).to_le_bytes()).collect();
+        let layout = TensorLayout::new(DType::F32, &[3, 100]);
+        let (restored, _) = decompress(&compress(&data, &layout).unwrap()).unwrap();
+        assert_eq!(restored, data);
+    }
+
+    #[test]
+    fn test_tensor_rejects_shape_mismatch_and_truncation() {
+        let data = bf16_blob(10);
+        assert!(compress(&data, &TensorLayout::new(DType::F16, &[11])).is_err());
+        let compressed = compress(&data, &TensorLayout::new(DType::F16, &[10])).unwrap();
+        assert!(decompress(&compressed[..compressed.len() - 1]).is_err());
+    }
+}
@@ -0,0 +1,312 @@
+//! Property-based roundtrip harness (`testing` feature)
+//!
+//! Exposes input generators and assertion helpers so codec plugins and CI can
+//! check roundtrips, truncation and bit-flip corruption the same way for every
+//! method. [`FaultInjector`] applies the same kinds of damage to any frame,
+//! so applications can check how their own error handling copes with it.
+//!
+//! ```ignore
+//! use proptest::prelude::*;
+//! use sigma_compress::testing::{any_input, assert_roundtrip, MethodCodec};
+//!
+//! proptest! {
+//!     #[test]
+//!     fn huffman_roundtrips(data in any_input()) {
+//!         assert_roundtrip(&MethodCodec::new(CompressionMethod::Huffman), &data);
+//!     }
+//! }
+//! ```
+
+use crate::error::CompressError;
+use crate::{CompressedOutput, CompressionMethod, Compressor};
+use proptest::prelude::*;
+
+/// Anything that turns bytes into an encoded form and back
+pub trait RoundtripCodec {
+    fn name(&self) -> String;
+    fn encode(&self, data: &[u8]) -> Result<Vec<u8>, CompressError>;
+    fn decode(&self, encoded: &[u8]) -> Result<Vec<u8>, CompressError>;
+}
+
+/// A built-in method wrapped in a checksummed frame
+pub struct MethodCodec {
+    compressor: Compressor,
+    method: CompressionMethod,
+}
+
+impl MethodCodec {
+    pub fn new(method: CompressionMethod) -> Self {
+        Self::with_compressor(Compressor::default(), method)
+    }
+
+    pub fn with_compressor(compressor: Compressor, method: CompressionMethod) -> Self {
+        Self { compressor, method }
+    }
+}
+
+impl RoundtripCodec for MethodCodec {
+    fn name(&self) -> String {
+        format!("{:?}", self.method)
+    }
+
+    fn encode(&self, data: &[u8]) -> Result<Vec<u8>, CompressError> {
+        Ok(self.compressor.compress(data, self.method)?.to_frame())
+    }
+
+    fn decode(&self, encoded: &[u8]) -> Result<Vec<u8>, CompressError> {
+        let info = crate::frame::inspect(encoded)?;
+        if info.frame_size != encoded.len() as u64 {
+            return Err(CompressError::FrameError("trailing bytes after frame".into()));
+        }
+        let output = CompressedOutput::from_frame(encoded)?;
+        let data = self.compressor.decompress(&output)?;
+        if data.len() != output.original_size {
+            return Err(CompressError::SizeMismatch {
+                expected: output.original_size,
+                actual: data.len(),
+            });
+        }
+        if let Some(expected) = output.checksum {
+            let actual = crc32fast::hash(&data);
+            if actual != expected {
+                return Err(CompressError::ChecksumMismatch { expected, actual });
+            }
+        }
+        Ok(data)
+    }
+}
+
+/// Every concrete (non-`Auto`) method
+pub fn all_methods() -> Vec<CompressionMethod> {
+    CompressionMethod::CONCRETE.to_vec()
+}
+
+/// Any concrete method
+pub fn any_method() -> impl Strategy<Value = CompressionMethod> {
+    prop::sample::select(all_methods())
+}
+
+/// Non-empty inputs covering the shapes codecs care about: random bytes, long
+/// runs, small alphabets and repeated phrases
+pub fn any_input() -> impl Strategy<Value = Vec<u8>> {
+    prop_oneof![
+        prop::collection::vec(any::<u8>(), 1..2048),
+        prop::collection::vec((any::<u8>(), 1usize..600), 1..20)
+            .prop_map(|runs| runs.into_iter().flat_map(|(b, n)| std::iter::repeat_n(b, n)).collect()),
+        prop::collection::vec(prop::sample::select(b"acgt".to_vec()), 1..4096),
+        (prop::collection::vec(any::<u8>(), 1..96), 1usize..64).prop_map(|(phrase, n)| phrase.repeat(n)),
+    ]
+}
+
+/// Assert that `data` survives an encode/decode cycle
+pub fn assert_roundtrip(codec: &impl RoundtripCodec, data: &[u8]) {
+    let encoded = codec
+        .encode(data)
+        .unwrap_or_else(|e| panic!("{}: encode failed: {}", codec.name(), e));
+    let decoded = codec
+        .decode(&encoded)
+        .unwrap_or_else(|e| panic!("{}: decode failed: {}", codec.name(), e));
+    assert!(decoded == data, "{}: roundtrip produced different data", codec.name());
+}
+
+/// Assert that every proper prefix of the encoding is rejected
+pub fn assert_truncation_detected(codec: &impl RoundtripCodec, data: &[u8]) {
+    let encoded = codec.encode(data).expect("encode failed");
+    for len in 0..encoded.len() {
+        if let Ok(decoded) = codec.decode(&encoded[..len]) {
+            assert!(
+                decoded != data,
+                "{}: truncation to {} of {} bytes went unnoticed",
+                codec.name(),
+                len,
+                encoded.len()
+            );
+        }
+    }
+}
+
+/// Assert that flipping bit `bit` (modulo the encoded length) never yields
+/// wrong data silently
+pub fn assert_bitflip_detected(codec: &impl RoundtripCodec, data: &[u8], bit: usize) {
+    let mut encoded = codec.encode(data).expect("encode failed");
+    let bit = bit % (encoded.len() * 8);
+    encoded[bit / 8] ^= 1 << (bit % 8);
+    if let Ok(decoded) = codec.decode(&encoded) {
+        assert!(
+            decoded == data,
+            "{}: flipping bit {} produced corrupt output without an error",
+            codec.name(),
+            bit
+        );
+    }
+}
+
+/// Where [`Fault::Truncate`] cuts a frame
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TruncationPoint {
+    /// Somewhere inside the header
+    Header,
+    /// Right after the header, before any payload
+    AfterHeader,
+    /// Somewhere inside the payload
+    Payload,
+    /// Only the last byte missing
+    LastByte,
+}
+
+/// Damage a [`FaultInjector`] can apply
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fault {
+    /// Flip this many distinct bits
+    FlipBits(usize),
+    /// Replace this many distinct bytes with different values
+    CorruptBytes(usize),
+    /// Keep only the first this many bytes
+    TruncateAt(usize),
+    /// Cut at a point chosen relative to the frame layout
+    Truncate(TruncationPoint),
+}
+
+/// Seeded source of realistic frame corruption; the same seed damages the
+/// same frame the same way
+#[derive(Debug, Clone)]
+pub struct FaultInjector {
+    state: u64,
+}
+
+impl FaultInjector {
+    pub fn new(seed: u64) -> Self {
+        Self { state: seed }
+    }
+
+    /// Copy of `frame` with `fault` applied. Counts are capped at what the
+    /// frame holds; headers that do not parse are taken to be the first
+    /// 23 bytes.
+    pub fn inject(&mut self, frame: &[u8], fault: Fault) -> Vec<u8> {
+        let mut damaged = frame.to_vec();
+        match fault {
+            Fault::FlipBits(n) => {
+                for bit in self.distinct(frame.len() * 8, n) {
+                    damaged[bit / 8] ^= 1 << (bit % 8);
+                }
+            }
+            Fault::CorruptBytes(n) => {
+                for at in self.distinct(frame.len(), n) {
+                    damaged[at] ^= 1 + self.below(255) as u8;
+                }
+            }
+            Fault::TruncateAt(len) => damaged.truncate(len),
+            Fault::Truncate(point) => {
+                let header = crate::frame::FrameHeader::parse(frame).map_or(23, |h| h.encoded_len()).min(frame.len());
+                let len = match point {
+                    TruncationPoint::Header => self.below(header.max(1)),
+                    TruncationPoint::AfterHeader => header,
+                    TruncationPoint::Payload => header + self.below((frame.len() - header).max(1)),
+                    TruncationPoint::LastByte => frame.len().saturating_sub(1),
+                };
+                damaged.truncate(len);
+            }
+        }
+        damaged
+    }
+
+    /// `n` distinct positions below `len`, capped at `len`
+    fn distinct(&mut self, len: usize, n: usize) -> Vec<usize> {
+        let mut positions: Vec<usize> = Vec::with_capacity(n.min(len));
+        while positions.len() < n.min(len) {
+            let candidate = self.below(len);
+            if !positions.contains(&candidate) {
+                positions.push(candidate);
+            }
+        }
+        positions
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        (self.next_u64() % n as u64) as usize
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        crate::splitmix64(&mut self.state)
+    }
+}
+
+/// Assert that `fault` applied to the encoding of `data` never yields wrong
+/// data silently
+pub fn assert_fault_detected(codec: &impl RoundtripCodec, data: &[u8], injector: &mut FaultInjector, fault: Fault) {
+    let encoded = codec.encode(data).expect("encode failed");
+    if let Ok(decoded) = codec.decode(&injector.inject(&encoded, fault)) {
+        assert!(
+            decoded == data,
+            "{}: {:?} produced corrupt output without an error",
+            codec.name(),
+            fault
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(64))]
+
+        #[test]
+        fn prop_all_methods_roundtrip(data in any_input(), method in any_method()) {
+            assert_roundtrip(&MethodCodec::new(method), &data);
+        }
+
+        #[test]
+        fn prop_bitflips_detected(data in any_input(), method in any_method(), bit in any::<usize>()) {
+            assert_bitflip_detected(&MethodCodec::new(method), &data, bit);
+        }
+    }
+
+    #[test]
+    fn test_truncation_detected() {
+        for method in all_methods() {
+            assert_truncation_detected(&MethodCodec::new(method), b"truncate this input, please");
+        }
+    }
+
+    #[test]
+    fn test_fault_injector_damage() {
+        let frame = MethodCodec::new(CompressionMethod::Lzss).encode(&b"inject faults here ".repeat(40)).unwrap();
+        let differing_bits = |damaged: &[u8]| -> u32 { frame.iter().zip(damaged).map(|(a, b)| (a ^ b).count_ones()).sum() };
+        let mut injector = FaultInjector::new(5);
+        assert_eq!(differing_bits(&injector.inject(&frame, Fault::FlipBits(7))), 7);
+        let corrupted = injector.inject(&frame, Fault::CorruptBytes(3));
+        assert_eq!(frame.iter().zip(&corrupted).filter(|(a, b)| a != b).count(), 3);
+        assert_eq!(FaultInjector::new(9).inject(&frame, Fault::FlipBits(2)), FaultInjector::new(9).inject(&frame, Fault::FlipBits(2)));
+
+        let header = crate::frame::FrameHeader::parse(&frame).unwrap().encoded_len();
+        assert!(injector.inject(&frame, Fault::Truncate(TruncationPoint::Header)).len() < header);
+        assert_eq!(injector.inject(&frame, Fault::Truncate(TruncationPoint::AfterHeader)).len(), header);
+        let cut = injector.inject(&frame, Fault::Truncate(TruncationPoint::Payload)).len();
+        assert!((header..frame.len()).contains(&cut));
+        assert_eq!(injector.inject(&frame, Fault::Truncate(TruncationPoint::LastByte)).len(), frame.len() - 1);
+        assert_eq!(injector.inject(&frame, Fault::FlipBits(usize::MAX)).len(), frame.len());
+    }
+
+    #[test]
+    fn test_faults_detected_for_every_method() {
+        let data = b"every method must notice damaged frames, ".repeat(20);
+        let mut injector = FaultInjector::new(11);
+        let faults = [
+            Fault::FlipBits(1),
+            Fault::FlipBits(16),
+            Fault::CorruptBytes(4),
+            Fault::TruncateAt(0),
+            Fault::Truncate(TruncationPoint::Header),
+            Fault::Truncate(TruncationPoint::AfterHeader),
+            Fault::Truncate(TruncationPoint::Payload),
+            Fault::Truncate(TruncationPoint::LastByte),
+        ];
+        for method in all_methods() {
+            for fault in faults {
+                assert_fault_detected(&MethodCodec::new(method), &data, &mut injector, fault);
+            }
+        }
+    }
+}
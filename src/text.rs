@@ -0,0 +1,69 @@
+//! Text mode: frames that are guaranteed to decode to UTF-8
+//!
+//! [`Compressor::compress_str`] takes a `&str`, so its input is valid UTF-8
+//! by construction, and marks the frame with [`FLAG_UTF8`].
+//! [`Compressor::decompress_to_string`] accepts only frames carrying that
+//! flag and hands back a `String`. The flag and the CRC are not
+//! authentication, so a crafted or corrupted frame could still claim to be
+//! text. The decoded bytes are therefore validated once more before being
+//! returned, and invalid UTF-8 never reaches the caller.
+
+use crate::error::CompressError;
+use crate::frame::{FrameHeader, FLAG_UTF8};
+use crate::{CompressedOutput, CompressionMethod, Compressor};
+
+impl Compressor {
+    /// Compress text; the output's frame is marked as UTF-8
+    pub fn compress_str(&self, text: &str, method: CompressionMethod) -> Result<CompressedOutput, CompressError> {
+        let mut output = self.compress(text.as_bytes(), method)?;
+        output.utf8 = true;
+        Ok(output)
+    }
+
+    /// Decode frames written from [`Compressor::compress_str`] outputs back
+    /// into a `String`. Every back-to-back frame must be marked as UTF-8.
+    pub fn decompress_to_string(&self, frames: &[u8]) -> Result<String, CompressError> {
+        for member in self.frame_members(frames)? {
+            if FrameHeader::parse(member)?.flags & FLAG_UTF8 == 0 {
+                return Err(CompressError::TextError("frame is not marked as UTF-8 text".into()));
+            }
+        }
+        String::from_utf8(self.decompress_frame(frames)?)
+            .map_err(|e| CompressError::TextError(format!("frame marked as text decodes to invalid UTF-8: {}", e)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_text_roundtrip() {
+        let compressor = Compressor::default();
+        let text = "naïve café — 日本語のテキスト 🚀 ".repeat(40);
+        let mut frames = Vec::new();
+        for method in [CompressionMethod::Huffman, CompressionMethod::Lz4Semantic, CompressionMethod::Auto] {
+            let output = compressor.compress_str(&text, method).unwrap();
+            assert!(output.utf8);
+            let frame = output.to_frame();
+            assert!(crate::frame::inspect(&frame).unwrap().is_utf8);
+            assert_eq!(compressor.decompress_to_string(&frame).unwrap(), text);
+            frames.extend_from_slice(&frame);
+        }
+        assert_eq!(compressor.decompress_to_string(&frames).unwrap(), text.repeat(3));
+        let reparsed = CompressedOutput::from_frame(&compressor.compress_str(&text, CompressionMethod::Huffman).unwrap().to_frame()).unwrap();
+        assert!(reparsed.utf8);
+    }
+
+    #[test]
+    fn test_text_mode_rejects_unmarked_and_invalid() {
+        let compressor = Compressor::default();
+        let bytes = compressor.compress(b"plain bytes", CompressionMethod::Stored).unwrap();
+        assert!(matches!(compressor.decompress_to_string(&bytes.to_frame()), Err(CompressError::TextError(_))));
+
+        // A forged flag on non-UTF-8 data is caught on decode
+        let mut forged = compressor.compress(&[0xFF, 0xFE, 0x80], CompressionMethod::Stored).unwrap();
+        forged.utf8 = true;
+        assert!(matches!(compressor.decompress_to_string(&forged.to_frame()), Err(CompressError::TextError(_))));
+    }
+}
@@ -0,0 +1,358 @@
+//! Token-aware compression for tokenizer-shaped text (LLM prompts and responses)
+//!
+//! Text is segmented into token ids by a [`Tokenizer`], and the ids are coded
+//! with a canonical Huffman code built from token frequencies learned ahead
+//! of time. Only the coded ids are stored; the tokenizer and learned counts
+//! are shared out of band, like [`crate::huffman::HuffmanModel`].
+//!
+//! Layout: `[token_count:varint][code bits, LSB-first]`
+
+use crate::error::CompressError;
+use crate::varint;
+use serde::{Deserialize, Serialize};
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+
+/// Splits bytes into token ids and back
+pub trait Tokenizer {
+    /// Number of distinct ids; every id returned by `encode` is below it
+    fn vocab_size(&self) -> usize;
+    fn encode(&self, text: &[u8]) -> Vec<u32>;
+    fn decode(&self, ids: &[u32]) -> Result<Vec<u8>, CompressError>;
+}
+
+/// Byte-level BPE: ids 0..256 are raw bytes, id `256 + i` is `merges[i]`
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(from = "Vec<(u32, u32)>", into = "Vec<(u32, u32)>")]
+pub struct BpeTokenizer {
+    merges: Vec<(u32, u32)>,
+    ranks: HashMap<(u32, u32), u32>,
+}
+
+impl From<Vec<(u32, u32)>> for BpeTokenizer {
+    fn from(merges: Vec<(u32, u32)>) -> Self {
+        Self::from_merges(merges)
+    }
+}
+
+impl From<BpeTokenizer> for Vec<(u32, u32)> {
+    fn from(bpe: BpeTokenizer) -> Self {
+        bpe.merges
+    }
+}
+
+impl BpeTokenizer {
+    /// Tokenizer from an existing merge list, lowest rank first
+    pub fn from_merges(merges: Vec<(u32, u32)>) -> Self {
+        let ranks = merges.iter().enumerate().map(|(i, &pair)| (pair, i as u32)).collect();
+        Self { merges, ranks }
+    }
+
+    /// Learn up to `num_merges` merges from `corpus`, most frequent pair first
+    pub fn train<S: AsRef<[u8]>>(corpus: &[S], num_merges: usize) -> Self {
+        let mut docs: Vec<Vec<u32>> = corpus
+            .iter()
+            .map(|d| d.as_ref().iter().map(|&b| b as u32).collect())
+            .collect();
+        let mut merges = Vec::with_capacity(num_merges);
+        for _ in 0..num_merges {
+            let mut counts: HashMap<(u32, u32), usize> = HashMap::new();
+            for doc in &docs {
+                for w in doc.windows(2) {
+                    *counts.entry((w[0], w[1])).or_default() += 1;
+                }
+            }
+            // Ties broken by pair value so training is deterministic
+            let Some((pair, count)) = counts.into_iter().max_by_key(|&(pair, n)| (n, Reverse(pair))) else {
+                break;
+            };
+            if count < 2 {
+                break;
+            }
+            let id = 256 + merges.len() as u32;
+            merges.push(pair);
+            for doc in &mut docs {
+                *doc = merge_pair(doc, pair, id);
+            }
+        }
+        Self::from_merges(merges)
+    }
+
+    /// Merge list, lowest rank first
+    pub fn merges(&self) -> &[(u32, u32)] {
+        &self.merges
+    }
+
+    fn expand(&self, id: u32, out: &mut Vec<u8>) -> Result<(), CompressError> {
+        if id < 256 {
+            out.push(id as u8);
+            return Ok(());
+        }
+        let &(a, b) = self
+            .merges
+            .get((id - 256) as usize)
+            .ok_or_else(|| CompressError::TokenError(format!("token id {} outside vocabulary", id)))?;
+        self.expand(a, out)?;
+        self.expand(b, out)
+    }
+}
+
+fn merge_pair(ids: &[u32], pair: (u32, u32), id: u32) -> Vec<u32> {
+    let mut out = Vec::with_capacity(ids.len());
+    let mut i = 0;
+    while i < ids.len() {
+        if i + 1 < ids.len() && (ids[i], ids[i + 1]) == pair {
+            out.push(id);
+            i += 2;
+        } else {
+            out.push(ids[i]);
+            i += 1;
+        }
+    }
+    out
+}
+
+impl Tokenizer for BpeTokenizer {
+    fn vocab_size(&self) -> usize {
+        256 + self.merges.len()
+    }
+
+    fn encode(&self, text: &[u8]) -> Vec<u32> {
+        let mut ids: Vec<u32> = text.iter().map(|&b| b as u32).collect();
+        loop {
+            let best = ids
+                .windows(2)
+                .filter_map(|w| self.ranks.get(&(w[0], w[1])).map(|&r| (r, (w[0], w[1]))))
+                .min();
+            let Some((rank, pair)) = best else {
+                return ids;
+            };
+            ids = merge_pair(&ids, pair, 256 + rank);
+        }
+    }
+
+    fn decode(&self, ids: &[u32]) -> Result<Vec<u8>, CompressError> {
+        let mut out = Vec::with_capacity(ids.len() * 2);
+        for &id in ids {
+            self.expand(id, &mut out)?;
+        }
+        Ok(out)
+    }
+}
+
+/// Canonical Huffman code over token ids
+#[derive(Debug, Clone)]
+struct TokenCode {
+    /// `(code, length)` per id
+    codes: Vec<(u64, u8)>,
+    /// Ids ordered by (length, id)
+    sorted: Vec<u32>,
+    /// Number of codes of each length
+    length_counts: Vec<u64>,
+}
+
+impl TokenCode {
+    /// Every count is bumped by one so unseen ids stay codable
+    fn from_counts(counts: &[u64]) -> Self {
+        let n = counts.len();
+        let lengths = if n == 1 { vec![1u8] } else { code_lengths(counts) };
+
+        let mut sorted: Vec<u32> = (0..n as u32).collect();
+        sorted.sort_by_key(|&id| (lengths[id as usize], id));
+        let max_len = lengths.iter().copied().max().unwrap_or(0) as usize;
+        let mut length_counts = vec![0u64; max_len + 1];
+        for &l in &lengths {
+            length_counts[l as usize] += 1;
+        }
+
+        let mut codes = vec![(0u64, 0u8); n];
+        let mut code = 0u64;
+        let mut prev_len = 0u8;
+        for &id in &sorted {
+            let len = lengths[id as usize];
+            code <<= len - prev_len;
+            codes[id as usize] = (code, len);
+            code += 1;
+            prev_len = len;
+        }
+        Self {
+            codes,
+            sorted,
+            length_counts,
+        }
+    }
+}
+
+/// Huffman code lengths for `counts` (each count + 1)
+fn code_lengths(counts: &[u64]) -> Vec<u8> {
+    let n = counts.len();
+    let mut parent = vec![usize::MAX; 2 * n - 1];
+    let mut heap: BinaryHeap<Reverse<(u64, usize)>> =
+        counts.iter().enumerate().map(|(i, &c)| Reverse((c + 1, i))).collect();
+    let mut next = n;
+    while heap.len() > 1 {
+        let Reverse((wa, a)) = heap.pop().unwrap();
+        let Reverse((wb, b)) = heap.pop().unwrap();
+        parent[a] = next;
+        parent[b] = next;
+        heap.push(Reverse((wa + wb, next)));
+        next += 1;
+    }
+    // Parents always have higher indices, so walk down from the root
+    let mut depth = vec![0u8; 2 * n - 1];
+    for node in (0..2 * n - 2).rev() {
+        depth[node] = depth[parent[node]] + 1;
+    }
+    depth.truncate(n);
+    depth
+}
+
+/// Compresses text as coded token ids using a learned frequency table
+#[derive(Debug, Clone)]
+pub struct TokenCodec<T: Tokenizer> {
+    tokenizer: T,
+    counts: Vec<u64>,
+    code: TokenCode,
+}
+
+impl<T: Tokenizer> TokenCodec<T> {
+    /// Learn token frequencies from `corpus`
+    pub fn learn<S: AsRef<[u8]>>(tokenizer: T, corpus: &[S]) -> Self {
+        let mut counts = vec![0u64; tokenizer.vocab_size()];
+        for doc in corpus {
+            for id in tokenizer.encode(doc.as_ref()) {
+                counts[id as usize] += 1;
+            }
+        }
+        Self::with_counts(tokenizer, counts)
+    }
+
+    /// Codec from previously learned counts; missing ids count as zero
+    pub fn with_counts(tokenizer: T, mut counts: Vec<u64>) -> Self {
+        counts.resize(tokenizer.vocab_size().max(1), 0);
+        let code = TokenCode::from_counts(&counts);
+        Self { tokenizer, counts, code }
+    }
+
+    /// Learned counts, for persisting next to the vocabulary
+    pub fn counts(&self) -> &[u64] {
+        &self.counts
+    }
+
+    pub fn tokenizer(&self) -> &T {
+        &self.tokenizer
+    }
+
+    pub fn compress(&self, text: &[u8]) -> Result<Vec<u8>, CompressError> {
+        let ids = self.tokenizer.encode(text);
+        let mut out = Vec::new();
+        varint::write_u64(&mut out, ids.len() as u64);
+        let mut byte = 0u8;
+        let mut bit_pos = 0;
+        for id in ids {
+            let &(code, len) = self
+                .code
+                .codes
+                .get(id as usize)
+                .ok_or_else(|| CompressError::TokenError(format!("token id {} outside vocabulary", id)))?;
+            for i in (0..len).rev() {
+                byte |= (((code >> i) & 1) as u8) << bit_pos;
+                bit_pos += 1;
+                if bit_pos == 8 {
+                    out.push(byte);
+                    byte = 0;
+                    bit_pos = 0;
+                }
+            }
+        }
+        if bit_pos > 0 {
+            out.push(byte);
+        }
+        Ok(out)
+    }
+
+    pub fn decompress(&self, data: &[u8]) -> Result<Vec<u8>, CompressError> {
+        let mut pos = 0;
+        let count = varint::read_usize(data, &mut pos)
+            .ok_or_else(|| CompressError::TokenError("missing token count".into()))?;
+        let mut ids = Vec::with_capacity(count.min(crate::MAX_PREALLOC));
+        let mut bits = data[pos..].iter().flat_map(|&b| (0..8).map(move |i| (b >> i) & 1));
+        for _ in 0..count {
+            // Canonical decode: `first` is the first code of the current length
+            let (mut code, mut first, mut offset) = (0u64, 0u64, 0u64);
+            let mut len = 0;
+            loop {
+                let bit = bits
+                    .next()
+                    .ok_or_else(|| CompressError::TokenError("truncated bitstream".into()))?;
+                code = code << 1 | bit as u64;
+                first <<= 1;
+                len += 1;
+                let n = *self
+                    .code
+                    .length_counts
+                    .get(len)
+                    .ok_or_else(|| CompressError::TokenError("invalid code".into()))?;
+                if code.wrapping_sub(first) < n {
+                    ids.push(self.code.sorted[(offset + code - first) as usize]);
+                    break;
+                }
+                offset += n;
+                first += n;
+            }
+        }
+        self.tokenizer.decode(&ids)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chat_corpus() -> Vec<String> {
+        (0..60)
+            .map(|i| {
+                format!(
+                    "<|user|> How do I compress conversation log number {}?\n<|assistant|> You can compress the conversation log with the token codec.\n",
+                    i
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_bpe_roundtrip() {
+        let corpus = chat_corpus();
+        let bpe = BpeTokenizer::train(&corpus, 200);
+        assert!(bpe.vocab_size() > 256);
+        let text = "<|assistant|> unseen text: ünïcødé ✓";
+        let ids = bpe.encode(text.as_bytes());
+        assert!(ids.len() < text.len());
+        assert_eq!(bpe.decode(&ids).unwrap(), text.as_bytes());
+    }
+
+    #[test]
+    fn test_token_codec_beats_byte_huffman() {
+        let corpus = chat_corpus();
+        let codec = TokenCodec::learn(BpeTokenizer::train(&corpus, 300), &corpus);
+        let text = "<|user|> How do I compress conversation log number 9000?\n<|assistant|> You can compress the conversation log with the token codec.\n";
+        let compressed = codec.compress(text.as_bytes()).unwrap();
+        assert_eq!(codec.decompress(&compressed).unwrap(), text.as_bytes());
+        let byte_level = crate::huffman::compress(text.as_bytes()).unwrap();
+        assert!(compressed.len() * 2 < byte_level.len());
+    }
+
+    #[test]
+    fn test_restored_codec_decodes_and_rejects_truncation() {
+        let corpus = chat_corpus();
+        let codec = TokenCodec::learn(BpeTokenizer::train(&corpus, 100), &corpus);
+        let compressed = codec.compress(b"<|user|> hi").unwrap();
+        let vocab = serde_json::to_string(codec.tokenizer()).unwrap();
+        let restored = TokenCodec::with_counts(
+            serde_json::from_str::<BpeTokenizer>(&vocab).unwrap(),
+            codec.counts().to_vec(),
+        );
+        assert_eq!(restored.decompress(&compressed).unwrap(), b"<|user|> hi");
+        assert!(restored.decompress(&compressed[..compressed.len() - 1]).is_err());
+    }
+}
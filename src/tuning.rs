@@ -0,0 +1,236 @@
+//! Feedback-driven tuning of block sizes
+//!
+//! [`AutoTuner`] wraps compression calls and measures the ratio and
+//! throughput each block size achieves. Inputs are bucketed into size
+//! classes (by the power of two of their length) since the right block size
+//! for a 200-byte message has nothing to do with the right one for a
+//! multi-gigabyte dump. Within a class, every few calls a neighboring size
+//! (half or double the current best) is tried, and the cheapest setting seen
+//! so far becomes the class default.
+
+use crate::config::{CompressionConfig, TunerConfig};
+use crate::error::CompressError;
+use crate::{CompressedOutput, CompressionMethod, Compressor};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+use std::time::Instant;
+
+/// Tuned settings and measurements of one input size class
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ClassProfile {
+    pub lz4_block_size: usize,
+    pub semantic_block_size: usize,
+    /// Smoothed cost of every LZ4 block size tried
+    pub lz4_costs: BTreeMap<usize, f64>,
+    /// Smoothed cost of every semantic block size tried
+    pub semantic_costs: BTreeMap<usize, f64>,
+    /// Calls measured in this class
+    pub observations: u64,
+}
+
+/// Everything the tuner has learned, keyed by size class
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct TunedProfile {
+    pub classes: BTreeMap<u32, ClassProfile>,
+}
+
+impl TunedProfile {
+    /// Write the profile as JSON
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), CompressError> {
+        let raw = serde_json::to_vec_pretty(self).map_err(|e| CompressError::SerializationError(e.to_string()))?;
+        fs::write(path, raw)?;
+        Ok(())
+    }
+
+    /// Read a profile written by [`TunedProfile::save`]
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, CompressError> {
+        let raw = fs::read(path)?;
+        serde_json::from_slice(&raw).map_err(|e| CompressError::SerializationError(e.to_string()))
+    }
+}
+
+/// Size class of an input of `len` bytes
+pub fn size_class(len: usize) -> u32 {
+    usize::BITS - len.max(1).leading_zeros() - 1
+}
+
+/// Block size knob a method is sensitive to
+#[derive(Clone, Copy)]
+enum Knob {
+    Lz4,
+    Semantic,
+}
+
+impl Knob {
+    fn of(method: CompressionMethod) -> Option<Self> {
+        match method {
+            CompressionMethod::Lz4Semantic => Some(Knob::Lz4),
+            CompressionMethod::SemanticDedupe => Some(Knob::Semantic),
+            _ => None,
+        }
+    }
+}
+
+/// Compresses with block sizes tuned from earlier calls
+pub struct AutoTuner {
+    config: CompressionConfig,
+    tuner: TunerConfig,
+    profile: TunedProfile,
+}
+
+impl AutoTuner {
+    /// Start from `config`'s block sizes with nothing learned yet; a block
+    /// size left at [`crate::config::AUTO`] starts from the machine's
+    pub fn new(mut config: CompressionConfig, tuner: TunerConfig) -> Self {
+        crate::hardware::HardwareProfile::for_config(&config).apply_defaults(&mut config);
+        Self {
+            config,
+            tuner,
+            profile: TunedProfile::default(),
+        }
+    }
+
+    /// Resume from a previously learned profile
+    pub fn with_profile(mut self, profile: TunedProfile) -> Self {
+        self.profile = profile;
+        self
+    }
+
+    pub fn profile(&self) -> &TunedProfile {
+        &self.profile
+    }
+
+    /// Config the next call for an input of `len` bytes would run with,
+    /// ignoring exploration
+    pub fn config_for(&self, len: usize) -> CompressionConfig {
+        let mut config = self.config.clone();
+        if let Some(class) = self.profile.classes.get(&size_class(len)) {
+            config.lz4_block_size = class.lz4_block_size;
+            config.semantic_block_size = class.semantic_block_size;
+        }
+        config
+    }
+
+    /// Compress `data`, measure the result and update the profile
+    pub fn compress(&mut self, data: &[u8], method: CompressionMethod) -> Result<CompressedOutput, CompressError> {
+        let class = size_class(data.len());
+        let mut config = self.config_for(data.len());
+        let observations = self.profile.classes.get(&class).map_or(0, |c| c.observations);
+        let interval = self.tuner.explore_interval.max(1);
+        if observations % interval == interval - 1 {
+            // Alternate between halving and doubling on successive explorations
+            let grow = (observations / interval).is_multiple_of(2);
+            config.lz4_block_size = self.neighbor(
+                config.lz4_block_size,
+                grow,
+                self.tuner.min_lz4_block_size,
+                self.tuner.max_lz4_block_size,
+            );
+            config.semantic_block_size = self.neighbor(
+                config.semantic_block_size,
+                grow,
+                self.tuner.min_semantic_block_size,
+                self.tuner.max_semantic_block_size,
+            );
+        }
+
+        let started = Instant::now();
+        let output = Compressor::new(config.clone()).compress(data, method)?;
+        let seconds_per_mib = started.elapsed().as_secs_f64() * (1024.0 * 1024.0) / data.len() as f64;
+        let cost = output.ratio + self.tuner.time_weight * seconds_per_mib;
+
+        let entry = self.profile.classes.entry(class).or_insert_with(|| ClassProfile {
+            lz4_block_size: self.config.lz4_block_size,
+            semantic_block_size: self.config.semantic_block_size,
+            lz4_costs: BTreeMap::new(),
+            semantic_costs: BTreeMap::new(),
+            observations: 0,
+        });
+        entry.observations += 1;
+        let (costs, size, current) = match Knob::of(output.method) {
+            Some(Knob::Lz4) => (&mut entry.lz4_costs, config.lz4_block_size, &mut entry.lz4_block_size),
+            Some(Knob::Semantic) => (
+                &mut entry.semantic_costs,
+                config.semantic_block_size,
+                &mut entry.semantic_block_size,
+            ),
+            None => return Ok(output),
+        };
+        let smoothing = self.tuner.smoothing;
+        costs
+            .entry(size)
+            .and_modify(|c| *c = *c * (1.0 - smoothing) + cost * smoothing)
+            .or_insert(cost);
+        if let Some((&best, _)) = costs.iter().min_by(|a, b| a.1.total_cmp(b.1)) {
+            *current = best;
+        }
+        Ok(output)
+    }
+
+    fn neighbor(&self, size: usize, grow: bool, min: usize, max: usize) -> usize {
+        let next = if grow { size.saturating_mul(2) } else { size / 2 };
+        next.clamp(min.max(1), max.max(min))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ratio_only() -> TunerConfig {
+        TunerConfig {
+            time_weight: 0.0,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_size_class() {
+        assert_eq!(size_class(0), 0);
+        assert_eq!(size_class(1), 0);
+        assert_eq!(size_class(1024), 10);
+        assert_eq!(size_class(2047), 10);
+    }
+
+    #[test]
+    fn test_tuner_grows_small_lz4_blocks() {
+        let config = CompressionConfig {
+            lz4_block_size: 1024,
+            ..Default::default()
+        };
+        let mut tuner = AutoTuner::new(config, ratio_only());
+        let data = "tune the block size for this stream of records ".repeat(2000);
+        for _ in 0..24 {
+            let out = tuner.compress(data.as_bytes(), CompressionMethod::Lz4Semantic).unwrap();
+            assert_eq!(Compressor::default().decompress(&out).unwrap(), data.as_bytes());
+        }
+        let class = &tuner.profile().classes[&size_class(data.len())];
+        assert_eq!(class.observations, 24);
+        assert!(class.lz4_costs.len() > 1);
+        assert!(class.lz4_block_size > 1024);
+        assert_eq!(tuner.config_for(data.len()).lz4_block_size, class.lz4_block_size);
+        assert_eq!(tuner.config_for(10).lz4_block_size, 1024);
+    }
+
+    #[test]
+    fn test_profile_persists() {
+        let mut tuner = AutoTuner::new(CompressionConfig::default(), ratio_only());
+        let data = vec![7u8; 5000];
+        for _ in 0..8 {
+            tuner.compress(&data, CompressionMethod::SemanticDedupe).unwrap();
+        }
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("profile.json");
+        tuner.profile().save(&path).unwrap();
+        let loaded = TunedProfile::load(&path).unwrap();
+        assert_eq!(&loaded, tuner.profile());
+
+        let resumed = AutoTuner::new(CompressionConfig::default(), ratio_only()).with_profile(loaded);
+        assert_eq!(
+            resumed.config_for(data.len()).semantic_block_size,
+            tuner.config_for(data.len()).semantic_block_size
+        );
+    }
+}
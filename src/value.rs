@@ -0,0 +1,153 @@
+//! Serialize-and-compress for serde values
+//!
+//! Consumers storing structured data used to serialize with one format and
+//! compress in a second step, then had to remember the format on the way
+//! back; a reader guessing JSON for a bincode blob fails in confusing ways.
+//! [`Compressor::compress_value`] does both steps and records the format in
+//! a small envelope around the frame, so [`Compressor::decompress_value`]
+//! always deserializes with the format the value was written in.
+//!
+//! Layout:
+//!
+//! ```text
+//! [SGVL][version u8][format u8][frame]
+//! ```
+
+use crate::error::CompressError;
+use crate::{CompressionMethod, Compressor};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+/// Magic bytes opening a compressed value
+pub const VALUE_MAGIC: [u8; 4] = *b"SGVL";
+
+/// Value envelope version
+pub const VALUE_VERSION: u8 = 1;
+
+const HEADER_LEN: usize = 6;
+
+/// Serialization format of a compressed value
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ValueFormat {
+    /// Compact binary, not self-describing
+    Bincode,
+    Json,
+    Cbor,
+}
+
+impl ValueFormat {
+    /// Stable numeric identifier stored in the envelope
+    pub fn id(self) -> u8 {
+        match self {
+            ValueFormat::Bincode => 1,
+            ValueFormat::Json => 2,
+            ValueFormat::Cbor => 3,
+        }
+    }
+
+    /// Inverse of [`ValueFormat::id`]
+    pub fn from_id(id: u8) -> Option<Self> {
+        match id {
+            1 => Some(ValueFormat::Bincode),
+            2 => Some(ValueFormat::Json),
+            3 => Some(ValueFormat::Cbor),
+            _ => None,
+        }
+    }
+
+    fn serialize<T: Serialize + ?Sized>(self, value: &T) -> Result<Vec<u8>, CompressError> {
+        let error = |e: &dyn std::fmt::Display| CompressError::SerializationError(e.to_string());
+        match self {
+            ValueFormat::Bincode => bincode::serialize(value).map_err(|e| error(&e)),
+            ValueFormat::Json => serde_json::to_vec(value).map_err(|e| error(&e)),
+            ValueFormat::Cbor => {
+                let mut out = Vec::new();
+                ciborium::into_writer(value, &mut out).map_err(|e| error(&e))?;
+                Ok(out)
+            }
+        }
+    }
+
+    fn deserialize<T: DeserializeOwned>(self, data: &[u8]) -> Result<T, CompressError> {
+        let error = |e: &dyn std::fmt::Display| CompressError::SerializationError(e.to_string());
+        match self {
+            ValueFormat::Bincode => bincode::deserialize(data).map_err(|e| error(&e)),
+            ValueFormat::Json => serde_json::from_slice(data).map_err(|e| error(&e)),
+            ValueFormat::Cbor => ciborium::from_reader(data).map_err(|e| error(&e)),
+        }
+    }
+}
+
+/// Serialization format recorded in a compressed value
+pub fn value_format(data: &[u8]) -> Result<ValueFormat, CompressError> {
+    if data.len() < HEADER_LEN || data[..4] != VALUE_MAGIC {
+        return Err(CompressError::SerializationError("not a compressed value".into()));
+    }
+    if data[4] != VALUE_VERSION {
+        return Err(CompressError::SerializationError(format!("unsupported value version {}", data[4])));
+    }
+    ValueFormat::from_id(data[5])
+        .ok_or_else(|| CompressError::SerializationError(format!("unknown value format {}", data[5])))
+}
+
+impl Compressor {
+    /// Serialize `value` in `format` and compress it with the adaptive method
+    pub fn compress_value<T: Serialize + ?Sized>(&self, value: &T, format: ValueFormat) -> Result<Vec<u8>, CompressError> {
+        let frame = self.compress(&format.serialize(value)?, CompressionMethod::Auto)?.to_frame();
+        let mut out = Vec::with_capacity(HEADER_LEN + frame.len());
+        out.extend_from_slice(&VALUE_MAGIC);
+        out.push(VALUE_VERSION);
+        out.push(format.id());
+        out.extend_from_slice(&frame);
+        Ok(out)
+    }
+
+    /// Decompress a value written by [`Compressor::compress_value`] and
+    /// deserialize it in the format it was written in
+    pub fn decompress_value<T: DeserializeOwned>(&self, data: &[u8]) -> Result<T, CompressError> {
+        let format = value_format(data)?;
+        format.deserialize(&self.decompress_frame(&data[HEADER_LEN..])?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    #[derive(Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+    struct Reading {
+        sensor: String,
+        samples: Vec<f64>,
+        tags: BTreeMap<String, u32>,
+    }
+
+    #[test]
+    fn test_value_roundtrip_all_formats() {
+        let compressor = Compressor::default();
+        let reading = Reading {
+            sensor: "boiler-7".into(),
+            samples: (0..500).map(|i| f64::from(i % 20) * 0.5).collect(),
+            tags: [("site".to_string(), 3), ("rack".to_string(), 12)].into_iter().collect(),
+        };
+        for format in [ValueFormat::Bincode, ValueFormat::Json, ValueFormat::Cbor] {
+            let packed = compressor.compress_value(&reading, format).unwrap();
+            assert_eq!(value_format(&packed).unwrap(), format);
+            assert_eq!(compressor.decompress_value::<Reading>(&packed).unwrap(), reading);
+        }
+    }
+
+    #[test]
+    fn test_value_rejects_bad_envelopes() {
+        let compressor = Compressor::default();
+        let frame = compressor.compress(b"not a value", CompressionMethod::Stored).unwrap().to_frame();
+        assert!(compressor.decompress_value::<String>(&frame).is_err());
+
+        let mut packed = compressor.compress_value("text", ValueFormat::Json).unwrap();
+        packed[5] = 0x7F;
+        assert!(matches!(compressor.decompress_value::<String>(&packed), Err(CompressError::SerializationError(_))));
+        packed[5] = ValueFormat::Json.id();
+        assert!(compressor.decompress_value::<u64>(&packed).is_err());
+        assert_eq!(compressor.decompress_value::<String>(&packed).unwrap(), "text");
+    }
+}
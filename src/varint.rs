@@ -0,0 +1,90 @@
+//! LEB128 variable-length integers used for lengths and counts in codec payloads
+
+/// Append `value` as an unsigned LEB128 varint
+pub fn write_u64(out: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        out.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+/// Read a varint at `*pos`, advancing past it; `None` if truncated or overlong
+pub fn read_u64(data: &[u8], pos: &mut usize) -> Option<u64> {
+    let mut value = 0u64;
+    let mut p = *pos;
+    for shift in (0..64).step_by(7) {
+        let byte = *data.get(p)?;
+        p += 1;
+        let bits = (byte & 0x7F) as u64;
+        if shift == 63 && bits > 1 {
+            return None;
+        }
+        value |= bits << shift;
+        if byte & 0x80 == 0 {
+            *pos = p;
+            return Some(value);
+        }
+    }
+    None
+}
+
+/// [`read_u64`] narrowed to `usize`
+pub fn read_usize(data: &[u8], pos: &mut usize) -> Option<usize> {
+    read_u64(data, pos).and_then(|v| usize::try_from(v).ok())
+}
+
+/// Append `value` zigzag-encoded, so small magnitudes of either sign stay short
+pub fn write_i64(out: &mut Vec<u8>, value: i64) {
+    write_u64(out, ((value << 1) ^ (value >> 63)) as u64);
+}
+
+/// Read a varint written by [`write_i64`]
+pub fn read_i64(data: &[u8], pos: &mut usize) -> Option<i64> {
+    let value = read_u64(data, pos)?;
+    Some((value >> 1) as i64 ^ -((value & 1) as i64))
+}
+
+/// Encoded length of `value`
+pub fn encoded_len(value: u64) -> usize {
+    (64 - (value | 1).leading_zeros() as usize).div_ceil(7)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_boundaries_roundtrip() {
+        for value in [0, 127, 128, 16383, 16384, u32::MAX as u64, u32::MAX as u64 + 1, u64::MAX] {
+            let mut out = Vec::new();
+            write_u64(&mut out, value);
+            assert_eq!(out.len(), encoded_len(value), "length of {}", value);
+            let mut pos = 0;
+            assert_eq!(read_u64(&out, &mut pos), Some(value));
+            assert_eq!(pos, out.len());
+        }
+    }
+
+    #[test]
+    fn test_zigzag_roundtrip() {
+        for value in [0, -1, 1, -64, 63, -65, i64::MIN, i64::MAX] {
+            let mut out = Vec::new();
+            write_i64(&mut out, value);
+            let mut pos = 0;
+            assert_eq!(read_i64(&out, &mut pos), Some(value));
+        }
+        let mut out = Vec::new();
+        write_i64(&mut out, -1);
+        assert_eq!(out, [1]);
+    }
+
+    #[test]
+    fn test_rejects_truncated_and_overlong() {
+        let mut pos = 0;
+        assert_eq!(read_u64(&[0x80, 0x80], &mut pos), None);
+        assert_eq!(pos, 0);
+        assert_eq!(read_u64(&[0xFF; 10], &mut pos), None);
+        assert_eq!(read_u64(&[0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x02], &mut pos), None);
+    }
+}
@@ -0,0 +1,155 @@
+//! Warm starts: exporting what a compressor has learned
+//!
+//! A fresh [`Compressor`] knows nothing about the data it will see. A
+//! long-lived one accumulates two kinds of knowledge: dictionaries trained on
+//! representative content and the [`MethodHistory`] of which method wins for
+//! which content profile. Short-lived workers rediscover both on every start.
+//! [`Compressor::warm_up`] gathers that knowledge from sample inputs in one
+//! call, [`Compressor::export_state`] captures it together with the
+//! configuration, and [`Compressor::from_state`] rebuilds an equally warm
+//! compressor in another process.
+//!
+//! Codec tables (Huffman code lengths, LZ windows) are built per input and
+//! travel inside every frame, so they are not part of the state. Frames
+//! written with a trained dictionary need that dictionary to decode, which
+//! the imported state provides.
+//!
+//! States serialize as JSON carrying [`STATE_VERSION`].
+
+use crate::config::CompressionConfig;
+use crate::dictionary::{self, DictionaryRegistry, DictionarySelection, SavedDictionary};
+use crate::error::CompressError;
+use crate::history::MethodHistory;
+use crate::Compressor;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+
+/// State format version written by this build
+pub const STATE_VERSION: u32 = 1;
+
+/// Name of the dictionary [`Compressor::warm_up`] trains
+pub const WARM_UP_DICTIONARY: &str = "warm-up";
+
+/// Configuration, trained dictionaries and method history of a compressor
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CompressorState {
+    pub version: u32,
+    pub config: CompressionConfig,
+    pub history: Option<MethodHistory>,
+    dictionaries: Vec<SavedDictionary>,
+}
+
+impl CompressorState {
+    pub fn to_bytes(&self) -> Result<Vec<u8>, CompressError> {
+        serde_json::to_vec(self).map_err(|e| CompressError::SerializationError(e.to_string()))
+    }
+
+    /// Parse a state from [`CompressorState::to_bytes`]
+    pub fn from_bytes(data: &[u8]) -> Result<Self, CompressError> {
+        let state: Self = serde_json::from_slice(data).map_err(|e| CompressError::SerializationError(e.to_string()))?;
+        if state.version != STATE_VERSION {
+            return Err(CompressError::SerializationError(format!(
+                "unsupported compressor state version {}",
+                state.version
+            )));
+        }
+        Ok(state)
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), CompressError> {
+        fs::write(path, self.to_bytes()?)?;
+        Ok(())
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, CompressError> {
+        Self::from_bytes(&fs::read(path)?)
+    }
+
+    /// Number of non-built-in dictionaries carried
+    pub fn dictionary_count(&self) -> usize {
+        self.dictionaries.len()
+    }
+}
+
+impl Compressor {
+    /// Learn from representative `samples`: train a dictionary on them
+    /// (selected automatically from then on unless a dictionary is already
+    /// configured) and record the winning method for each with
+    /// [`Compressor::compress_adaptive`], attaching a history if there is none
+    pub fn warm_up<S: AsRef<[u8]>>(mut self, samples: &[S]) -> Result<Self, CompressError> {
+        let trained = dictionary::train(WARM_UP_DICTIONARY, samples)?;
+        if !trained.entries.is_empty() {
+            self.dictionaries.insert(trained)?;
+            if self.config.dictionary == DictionarySelection::None {
+                self.config.dictionary = DictionarySelection::Auto;
+            }
+        }
+        if self.history.is_none() {
+            self = self.with_history(MethodHistory::default());
+        }
+        for sample in samples.iter().map(AsRef::as_ref).filter(|s| !s.is_empty()) {
+            self.compress_adaptive(sample)?;
+        }
+        Ok(self)
+    }
+
+    /// Everything needed to start another compressor as warm as this one
+    pub fn export_state(&self) -> CompressorState {
+        CompressorState {
+            version: STATE_VERSION,
+            config: self.config.clone(),
+            history: self.history(),
+            dictionaries: self.dictionaries.saved(),
+        }
+    }
+
+    /// Compressor with the configuration, dictionaries and history of `state`
+    pub fn from_state(state: CompressorState) -> Result<Self, CompressError> {
+        let compressor =
+            Compressor::new(state.config).with_dictionaries(DictionaryRegistry::from_saved(state.dictionaries)?);
+        Ok(match state.history {
+            Some(history) => compressor.with_history(history),
+            None => compressor,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(i: usize) -> Vec<u8> {
+        (0..40)
+            .flat_map(|j| {
+                format!("{{\"service\":\"checkout\",\"request\":{},\"span\":{},\"status\":\"ok\"}}\n", i, j).into_bytes()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_exported_state_restores_warm_compressor() {
+        let samples: Vec<Vec<u8>> = (0..8).map(record).collect();
+        let warm = Compressor::default().warm_up(&samples).unwrap();
+        let state = warm.export_state();
+        assert_eq!(state.dictionary_count(), 1);
+        assert!(!state.history.as_ref().unwrap().records.is_empty());
+
+        let restored = Compressor::from_state(CompressorState::from_bytes(&state.to_bytes().unwrap()).unwrap()).unwrap();
+        assert_eq!(restored.history(), warm.history());
+        assert_eq!(restored.dictionaries().ids(), warm.dictionaries().ids());
+        // Frames written by either decode with the other
+        let frame = warm.compress_adaptive(&record(99)).unwrap().to_frame();
+        assert_eq!(restored.decompress_frame(&frame).unwrap(), record(99));
+        assert!(Compressor::default().decompress_frame(&frame).is_err());
+    }
+
+    #[test]
+    fn test_state_version_checked() {
+        let mut state = Compressor::default().export_state();
+        assert_eq!((state.dictionary_count(), state.history.is_none()), (0, true));
+        state.version = STATE_VERSION + 1;
+        assert!(CompressorState::from_bytes(&state.to_bytes().unwrap()).is_err());
+        assert!(CompressorState::from_bytes(b"{").is_err());
+    }
+}
@@ -0,0 +1,144 @@
+//! Golden files pinning the encoded format of every method
+//!
+//! `tests/golden/input` holds fixed inputs and `tests/golden/v<N>` the frame
+//! each method wrote for each input under frame format version N, next to
+//! `versions.json`, the [`FormatVersion`] they were written with. Frames of
+//! the current version must decode to their input and be reproduced byte for
+//! byte, so a change to an encoder or a decoder that breaks compatibility
+//! fails here. After an intentional format change, bump the version and
+//! regenerate with `SIGMA_BLESS_GOLDEN=1 cargo test --test golden_test`.
+//!
+//! The dedup payload inside `SemanticDedupe` and `SemanticLz` frames has a
+//! version of its own; frames written at earlier ones are kept under
+//! `tests/golden/semantic-v<N>` and must keep decoding. Move the current
+//! frames there before blessing a new dedup version.
+
+#![cfg(feature = "std")]
+
+use sigma_compress::frame::FormatVersion;
+use sigma_compress::config::{CompressionConfig, LzssConfig, DEFAULT_HASH_BITS, DEFAULT_LZ4_BLOCK_SIZE};
+use sigma_compress::*;
+use std::fs;
+use std::path::PathBuf;
+
+/// Methods whose frames carry a versioned dedup payload
+const SEMANTIC_METHODS: [CompressionMethod; 2] = [CompressionMethod::SemanticDedupe, CompressionMethod::SemanticLz];
+
+/// Dedup payload versions before the current one
+const PREVIOUS_SEMANTIC_VERSIONS: [u8; 2] = [1, 2];
+
+const METHODS: [CompressionMethod; 8] = [
+    CompressionMethod::Huffman,
+    CompressionMethod::Lz4Semantic,
+    CompressionMethod::EntropyCoding,
+    CompressionMethod::SemanticDedupe,
+    CompressionMethod::LogDedupe,
+    CompressionMethod::Stored,
+    CompressionMethod::Lzss,
+    CompressionMethod::SemanticLz,
+];
+
+/// Compressor whose settings do not depend on the machine running the tests
+fn compressor() -> Compressor {
+    Compressor::new(CompressionConfig {
+        lz4_block_size: DEFAULT_LZ4_BLOCK_SIZE,
+        lzss: LzssConfig {
+            hash_bits: DEFAULT_HASH_BITS,
+            ..LzssConfig::default()
+        },
+        ..CompressionConfig::default()
+    })
+}
+
+fn golden_dir() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/golden")
+}
+
+fn bless() -> bool {
+    std::env::var_os("SIGMA_BLESS_GOLDEN").is_some()
+}
+
+/// `(name, contents)` of every fixed input
+fn inputs() -> Vec<(String, Vec<u8>)> {
+    let mut inputs: Vec<(String, Vec<u8>)> = fs::read_dir(golden_dir().join("input"))
+        .unwrap()
+        .map(|entry| {
+            let path = entry.unwrap().path();
+            (path.file_name().unwrap().to_string_lossy().into_owned(), fs::read(&path).unwrap())
+        })
+        .collect();
+    inputs.sort();
+    assert!(!inputs.is_empty());
+    inputs
+}
+
+fn golden_path(input: &str, method: CompressionMethod) -> PathBuf {
+    golden_dir()
+        .join(format!("v{}", FormatVersion::current().frame))
+        .join(format!("{}.{:?}.sgma", input, method))
+}
+
+#[test]
+fn test_format_versions_match_golden() {
+    let path = golden_dir().join("versions.json");
+    let current = FormatVersion::current();
+    if bless() {
+        fs::write(&path, serde_json::to_vec_pretty(&current).unwrap()).unwrap();
+    }
+    let recorded: FormatVersion = serde_json::from_slice(&fs::read(&path).unwrap()).unwrap();
+    assert_eq!(recorded, current, "format version changed; regenerate the golden files");
+}
+
+#[test]
+fn test_golden_frames_decode() {
+    let compressor = compressor();
+    for (name, data) in inputs() {
+        for method in METHODS {
+            let path = golden_path(&name, method);
+            if bless() {
+                let frame = compressor.compress(&data, method).unwrap().to_frame();
+                fs::create_dir_all(path.parent().unwrap()).unwrap();
+                fs::write(&path, frame).unwrap();
+            }
+            let frame = fs::read(&path).unwrap_or_else(|e| panic!("{}: {}", path.display(), e));
+            let header = frame::FrameHeader::parse(&frame).unwrap();
+            assert_eq!(header.method, method, "{}", path.display());
+            assert_eq!(compressor.decompress_frame(&frame).unwrap(), data, "{}", path.display());
+        }
+    }
+}
+
+#[test]
+fn test_encoders_reproduce_golden_frames() {
+    if bless() {
+        // Being rewritten by `test_golden_frames_decode`
+        return;
+    }
+    let compressor = compressor();
+    for (name, data) in inputs() {
+        for method in METHODS {
+            let path = golden_path(&name, method);
+            let expected = fs::read(&path).unwrap_or_else(|e| panic!("{}: {}", path.display(), e));
+            let frame = compressor.compress(&data, method).unwrap().to_frame();
+            assert!(frame == expected, "{:?} no longer encodes {} as in {}", method, name, path.display());
+        }
+    }
+}
+
+#[test]
+fn test_previous_semantic_payloads_decode() {
+    let compressor = compressor();
+    assert!(PREVIOUS_SEMANTIC_VERSIONS.iter().all(|&v| v < FormatVersion::current().semantic));
+    for version in PREVIOUS_SEMANTIC_VERSIONS {
+        for (name, data) in inputs() {
+            for method in SEMANTIC_METHODS {
+                let path = golden_dir()
+                    .join(format!("semantic-v{}", version))
+                    .join(format!("{}.{:?}.sgma", name, method));
+                let frame = fs::read(&path).unwrap_or_else(|e| panic!("{}: {}", path.display(), e));
+                assert!(frame != fs::read(golden_path(&name, method)).unwrap(), "{}", path.display());
+                assert_eq!(compressor.decompress_frame(&frame).unwrap(), data, "{}", path.display());
+            }
+        }
+    }
+}
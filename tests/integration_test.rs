@@ -1,5 +1,7 @@
 //! Integration tests for sigma-compress
 
+#![cfg(feature = "std")]
+
 use sigma_compress::*;
 
 #[test]
@@ -22,6 +24,10 @@ fn test_all_methods_roundtrip() {
         CompressionMethod::Lz4Semantic,
         CompressionMethod::EntropyCoding,
         CompressionMethod::SemanticDedupe,
+        CompressionMethod::LogDedupe,
+        CompressionMethod::Stored,
+        CompressionMethod::Lzss,
+        CompressionMethod::SemanticLz,
     ] {
         let compressed = compressor.compress(data, method).unwrap();
         let decompressed = compressor.decompress(&compressed).unwrap();
@@ -86,3 +92,22 @@ fn test_empty_input_error() {
     let result = compressor.compress(b"", CompressionMethod::Huffman);
     assert!(result.is_err());
 }
+
+#[test]
+fn test_cli_list_archive() {
+    use sigma_compress::archive::Archive;
+    let dir = tempfile::tempdir().unwrap();
+    let mut archive = Archive::create(dir.path()).unwrap();
+    archive.add_file("src/lib.rs", b"pub fn listed() {}").unwrap();
+    archive.commit().unwrap();
+
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_sigma-compress"))
+        .arg("list")
+        .arg(dir.path())
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.trim_end().ends_with(" src/lib.rs"));
+    assert!(stdout.contains(&format!("{:08x}", crc32fast::hash(b"pub fn listed() {}"))));
+}